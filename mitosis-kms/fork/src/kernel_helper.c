@@ -2,5 +2,6 @@
 
 #define DEFAULT_PERMISSION S_IRUSR | S_IWUSR | S_IRGRP | S_IROTH
 
+// a negative ID means that the ID is negotiated with the peers
 long mac_id = 0;
 module_param(mac_id, long, DEFAULT_PERMISSION);
@@ -12,7 +12,7 @@ use mitosis::syscalls::*;
 
 use mitosis_macros::declare_module_param; 
 
-declare_module_param!(mac_id, u64);
+declare_module_param!(mac_id, i64);
 
 /// The module corresponding to the kernel module lifetime
 #[allow(dead_code)]
@@ -26,7 +26,11 @@ impl linux_kernel_module::KernelModule for Module {
     /// Called by the kernel upon the kernel module creation
     fn init() -> linux_kernel_module::KernelResult<Self> {
         let id = mac_id::read();
-        log::info!("Remote fork kernel module assigned ID={}", id);
+        if id < 0 {
+            log::info!("Remote fork kernel module uses auto-assigned ID");
+        } else {
+            log::info!("Remote fork kernel module assigned ID={}", id);
+        }
 
         // Currently, we use a default configuration of MITOSIS
         let mut config: mitosis::Config = Default::default();
@@ -35,7 +39,8 @@ impl linux_kernel_module::KernelModule for Module {
             .set_num_nics_used(1)
             .set_rpc_threads(2)
             .set_init_dc_targets(12)
-            .set_machine_id(id as usize);
+            .set_machine_id(core::cmp::max(id, 0) as usize)
+            .set_auto_machine_id(id < 0);
 
         assert!(start_instance(config.clone()).is_some());
 
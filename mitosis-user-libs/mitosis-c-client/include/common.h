@@ -1,22 +1,167 @@
-//#include <stdint.h>
+// Generated from mitosis-user-libs/mitosis-protocol, do not edit.
+// Re-generate with: cargo run --example gen_c_header > <this file>
+#pragma once
+
+#define MITOSIS_ABI_VERSION 11
+#define MITOSIS_GID_STR_LEN 39
+
+#define MITOSIS_REGION_EXCLUDE 1
+#define MITOSIS_REGION_HOT 2
+#define MITOSIS_MAX_PREPARE_REGIONS 64
+#define MITOSIS_PREPARE_PING 1
+#define MITOSIS_PREPARE_UPDATE 2
+#define MITOSIS_PREPARE_COMPRESS 4
+#define MITOSIS_PREPARE_DEDUP 8
+
+#define MITOSIS_INPUT_SET_RET 1
+#define MITOSIS_RESUME_NOTIFY_EXIT 2
+#define MITOSIS_CHILD_DETACHED (-1)
+#define MITOSIS_MAX_RESUME_INPUT 65536
+
+#define MITOSIS_DEFAULT_MATERIALIZE_BATCH 32
+#define MITOSIS_MAX_MATERIALIZE_BATCH 1024
+#define MITOSIS_MATERIALIZE_NONE 0
+#define MITOSIS_MATERIALIZE_RUNNING 1
+#define MITOSIS_MATERIALIZE_DONE 2
+#define MITOSIS_MATERIALIZE_FAILED 3
+
+#define MITOSIS_IMAGE_EVICTABLE 1
+
+#define MITOSIS_FEATURE_COW 0x1ULL // copy-on-write prepare
+#define MITOSIS_FEATURE_EAGER_RESUME 0x2ULL // eager resume
+#define MITOSIS_FEATURE_PREFETCH 0x4ULL // prefetch at page faults
+#define MITOSIS_FEATURE_PAGE_CACHE 0x8ULL // cache remote page tables and pages
+#define MITOSIS_FEATURE_USE_RC 0x10ULL // use RC instead of DCT
+#define MITOSIS_FEATURE_RESUME_PROFILE 0x20ULL // profile the resume
+#define MITOSIS_FEATURE_AUTO_MACHINE_ID 0x40ULL // negotiated machine ID
 
 enum LibMITOSISCmd {
-    Nil = 0,  // for test only
+    Nil = 0, // for test only
     Connect = 3, // connect to remote session
     Prepare = 4, // prepare the memory mapping of this process
     ResumeLocal = 5, // resume to another process
     ResumeRemote = 6, // resume to another process of remote via RPC
     PreparePing = 7, // Prepare the memory mapping of this process (and ping it in kernel)
-    NilRPC = 8,     // Call the nil RPC function
+    NilRPC = 8, // Call the nil RPC function
+    Leave = 9, // leave the cluster, so that the machine can re-join later
+    GetInfo = 10, // query the ABI version, features and limits of the kernel
+    ResumeRemoteAsync = 11, // submit a resume of remote, which returns before the image is fetched
+    ResumeCommit = 12, // commit the image fetched by ResumeRemoteAsync, EAGAIN if not fetched yet
+    PrepareWHints = 13, // prepare (or refresh) with the address ranges to exclude or to transfer eagerly
+    ResumeRemoteWInput = 14, // resume to another process of remote, with the input (and return value) of the child
+    PollChildExit = 15, // pop the report of an exited child of the prepared image, EAGAIN if none
+    Materialize = 16, // fetch the remaining pages in the background, then detach from the parent
+    MaterializeStatus = 17, // query the progress of Materialize
+    DetachImage = 18, // keep the prepared image registered after the caller exits
+    DropImage = 19, // unregister a detached image of the caller's user
+    ImageStat = 20, // query the stats of the prepared image, e.g., the compression ratio
 };
 
 typedef struct {
-    unsigned int machine_id; // should not be zero!
+    unsigned int machine_id; // a hint only, the real ID is negotiated by the kernel
     unsigned int nic_id; // nic idx according to gid
-    const char *gid;
+    const char *gid; // points to a GID string of MITOSIS_GID_STR_LEN bytes
 } connect_req_t;
 
 typedef struct {
     unsigned int machine_id;    // keep `machine_id` the same as that in `connect_req_t`
     unsigned int handler_id;
-} resume_remote_req_t;
\ No newline at end of file
+} resume_remote_req_t;
+
+typedef struct {
+    unsigned int machine_id;
+    unsigned int handler_id;
+    int eventfd; // signaled once the image is fetched, -1 to poll ResumeCommit instead
+    unsigned int reserved;
+} resume_async_req_t;
+
+typedef struct {
+    unsigned long long start;
+    unsigned long long end; // exclusive, extended to page boundaries by the kernel
+    unsigned int hint; // MITOSIS_REGION_*
+    unsigned int reserved;
+} prepare_region_t;
+
+typedef struct {
+    unsigned long long key;
+    const prepare_region_t *regions;
+    unsigned int region_count; // at most MITOSIS_MAX_PREPARE_REGIONS
+    unsigned int flags; // MITOSIS_PREPARE_* bits
+} prepare_req_t;
+
+typedef struct {
+    unsigned int machine_id;
+    unsigned int handler_id;
+    const void *input; // copied before the resume
+    unsigned long long input_addr; // where to write the input in the child
+    unsigned int input_len; // at most MITOSIS_MAX_RESUME_INPUT, 0 for no input
+    unsigned int flags; // MITOSIS_INPUT_SET_RET | MITOSIS_RESUME_NOTIFY_EXIT
+    unsigned int ret; // returned by the child if MITOSIS_INPUT_SET_RET, at most INT_MAX
+    unsigned int reserved;
+} resume_input_req_t;
+
+typedef struct {
+    unsigned int machine_id; // the machine of the child
+    unsigned int handler_id;
+    int status; // as reported by wait(2), or MITOSIS_CHILD_DETACHED
+    unsigned int reserved;
+    unsigned long long runtime_usec;
+    unsigned long long fault_pages;
+} child_exit_info_t;
+
+typedef struct {
+    unsigned int pages_per_sec; // 0 for no limit
+    unsigned int batch; // at most MITOSIS_MAX_MATERIALIZE_BATCH, 0 for the default
+} materialize_req_t;
+
+typedef struct {
+    unsigned int state; // MITOSIS_MATERIALIZE_*
+    unsigned int reserved;
+    unsigned long long fetched_pages;
+    unsigned long long total_pages; // an upper bound of the pages to fetch
+} materialize_status_t;
+
+typedef struct {
+    unsigned int ttl_sec; // 0 to keep the image until dropped
+    unsigned int flags; // MITOSIS_IMAGE_EVICTABLE
+} detach_image_req_t;
+
+typedef struct {
+    unsigned long long pages;
+    unsigned long long zero_pages; // not transferred
+    unsigned long long compressed_pages; // with MITOSIS_PREPARE_COMPRESS
+    unsigned long long stored_bytes; // read by a child to fetch all the pages
+    unsigned long long dedup_pages; // with MITOSIS_PREPARE_DEDUP
+    unsigned long long host_dedup_pages; // saved across all the images on the host
+} image_stat_t;
+
+typedef struct {
+    unsigned int abi_version; // check it against MITOSIS_ABI_VERSION before using other fields
+    unsigned int size;
+    unsigned long long features; // MITOSIS_FEATURE_* bits
+    unsigned int machine_id;
+    unsigned int nic_count;
+    unsigned int max_cluster_size;
+    unsigned int max_caller_num;
+    unsigned int prefetch_step;
+    unsigned int reserved;
+} mitosis_info_t;
+
+#ifdef __cplusplus
+#define MITOSIS_STATIC_ASSERT static_assert
+#else
+#define MITOSIS_STATIC_ASSERT _Static_assert
+#endif
+
+MITOSIS_STATIC_ASSERT(sizeof(connect_req_t) == 16, "connect_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(resume_remote_req_t) == 8, "resume_remote_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(resume_async_req_t) == 16, "resume_async_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(prepare_region_t) == 24, "prepare_region_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(prepare_req_t) == 24, "prepare_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(resume_input_req_t) == 40, "resume_input_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(child_exit_info_t) == 32, "child_exit_info_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(materialize_req_t) == 8, "materialize_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(materialize_status_t) == 24, "materialize_status_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(detach_image_req_t) == 8, "detach_image_req_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(image_stat_t) == 48, "image_stat_t mismatches the kernel");
+MITOSIS_STATIC_ASSERT(sizeof(mitosis_info_t) == 40, "mitosis_info_t mismatches the kernel");
@@ -20,6 +20,10 @@ call_nil(int sd) {
     return 0;
 }
 
+/*
+  Connect to the remote machine with the gid (addr).
+  Return the remote machine ID, or -1 on failure.
+ */
 static inline int
 call_connect(int sd, const char *addr, unsigned int mac_id, unsigned int nic_id) {
     connect_req_t req;
@@ -27,7 +31,24 @@ call_connect(int sd, const char *addr, unsigned int mac_id, unsigned int nic_id)
     req.machine_id = mac_id;
     req.nic_id = nic_id;
 
-    if (ioctl(sd, Connect, &req) == -1) {
+    return ioctl(sd, Connect, &req);
+}
+
+static inline int
+call_leave(int sd) {
+    if (ioctl(sd, Leave, 0) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Query the ABI version, features (MITOSIS_FEATURE_*) and limits of the kernel.
+ */
+static inline int
+call_get_info(int sd, mitosis_info_t *info) {
+    if (ioctl(sd, GetInfo, info) == -1) {
         return -1;
     }
 
@@ -56,6 +77,82 @@ fork_prepare_ping(int sd, unsigned long key) {
     return 0;
 }
 
+/*
+  Prepare with hints on the address ranges, i.e., MITOSIS_REGION_EXCLUDE ranges are not
+  transferred (the child reads zero), and MITOSIS_REGION_HOT ranges are transferred at resume.
+ */
+static inline int
+fork_prepare_w_hints(int sd, unsigned long key, const prepare_region_t *regions,
+                     unsigned int count, int ping) {
+    prepare_req_t req;
+    req.key = key;
+    req.regions = regions;
+    req.region_count = count;
+    req.flags = ping ? MITOSIS_PREPARE_PING : 0;
+
+    if (ioctl(sd, PrepareWHints, &req) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Same as fork_prepare_w_hints, except that the recorded pages are stored compressed,
+  which the children decompress at the page faults. See fork_image_stat for the ratio.
+ */
+static inline int
+fork_prepare_compressed(int sd, unsigned long key, const prepare_region_t *regions,
+                        unsigned int count, int ping) {
+    prepare_req_t req;
+    req.key = key;
+    req.regions = regions;
+    req.region_count = count;
+    req.flags = MITOSIS_PREPARE_COMPRESS | (ping ? MITOSIS_PREPARE_PING : 0);
+
+    if (ioctl(sd, PrepareWHints, &req) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Same as fork_prepare_w_hints, except that the pinned pages identical to the ones of
+  the other images on the host are shared with them. See fork_image_stat for the pages saved.
+ */
+static inline int
+fork_prepare_dedup(int sd, unsigned long key, const prepare_region_t *regions,
+                   unsigned int count, int ping) {
+    prepare_req_t req;
+    req.key = key;
+    req.regions = regions;
+    req.region_count = count;
+    req.flags = MITOSIS_PREPARE_DEDUP | (ping ? MITOSIS_PREPARE_PING : 0);
+
+    if (ioctl(sd, PrepareWHints, &req) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Refresh the image prepared by the caller with key, only the pages changed since the last
+  prepare (or refresh) are recorded again. Return the generation of the image, or -1 on failure.
+ */
+static inline int
+fork_prepare_update(int sd, unsigned long key, const prepare_region_t *regions,
+                    unsigned int count) {
+    prepare_req_t req;
+    req.key = key;
+    req.regions = regions;
+    req.region_count = count;
+    req.flags = MITOSIS_PREPARE_UPDATE;
+
+    return ioctl(sd, PrepareWHints, &req);
+}
+
 static inline int
 fork_resume_local(int sd, unsigned long key) {
     if (ioctl(sd, ResumeLocal, key) == -1) {
@@ -79,6 +176,146 @@ fork_resume_remote(int sd, unsigned long mac_id, unsigned long handler_id) {
     return 0;
 }
 
+/*
+  Resume from the remote process, and write the input (of len bytes) to input_addr of the child.
+  The child returns ret from its system call if ret >= 0, or 0 if ret < 0.
+  If notify_exit is set, the exit of the child is reported to the parent (see fork_poll_child_exit).
+ */
+static inline int
+fork_resume_remote_w_input(int sd, unsigned long mac_id, unsigned long handler_id,
+                           unsigned long long input_addr, const void *input, unsigned int len,
+                           int ret, int notify_exit) {
+    resume_input_req_t req;
+    req.machine_id = mac_id;
+    req.handler_id = handler_id;
+    req.input = input;
+    req.input_addr = input_addr;
+    req.input_len = len;
+    req.flags = (ret >= 0 ? MITOSIS_INPUT_SET_RET : 0) |
+                (notify_exit ? MITOSIS_RESUME_NOTIFY_EXIT : 0);
+    req.ret = ret >= 0 ? ret : 0;
+    req.reserved = 0;
+
+    return ioctl(sd, ResumeRemoteWInput, &req);
+}
+
+/*
+  Start resuming from the remote process, while its image is fetched in the background.
+  The eventfd (or -1 for none) is signaled once the image is fetched;
+  the resume takes effect after fork_resume_commit.
+ */
+static inline int
+fork_resume_remote_async(int sd, unsigned long mac_id, unsigned long handler_id, int eventfd) {
+    resume_async_req_t req;
+    req.machine_id = mac_id;
+    req.handler_id = handler_id;
+    req.eventfd = eventfd;
+    req.reserved = 0;
+
+    if (ioctl(sd, ResumeRemoteAsync, &req) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Apply the image fetched by fork_resume_remote_async.
+  Fails with errno EAGAIN if the image is still being fetched.
+ */
+static inline int
+fork_resume_commit(int sd) {
+    if (ioctl(sd, ResumeCommit, 0) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Pop the report of an exited child resumed from the image prepared by the caller.
+  Fails with errno EAGAIN if no child has exited since the last poll.
+ */
+static inline int
+fork_poll_child_exit(int sd, child_exit_info_t *info) {
+    if (ioctl(sd, PollChildExit, info) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Fetch the remaining remote pages of the resumed caller in the background,
+  after which the caller no longer depends on its parent.
+  pages_per_sec is the max fetch rate (0 for no limit),
+  and batch is the number of pages fetched at a time (0 for the default).
+ */
+static inline int
+fork_materialize(int sd, unsigned int pages_per_sec, unsigned int batch) {
+    materialize_req_t req;
+    req.pages_per_sec = pages_per_sec;
+    req.batch = batch;
+    if (ioctl(sd, Materialize, &req) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Query the progress of fork_materialize, see MITOSIS_MATERIALIZE_*.
+ */
+static inline int
+fork_materialize_status(int sd, materialize_status_t *status) {
+    if (ioctl(sd, MaterializeStatus, status) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Keep the image prepared by the caller registered after the caller exits.
+  ttl_sec is 0 to keep it until fork_drop_image, flags is MITOSIS_IMAGE_EVICTABLE or 0.
+ */
+static inline int
+fork_detach_image(int sd, unsigned int ttl_sec, unsigned int flags) {
+    detach_image_req_t req;
+    req.ttl_sec = ttl_sec;
+    req.flags = flags;
+    if (ioctl(sd, DetachImage, &req) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Unregister the detached image prepared with key, owned by the caller's user.
+ */
+static inline int
+fork_drop_image(int sd, unsigned long key) {
+    if (ioctl(sd, DropImage, key) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
+/*
+  Query the stats of the image prepared by the caller, e.g., with MITOSIS_PREPARE_COMPRESS,
+  the compression pays off if stored_bytes is well below (pages - zero_pages) * 4096.
+ */
+static inline int
+fork_image_stat(int sd, image_stat_t *stat) {
+    if (ioctl(sd, ImageStat, stat) == -1) {
+        return -1;
+    }
+
+    return 0;
+}
+
 static inline int
 nil_rpc(int sd, unsigned long mac_id, unsigned long handler_id) {
     resume_remote_req_t req;
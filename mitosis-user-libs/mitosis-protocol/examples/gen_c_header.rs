@@ -0,0 +1,15 @@
+//! Print the C header of the protocol, i.e.,
+//! cargo run --example gen_c_header > ../mitosis-c-client/include/common.h
+
+struct Stdout;
+
+impl core::fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+fn main() {
+    mitosis_protocol::c_header::write_c_header(&mut Stdout).unwrap();
+}
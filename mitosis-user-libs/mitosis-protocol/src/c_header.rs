@@ -0,0 +1,236 @@
+//! Generate the C header (`common.h`) of the protocol,
+//! so that the C clients never drift from the kernel.
+
+use core::fmt::{Result, Write};
+use core::mem::size_of;
+
+use crate::*;
+
+/// The C definitions of the requests and replies, must match the repr(C) types in the crate
+const TYPES: &[(&str, &str, usize)] = &[
+    (
+        "connect_req_t",
+        "    unsigned int machine_id; // a hint only, the real ID is negotiated by the kernel
+    unsigned int nic_id; // nic idx according to gid
+    const char *gid; // points to a GID string of MITOSIS_GID_STR_LEN bytes
+",
+        size_of::<ConnectReq>(),
+    ),
+    (
+        "resume_remote_req_t",
+        "    unsigned int machine_id;    // keep `machine_id` the same as that in `connect_req_t`
+    unsigned int handler_id;
+",
+        size_of::<ResumeRemoteReq>(),
+    ),
+    (
+        "resume_async_req_t",
+        "    unsigned int machine_id;
+    unsigned int handler_id;
+    int eventfd; // signaled once the image is fetched, -1 to poll ResumeCommit instead
+    unsigned int reserved;
+",
+        size_of::<ResumeAsyncReq>(),
+    ),
+    (
+        "prepare_region_t",
+        "    unsigned long long start;
+    unsigned long long end; // exclusive, extended to page boundaries by the kernel
+    unsigned int hint; // MITOSIS_REGION_*
+    unsigned int reserved;
+",
+        size_of::<PrepareRegion>(),
+    ),
+    (
+        "prepare_req_t",
+        "    unsigned long long key;
+    const prepare_region_t *regions;
+    unsigned int region_count; // at most MITOSIS_MAX_PREPARE_REGIONS
+    unsigned int flags; // MITOSIS_PREPARE_* bits
+",
+        size_of::<PrepareReq>(),
+    ),
+    (
+        "resume_input_req_t",
+        "    unsigned int machine_id;
+    unsigned int handler_id;
+    const void *input; // copied before the resume
+    unsigned long long input_addr; // where to write the input in the child
+    unsigned int input_len; // at most MITOSIS_MAX_RESUME_INPUT, 0 for no input
+    unsigned int flags; // MITOSIS_INPUT_SET_RET | MITOSIS_RESUME_NOTIFY_EXIT
+    unsigned int ret; // returned by the child if MITOSIS_INPUT_SET_RET, at most INT_MAX
+    unsigned int reserved;
+",
+        size_of::<ResumeInputReq>(),
+    ),
+    (
+        "child_exit_info_t",
+        "    unsigned int machine_id; // the machine of the child
+    unsigned int handler_id;
+    int status; // as reported by wait(2), or MITOSIS_CHILD_DETACHED
+    unsigned int reserved;
+    unsigned long long runtime_usec;
+    unsigned long long fault_pages;
+",
+        size_of::<ChildExitInfo>(),
+    ),
+    (
+        "materialize_req_t",
+        "    unsigned int pages_per_sec; // 0 for no limit
+    unsigned int batch; // at most MITOSIS_MAX_MATERIALIZE_BATCH, 0 for the default
+",
+        size_of::<MaterializeReq>(),
+    ),
+    (
+        "materialize_status_t",
+        "    unsigned int state; // MITOSIS_MATERIALIZE_*
+    unsigned int reserved;
+    unsigned long long fetched_pages;
+    unsigned long long total_pages; // an upper bound of the pages to fetch
+",
+        size_of::<MaterializeStatus>(),
+    ),
+    (
+        "detach_image_req_t",
+        "    unsigned int ttl_sec; // 0 to keep the image until dropped
+    unsigned int flags; // MITOSIS_IMAGE_EVICTABLE
+",
+        size_of::<DetachImageReq>(),
+    ),
+    (
+        "image_stat_t",
+        "    unsigned long long pages;
+    unsigned long long zero_pages; // not transferred
+    unsigned long long compressed_pages; // with MITOSIS_PREPARE_COMPRESS
+    unsigned long long stored_bytes; // read by a child to fetch all the pages
+    unsigned long long dedup_pages; // with MITOSIS_PREPARE_DEDUP
+    unsigned long long host_dedup_pages; // saved across all the images on the host
+",
+        size_of::<ImageStat>(),
+    ),
+    (
+        "mitosis_info_t",
+        "    unsigned int abi_version; // check it against MITOSIS_ABI_VERSION before using other fields
+    unsigned int size;
+    unsigned long long features; // MITOSIS_FEATURE_* bits
+    unsigned int machine_id;
+    unsigned int nic_count;
+    unsigned int max_cluster_size;
+    unsigned int max_caller_num;
+    unsigned int prefetch_step;
+    unsigned int reserved;
+",
+        size_of::<MitosisInfo>(),
+    ),
+];
+
+/// Write the C header to `w`
+pub fn write_c_header<W: Write>(w: &mut W) -> Result {
+    writeln!(
+        w,
+        "// Generated from mitosis-user-libs/mitosis-protocol, do not edit.\n\
+         // Re-generate with: cargo run --example gen_c_header > <this file>\n\
+         #pragma once\n"
+    )?;
+
+    writeln!(w, "#define MITOSIS_ABI_VERSION {}", ABI_VERSION)?;
+    writeln!(w, "#define MITOSIS_GID_STR_LEN {}\n", GID_STR_LEN)?;
+
+    writeln!(w, "#define MITOSIS_REGION_EXCLUDE {}", REGION_EXCLUDE)?;
+    writeln!(w, "#define MITOSIS_REGION_HOT {}", REGION_HOT)?;
+    writeln!(w, "#define MITOSIS_MAX_PREPARE_REGIONS {}", MAX_PREPARE_REGIONS)?;
+    writeln!(w, "#define MITOSIS_PREPARE_PING {}", PREPARE_PING)?;
+    writeln!(w, "#define MITOSIS_PREPARE_UPDATE {}", PREPARE_UPDATE)?;
+    writeln!(w, "#define MITOSIS_PREPARE_COMPRESS {}", PREPARE_COMPRESS)?;
+    writeln!(w, "#define MITOSIS_PREPARE_DEDUP {}\n", PREPARE_DEDUP)?;
+
+    writeln!(w, "#define MITOSIS_INPUT_SET_RET {}", INPUT_SET_RET)?;
+    writeln!(w, "#define MITOSIS_RESUME_NOTIFY_EXIT {}", RESUME_NOTIFY_EXIT)?;
+    writeln!(w, "#define MITOSIS_CHILD_DETACHED ({})", CHILD_DETACHED)?;
+    writeln!(w, "#define MITOSIS_MAX_RESUME_INPUT {}\n", MAX_RESUME_INPUT)?;
+
+    writeln!(w, "#define MITOSIS_DEFAULT_MATERIALIZE_BATCH {}", DEFAULT_MATERIALIZE_BATCH)?;
+    writeln!(w, "#define MITOSIS_MAX_MATERIALIZE_BATCH {}", MAX_MATERIALIZE_BATCH)?;
+    writeln!(w, "#define MITOSIS_MATERIALIZE_NONE {}", MATERIALIZE_NONE)?;
+    writeln!(w, "#define MITOSIS_MATERIALIZE_RUNNING {}", MATERIALIZE_RUNNING)?;
+    writeln!(w, "#define MITOSIS_MATERIALIZE_DONE {}", MATERIALIZE_DONE)?;
+    writeln!(w, "#define MITOSIS_MATERIALIZE_FAILED {}\n", MATERIALIZE_FAILED)?;
+
+    writeln!(w, "#define MITOSIS_IMAGE_EVICTABLE {}\n", IMAGE_EVICTABLE)?;
+
+    for (name, bit, doc) in FEATURES {
+        writeln!(w, "#define {} 0x{:x}ULL // {}", name, bit, doc)?;
+    }
+    writeln!(w)?;
+
+    writeln!(w, "enum LibMITOSISCmd {{")?;
+    for c in COMMANDS {
+        writeln!(w, "    {} = {}, // {}", c.name, c.cmd, c.doc)?;
+    }
+    writeln!(w, "}};\n")?;
+
+    for (name, fields, _) in TYPES {
+        writeln!(w, "typedef struct {{\n{}}} {};\n", fields, name)?;
+    }
+
+    writeln!(
+        w,
+        "#ifdef __cplusplus\n\
+         #define MITOSIS_STATIC_ASSERT static_assert\n\
+         #else\n\
+         #define MITOSIS_STATIC_ASSERT _Static_assert\n\
+         #endif\n"
+    )?;
+    for (name, _, size) in TYPES {
+        writeln!(
+            w,
+            "MITOSIS_STATIC_ASSERT(sizeof({}) == {}, \"{} mismatches the kernel\");",
+            name, size, name
+        )?;
+    }
+    Ok(())
+}
+
+/// Check that every request (and reply) used by the commands has a C definition
+pub const fn types_are_defined() -> bool {
+    let mut i = 0;
+    while i < COMMANDS.len() {
+        let (name, size) = match COMMANDS[i].arg {
+            CmdArg::Request { name, size } | CmdArg::Reply { name, size } => (name, size),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let mut found = false;
+        let mut j = 0;
+        while j < TYPES.len() {
+            if str_eq(TYPES[j].0, name) && TYPES[j].2 == size {
+                found = true;
+            }
+            j += 1;
+        }
+        if !found {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(types_are_defined());
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
@@ -0,0 +1,41 @@
+//! The errnos reported by the MITOSIS system calls (the values of Linux).
+//!
+//! On failures, the kernel returns `-errno` from the ioctl,
+//! so the user sees `-1` with `errno` set to one of the following.
+
+pub type Errno = i32;
+
+/// The process (e.g., the handler ID) is not found
+pub const ENOENT: Errno = 2;
+/// Other failures of the kernel, see dmesg for details
+pub const EIO: Errno = 5;
+/// The asynchronous resume is still in progress, try again later
+pub const EAGAIN: Errno = 11;
+/// Out of memory, or the RDMA resources (e.g., the DC pool) are exhausted
+pub const ENOMEM: Errno = 12;
+/// The caller does not own the image
+pub const EACCES: Errno = 13;
+/// Failed to copy the request from (or to) the user
+pub const EFAULT: Errno = 14;
+/// The key has been prepared
+pub const EEXIST: Errno = 17;
+/// The request is malformed
+pub const EINVAL: Errno = 22;
+/// Unknown command
+pub const ENOTTY: Errno = 25;
+/// Malformed message from the remote, e.g., failed to deserialize the descriptor
+pub const EPROTO: Errno = 71;
+/// The command is not supported by this kernel
+pub const EOPNOTSUPP: Errno = 95;
+/// The machine ID has been occupied by another machine
+pub const EADDRINUSE: Errno = 98;
+/// The remote machine is not connected
+pub const ENOTCONN: Errno = 107;
+/// The remote machine does not reply in time
+pub const ETIMEDOUT: Errno = 110;
+/// The remote machine refused the connection
+pub const ECONNREFUSED: Errno = 111;
+/// The remote machine is unreachable
+pub const EHOSTUNREACH: Errno = 113;
+/// A prepare (or resume) is already active on the fd
+pub const EALREADY: Errno = 114;
@@ -1,19 +1,658 @@
 #![no_std]
 
+//! The ioctl protocol between the MITOSIS kernel module and its user-space clients.
+//!
+//! This crate is the single source of truth of the protocol:
+//! the kernel and the rust client use it directly,
+//! and the C header (mitosis-c-client/include/common.h) is generated from it,
+//! see `c_header` and `examples/gen_c_header.rs`.
+
+use core::mem::size_of;
+
+pub mod c_header;
+pub mod errno;
+
+/// The version of the protocol.
+/// Bump it once a command, or the layout of a request changes.
+pub const ABI_VERSION: u32 = 11;
+
 pub type IoctlCmdType = u32;
 
-/// a simple call cmd to test the work of system calls 
-pub const CALL_NIL : IoctlCmdType = 0;
+/// a simple call cmd to test the work of system calls
+pub const CALL_NIL: IoctlCmdType = 0;
 
 /// Establish a UD connection from the remote end
-pub const CALL_CONNECT : IoctlCmdType = 3;
+pub const CALL_CONNECT: IoctlCmdType = 3;
 
 /// Prepare the caller process's state to a shadow process at the caller machine
-pub const CALL_PREPARE : IoctlCmdType = 4;
+pub const CALL_PREPARE: IoctlCmdType = 4;
 
 /// Resume from a local image
-pub const CALL_RESUME_LOCAL : IoctlCmdType = 5;
-pub const CALL_RESUME_LOCAL_W_RPC : IoctlCmdType = 6;
+pub const CALL_RESUME_LOCAL: IoctlCmdType = 5;
+
+/// Resume from a remote image (via RPC)
+pub const CALL_RESUME_REMOTE: IoctlCmdType = 6;
+
+/// Prepare the caller process, and ping the image in the kernel
+pub const CALL_PREPARE_PING: IoctlCmdType = 7;
+
+/// Call the nil RPC at the remote end
+pub const CALL_NIL_RPC: IoctlCmdType = 8;
+
+/// Leave the cluster, so that the machine can re-join later
+pub const CALL_LEAVE: IoctlCmdType = 9;
+
+/// Query the ABI version, features and limits of the kernel, see `MitosisInfo`
+pub const CALL_GET_INFO: IoctlCmdType = 10;
+
+/// Submit a resume from a remote image, which returns before the image is fetched
+pub const CALL_RESUME_REMOTE_ASYNC: IoctlCmdType = 11;
+
+/// Commit the image fetched by `CALL_RESUME_REMOTE_ASYNC` into the caller process
+pub const CALL_RESUME_COMMIT: IoctlCmdType = 12;
+
+/// Prepare (or refresh, see `PREPARE_UPDATE`) the caller process with the address ranges
+/// to exclude or to transfer eagerly, see `PrepareReq`
+pub const CALL_PREPARE_W_HINTS: IoctlCmdType = 13;
+
+/// Resume from a remote image, and inject the input of the child, see `ResumeInputReq`
+pub const CALL_RESUME_REMOTE_W_INPUT: IoctlCmdType = 14;
+
+/// Pop the report of an exited child of the caller's prepared image, see `ChildExitInfo`
+pub const CALL_POLL_CHILD_EXIT: IoctlCmdType = 15;
+
+/// Fetch the remaining remote pages of the resumed caller in the background,
+/// after which the caller no longer depends on its parent, see `MaterializeReq`
+pub const CALL_MATERIALIZE: IoctlCmdType = 16;
+
+/// Query the progress of `CALL_MATERIALIZE`, see `MaterializeStatus`
+pub const CALL_MATERIALIZE_STATUS: IoctlCmdType = 17;
+
+/// Detach the image prepared by the caller, so that it stays registered after the caller exits,
+/// see `DetachImageReq`
+pub const CALL_DETACH_IMAGE: IoctlCmdType = 18;
+
+/// Unregister the detached image whose key is passed by value, only allowed to its owner
+pub const CALL_DROP_IMAGE: IoctlCmdType = 19;
+
+/// Query the stats of the image prepared by the caller, see `ImageStat`
+pub const CALL_IMAGE_STAT: IoctlCmdType = 20;
+
+/// The length of a GID string, e.g., fe80:0000:0000:0000:ec0d:9a03:00ca:2f4c.
+/// The kernel always reads this number of bytes from the `gid` of `ConnectReq`
+pub const GID_STR_LEN: usize = 39;
+
+// The requests use fixed-width types (`unsigned int` is u32 on all supported targets),
+// since the kernel toolchain has no `core::ffi`
+
+/// The request of `CALL_CONNECT`, i.e., `connect_req_t` in C
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectReq {
+    /// A hint only, the real machine ID is negotiated by the kernel
+    pub machine_id: u32,
+    /// nic idx according to gid
+    pub nic_id: u32,
+    /// Points to a GID string of `GID_STR_LEN` bytes
+    pub gid: *const u8,
+}
+
+impl Default for ConnectReq {
+    fn default() -> Self {
+        Self {
+            machine_id: 0,
+            nic_id: 0,
+            gid: core::ptr::null(),
+        }
+    }
+}
+
+/// The request of `CALL_RESUME_REMOTE` and `CALL_NIL_RPC`, i.e., `resume_remote_req_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeRemoteReq {
+    /// keep `machine_id` the same as that in `ConnectReq`
+    pub machine_id: u32,
+    pub handler_id: u32,
+}
+
+/// The request of `CALL_RESUME_REMOTE_ASYNC`, i.e., `resume_async_req_t` in C
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeAsyncReq {
+    pub machine_id: u32,
+    pub handler_id: u32,
+    /// The eventfd signaled once the image is fetched (or failed to),
+    /// `NO_EVENTFD` if the user polls `CALL_RESUME_COMMIT` instead
+    pub eventfd: i32,
+    pub reserved: u32,
+}
+
+pub const NO_EVENTFD: i32 = -1;
+
+impl Default for ResumeAsyncReq {
+    fn default() -> Self {
+        Self {
+            machine_id: 0,
+            handler_id: 0,
+            eventfd: NO_EVENTFD,
+            reserved: 0,
+        }
+    }
+}
+
+/// The request of `CALL_PREPARE_W_HINTS`, i.e., `prepare_req_t` in C
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PrepareReq {
+    /// The user key, i.e., the handler ID to resume the process
+    pub key: u64,
+    /// Points to an array of `region_count` regions
+    pub regions: *const PrepareRegion,
+    /// At most `MAX_PREPARE_REGIONS`
+    pub region_count: u32,
+    /// `PREPARE_*` bits
+    pub flags: u32,
+}
+
+impl Default for PrepareReq {
+    fn default() -> Self {
+        Self {
+            key: 0,
+            regions: core::ptr::null(),
+            region_count: 0,
+            flags: 0,
+        }
+    }
+}
+
+/// An address range [start, end) of the parent with a `REGION_*` hint,
+/// i.e., `prepare_region_t` in C.
+/// The range is extended to page boundaries by the kernel.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrepareRegion {
+    pub start: u64,
+    pub end: u64,
+    pub hint: u32,
+    pub reserved: u32,
+}
+
+/// The pages are not transferred, the child reads them as zero
+pub const REGION_EXCLUDE: u32 = 1;
+/// The pages are transferred eagerly at resume, instead of on page faults
+pub const REGION_HOT: u32 = 2;
+
+/// The max number of regions in a `PrepareReq`
+pub const MAX_PREPARE_REGIONS: u32 = 64;
+
+/// Ping the image in the kernel, as `CALL_PREPARE_PING`
+pub const PREPARE_PING: u32 = 1;
+
+/// Refresh the image already prepared by the caller with `key`, instead of preparing a new one.
+/// Only the pages changed since the last prepare are recorded again,
+/// and the children resumed from the previous generations keep reading their pages.
+pub const PREPARE_UPDATE: u32 = 2;
+
+/// Store the recorded pages compressed (with LZ4), which the children read
+/// and decompress at the page faults. It pays off on bandwidth-limited links
+/// if the pages are compressible, see `ImageStat`.
+/// The image cannot be refreshed with `PREPARE_UPDATE` afterwards.
+pub const PREPARE_COMPRESS: u32 = 4;
+
+/// Share the pinned pages identical to the ones of the other images on the host,
+/// e.g., the runtime and the libraries of the functions prepared from the same base.
+/// Only the images marking their pages COW (i.e., with the cow feature) can be deduplicated,
+/// and the pages changed by `PREPARE_UPDATE` are shared again.
+pub const PREPARE_DEDUP: u32 = 8;
+
+/// The request of `CALL_RESUME_REMOTE_W_INPUT`, i.e., `resume_input_req_t` in C.
+///
+/// Once the image is applied, the kernel copies `input_len` bytes from `input`
+/// (in the address space of the caller before the resume) to `input_addr` of the child,
+/// and the resumed child returns `ret` from its system call if `INPUT_SET_RET` is set.
+/// The resume fails with EFAULT (and the caller is kept) if `input_addr` is not writable
+/// in the parent, and the child is killed if the input cannot be written once it is resumed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ResumeInputReq {
+    pub machine_id: u32,
+    pub handler_id: u32,
+    pub input: *const u8,
+    /// The address in the child to write the input, must be writable in the parent
+    pub input_addr: u64,
+    /// At most `MAX_RESUME_INPUT`, 0 for no input
+    pub input_len: u32,
+    /// `INPUT_SET_RET` and `RESUME_NOTIFY_EXIT` bits
+    pub flags: u32,
+    /// At most `i32::MAX`, since the C library treats negative returns as errors
+    pub ret: u32,
+    pub reserved: u32,
+}
+
+impl Default for ResumeInputReq {
+    fn default() -> Self {
+        Self {
+            machine_id: 0,
+            handler_id: 0,
+            input: core::ptr::null(),
+            input_addr: 0,
+            input_len: 0,
+            flags: 0,
+            ret: 0,
+            reserved: 0,
+        }
+    }
+}
+
+/// The child returns `ResumeInputReq::ret` instead of 0
+pub const INPUT_SET_RET: u32 = 1;
+
+/// The child reports its exit to the machine of the parent, see `CALL_POLL_CHILD_EXIT`
+pub const RESUME_NOTIFY_EXIT: u32 = 2;
+
+/// The max length of the input injected to a child
+pub const MAX_RESUME_INPUT: u32 = 64 * 1024;
+
+/// The reply of `CALL_POLL_CHILD_EXIT`, i.e., `child_exit_info_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChildExitInfo {
+    /// The machine of the child
+    pub machine_id: u32,
+    /// The key of the image that the child resumed from
+    pub handler_id: u32,
+    /// The status as reported by wait(2), or `CHILD_DETACHED`
+    pub status: i32,
+    pub reserved: u32,
+    /// The time since the resume
+    pub runtime_usec: u64,
+    /// The number of page faults handled by MITOSIS
+    pub fault_pages: u64,
+}
+
+/// The child closed the MITOSIS device without exiting
+pub const CHILD_DETACHED: i32 = -1;
+
+/// The request of `CALL_MATERIALIZE`, i.e., `materialize_req_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MaterializeReq {
+    /// The max number of pages fetched per second, 0 for no limit
+    pub pages_per_sec: u32,
+    /// The number of pages fetched at a time, at most `MAX_MATERIALIZE_BATCH`,
+    /// 0 for `DEFAULT_MATERIALIZE_BATCH`
+    pub batch: u32,
+}
+
+pub const DEFAULT_MATERIALIZE_BATCH: u32 = 32;
+pub const MAX_MATERIALIZE_BATCH: u32 = 1024;
+
+/// The reply of `CALL_MATERIALIZE_STATUS`, i.e., `materialize_status_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MaterializeStatus {
+    /// `MATERIALIZE_*`
+    pub state: u32,
+    pub reserved: u32,
+    /// The number of pages fetched by the materialization
+    pub fetched_pages: u64,
+    /// The number of pages recorded in the image, an upper bound of the pages to fetch
+    pub total_pages: u64,
+}
+
+/// `CALL_MATERIALIZE` has not been called
+pub const MATERIALIZE_NONE: u32 = 0;
+pub const MATERIALIZE_RUNNING: u32 = 1;
+/// All the remote pages are fetched, and the child is detached from its parent
+pub const MATERIALIZE_DONE: u32 = 2;
+/// Stopped on an error, e.g., the parent is unreachable or the memcg is exhausted
+pub const MATERIALIZE_FAILED: u32 = 3;
+
+/// The request of `CALL_DETACH_IMAGE`, i.e., `detach_image_req_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DetachImageReq {
+    /// The image is unregistered `ttl_sec` seconds after the detach, 0 to keep it until dropped
+    pub ttl_sec: u32,
+    /// `IMAGE_*` bits
+    pub flags: u32,
+}
+
+/// The kernel may unregister the image under memory pressure, the least recently resumed first
+pub const IMAGE_EVICTABLE: u32 = 1;
+
+/// The reply of `CALL_IMAGE_STAT`, i.e., `image_stat_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImageStat {
+    /// The number of pages recorded in the image
+    pub pages: u64,
+    /// The number of recorded pages filled with zeros, which are not transferred
+    pub zero_pages: u64,
+    /// The number of pages stored compressed, see `PREPARE_COMPRESS`
+    pub compressed_pages: u64,
+    /// The number of bytes a child reads to fetch all the recorded pages,
+    /// the compression pays off if it is well below `(pages - zero_pages) * 4096`
+    pub stored_bytes: u64,
+    /// The number of recorded pages shared with the other images, see `PREPARE_DEDUP`
+    pub dedup_pages: u64,
+    /// The number of pages saved by the deduplication across all the images on the host
+    pub host_dedup_pages: u64,
+}
+
+/// The reply of `CALL_GET_INFO`, i.e., `mitosis_info_t` in C
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MitosisInfo {
+    /// The `ABI_VERSION` of the kernel, check it before using other fields
+    pub abi_version: u32,
+    /// The size of the struct filled by the kernel
+    pub size: u32,
+    /// The `FEATURE_*` bits compiled in the kernel
+    pub features: u64,
+    pub machine_id: u32,
+    /// The number of RNICs used by the kernel
+    pub nic_count: u32,
+    pub max_cluster_size: u32,
+    /// The number of RPC callers, i.e., the max number of concurrent resumes
+    pub max_caller_num: u32,
+    /// The number of pages prefetched at each fault, valid if `FEATURE_PREFETCH` is set
+    pub prefetch_step: u32,
+    pub reserved: u32,
+}
+
+impl MitosisInfo {
+    #[inline]
+    pub fn has_feature(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+}
+
+/// Use copy-on-write to prepare the parent
+pub const FEATURE_COW: u64 = 1 << 0;
+/// Fetch all the pages at resume, instead of on page faults
+pub const FEATURE_EAGER_RESUME: u64 = 1 << 1;
+/// Prefetch pages at page faults
+pub const FEATURE_PREFETCH: u64 = 1 << 2;
+/// Cache the remote page tables, and the remote pages read by the concurrent children on a host
+pub const FEATURE_PAGE_CACHE: u64 = 1 << 3;
+/// Use RDMA's reliable connection instead of DCT
+pub const FEATURE_USE_RC: u64 = 1 << 4;
+/// Profile the resume
+pub const FEATURE_RESUME_PROFILE: u64 = 1 << 5;
+/// The machine ID is negotiated with the peers
+pub const FEATURE_AUTO_MACHINE_ID: u64 = 1 << 6;
+
+/// All the features: (name in C, bit, doc)
+pub const FEATURES: &[(&str, u64, &str)] = &[
+    ("MITOSIS_FEATURE_COW", FEATURE_COW, "copy-on-write prepare"),
+    ("MITOSIS_FEATURE_EAGER_RESUME", FEATURE_EAGER_RESUME, "eager resume"),
+    ("MITOSIS_FEATURE_PREFETCH", FEATURE_PREFETCH, "prefetch at page faults"),
+    ("MITOSIS_FEATURE_PAGE_CACHE", FEATURE_PAGE_CACHE, "cache remote page tables and pages"),
+    ("MITOSIS_FEATURE_USE_RC", FEATURE_USE_RC, "use RC instead of DCT"),
+    ("MITOSIS_FEATURE_RESUME_PROFILE", FEATURE_RESUME_PROFILE, "profile the resume"),
+    ("MITOSIS_FEATURE_AUTO_MACHINE_ID", FEATURE_AUTO_MACHINE_ID, "negotiated machine ID"),
+];
+
+// The layouts are part of the ABI, changing them requires bumping `ABI_VERSION`
+const _: () = assert!(size_of::<ConnectReq>() == 16);
+const _: () = assert!(size_of::<ResumeRemoteReq>() == 8);
+const _: () = assert!(size_of::<MitosisInfo>() == 40);
+const _: () = assert!(size_of::<ResumeAsyncReq>() == 16);
+const _: () = assert!(size_of::<PrepareReq>() == 24);
+const _: () = assert!(size_of::<PrepareRegion>() == 24);
+const _: () = assert!(size_of::<ResumeInputReq>() == 40);
+const _: () = assert!(size_of::<ChildExitInfo>() == 32);
+const _: () = assert!(size_of::<MaterializeReq>() == 8);
+const _: () = assert!(size_of::<MaterializeStatus>() == 24);
+const _: () = assert!(size_of::<DetachImageReq>() == 8);
+const _: () = assert!(size_of::<ImageStat>() == 48);
+
+/// How the argument of a command is passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmdArg {
+    /// The argument is ignored
+    Nil,
+    /// The argument is passed by value, e.g., the key of prepare
+    Value,
+    /// The argument points to a request of the C type `name`
+    Request { name: &'static str, size: usize },
+    /// The argument points to a buffer of the C type `name`, which is filled by the kernel
+    Reply { name: &'static str, size: usize },
+}
+
+/// The description of a command
+#[derive(Debug, Clone, Copy)]
+pub struct CmdDesc {
+    /// The name in C, i.e., the variant of `LibMITOSISCmd`
+    pub name: &'static str,
+    pub cmd: IoctlCmdType,
+    pub arg: CmdArg,
+    pub doc: &'static str,
+}
+
+const CONNECT_REQ: CmdArg = CmdArg::Request {
+    name: "connect_req_t",
+    size: size_of::<ConnectReq>(),
+};
+
+const RESUME_REMOTE_REQ: CmdArg = CmdArg::Request {
+    name: "resume_remote_req_t",
+    size: size_of::<ResumeRemoteReq>(),
+};
+
+const MITOSIS_INFO: CmdArg = CmdArg::Reply {
+    name: "mitosis_info_t",
+    size: size_of::<MitosisInfo>(),
+};
+
+const RESUME_ASYNC_REQ: CmdArg = CmdArg::Request {
+    name: "resume_async_req_t",
+    size: size_of::<ResumeAsyncReq>(),
+};
+
+const PREPARE_REQ: CmdArg = CmdArg::Request {
+    name: "prepare_req_t",
+    size: size_of::<PrepareReq>(),
+};
+
+const RESUME_INPUT_REQ: CmdArg = CmdArg::Request {
+    name: "resume_input_req_t",
+    size: size_of::<ResumeInputReq>(),
+};
+
+const CHILD_EXIT_INFO: CmdArg = CmdArg::Reply {
+    name: "child_exit_info_t",
+    size: size_of::<ChildExitInfo>(),
+};
+
+const MATERIALIZE_REQ: CmdArg = CmdArg::Request {
+    name: "materialize_req_t",
+    size: size_of::<MaterializeReq>(),
+};
+
+const MATERIALIZE_STATUS: CmdArg = CmdArg::Reply {
+    name: "materialize_status_t",
+    size: size_of::<MaterializeStatus>(),
+};
+
+const DETACH_IMAGE_REQ: CmdArg = CmdArg::Request {
+    name: "detach_image_req_t",
+    size: size_of::<DetachImageReq>(),
+};
+
+const IMAGE_STAT: CmdArg = CmdArg::Reply {
+    name: "image_stat_t",
+    size: size_of::<ImageStat>(),
+};
+
+/// All the commands, in the order of their IDs
+pub const COMMANDS: &[CmdDesc] = &[
+    CmdDesc {
+        name: "Nil",
+        cmd: CALL_NIL,
+        arg: CmdArg::Nil,
+        doc: "for test only",
+    },
+    CmdDesc {
+        name: "Connect",
+        cmd: CALL_CONNECT,
+        arg: CONNECT_REQ,
+        doc: "connect to remote session",
+    },
+    CmdDesc {
+        name: "Prepare",
+        cmd: CALL_PREPARE,
+        arg: CmdArg::Value,
+        doc: "prepare the memory mapping of this process",
+    },
+    CmdDesc {
+        name: "ResumeLocal",
+        cmd: CALL_RESUME_LOCAL,
+        arg: CmdArg::Value,
+        doc: "resume to another process",
+    },
+    CmdDesc {
+        name: "ResumeRemote",
+        cmd: CALL_RESUME_REMOTE,
+        arg: RESUME_REMOTE_REQ,
+        doc: "resume to another process of remote via RPC",
+    },
+    CmdDesc {
+        name: "PreparePing",
+        cmd: CALL_PREPARE_PING,
+        arg: CmdArg::Value,
+        doc: "Prepare the memory mapping of this process (and ping it in kernel)",
+    },
+    CmdDesc {
+        name: "NilRPC",
+        cmd: CALL_NIL_RPC,
+        arg: RESUME_REMOTE_REQ,
+        doc: "Call the nil RPC function",
+    },
+    CmdDesc {
+        name: "Leave",
+        cmd: CALL_LEAVE,
+        arg: CmdArg::Nil,
+        doc: "leave the cluster, so that the machine can re-join later",
+    },
+    CmdDesc {
+        name: "GetInfo",
+        cmd: CALL_GET_INFO,
+        arg: MITOSIS_INFO,
+        doc: "query the ABI version, features and limits of the kernel",
+    },
+    CmdDesc {
+        name: "ResumeRemoteAsync",
+        cmd: CALL_RESUME_REMOTE_ASYNC,
+        arg: RESUME_ASYNC_REQ,
+        doc: "submit a resume of remote, which returns before the image is fetched",
+    },
+    CmdDesc {
+        name: "ResumeCommit",
+        cmd: CALL_RESUME_COMMIT,
+        arg: CmdArg::Nil,
+        doc: "commit the image fetched by ResumeRemoteAsync, EAGAIN if not fetched yet",
+    },
+    CmdDesc {
+        name: "PrepareWHints",
+        cmd: CALL_PREPARE_W_HINTS,
+        arg: PREPARE_REQ,
+        doc: "prepare (or refresh) with the address ranges to exclude or to transfer eagerly",
+    },
+    CmdDesc {
+        name: "ResumeRemoteWInput",
+        cmd: CALL_RESUME_REMOTE_W_INPUT,
+        arg: RESUME_INPUT_REQ,
+        doc: "resume to another process of remote, with the input (and return value) of the child",
+    },
+    CmdDesc {
+        name: "PollChildExit",
+        cmd: CALL_POLL_CHILD_EXIT,
+        arg: CHILD_EXIT_INFO,
+        doc: "pop the report of an exited child of the prepared image, EAGAIN if none",
+    },
+    CmdDesc {
+        name: "Materialize",
+        cmd: CALL_MATERIALIZE,
+        arg: MATERIALIZE_REQ,
+        doc: "fetch the remaining pages in the background, then detach from the parent",
+    },
+    CmdDesc {
+        name: "MaterializeStatus",
+        cmd: CALL_MATERIALIZE_STATUS,
+        arg: MATERIALIZE_STATUS,
+        doc: "query the progress of Materialize",
+    },
+    CmdDesc {
+        name: "DetachImage",
+        cmd: CALL_DETACH_IMAGE,
+        arg: DETACH_IMAGE_REQ,
+        doc: "keep the prepared image registered after the caller exits",
+    },
+    CmdDesc {
+        name: "DropImage",
+        cmd: CALL_DROP_IMAGE,
+        arg: CmdArg::Value,
+        doc: "unregister a detached image of the caller's user",
+    },
+    CmdDesc {
+        name: "ImageStat",
+        cmd: CALL_IMAGE_STAT,
+        arg: IMAGE_STAT,
+        doc: "query the stats of the prepared image, e.g., the compression ratio",
+    },
+];
+
+/// Find the description of the command
+pub const fn find_cmd(cmd: IoctlCmdType) -> Option<&'static CmdDesc> {
+    let mut i = 0;
+    while i < COMMANDS.len() {
+        if COMMANDS[i].cmd == cmd {
+            return Some(&COMMANDS[i]);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The size of the request that `cmd` expects the argument to point to.
+///
+/// Return
+/// * None if the command is unknown, or its argument is not a pointer
+pub const fn request_size(cmd: IoctlCmdType) -> Option<usize> {
+    match find_cmd(cmd) {
+        Some(CmdDesc {
+            arg: CmdArg::Request { size, .. },
+            ..
+        }) => Some(*size),
+        _ => None,
+    }
+}
+
+/// Check whether `T` is the request of `cmd`, e.g., before copying it from the user
+#[inline]
+pub const fn check_request<T>(cmd: IoctlCmdType) -> bool {
+    matches!(request_size(cmd), Some(size) if size == size_of::<T>())
+}
+
+/// The size of the buffer that `cmd` fills.
+///
+/// Return
+/// * None if the command is unknown, or it replies nothing
+pub const fn reply_size(cmd: IoctlCmdType) -> Option<usize> {
+    match find_cmd(cmd) {
+        Some(CmdDesc {
+            arg: CmdArg::Reply { size, .. },
+            ..
+        }) => Some(*size),
+        _ => None,
+    }
+}
 
-/// Resume from a remote image
-pub const CALL_RESUME_REMOTE : IoctlCmdType = 7;
+/// Check whether `T` is the reply of `cmd`, e.g., before copying it to the user
+#[inline]
+pub const fn check_reply<T>(cmd: IoctlCmdType) -> bool {
+    matches!(reply_size(cmd), Some(size) if size == size_of::<T>())
+}
@@ -0,0 +1,91 @@
+use mitosis_protocol::*;
+
+fn generated() -> String {
+    let mut header = String::new();
+    c_header::write_c_header(&mut header).unwrap();
+    header
+}
+
+#[test]
+fn c_headers_are_up_to_date() {
+    let checked_in = [
+        include_str!("../../mitosis-c-client/include/common.h"),
+        include_str!("../../mitosis-lean-container/app/simple_child/common.h"),
+    ];
+    for h in checked_in {
+        assert_eq!(
+            h,
+            generated(),
+            "common.h is stale, re-generate it with `cargo run --example gen_c_header`"
+        );
+    }
+}
+
+#[test]
+fn commands() {
+    let mut last = None;
+    for c in COMMANDS {
+        assert!(last < Some(c.cmd), "{} is out of order", c.name);
+        last = Some(c.cmd);
+        assert!(generated().contains(&format!("    {} = {},", c.name, c.cmd)));
+    }
+
+    assert_eq!(find_cmd(CALL_CONNECT).unwrap().name, "Connect");
+    assert!(find_cmd(1024).is_none());
+}
+
+#[test]
+fn request_sizes() {
+    assert_eq!(request_size(CALL_CONNECT), Some(16));
+    assert_eq!(request_size(CALL_RESUME_REMOTE), Some(8));
+    assert_eq!(request_size(CALL_NIL_RPC), Some(8));
+    assert_eq!(request_size(CALL_RESUME_REMOTE_ASYNC), Some(16));
+    assert_eq!(request_size(CALL_RESUME_COMMIT), None);
+    assert_eq!(request_size(CALL_PREPARE_W_HINTS), Some(24));
+    assert_eq!(request_size(CALL_RESUME_REMOTE_W_INPUT), Some(40));
+    assert_eq!(request_size(CALL_MATERIALIZE), Some(8));
+    assert_eq!(request_size(CALL_DETACH_IMAGE), Some(8));
+    // passed by value
+    assert_eq!(request_size(CALL_PREPARE), None);
+    assert_eq!(request_size(CALL_GET_INFO), None);
+    assert_eq!(request_size(CALL_DROP_IMAGE), None);
+    assert_eq!(request_size(1024), None);
+
+    assert!(check_request::<ConnectReq>(CALL_CONNECT));
+    assert!(check_request::<ResumeRemoteReq>(CALL_NIL_RPC));
+    assert!(!check_request::<ResumeRemoteReq>(CALL_CONNECT));
+    assert!(!check_request::<u64>(CALL_PREPARE));
+    assert!(check_request::<PrepareReq>(CALL_PREPARE_W_HINTS));
+}
+
+#[test]
+fn reply_sizes() {
+    assert_eq!(reply_size(CALL_GET_INFO), Some(40));
+    assert_eq!(reply_size(CALL_POLL_CHILD_EXIT), Some(32));
+    assert_eq!(reply_size(CALL_MATERIALIZE_STATUS), Some(24));
+    assert_eq!(reply_size(CALL_IMAGE_STAT), Some(48));
+    assert_eq!(reply_size(CALL_CONNECT), None);
+
+    assert!(check_reply::<MitosisInfo>(CALL_GET_INFO));
+    assert!(!check_reply::<MitosisInfo>(CALL_RESUME_REMOTE));
+    assert!(check_reply::<ImageStat>(CALL_IMAGE_STAT));
+}
+
+#[test]
+fn features() {
+    let mut all = 0;
+    for (name, bit, _) in FEATURES {
+        assert_eq!(bit.count_ones(), 1, "{} is not a single bit", name);
+        assert_eq!(all & bit, 0, "{} overlaps", name);
+        all |= bit;
+        assert!(generated().contains(name));
+    }
+
+    let info = MitosisInfo {
+        features: FEATURE_COW | FEATURE_PREFETCH,
+        ..Default::default()
+    };
+    assert!(info.has_feature(FEATURE_COW));
+    assert!(info.has_feature(FEATURE_COW | FEATURE_PREFETCH));
+    assert!(!info.has_feature(FEATURE_COW | FEATURE_USE_RC));
+}
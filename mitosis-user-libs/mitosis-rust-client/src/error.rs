@@ -0,0 +1,153 @@
+use crate::nix::errno::Errno;
+
+use mitosis_protocol::errno;
+
+/// Errors of the MITOSIS system calls, decoded from the errno set by the kernel.
+/// The variants are the same as `MitosisError` of the kernel, see `mitosis_protocol::errno`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MClientError {
+    /// The process (e.g., the handler ID) is not found (ENOENT)
+    NotFound,
+    /// The remote machine is not connected (ENOTCONN)
+    NotConnected,
+    /// The remote machine is unreachable (EHOSTUNREACH)
+    Unreachable,
+    /// The remote machine does not reply in time (ETIMEDOUT)
+    Timeout,
+    /// A prepare (or resume) is already active on the client (EALREADY)
+    AlreadyActive,
+    /// The asynchronous resume is still in progress (EAGAIN)
+    InProgress,
+    /// The key has been prepared (EEXIST)
+    AlreadyExists,
+    /// The kernel is out of memory or RDMA resources,
+    /// or the image to resume cannot fit the memcg of the caller (ENOMEM)
+    OutOfMemory,
+    /// Malformed message from the remote machine (EPROTO)
+    Protocol,
+    /// The remote machine refused the connection (ECONNREFUSED)
+    ConnectionRefused,
+    /// The machine ID has been occupied by another machine (EADDRINUSE)
+    MachineIdConflict,
+    /// The argument is malformed, e.g., a GID of a wrong format (EINVAL)
+    InvalidArgument,
+    /// The kernel failed to copy the request (EFAULT)
+    BadAddress,
+    /// The caller does not own the image, e.g., to drop it (EACCES)
+    PermissionDenied,
+    /// The command is not supported by the kernel (EOPNOTSUPP)
+    Unsupported,
+    /// The device does not know the command, e.g., not a MITOSIS device (ENOTTY)
+    UnknownCommand,
+    /// Other failures of the kernel, see dmesg (EIO)
+    Internal,
+    /// Unspecified failure reported by kernels before errno support (EPERM)
+    Failed,
+    /// Other errno
+    Other(Errno),
+}
+
+pub type MClientResult<T> = core::result::Result<T, MClientError>;
+
+impl From<Errno> for MClientError {
+    fn from(e: Errno) -> Self {
+        match e as i32 {
+            errno::ENOENT => Self::NotFound,
+            errno::ENOTCONN => Self::NotConnected,
+            errno::EHOSTUNREACH => Self::Unreachable,
+            errno::ETIMEDOUT => Self::Timeout,
+            errno::EALREADY => Self::AlreadyActive,
+            errno::EAGAIN => Self::InProgress,
+            errno::EEXIST => Self::AlreadyExists,
+            errno::ENOMEM => Self::OutOfMemory,
+            errno::EPROTO => Self::Protocol,
+            errno::ECONNREFUSED => Self::ConnectionRefused,
+            errno::EADDRINUSE => Self::MachineIdConflict,
+            errno::EINVAL => Self::InvalidArgument,
+            errno::EFAULT => Self::BadAddress,
+            errno::EACCES => Self::PermissionDenied,
+            errno::EOPNOTSUPP => Self::Unsupported,
+            errno::ENOTTY => Self::UnknownCommand,
+            errno::EIO => Self::Internal,
+            _ if e == Errno::EPERM => Self::Failed,
+            _ => Self::Other(e),
+        }
+    }
+}
+
+impl MClientError {
+    /// The errno corresponding to the error
+    pub fn errno(&self) -> Errno {
+        let raw = match self {
+            Self::NotFound => errno::ENOENT,
+            Self::NotConnected => errno::ENOTCONN,
+            Self::Unreachable => errno::EHOSTUNREACH,
+            Self::Timeout => errno::ETIMEDOUT,
+            Self::AlreadyActive => errno::EALREADY,
+            Self::InProgress => errno::EAGAIN,
+            Self::AlreadyExists => errno::EEXIST,
+            Self::OutOfMemory => errno::ENOMEM,
+            Self::Protocol => errno::EPROTO,
+            Self::ConnectionRefused => errno::ECONNREFUSED,
+            Self::MachineIdConflict => errno::EADDRINUSE,
+            Self::InvalidArgument => errno::EINVAL,
+            Self::BadAddress => errno::EFAULT,
+            Self::PermissionDenied => errno::EACCES,
+            Self::Unsupported => errno::EOPNOTSUPP,
+            Self::UnknownCommand => errno::ENOTTY,
+            Self::Internal => errno::EIO,
+            Self::Failed => return Errno::EPERM,
+            Self::Other(e) => return *e,
+        };
+        Errno::from_i32(raw)
+    }
+}
+
+impl core::fmt::Display for MClientError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "MITOSIS call error: {:?} ({})",
+            self,
+            self.errno().desc()
+        )
+    }
+}
+
+impl std::error::Error for MClientError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errno_round_trip() {
+        let errors = [
+            MClientError::NotFound,
+            MClientError::NotConnected,
+            MClientError::Unreachable,
+            MClientError::Timeout,
+            MClientError::AlreadyActive,
+            MClientError::InProgress,
+            MClientError::AlreadyExists,
+            MClientError::OutOfMemory,
+            MClientError::Protocol,
+            MClientError::ConnectionRefused,
+            MClientError::MachineIdConflict,
+            MClientError::InvalidArgument,
+            MClientError::BadAddress,
+            MClientError::PermissionDenied,
+            MClientError::Unsupported,
+            MClientError::UnknownCommand,
+            MClientError::Internal,
+            MClientError::Failed,
+            MClientError::Other(Errno::EBUSY),
+        ];
+        for e in errors {
+            assert_eq!(MClientError::from(e.errno()), e);
+        }
+        // the errnos of the protocol are the same as the ones of the host
+        assert_eq!(MClientError::Timeout.errno(), Errno::ETIMEDOUT);
+        assert_eq!(MClientError::Protocol.errno(), Errno::EPROTO);
+    }
+}
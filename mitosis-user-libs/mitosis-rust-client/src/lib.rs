@@ -1,13 +1,41 @@
-use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::os::unix::prelude::AsRawFd;
 
-pub(crate) use libc;
-#[allow(unused_imports)]
-pub(crate) use nix;
+pub use libc;
+pub use nix;
 
-pub const DEFAULT_SYSCALL_PATH: &'static str = "/dev/mitosis-syscalls";
+pub use mitosis_protocol::IoctlCmdType;
+
+pub const DEFAULT_SYSCALL_PATH: &str = "/dev/mitosis-syscalls";
+
+pub mod error;
+pub use error::*;
+
+/// The device that handles MITOSIS system calls, i.e., `/dev/mitosis-syscalls`.
+/// Other implementations (e.g., a mock device) can be used for testing.
+pub trait Device {
+    /// Issue the command with a raw argument, which is either a value or a pointer
+    ///
+    /// # Safety
+    /// If the command takes a pointer, `arg` must point to a valid request of the command
+    unsafe fn ioctl(
+        &mut self,
+        cmd: IoctlCmdType,
+        arg: crate::libc::c_ulong,
+    ) -> crate::nix::Result<crate::libc::c_int>;
+}
+
+impl Device for std::fs::File {
+    unsafe fn ioctl(
+        &mut self,
+        cmd: IoctlCmdType,
+        arg: crate::libc::c_ulong,
+    ) -> crate::nix::Result<crate::libc::c_int> {
+        mitosis_test(self.as_raw_fd(), cmd as _, arg as *const usize)
+    }
+}
 
 /// The client ot issue MITOSIS system calls in rust
-/// Must be created using MClientOptions
+/// Must be created using MClientOptions (or `MClient::new_with_device`)
 ///
 /// # Examples
 ///
@@ -16,10 +44,11 @@ pub const DEFAULT_SYSCALL_PATH: &'static str = "/dev/mitosis-syscalls";
 /// use mitosis_rust_client::MClientOptions;
 ///
 /// let client = MClientOptions::new().set_device_name("Cargo.toml".to_string()).open().unwrap();
-#[allow(dead_code)]
-pub struct MClient {
-    fd: RawFd,
-    file: std::fs::File,
+pub struct MClient<D: Device = std::fs::File> {
+    device: D,
+
+    // the key of the prepared process
+    prepared_key: Option<u64>,
 }
 
 pub mod signatures;
@@ -29,61 +58,468 @@ pub use signatures::*;
 /// A process is identified globally a (u64, u64),
 /// where the first u64 is the container ID, and the second u64 is a user-provided key
 ///
-impl MClient {
-    pub fn nil(&mut self) -> crate::nix::Result<crate::libc::c_int> {
+impl<D: Device> MClient<D> {
+    pub fn nil(&mut self) -> MClientResult<crate::libc::c_int> {
         let data: usize = 0;
-        unsafe { mitosis_syscall_nil(self.fd, &data) }
+        self.call_w_ptr(mitosis_protocol::CALL_NIL, &data)
     }
 
     /// Prepare the process
     /// Arguments
-    /// * key : the user key
+    /// * key : the user key, which is the handler ID used to resume the process
+    pub fn prepare(&mut self, key: u64) -> MClientResult<crate::libc::c_int> {
+        let res = self.call(mitosis_protocol::CALL_PREPARE, key as _)?;
+        self.prepared_key = Some(key);
+        Ok(res)
+    }
+
+    /// Prepare the process, and the prepared image is kept in the kernel
+    /// even after the process exits
+    pub fn prepare_ping(&mut self, key: u64) -> MClientResult<crate::libc::c_int> {
+        let res = self.call(mitosis_protocol::CALL_PREPARE_PING, key as _)?;
+        self.prepared_key = Some(key);
+        Ok(res)
+    }
+
+    /// Prepare the process with hints on its address ranges,
+    /// e.g., to exclude scratch buffers (`mitosis_protocol::REGION_EXCLUDE`),
+    /// or to transfer the hot data eagerly at resume (`mitosis_protocol::REGION_HOT`)
+    ///
+    /// Arguments
+    /// * ping : whether the prepared image is kept in the kernel, see `prepare_ping`
+    pub fn prepare_w_hints(
+        &mut self,
+        key: u64,
+        regions: &[PrepareRegion],
+        ping: bool,
+    ) -> MClientResult<crate::libc::c_int> {
+        let flags = if ping { mitosis_protocol::PREPARE_PING } else { 0 };
+        let res = self.call_prepare_req(key, regions, flags)?;
+        self.prepared_key = Some(key);
+        Ok(res)
+    }
+
+    /// Same as `prepare_w_hints`, except that the recorded pages are stored compressed,
+    /// which the children decompress at the page faults.
+    /// It pays off on bandwidth-limited links if the pages are compressible,
+    /// see `image_stat` and `compression_ratio`.
+    /// The image cannot be refreshed by `prepare_update`.
+    pub fn prepare_compressed(
+        &mut self,
+        key: u64,
+        regions: &[PrepareRegion],
+        ping: bool,
+    ) -> MClientResult<crate::libc::c_int> {
+        let mut flags = mitosis_protocol::PREPARE_COMPRESS;
+        if ping {
+            flags |= mitosis_protocol::PREPARE_PING;
+        }
+        let res = self.call_prepare_req(key, regions, flags)?;
+        self.prepared_key = Some(key);
+        Ok(res)
+    }
+
+    /// Same as `prepare_w_hints`, except that the pages identical to the ones of the other images
+    /// on the host (e.g., of the runtime and the libraries) are shared with them,
+    /// which saves the memory pinned by the images, see `image_stat`.
+    /// The pages changed by `prepare_update` are shared again.
+    ///
+    /// Return
+    /// * `MClientError::Unsupported` if the kernel copies the pages at prepare
+    pub fn prepare_dedup(
+        &mut self,
+        key: u64,
+        regions: &[PrepareRegion],
+        ping: bool,
+    ) -> MClientResult<crate::libc::c_int> {
+        let mut flags = mitosis_protocol::PREPARE_DEDUP;
+        if ping {
+            flags |= mitosis_protocol::PREPARE_PING;
+        }
+        let res = self.call_prepare_req(key, regions, flags)?;
+        self.prepared_key = Some(key);
+        Ok(res)
+    }
+
+    /// Refresh the image prepared by this client with the current state of the process,
+    /// only the pages changed since the last prepare (or refresh) are recorded again.
+    /// The children resumed from the previous image keep running on it.
+    ///
+    /// Arguments
+    /// * regions : the hints of the refreshed image, see `prepare_w_hints`
     ///
     /// Return
-    /// * the handler ID
-    pub fn prepare(&mut self, key: u64) -> crate::nix::Result<crate::libc::c_int> {
-        unsafe { mitosis_syscall_prepare(self.fd, key as _) }
+    /// * the generation of the image, which is bumped by each refresh
+    /// * `MClientError::InvalidArgument` if this client has not prepared
+    /// * `MClientError::Unsupported` if the kernel copies the pages at prepare,
+    ///   or the image is prepared by `prepare_compressed`
+    pub fn prepare_update(
+        &mut self,
+        regions: &[PrepareRegion],
+    ) -> MClientResult<crate::libc::c_int> {
+        let key = self.prepared_key.ok_or(MClientError::InvalidArgument)?;
+        self.call_prepare_req(key, regions, mitosis_protocol::PREPARE_UPDATE)
     }
 
     /// Connect the local MITOSIS daemon to a host
     ///
     /// Return
     /// * the remote machine ID (generated by MITOSIS)
-    pub fn connect(&mut self, remote_gid : String) -> crate::nix::Result<crate::libc::c_int> {
-        unimplemented!();
+    pub fn connect(&mut self, remote_gid: String) -> MClientResult<crate::libc::c_int> {
+        self.connect_w_nic(remote_gid, 0)
+    }
+
+    /// Connect to a host through its `nic_id`th RNIC
+    pub fn connect_w_nic(
+        &mut self,
+        remote_gid: String,
+        nic_id: u32,
+    ) -> MClientResult<crate::libc::c_int> {
+        // the kernel reads exactly GID_STR_LEN bytes
+        if remote_gid.len() != GID_STR_LEN {
+            return Err(MClientError::InvalidArgument);
+        }
+        let gid =
+            std::ffi::CString::new(remote_gid).map_err(|_| MClientError::InvalidArgument)?;
+        let req = ConnectReq {
+            machine_id: 0,
+            nic_id,
+            gid: gid.as_ptr() as _,
+        };
+        self.call_req(mitosis_protocol::CALL_CONNECT, &req)
+    }
+
+    /// Resume from the process (`process_handler_id`) prepared at the remote machine
+    pub fn resume(
+        &mut self,
+        remote_mac_id: u64,
+        process_handler_id: u64,
+    ) -> MClientResult<crate::libc::c_int> {
+        let req = Self::resume_remote_req(remote_mac_id, process_handler_id)?;
+        self.call_req(mitosis_protocol::CALL_RESUME_REMOTE, &req)
+    }
+
+    /// Resume from the remote process, and write `input` to `input_addr` of the child
+    ///
+    /// Arguments
+    /// * ret : the value returned by the child from its system call (0 if None),
+    ///   e.g., to tell each child its index
+    /// * notify_exit : report the exit of the child to the parent, see `poll_child_exit`
+    pub fn resume_w_input(
+        &mut self,
+        remote_mac_id: u64,
+        process_handler_id: u64,
+        input_addr: u64,
+        input: &[u8],
+        ret: Option<u32>,
+        notify_exit: bool,
+    ) -> MClientResult<crate::libc::c_int> {
+        let ResumeRemoteReq {
+            machine_id,
+            handler_id,
+        } = Self::resume_remote_req(remote_mac_id, process_handler_id)?;
+        if input.len() > mitosis_protocol::MAX_RESUME_INPUT as usize
+            || ret.is_some_and(|r| r > i32::MAX as u32)
+        {
+            return Err(MClientError::InvalidArgument);
+        }
+        let req = ResumeInputReq {
+            machine_id,
+            handler_id,
+            input: input.as_ptr(),
+            input_addr,
+            input_len: input.len() as _,
+            flags: if ret.is_some() {
+                mitosis_protocol::INPUT_SET_RET
+            } else {
+                0
+            } | if notify_exit {
+                mitosis_protocol::RESUME_NOTIFY_EXIT
+            } else {
+                0
+            },
+            ret: ret.unwrap_or(0),
+            ..Default::default()
+        };
+        self.call_req(mitosis_protocol::CALL_RESUME_REMOTE_W_INPUT, &req)
+    }
+
+    /// Start resuming from the remote process without waiting for its image
+    ///
+    /// The image is fetched by the kernel in the background.
+    /// Once fetched, the kernel signals the `eventfd` (if any),
+    /// and the resume takes effect after `resume_commit`.
+    pub fn resume_async(
+        &mut self,
+        remote_mac_id: u64,
+        process_handler_id: u64,
+        eventfd: Option<std::os::unix::io::RawFd>,
+    ) -> MClientResult<crate::libc::c_int> {
+        let ResumeRemoteReq {
+            machine_id,
+            handler_id,
+        } = Self::resume_remote_req(remote_mac_id, process_handler_id)?;
+        let req = ResumeAsyncReq {
+            machine_id,
+            handler_id,
+            eventfd: eventfd.unwrap_or(NO_EVENTFD),
+            ..Default::default()
+        };
+        self.call_req(mitosis_protocol::CALL_RESUME_REMOTE_ASYNC, &req)
+    }
+
+    /// Apply the image fetched by `resume_async`
+    ///
+    /// Return
+    /// * `MClientError::InProgress` if the image is still being fetched, retry later
+    pub fn resume_commit(&mut self) -> MClientResult<crate::libc::c_int> {
+        let data: usize = 0;
+        self.call_w_ptr(mitosis_protocol::CALL_RESUME_COMMIT, &data)
     }
 
-    pub fn resume(&mut self, remote_mac_id: u64, process_handler_id : u64) -> crate::nix::Result<crate::libc::c_int> {
-        unimplemented!();
+    /// Poll the exit of a child resumed (with `notify_exit`) from the process prepared by this client
+    ///
+    /// Return
+    /// * `MClientError::InProgress` if no child has exited since the last poll
+    /// * `MClientError::InvalidArgument` if this client has not prepared
+    pub fn poll_child_exit(&mut self) -> MClientResult<ChildExitInfo> {
+        let mut info = ChildExitInfo::default();
+        self.call_reply(mitosis_protocol::CALL_POLL_CHILD_EXIT, &mut info)?;
+        Ok(info)
     }
 
-    // query the prepared results
-    pub fn query(&mut self) -> Option<u64> {
-        unimplemented!();
+    /// Fetch the remaining remote pages of the resumed caller in the background,
+    /// after which the caller no longer depends on its parent, see `materialize_status`
+    ///
+    /// Arguments
+    /// * pages_per_sec : the max number of pages fetched per second, None for no limit
+    /// * batch : the number of pages fetched at a time, None for the default of the kernel
+    ///
+    /// Return
+    /// * `MClientError::InvalidArgument` if the caller has not resumed
+    /// * `MClientError::AlreadyActive` if the caller is being (or has been) materialized
+    /// * `MClientError::Unsupported` if processes have been forked from the caller
+    pub fn materialize(
+        &mut self,
+        pages_per_sec: Option<u32>,
+        batch: Option<u32>,
+    ) -> MClientResult<crate::libc::c_int> {
+        if pages_per_sec == Some(0)
+            || batch.is_some_and(|b| b == 0 || b > mitosis_protocol::MAX_MATERIALIZE_BATCH)
+        {
+            return Err(MClientError::InvalidArgument);
+        }
+        let req = MaterializeReq {
+            pages_per_sec: pages_per_sec.unwrap_or(0),
+            batch: batch.unwrap_or(0),
+        };
+        self.call_req(mitosis_protocol::CALL_MATERIALIZE, &req)
+    }
+
+    /// Query the progress of `materialize`,
+    /// whose state is `MATERIALIZE_NONE` if it has not been called
+    pub fn materialize_status(&mut self) -> MClientResult<MaterializeStatus> {
+        let mut status = MaterializeStatus::default();
+        self.call_reply(mitosis_protocol::CALL_MATERIALIZE_STATUS, &mut status)?;
+        Ok(status)
+    }
+
+    /// Keep the image prepared by this client registered after the client exits,
+    /// until it is dropped (see `drop_image`) or expires
+    ///
+    /// Arguments
+    /// * ttl_sec : the image is dropped by the kernel after the TTL, None to keep it until dropped
+    /// * evictable : the kernel may drop the image under memory pressure, the least recently
+    ///   resumed ones first
+    ///
+    /// Return
+    /// * `MClientError::InvalidArgument` if this client has not prepared
+    /// * `MClientError::AlreadyExists` if the image has been detached, e.g., by `prepare_ping`
+    pub fn detach_image(
+        &mut self,
+        ttl_sec: Option<u32>,
+        evictable: bool,
+    ) -> MClientResult<crate::libc::c_int> {
+        if ttl_sec == Some(0) {
+            return Err(MClientError::InvalidArgument);
+        }
+        let req = DetachImageReq {
+            ttl_sec: ttl_sec.unwrap_or(0),
+            flags: if evictable {
+                mitosis_protocol::IMAGE_EVICTABLE
+            } else {
+                0
+            },
+        };
+        self.call_req(mitosis_protocol::CALL_DETACH_IMAGE, &req)
+    }
+
+    /// Unregister the detached image `key`, which must be owned by the caller's user
+    /// (unless the caller has `CAP_SYS_ADMIN`)
+    ///
+    /// Return
+    /// * `MClientError::NotFound` if no image is prepared with `key`
+    /// * `MClientError::InvalidArgument` if the image is not detached
+    /// * `MClientError::PermissionDenied` if the image is owned by another user
+    pub fn drop_image(&mut self, key: u64) -> MClientResult<crate::libc::c_int> {
+        self.call(mitosis_protocol::CALL_DROP_IMAGE, key as _)
+    }
+
+    /// Query the stats of the image prepared by this client
+    ///
+    /// Return
+    /// * `MClientError::InvalidArgument` if this client has not prepared
+    pub fn image_stat(&mut self) -> MClientResult<ImageStat> {
+        let mut stat = ImageStat::default();
+        self.call_reply(mitosis_protocol::CALL_IMAGE_STAT, &mut stat)?;
+        Ok(stat)
+    }
+
+    /// Resume from the process prepared at the local machine
+    pub fn resume_local(&mut self, process_handler_id: u64) -> MClientResult<crate::libc::c_int> {
+        self.call(mitosis_protocol::CALL_RESUME_LOCAL, process_handler_id as _)
+    }
+
+    /// Call the nil RPC at the remote machine
+    pub fn nil_rpc(
+        &mut self,
+        remote_mac_id: u64,
+        process_handler_id: u64,
+    ) -> MClientResult<crate::libc::c_int> {
+        let req = Self::resume_remote_req(remote_mac_id, process_handler_id)?;
+        self.call_req(mitosis_protocol::CALL_NIL_RPC, &req)
+    }
+
+    /// Leave all the connected hosts, so that the local MITOSIS can re-join later
+    pub fn leave(&mut self) -> MClientResult<crate::libc::c_int> {
+        let data: usize = 0;
+        self.call_w_ptr(mitosis_protocol::CALL_LEAVE, &data)
+    }
+
+    /// Query the ABI version, features (`mitosis_protocol::FEATURE_*`) and limits of the kernel
+    pub fn info(&mut self) -> MClientResult<MitosisInfo> {
+        let mut info = MitosisInfo::default();
+        self.call_reply(mitosis_protocol::CALL_GET_INFO, &mut info)?;
+        Ok(info)
+    }
+
+    /// Query the image prepared by this client, which the kernel may no longer hold,
+    /// e.g., once it is dropped or evicted after `detach_image`
+    ///
+    /// Return
+    /// * the handler ID of the image, None if this client has not prepared,
+    ///   or the image has been unregistered
+    pub fn query(&mut self) -> MClientResult<Option<u64>> {
+        let mut stat = ImageStat::default();
+        match self.call_reply(mitosis_protocol::CALL_IMAGE_STAT, &mut stat) {
+            Ok(_) => Ok(self.prepared_key),
+            Err(MClientError::InvalidArgument) | Err(MClientError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     // a wrapper to test arbitrary cmd
-    pub fn test(&mut self, cmd: crate::libc::c_int) -> crate::nix::Result<crate::libc::c_int> {
+    pub fn test(&mut self, cmd: crate::libc::c_int) -> MClientResult<crate::libc::c_int> {
         let data: usize = 0;
-        unsafe { mitosis_test(self.fd, cmd as _, &data) }
+        self.call_w_ptr(cmd as _, &data)
     }
 
     pub fn test_w_arg<T>(
         &mut self,
         cmd: crate::libc::c_int,
         data: *const T,
-    ) -> crate::nix::Result<crate::libc::c_int> {
-        unsafe { mitosis_test(self.fd, cmd as _, data as _) }
+    ) -> MClientResult<crate::libc::c_int> {
+        unsafe { Ok(self.device.ioctl(cmd as _, data as _)?) }
     }
 }
 
-impl MClient {
-    pub(crate) fn new(f: std::fs::File) -> Self {
+impl<D: Device> MClient<D> {
+    /// Create a client upon an opened device
+    pub fn new_with_device(device: D) -> Self {
         Self {
-            fd: f.as_raw_fd(),
-            file: f,
+            device,
+            prepared_key: None,
         }
     }
+
+    pub fn get_device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    #[inline]
+    fn call(
+        &mut self,
+        cmd: IoctlCmdType,
+        arg: crate::libc::c_ulong,
+    ) -> MClientResult<crate::libc::c_int> {
+        // safety: the arg is passed by value
+        unsafe { Ok(self.device.ioctl(cmd, arg)?) }
+    }
+
+    #[inline]
+    fn call_w_ptr<T>(&mut self, cmd: IoctlCmdType, req: &T) -> MClientResult<crate::libc::c_int> {
+        // safety: the request is borrowed during the call
+        unsafe { Ok(self.device.ioctl(cmd, req as *const T as _)?) }
+    }
+
+    /// Pass a request defined by the protocol
+    #[inline]
+    fn call_req<T>(&mut self, cmd: IoctlCmdType, req: &T) -> MClientResult<crate::libc::c_int> {
+        debug_assert!(mitosis_protocol::check_request::<T>(cmd));
+        self.call_w_ptr(cmd, req)
+    }
+
+    /// Receive a reply defined by the protocol
+    #[inline]
+    fn call_reply<T>(&mut self, cmd: IoctlCmdType, reply: &mut T) -> MClientResult<crate::libc::c_int> {
+        debug_assert!(mitosis_protocol::check_reply::<T>(cmd));
+        // safety: the reply is borrowed during the call
+        unsafe { Ok(self.device.ioctl(cmd, reply as *mut T as _)?) }
+    }
+
+    #[inline]
+    fn call_prepare_req(
+        &mut self,
+        key: u64,
+        regions: &[PrepareRegion],
+        flags: u32,
+    ) -> MClientResult<crate::libc::c_int> {
+        if regions.len() > mitosis_protocol::MAX_PREPARE_REGIONS as usize
+            || regions.iter().any(|r| r.start >= r.end)
+        {
+            return Err(MClientError::InvalidArgument);
+        }
+        let req = PrepareReq {
+            key,
+            regions: regions.as_ptr(),
+            region_count: regions.len() as _,
+            flags,
+        };
+        self.call_req(mitosis_protocol::CALL_PREPARE_W_HINTS, &req)
+    }
+
+    #[inline]
+    fn resume_remote_req(remote_mac_id: u64, handler_id: u64) -> MClientResult<ResumeRemoteReq> {
+        Ok(ResumeRemoteReq {
+            machine_id: remote_mac_id
+                .try_into()
+                .map_err(|_| MClientError::InvalidArgument)?,
+            handler_id: handler_id
+                .try_into()
+                .map_err(|_| MClientError::InvalidArgument)?,
+        })
+    }
+}
+
+/// The bytes read by a child to fetch all the pages of the image over the ones without
+/// the compression (see `MClient::prepare_compressed`), where the zero pages are not counted.
+/// The compression pays off if it is well below 1.
+pub fn compression_ratio(stat: &ImageStat) -> f64 {
+    let raw = (stat.pages - stat.zero_pages) * 4096;
+    if raw == 0 {
+        return 1.0;
+    }
+    stat.stored_bytes as f64 / raw as f64
 }
 
 /// Options to open a mitosis client that can use to call requests
@@ -100,6 +536,12 @@ pub struct MClientOptions {
     ioctl_device_name: String,
 }
 
+impl Default for MClientOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MClientOptions {
     pub fn new() -> Self {
         Self {
@@ -113,7 +555,7 @@ impl MClientOptions {
     }
 
     pub fn open(&self) -> std::io::Result<MClient> {
-        Ok(MClient::new(
+        Ok(MClient::new_with_device(
             std::fs::File::options()
                 .read(true)
                 .write(true)
@@ -141,8 +583,18 @@ mod tests {
         println!("check CALL nil {}", CALL_NIL);
     }
 
+    #[test]
+    fn test_not_a_device() {
+        let mut client = MClientOptions::new()
+            .set_device_name("Cargo.toml".to_string())
+            .open()
+            .unwrap();
+        assert_eq!(client.nil(), Err(MClientError::UnknownCommand));
+    }
+
     #[cfg(target_os = "linux")]
     #[test]
+    #[ignore = "requires the MITOSIS kernel module"]
     fn test_call_nil() {
         let _client = MClientOptions::new()
             .set_device_name(crate::DEFAULT_SYSCALL_PATH.to_string())
@@ -18,6 +18,8 @@
 macro_rules! ioctl_write {
     ($(#[$attr:meta])* $name:ident, $nr:expr, $ty:ty) => (
         $(#[$attr])*
+        /// # Safety
+        /// `data` must point to a valid request of the command
         pub unsafe fn $name(fd: $crate::libc::c_int,
                             data: *const $ty)
                             -> $crate::nix::Result<$crate::libc::c_int> {
@@ -31,6 +33,8 @@ macro_rules! ioctl_write {
 macro_rules! ioctl_read {
     ($(#[$attr:meta])* $name:ident, $nr:expr, $ty:ty) => (
         $(#[$attr])*
+        /// # Safety
+        /// `data` must point to a valid buffer of the command
         pub unsafe fn $name(fd: $crate::libc::c_int,
                             data: *mut $ty)
                             -> $crate::nix::Result<$crate::libc::c_int> {
@@ -39,10 +43,27 @@ macro_rules! ioctl_read {
     )
 }
 
+/// The argument is passed by value instead of a pointer
+#[macro_export]
+macro_rules! ioctl_write_int {
+    ($(#[$attr:meta])* $name:ident, $nr:expr) => (
+        $(#[$attr])*
+        /// # Safety
+        /// `fd` must be a MITOSIS device
+        pub unsafe fn $name(fd: $crate::libc::c_int,
+                            data: $crate::libc::c_ulong)
+                            -> $crate::nix::Result<$crate::libc::c_int> {
+            $crate::nix::errno::Errno::result($crate::libc::ioctl(fd,  $nr, data))
+        }
+    )
+}
+
 #[macro_export]
 macro_rules! ioctl_test {
     ($(#[$attr:meta])* $name:ident, $ty:ty) => (
         $(#[$attr])*
+        /// # Safety
+        /// `data` must be a valid argument of the command `nr`
         pub unsafe fn $name(fd: $crate::libc::c_int,
                             nr : u64,
                             data: *const $ty)
@@ -55,16 +76,19 @@ macro_rules! ioctl_test {
 #[cfg(test)]
 mod tests {
 
-    #[allow(unused_imports)]    
-    use super::*;
-
     #[allow(dead_code)]
     pub struct TestArg { data : u64} 
 
-    #[allow(dead_code)]
     ioctl_write!(test_arg, 0, TestArg); 
+    ioctl_write_int!(test_int_arg, 0);
 
     #[test]
     fn ioctrl_macros() {        
+        // a regular file does not support the ioctl
+        let file = std::fs::File::open("Cargo.toml").unwrap();
+        let fd = std::os::unix::io::AsRawFd::as_raw_fd(&file);
+        let arg = TestArg { data: 0 };
+        assert!(unsafe { test_arg(fd, &arg) }.is_err());
+        assert!(unsafe { test_int_arg(fd, 0) }.is_err());
     }
 }
@@ -1,6 +1,31 @@
-use crate::{ioctl_write, ioctl_test};
+use crate::{ioctl_read, ioctl_test, ioctl_write, ioctl_write_int};
+
+/// The requests are defined in the protocol crate, which is shared with the kernel
+pub use mitosis_protocol::{
+    ChildExitInfo, ConnectReq, DetachImageReq, ImageStat, MaterializeReq, MaterializeStatus,
+    MitosisInfo, PrepareRegion, PrepareReq, ResumeAsyncReq, ResumeInputReq, ResumeRemoteReq,
+    CHILD_DETACHED, GID_STR_LEN, IMAGE_EVICTABLE, MATERIALIZE_DONE, MATERIALIZE_FAILED,
+    MATERIALIZE_NONE, MATERIALIZE_RUNNING, MAX_MATERIALIZE_BATCH, NO_EVENTFD,
+};
 
 ioctl_write!(mitosis_syscall_nil, mitosis_protocol::CALL_NIL as _, usize);
-ioctl_write!(mitosis_syscall_prepare, mitosis_protocol::CALL_PREPARE as _, u64);
+ioctl_write!(mitosis_syscall_connect, mitosis_protocol::CALL_CONNECT as _, ConnectReq);
+ioctl_write_int!(mitosis_syscall_prepare, mitosis_protocol::CALL_PREPARE as _);
+ioctl_write_int!(mitosis_syscall_prepare_ping, mitosis_protocol::CALL_PREPARE_PING as _);
+ioctl_write!(mitosis_syscall_prepare_w_hints, mitosis_protocol::CALL_PREPARE_W_HINTS as _, PrepareReq);
+ioctl_write_int!(mitosis_syscall_resume_local, mitosis_protocol::CALL_RESUME_LOCAL as _);
+ioctl_write!(mitosis_syscall_resume_remote, mitosis_protocol::CALL_RESUME_REMOTE as _, ResumeRemoteReq);
+ioctl_write!(mitosis_syscall_resume_remote_w_input, mitosis_protocol::CALL_RESUME_REMOTE_W_INPUT as _, ResumeInputReq);
+ioctl_write!(mitosis_syscall_resume_remote_async, mitosis_protocol::CALL_RESUME_REMOTE_ASYNC as _, ResumeAsyncReq);
+ioctl_write!(mitosis_syscall_resume_commit, mitosis_protocol::CALL_RESUME_COMMIT as _, usize);
+ioctl_write!(mitosis_syscall_nil_rpc, mitosis_protocol::CALL_NIL_RPC as _, ResumeRemoteReq);
+ioctl_write!(mitosis_syscall_leave, mitosis_protocol::CALL_LEAVE as _, usize);
+ioctl_read!(mitosis_syscall_get_info, mitosis_protocol::CALL_GET_INFO as _, MitosisInfo);
+ioctl_read!(mitosis_syscall_poll_child_exit, mitosis_protocol::CALL_POLL_CHILD_EXIT as _, ChildExitInfo);
+ioctl_write!(mitosis_syscall_materialize, mitosis_protocol::CALL_MATERIALIZE as _, MaterializeReq);
+ioctl_read!(mitosis_syscall_materialize_status, mitosis_protocol::CALL_MATERIALIZE_STATUS as _, MaterializeStatus);
+ioctl_write!(mitosis_syscall_detach_image, mitosis_protocol::CALL_DETACH_IMAGE as _, DetachImageReq);
+ioctl_write_int!(mitosis_syscall_drop_image, mitosis_protocol::CALL_DROP_IMAGE as _);
+ioctl_read!(mitosis_syscall_image_stat, mitosis_protocol::CALL_IMAGE_STAT as _, ImageStat);
 
 ioctl_test!(mitosis_test,  usize);
@@ -0,0 +1,462 @@
+mod common;
+
+use common::*;
+use mitosis_protocol::*;
+use mitosis_rust_client::*;
+use nix::errno::Errno;
+
+fn last(client: &mut MClient<RecordingDevice>) -> Ioctl {
+    client.get_device_mut().ioctls.last().cloned().unwrap()
+}
+
+#[test]
+fn nil() {
+    let mut client = recording_client();
+    assert_eq!(client.nil(), Ok(0));
+    assert_eq!(
+        client.get_device_mut().ioctls,
+        vec![Ioctl {
+            cmd: CALL_NIL,
+            arg: Arg::Nil
+        }]
+    );
+}
+
+#[test]
+fn connect() {
+    let mut client = recording_client();
+    // the kernel reads exactly GID_STR_LEN bytes of the GID
+    client.get_device_mut().deref = Some(|_, req| {
+        let req: ConnectReq = unsafe { std::ptr::read_unaligned(req.as_ptr() as _) };
+        read_user(req.gid as u64, GID_STR_LEN)
+    });
+    client.get_device_mut().push_result(Ok(2));
+    assert_eq!(client.connect_w_nic(GID_0.to_string(), 1), Ok(2));
+
+    let ioctl = last(&mut client);
+    assert_eq!(ioctl.cmd, CALL_CONNECT);
+    let req: ConnectReq = decode(&ioctl);
+    assert_eq!((req.machine_id, req.nic_id), (0, 1));
+    assert_eq!(client.get_device_mut().derefed, vec![GID_0.as_bytes().to_vec()]);
+}
+
+#[test]
+fn connect_malformed_gid() {
+    let mut client = recording_client();
+    assert_eq!(
+        client.connect("fe80::1".to_string()),
+        Err(MClientError::InvalidArgument)
+    );
+    assert_eq!(
+        client.connect(format!("{}\0", &GID_0[..GID_STR_LEN - 1])),
+        Err(MClientError::InvalidArgument)
+    );
+    // malformed GIDs never reach the kernel
+    assert!(client.get_device_mut().ioctls.is_empty());
+}
+
+#[test]
+fn prepare() {
+    let mut client = recording_client();
+    assert_eq!(client.prepare(73), Ok(0));
+    assert_eq!(client.prepare_ping(74), Ok(0));
+    assert_eq!(client.drop_image(75), Ok(0));
+    assert_eq!(client.resume_local(76), Ok(0));
+
+    let ioctls: Vec<_> = client.get_device_mut().ioctls.clone();
+    assert_eq!(
+        ioctls,
+        vec![
+            Ioctl {
+                cmd: CALL_PREPARE,
+                arg: Arg::Value(73)
+            },
+            Ioctl {
+                cmd: CALL_PREPARE_PING,
+                arg: Arg::Value(74)
+            },
+            Ioctl {
+                cmd: CALL_DROP_IMAGE,
+                arg: Arg::Value(75)
+            },
+            Ioctl {
+                cmd: CALL_RESUME_LOCAL,
+                arg: Arg::Value(76)
+            },
+        ]
+    );
+}
+
+#[test]
+fn query() {
+    let mut client = recording_client();
+    // not prepared by the caller
+    client.get_device_mut().push_result(Err(Errno::EINVAL));
+    assert_eq!(client.query(), Ok(None));
+
+    client.prepare(73).unwrap();
+    assert_eq!(client.query(), Ok(Some(73)));
+    // the kernel no longer holds the image, e.g., it has been evicted
+    client.get_device_mut().push_result(Err(Errno::ENOENT));
+    assert_eq!(client.query(), Ok(None));
+    client.get_device_mut().push_result(Err(Errno::EIO));
+    assert_eq!(client.query(), Err(MClientError::Internal));
+
+    // each query asks the kernel
+    let stats = client
+        .get_device_mut()
+        .ioctls
+        .iter()
+        .filter(|i| i.cmd == CALL_IMAGE_STAT && i.arg == Arg::Reply)
+        .count();
+    assert_eq!(stats, 4);
+}
+
+#[test]
+fn prepare_w_hints() {
+    let mut client = recording_client();
+    client.get_device_mut().deref = Some(|_, req| {
+        let req: PrepareReq = unsafe { std::ptr::read_unaligned(req.as_ptr() as _) };
+        let len = req.region_count as usize * std::mem::size_of::<PrepareRegion>();
+        read_user(req.regions as u64, len)
+    });
+    let regions = [
+        PrepareRegion {
+            start: 0x1000,
+            end: 0x3000,
+            hint: REGION_EXCLUDE,
+            ..Default::default()
+        },
+        PrepareRegion {
+            start: 0x8000,
+            end: 0x8800,
+            hint: REGION_HOT,
+            ..Default::default()
+        },
+    ];
+    assert_eq!(client.prepare_w_hints(73, &regions, true), Ok(0));
+    assert_eq!(client.prepare_compressed(73, &[], false), Ok(0));
+    assert_eq!(client.prepare_dedup(73, &[], true), Ok(0));
+
+    let ioctls = client.get_device_mut().ioctls.clone();
+    assert!(ioctls.iter().all(|i| i.cmd == CALL_PREPARE_W_HINTS));
+    let reqs: Vec<PrepareReq> = ioctls.iter().map(decode).collect();
+    assert!(reqs.iter().all(|r| r.key == 73));
+    assert_eq!(reqs[0].flags, PREPARE_PING);
+    assert_eq!(reqs[0].region_count, 2);
+    assert_eq!(reqs[1].flags, PREPARE_COMPRESS);
+    assert_eq!(reqs[1].region_count, 0);
+    assert_eq!(reqs[2].flags, PREPARE_DEDUP | PREPARE_PING);
+
+    let derefed = &client.get_device_mut().derefed;
+    let bytes: Vec<u8> = regions.iter().flat_map(|r| as_bytes(r).to_vec()).collect();
+    assert_eq!(derefed[0], bytes);
+}
+
+#[test]
+fn prepare_update() {
+    let mut client = recording_client();
+    // the key of the image is unknown
+    assert_eq!(client.prepare_update(&[]), Err(MClientError::InvalidArgument));
+    assert!(client.get_device_mut().ioctls.is_empty());
+
+    client.prepare(73).unwrap();
+    client.get_device_mut().push_result(Ok(1));
+    assert_eq!(client.prepare_update(&[]), Ok(1));
+    let req: PrepareReq = decode(&last(&mut client));
+    assert_eq!((req.key, req.flags), (73, PREPARE_UPDATE));
+
+    // a failed prepare does not change the image updated
+    client.get_device_mut().push_result(Err(Errno::EEXIST));
+    assert_eq!(client.prepare(74), Err(MClientError::AlreadyExists));
+    client.prepare_update(&[]).unwrap();
+    assert_eq!(decode::<PrepareReq>(&last(&mut client)).key, 73);
+}
+
+#[test]
+fn prepare_w_bad_hints() {
+    let mut client = recording_client();
+    let empty = PrepareRegion {
+        start: 0x2000,
+        end: 0x2000,
+        hint: REGION_HOT,
+        ..Default::default()
+    };
+    assert_eq!(
+        client.prepare_w_hints(73, &[empty], false),
+        Err(MClientError::InvalidArgument)
+    );
+    let too_many = vec![
+        PrepareRegion {
+            start: 0,
+            end: 0x1000,
+            hint: REGION_HOT,
+            ..Default::default()
+        };
+        MAX_PREPARE_REGIONS as usize + 1
+    ];
+    assert_eq!(
+        client.prepare_w_hints(73, &too_many, false),
+        Err(MClientError::InvalidArgument)
+    );
+    assert!(client.get_device_mut().ioctls.is_empty());
+}
+
+#[test]
+fn resume() {
+    let mut client = recording_client();
+    assert_eq!(client.resume(1, 73), Ok(0));
+    assert_eq!(client.nil_rpc(2, 74), Ok(0));
+
+    let ioctls = client.get_device_mut().ioctls.clone();
+    assert_eq!(ioctls[0].cmd, CALL_RESUME_REMOTE);
+    assert_eq!(ioctls[1].cmd, CALL_NIL_RPC);
+    let reqs: Vec<ResumeRemoteReq> = ioctls.iter().map(decode).collect();
+    assert_eq!((reqs[0].machine_id, reqs[0].handler_id), (1, 73));
+    assert_eq!((reqs[1].machine_id, reqs[1].handler_id), (2, 74));
+
+    // the IDs must fit in the request
+    assert_eq!(client.resume(u64::MAX, 73), Err(MClientError::InvalidArgument));
+    assert_eq!(client.nil_rpc(1, u64::MAX), Err(MClientError::InvalidArgument));
+    assert_eq!(client.get_device_mut().ioctls.len(), 2);
+}
+
+#[test]
+fn resume_w_input() {
+    let mut client = recording_client();
+    client.get_device_mut().deref = Some(|_, req| {
+        let req: ResumeInputReq = unsafe { std::ptr::read_unaligned(req.as_ptr() as _) };
+        read_user(req.input as u64, req.input_len as usize)
+    });
+    let input = b"hello, child";
+    client.get_device_mut().push_result(Ok(3));
+    assert_eq!(client.resume_w_input(1, 73, 0x1000, input, Some(3), true), Ok(3));
+    assert_eq!(client.resume_w_input(1, 73, 0x2000, &[], None, false), Ok(0));
+
+    let ioctls = client.get_device_mut().ioctls.clone();
+    assert!(ioctls.iter().all(|i| i.cmd == CALL_RESUME_REMOTE_W_INPUT));
+    let reqs: Vec<ResumeInputReq> = ioctls.iter().map(decode).collect();
+    assert_eq!((reqs[0].machine_id, reqs[0].handler_id), (1, 73));
+    assert_eq!(reqs[0].input_addr, 0x1000);
+    assert_eq!(reqs[0].input_len, input.len() as u32);
+    assert_eq!(reqs[0].flags, INPUT_SET_RET | RESUME_NOTIFY_EXIT);
+    assert_eq!(reqs[0].ret, 3);
+    assert_eq!((reqs[1].flags, reqs[1].ret, reqs[1].input_len), (0, 0, 0));
+    assert_eq!(client.get_device_mut().derefed[0], input.to_vec());
+}
+
+#[test]
+fn resume_w_bad_input() {
+    let mut client = recording_client();
+    let too_long = vec![0u8; MAX_RESUME_INPUT as usize + 1];
+    assert_eq!(
+        client.resume_w_input(0, 73, 0x1000, &too_long, None, false),
+        Err(MClientError::InvalidArgument)
+    );
+    // negative returns are errors to the child
+    assert_eq!(
+        client.resume_w_input(0, 73, 0x1000, &[], Some(u32::MAX), false),
+        Err(MClientError::InvalidArgument)
+    );
+    assert!(client.get_device_mut().ioctls.is_empty());
+}
+
+#[test]
+fn resume_async() {
+    let mut client = recording_client();
+    assert_eq!(client.resume_async(1, 73, Some(5)), Ok(0));
+    assert_eq!(client.resume_async(1, 74, None), Ok(0));
+    client.get_device_mut().push_result(Err(Errno::EAGAIN));
+    assert_eq!(client.resume_commit(), Err(MClientError::InProgress));
+
+    let ioctls = client.get_device_mut().ioctls.clone();
+    let reqs: Vec<ResumeAsyncReq> = ioctls[..2].iter().map(decode).collect();
+    assert!(ioctls[..2].iter().all(|i| i.cmd == CALL_RESUME_REMOTE_ASYNC));
+    assert_eq!(
+        reqs[0],
+        ResumeAsyncReq {
+            machine_id: 1,
+            handler_id: 73,
+            eventfd: 5,
+            reserved: 0,
+        }
+    );
+    assert_eq!(reqs[1].eventfd, NO_EVENTFD);
+    assert_eq!(
+        ioctls[2],
+        Ioctl {
+            cmd: CALL_RESUME_COMMIT,
+            arg: Arg::Nil
+        }
+    );
+    assert_eq!(client.resume_async(u64::MAX, 73, None), Err(MClientError::InvalidArgument));
+}
+
+#[test]
+fn materialize() {
+    let mut client = recording_client();
+    assert_eq!(client.materialize(Some(1000), None), Ok(0));
+    assert_eq!(
+        decode::<MaterializeReq>(&last(&mut client)),
+        MaterializeReq {
+            pages_per_sec: 1000,
+            batch: 0,
+        }
+    );
+
+    // checked before the call
+    assert_eq!(client.materialize(Some(0), None), Err(MClientError::InvalidArgument));
+    assert_eq!(
+        client.materialize(None, Some(MAX_MATERIALIZE_BATCH + 1)),
+        Err(MClientError::InvalidArgument)
+    );
+    assert_eq!(client.get_device_mut().ioctls.len(), 1);
+
+    let status = MaterializeStatus {
+        state: MATERIALIZE_RUNNING,
+        fetched_pages: 12,
+        total_pages: 256,
+        ..Default::default()
+    };
+    client.get_device_mut().push_reply(&status);
+    assert_eq!(client.materialize_status(), Ok(status));
+    assert_eq!(last(&mut client).cmd, CALL_MATERIALIZE_STATUS);
+}
+
+#[test]
+fn detach_image() {
+    let mut client = recording_client();
+    assert_eq!(client.detach_image(Some(60), true), Ok(0));
+    assert_eq!(client.detach_image(None, false), Ok(0));
+    assert_eq!(client.detach_image(Some(0), false), Err(MClientError::InvalidArgument));
+
+    let ioctls = client.get_device_mut().ioctls.clone();
+    assert!(ioctls.iter().all(|i| i.cmd == CALL_DETACH_IMAGE));
+    let reqs: Vec<DetachImageReq> = ioctls.iter().map(decode).collect();
+    assert_eq!(
+        reqs,
+        vec![
+            DetachImageReq {
+                ttl_sec: 60,
+                flags: IMAGE_EVICTABLE,
+            },
+            DetachImageReq {
+                ttl_sec: 0,
+                flags: 0,
+            },
+        ]
+    );
+}
+
+#[test]
+fn replies() {
+    let mut client = recording_client();
+
+    let info = MitosisInfo {
+        abi_version: ABI_VERSION,
+        size: std::mem::size_of::<MitosisInfo>() as _,
+        features: FEATURE_PREFETCH | FEATURE_AUTO_MACHINE_ID,
+        nic_count: 1,
+        max_cluster_size: 128,
+        max_caller_num: 48,
+        prefetch_step: 1,
+        ..Default::default()
+    };
+    client.get_device_mut().push_reply(&info);
+    assert_eq!(client.info(), Ok(info));
+
+    let exit = ChildExitInfo {
+        machine_id: 1,
+        handler_id: 73,
+        status: CHILD_DETACHED,
+        runtime_usec: 1000,
+        fault_pages: 12,
+        ..Default::default()
+    };
+    client.get_device_mut().push_reply(&exit);
+    assert_eq!(client.poll_child_exit(), Ok(exit));
+
+    let stat = ImageStat {
+        pages: 256,
+        zero_pages: 64,
+        compressed_pages: 96,
+        stored_bytes: 96 * 4096 + 96 * 1024,
+        ..Default::default()
+    };
+    client.get_device_mut().push_reply(&stat);
+    assert_eq!(client.image_stat(), Ok(stat));
+    assert_eq!(compression_ratio(&stat), 0.625);
+    assert_eq!(compression_ratio(&ImageStat::default()), 1.0);
+
+    let cmds: Vec<_> = client.get_device_mut().ioctls.iter().map(|i| i.cmd).collect();
+    assert_eq!(cmds, vec![CALL_GET_INFO, CALL_POLL_CHILD_EXIT, CALL_IMAGE_STAT]);
+}
+
+#[test]
+fn leave() {
+    let mut client = recording_client();
+    assert_eq!(client.leave(), Ok(0));
+    assert_eq!(
+        last(&mut client),
+        Ioctl {
+            cmd: CALL_LEAVE,
+            arg: Arg::Nil
+        }
+    );
+}
+
+#[test]
+fn errors() {
+    let mut client = recording_client();
+
+    let cases = [
+        (Errno::EPERM, MClientError::Failed),
+        (Errno::ENOENT, MClientError::NotFound),
+        (Errno::ENOTCONN, MClientError::NotConnected),
+        (Errno::EHOSTUNREACH, MClientError::Unreachable),
+        (Errno::ETIMEDOUT, MClientError::Timeout),
+        (Errno::EALREADY, MClientError::AlreadyActive),
+        (Errno::EAGAIN, MClientError::InProgress),
+        (Errno::EEXIST, MClientError::AlreadyExists),
+        (Errno::ENOMEM, MClientError::OutOfMemory),
+        (Errno::EPROTO, MClientError::Protocol),
+        (Errno::ECONNREFUSED, MClientError::ConnectionRefused),
+        (Errno::EADDRINUSE, MClientError::MachineIdConflict),
+        (Errno::EINVAL, MClientError::InvalidArgument),
+        (Errno::EFAULT, MClientError::BadAddress),
+        (Errno::EACCES, MClientError::PermissionDenied),
+        (Errno::EOPNOTSUPP, MClientError::Unsupported),
+        (Errno::EIO, MClientError::Internal),
+        (Errno::EBUSY, MClientError::Other(Errno::EBUSY)),
+    ];
+    for (errno, err) in cases {
+        client.get_device_mut().push_result(Err(errno));
+        assert_eq!(client.connect(GID_0.to_string()), Err(err));
+        assert_eq!(err.errno(), errno);
+    }
+
+    // unknown to the protocol, and to the kernel
+    assert_eq!(client.test(1024), Err(MClientError::UnknownCommand));
+    assert_eq!(MClientError::UnknownCommand.errno(), Errno::ENOTTY);
+}
+
+#[test]
+fn commands_in_protocol() {
+    // the commands are dispatched by their IDs in the kernel
+    for (i, desc) in COMMANDS.iter().enumerate() {
+        assert!(i == 0 || COMMANDS[i - 1].cmd < desc.cmd, "{} is out of order", desc.name);
+        assert_eq!(find_cmd(desc.cmd).map(|d| d.name), Some(desc.name));
+    }
+    assert!(check_request::<ConnectReq>(CALL_CONNECT));
+    assert!(check_request::<ResumeRemoteReq>(CALL_RESUME_REMOTE));
+    assert!(check_request::<ResumeRemoteReq>(CALL_NIL_RPC));
+    assert!(check_request::<ResumeAsyncReq>(CALL_RESUME_REMOTE_ASYNC));
+    assert!(check_request::<PrepareReq>(CALL_PREPARE_W_HINTS));
+    assert!(check_request::<ResumeInputReq>(CALL_RESUME_REMOTE_W_INPUT));
+    assert!(check_request::<MaterializeReq>(CALL_MATERIALIZE));
+    assert!(check_request::<DetachImageReq>(CALL_DETACH_IMAGE));
+    assert!(check_reply::<MitosisInfo>(CALL_GET_INFO));
+    assert!(check_reply::<ChildExitInfo>(CALL_POLL_CHILD_EXIT));
+    assert!(check_reply::<MaterializeStatus>(CALL_MATERIALIZE_STATUS));
+    assert!(check_reply::<ImageStat>(CALL_IMAGE_STAT));
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+use mitosis_protocol::{find_cmd, CmdArg};
+use mitosis_rust_client::*;
+use nix::errno::Errno;
+
+/// The argument of an ioctl, decoded as the protocol (see `mitosis_protocol::COMMANDS`) defines it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    Nil,
+    Value(u64),
+    /// The bytes of the request, as copied by the kernel
+    Request(Vec<u8>),
+    Reply,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ioctl {
+    pub cmd: IoctlCmdType,
+    pub arg: Arg,
+}
+
+/// Read the bytes a request points to, given (the command, the request)
+pub type DerefFn = fn(IoctlCmdType, &[u8]) -> Vec<u8>;
+
+/// A device that checks each ioctl against the protocol, and records it.
+/// It does not emulate the kernel: the results and the replies are given by the tests.
+#[derive(Default)]
+pub struct RecordingDevice {
+    pub ioctls: Vec<Ioctl>,
+    // the results of the next ioctls, Ok(0) once drained
+    pub results: VecDeque<nix::Result<libc::c_int>>,
+    // the bytes written to the reply buffers of the next ioctls, zeros once drained
+    pub replies: VecDeque<Vec<u8>>,
+    // the bytes the requests point to, e.g., the GID of connect, read during the ioctl
+    pub deref: Option<DerefFn>,
+    pub derefed: Vec<Vec<u8>>,
+}
+
+impl RecordingDevice {
+    pub fn push_result(&mut self, res: nix::Result<libc::c_int>) {
+        self.results.push_back(res);
+    }
+
+    pub fn push_reply<T: Copy>(&mut self, reply: &T) {
+        self.replies.push_back(as_bytes(reply).to_vec());
+    }
+}
+
+impl Device for RecordingDevice {
+    unsafe fn ioctl(
+        &mut self,
+        cmd: IoctlCmdType,
+        arg: libc::c_ulong,
+    ) -> nix::Result<libc::c_int> {
+        let desc = match find_cmd(cmd) {
+            Some(desc) => desc,
+            None => {
+                // the kernel rejects the unknown commands
+                self.ioctls.push(Ioctl {
+                    cmd,
+                    arg: Arg::Value(arg),
+                });
+                return Err(Errno::ENOTTY);
+            }
+        };
+        let arg = match desc.arg {
+            CmdArg::Nil => Arg::Nil,
+            CmdArg::Value => Arg::Value(arg),
+            CmdArg::Request { size, .. } => {
+                let req = std::slice::from_raw_parts(arg as *const u8, size).to_vec();
+                if let Some(deref) = self.deref {
+                    self.derefed.push(deref(cmd, &req));
+                }
+                Arg::Request(req)
+            }
+            CmdArg::Reply { size, .. } => {
+                let reply = self.replies.pop_front().unwrap_or_else(|| vec![0; size]);
+                assert_eq!(reply.len(), size, "the reply of {} is malformed", desc.name);
+                std::ptr::copy_nonoverlapping(reply.as_ptr(), arg as *mut u8, size);
+                Arg::Reply
+            }
+        };
+        self.ioctls.push(Ioctl { cmd, arg });
+        self.results.pop_front().unwrap_or(Ok(0))
+    }
+}
+
+pub fn recording_client() -> MClient<RecordingDevice> {
+    MClient::new_with_device(RecordingDevice::default())
+}
+
+pub fn as_bytes<T: Copy>(v: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(v as *const T as *const u8, std::mem::size_of::<T>()) }
+}
+
+/// Decode the request of the C type `T`, whose size must match the one of the protocol
+pub fn decode<T: Copy>(ioctl: &Ioctl) -> T {
+    match &ioctl.arg {
+        Arg::Request(bytes) => {
+            assert_eq!(bytes.len(), std::mem::size_of::<T>());
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+        }
+        arg => panic!("{} expects no request, got {:?}", ioctl.cmd, arg),
+    }
+}
+
+/// Read the `len` bytes a request points to, during the ioctl
+pub fn read_user(ptr: u64, len: usize) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(ptr as *const u8, len).to_vec() }
+}
+
+pub const GID_0: &str = "fe80:0000:0000:0000:ec0d:9a03:00ca:2f4c";
@@ -1,15 +1,7 @@
 use std::env;
 use std::path::PathBuf;
 
-// types from customized syscalls
-const INCLUDED_ENUMS: &[&str] = &[
-    "LibMITOSISCmd"
-];
-
-const INCLUDED_TYPES: &[&str] = &[
-    "connect_req_t",
-    "resume_remote_req_t"
-];
+// The types of customized syscalls are defined in the mitosis-protocol crate
 
 // types from kernel
 const INCLUDED_KERNEL_TYPES: &[&str] = &[
@@ -109,9 +101,13 @@ const INCLUDED_VARS: &[&str] = &[
     "PMEM_PROT_WRITE",
     "PMEM_PROT_EXEC",
     "PMEM_PROT_GROWSUP",
+    "PMEM_MREMAP_MAYMOVE",
+    "PMEM_MREMAP_FIXED",
     "PMEM_VM_FAULT_SIGSEGV",
+    "PMEM_VM_FAULT_OOM",
     "PMEM_GFP_HIGHUSER",
-    "PMEM_GFP_USER"
+    "PMEM_GFP_USER",
+    "PMEM_GFP_HIGHUSER_ZERO"
 ];
 
 // Takes the CFLAGS from the kernel Makefile and changes all the include paths to be absolute
@@ -177,20 +173,9 @@ fn main() {
         .header("src/native/kernel_helper.h")
         .whitelist_function("pmem_*");
 
-    println!("cargo:rerun-if-changed=../mitosis-user-libs/mitosis-c-client/include/common.h");
-    builder = builder.header("../mitosis-user-libs/mitosis-c-client/include/common.h");
     // non-rust translatable type
     builder = builder.opaque_type("xregs_state");
 
-    for t in INCLUDED_ENUMS {
-        builder = builder.whitelist_type(t);
-        builder = builder.constified_enum_module(t);
-    }
-
-    for t in INCLUDED_TYPES {
-        builder = builder.whitelist_type(t);
-    }
-
     for t in INCLUDED_KERNEL_TYPES {
         builder = builder.whitelist_type(t);
     }
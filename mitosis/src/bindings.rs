@@ -125,6 +125,7 @@ bitflags::bitflags! {
 bitflags::bitflags! {
     pub struct FaultFlags : crate::linux_kernel_module::c_types::c_uint {
         const SIGSEGV = PMEM_VM_FAULT_SIGSEGV;
+        const OOM = PMEM_VM_FAULT_OOM;
     }
 }
 
@@ -0,0 +1,315 @@
+use alloc::collections::VecDeque;
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use hashbrown::HashMap;
+
+use rust_kernel_linux_util::kthread;
+use rust_kernel_linux_util::kthread::JoinHandler;
+
+use os_network::timeout::TimeoutWRef;
+use os_network::block_on;
+use os_network::serialize::Serialize;
+
+use mitosis_protocol::{ChildExitInfo, CHILD_DETACHED};
+
+use crate::bindings::mm_struct;
+use crate::error::{MitosisError, MitosisResult};
+use crate::kern_wrappers::wait_queue::WaitQueue;
+use crate::linux_kernel_module::c_types::{c_int, c_void};
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+use crate::rpc_handlers::{ChildExitReport, DetachReport, RPCId};
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+const TIMEOUT_USEC: i64 = 1000_000; // 1s
+
+/// The max number of unpolled reports of one prepared image,
+/// the oldest ones are dropped once exceeded
+pub const MAX_REPORTS_PER_KEY: usize = 1024;
+
+/// The exit reports of the children, indexed by the key of their parent's image.
+/// The reports are pushed by the RPC handlers, and polled by the prepared processes.
+pub struct ChildExitService {
+    reports: BoxedLockBundler<HashMap<usize, VecDeque<ChildExitInfo>>>,
+}
+
+impl ChildExitService {
+    pub fn new() -> Self {
+        Self {
+            reports: LockBundler::new(Default::default()),
+        }
+    }
+
+    pub(crate) fn push(&self, report: ChildExitReport) {
+        let info = ChildExitInfo {
+            machine_id: report.mac_id as _,
+            handler_id: report.handler_id as _,
+            status: report.status,
+            reserved: 0,
+            runtime_usec: report.runtime_usec,
+            fault_pages: report.fault_pages,
+        };
+        self.reports.lock(|r| {
+            let q = r.entry(report.handler_id).or_default();
+            if q.len() >= MAX_REPORTS_PER_KEY {
+                q.pop_front();
+            }
+            q.push_back(info);
+        });
+    }
+
+    /// Pop the oldest report of the children of the image `key`
+    pub fn pop(&self, key: usize) -> core::option::Option<ChildExitInfo> {
+        self.reports
+            .lock(|r| r.get_mut(&key).and_then(|q| q.pop_front()))
+    }
+
+    /// Drop the reports once the image is unregistered
+    pub fn remove(&self, key: usize) {
+        self.reports.lock(|r| r.remove(&key));
+    }
+}
+
+/// A report sent to the machine of the parent by the `ExitReporter`
+enum ParentReport {
+    Exit(ChildExitReport),
+    Detach(DetachReport),
+}
+
+/// One attachment of a resumed child to the image of its parent, i.e., one resume.
+///
+/// The parent keeps the pages of the image until each of its attachments is released,
+/// which happens once, when the attachment is dropped (e.g., the resume fails, or the image
+/// is no longer used by the child and its local forks) or the child is materialized.
+pub(crate) struct Attachment {
+    parent_mac_id: usize,
+    handler_id: usize,
+    token: u64,
+    released: bool,
+}
+
+impl Attachment {
+    /// Unique on the host across the resumes, which identifies the child at the parent
+    #[inline]
+    pub(crate) fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Tell the machine of the parent that the child no longer reads the pages of its image,
+    /// in the background. Only the first call takes effect.
+    pub(crate) fn release(&mut self) {
+        if self.released {
+            return;
+        }
+        self.released = true;
+        let report = DetachReport {
+            mac_id: unsafe { crate::get_mac_id() },
+            handler_id: self.handler_id,
+            token: self.token,
+        };
+        let reporter = unsafe { crate::get_exit_reporter_ref() };
+        reporter.push(self.parent_mac_id, ParentReport::Detach(report));
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// The exits (and detaches) of the resumed children reported to the machines of their parents.
+///
+/// The exit status of a child is captured by the exit hook in the context of its exiting tasks,
+/// while the report is sent once its resume states are dropped, which may happen in
+/// another context (e.g., under the mmap lock of the one releasing its mm).
+/// So the reports are sent by the reporter thread instead.
+pub struct ExitReporter {
+    // the mm of each watched child -> the exit status of its process, once its tasks exit
+    watched: BoxedLockBundler<HashMap<usize, Option<c_int>>>,
+    // the hook is called by every exiting task of the host, which skips the lock if none is watched
+    num_watched: AtomicUsize,
+    // the (machine of the parent, report) to send
+    queue: BoxedLockBundler<VecDeque<(usize, ParentReport)>>,
+    wq: WaitQueue,
+    // the token of the next attachment, see `Attachment`
+    next_token: AtomicU64,
+    thread: Option<JoinHandler>,
+}
+
+impl ExitReporter {
+    pub fn new() -> Self {
+        Self {
+            watched: LockBundler::new(Default::default()),
+            num_watched: AtomicUsize::new(0),
+            queue: LockBundler::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+            next_token: AtomicU64::new(1),
+            thread: None,
+        }
+    }
+
+    /// Start the reporter and the exit hook,
+    /// must be called after the service is installed as the global one
+    pub fn start(&mut self) -> core::option::Option<()> {
+        let builder = kthread::Builder::new()
+            .set_name(alloc::string::String::from("MITOSIS exit reporter"))
+            .set_parameter(core::ptr::null_mut());
+        self.thread = Some(builder.spawn(Self::worker).ok()?);
+        if unsafe { crate::bindings::pmem_register_exit_hook(Some(Self::on_task_exit)) } != 0 {
+            crate::log::error!("failed to register the exit hook");
+            return None;
+        }
+        Some(())
+    }
+
+    /// Capture the exit status of the child whose mm is `mm`, see `unwatch`
+    pub fn watch(&self, mm: *mut mm_struct) {
+        // the mm is not reused by another process until it is unwatched
+        unsafe { crate::bindings::pmem_mmgrab(mm) };
+        let fresh = self.watched.lock(|w| w.insert(mm as usize, None).is_none());
+        if fresh {
+            self.num_watched.fetch_add(1, Ordering::SeqCst);
+        } else {
+            unsafe { crate::bindings::pmem_mmdrop(mm) };
+        }
+    }
+
+    /// Stop watching the child whose mm is `mm`
+    ///
+    /// Return
+    /// * the exit status of the child, or `CHILD_DETACHED` if it has not exited,
+    ///   e.g., its resumed VMAs are unmapped, or it executes another program
+    pub fn unwatch(&self, mm: *mut mm_struct) -> c_int {
+        let status = match self.watched.lock(|w| w.remove(&(mm as usize))) {
+            Some(status) => status,
+            None => return CHILD_DETACHED,
+        };
+        self.num_watched.fetch_sub(1, Ordering::SeqCst);
+        unsafe { crate::bindings::pmem_mmdrop(mm) };
+        status.unwrap_or(CHILD_DETACHED)
+    }
+
+    /// A new attachment to the image `handler_id` of machine `parent_mac_id`,
+    /// which should be sent with the lookup of the image
+    pub(crate) fn attach(&self, parent_mac_id: usize, handler_id: usize) -> Attachment {
+        Attachment {
+            parent_mac_id,
+            handler_id,
+            token: self.next_token.fetch_add(1, Ordering::SeqCst),
+            released: false,
+        }
+    }
+
+    /// Send the report to the machine of the parent in the background
+    pub(crate) fn submit(&self, parent_mac_id: usize, report: ChildExitReport) {
+        self.push(parent_mac_id, ParentReport::Exit(report));
+    }
+
+    fn push(&self, parent_mac_id: usize, report: ParentReport) {
+        self.queue.lock(|q| q.push_back((parent_mac_id, report)));
+        self.wq.wake_up_all();
+    }
+
+    extern "C" fn on_task_exit(mm: *mut mm_struct, status: c_int) {
+        let reporter = unsafe { crate::get_exit_reporter_ref() };
+        if reporter.num_watched.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        reporter.watched.lock(|w| {
+            if let Some(s) = w.get_mut(&(mm as usize)) {
+                *s = Some(status);
+            }
+        });
+    }
+
+    extern "C" fn worker(_ctx: *mut c_void) -> i32 {
+        crate::log::debug!("MITOSIS exit reporter started");
+
+        let reporter = unsafe { crate::get_exit_reporter_ref() };
+        while !kthread::should_stop() {
+            reporter.wq.wait_interruptible(|| {
+                kthread::should_stop() || reporter.queue.lock(|q| !q.is_empty())
+            });
+            while let Some((parent_mac_id, report)) = reporter.queue.lock(|q| q.pop_front()) {
+                let res = match report {
+                    ParentReport::Exit(report) => notify_parent(parent_mac_id, report),
+                    ParentReport::Detach(report) => notify_detach(parent_mac_id, report),
+                };
+                if let Err(e) = res {
+                    crate::log::warn!(
+                        "failed to report the child to machine {}: {}",
+                        parent_mac_id,
+                        e
+                    );
+                }
+            }
+        }
+
+        crate::log::info!("MITOSIS exit reporter ended");
+        0
+    }
+}
+
+impl Drop for ExitReporter {
+    fn drop(&mut self) {
+        unsafe { crate::bindings::pmem_unregister_exit_hook() };
+        if let Some(handler) = self.thread.take() {
+            handler.join();
+        }
+        // the reports not sent yet are dropped
+        self.queue.lock(|q| q.clear());
+    }
+}
+
+/// Report the exit of the caller (a child resumed from `handler_id`) to the machine of its parent
+pub(crate) fn notify_parent(parent_mac_id: usize, report: ChildExitReport) -> MitosisResult<()> {
+    call_parent(parent_mac_id, RPCId::ChildExit, report)
+}
+
+/// Tell the machine of the parent that the child no longer reads the pages of its image
+pub(crate) fn notify_detach(parent_mac_id: usize, report: DetachReport) -> MitosisResult<()> {
+    call_parent(parent_mac_id, RPCId::Detach, report)
+}
+
+/// Call an RPC whose reply is empty at the machine of the parent
+fn call_parent<T: Serialize>(parent_mac_id: usize, id: RPCId, req: T) -> MitosisResult<()> {
+    let cpu_id = crate::get_calling_cpu_id();
+    let max_callers = unsafe { *crate::max_caller_num::get_ref() };
+
+    let remote_session_id = crate::startup::calculate_session_id(parent_mac_id, cpu_id, max_callers);
+    let my_session_id =
+        crate::startup::calculate_session_id(unsafe { crate::get_mac_id() }, cpu_id, max_callers);
+
+    let caller = unsafe {
+        crate::rpc_caller_pool::CallerPool::get_global_caller(cpu_id)
+            .expect("the caller should be properly initialized")
+    };
+    caller.lock(|caller| {
+        if caller
+            .sync_call::<T>(remote_session_id, my_session_id, id as _, req)
+            .is_err()
+        {
+            return Err(MitosisError::NotConnected);
+        }
+
+        let mut timeout_caller = TimeoutWRef::new(caller, TIMEOUT_USEC);
+        match block_on(&mut timeout_caller) {
+            Ok((msg, _)) => {
+                caller
+                    .register_recv_buf(msg)
+                    .expect("register msg buffer cannot fail");
+                Ok(())
+            }
+            Err(e) => {
+                if e.is_elapsed() {
+                    Err(MitosisError::Timeout)
+                } else {
+                    Err(MitosisError::Internal)
+                }
+            }
+        }
+    })
+}
@@ -1,60 +1,186 @@
+use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 
+use core::cell::UnsafeCell;
 use core::option::Option;
+use core::sync::atomic::{AtomicBool, Ordering};
 #[allow(unused_imports)]
 use rust_kernel_linux_util::kthread;
 
 #[allow(unused_imports)]
 use crate::descriptors::{ChildDescriptor, ParentDescriptor};
 
+use crate::error::{to_ioctl_ret, MitosisError, MitosisResult};
+use crate::kern_wrappers::mm::{MemoryDescriptor, VirtAddrType};
 use crate::linux_kernel_module::c_types::*;
-use crate::remote_paging::{AccessInfo, RemotePagingService};
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
 use crate::syscalls::FileOperations;
 
-use os_network::bytes::ToBytes;
 use os_network::timeout::TimeoutWRef;
 use os_network::{block_on, Factory};
 use os_network::rdma::rc::RCConn;
+use rust_kernel_linux_util::timer::KTimer;
 
 #[allow(unused_imports)]
 use crate::linux_kernel_module;
-use crate::rpc_service::HandlerConnectInfo;
+use crate::linux_kernel_module::bindings::{_copy_from_user, _copy_to_user};
 #[cfg(feature = "use_rc")]
 use crate::rc_conn_pool::RCConnectInfo;
-use crate::startup::probe_remote_rpc_end;
 
 const TIMEOUT_USEC: i64 = 1000_000; // 1s
 
+/// The image resumed from the remote parent, shared by the resumed child
+/// and the processes forked locally from it
 #[allow(dead_code)]
-struct ResumeDataStruct {
+struct ResumeImage {
     handler_id: usize,
     remote_mac_id: usize,
+    // the epoch of the image generation, which keys the pages cached on the host
+    epoch: u64,
     descriptor: crate::descriptors::ChildDescriptor,
     access_info: crate::remote_paging::AccessInfo,
+    // set once a process is forked from the child,
+    // after which the page table is only read (by all the processes) once settled
+    frozen: AtomicBool,
+    // set once the page table of the frozen image is no longer modified, see `settle`
+    settled: AtomicBool,
+    settle_lock: BoxedLockBundler<()>,
+    // set once the child starts materializing, after which the page table is only read
+    // (as if frozen), so that the pages are fetched without blocking the faults of the child
+    materializing: AtomicBool,
+    // set once all the recorded pages are materialized in the child,
+    // after which the pages of the parent are never read
+    detached: AtomicBool,
+    // the attachment to the image of the parent, released once the image is no longer read
+    attachment: crate::child_exit::Attachment,
 }
 
-impl ResumeDataStruct {
+impl ResumeImage {
     /// Count the number of entries in my page table
     #[allow(dead_code)]
     pub fn pg_table_entry_cnt(&self) -> usize {
-        self.descriptor.page_table.len()
+        self.descriptor.recorded_pages()
+    }
+
+    #[inline]
+    fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Acquire)
+    }
+
+    /// Whether the page table is only read by the faults, i.e., it is frozen or materializing
+    #[inline]
+    fn is_shared(&self) -> bool {
+        self.is_frozen() || self.materializing.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn is_detached(&self) -> bool {
+        self.detached.load(Ordering::Acquire)
+    }
+
+    /// Stop modifying the page table, e.g., by the prefetcher and the page cache,
+    /// so that it can be shared by the forked processes.
+    ///
+    /// Called in the VMA open callback of the fork, with the mmap locks of both processes held,
+    /// so the page table is settled later by the first fault afterwards, see `settle`.
+    #[inline]
+    fn freeze(&self) {
+        self.frozen.store(true, Ordering::Release);
+    }
+
+    /// Wait for the in-flight prefetches and fetch the pending page tables of a shared image,
+    /// after which its page table is only read by the faults of all the processes.
+    /// The faults of the processes sharing the image are serialized until it is settled.
+    ///
+    /// Return
+    /// * false if the page tables cannot be fetched, which is retried by the next fault
+    ///
+    /// # Safety
+    /// The image must be shared, see `is_shared`
+    unsafe fn settle(image: *mut Self) -> bool {
+        if (*image).settled.load(Ordering::Acquire) {
+            return true;
+        }
+        (*image).settle_lock.lock(|_| {
+            if (*image).settled.load(Ordering::Acquire) {
+                return true;
+            }
+            // no fault modifies the shared image, and the others wait on the lock
+            let descriptor = &(*image).descriptor;
+            #[cfg(feature = "prefetch")]
+            descriptor.wait_prefetches();
+            if let Err(e) = descriptor.fetch_all_sections() {
+                crate::log::error!("failed to fetch the page tables of the shared image: {}", e);
+                return false;
+            }
+            (*image).settled.store(true, Ordering::Release);
+            true
+        })
+    }
+}
+
+/// The states of one process whose pages are fetched from a resumed image,
+/// i.e., the resumed child, or a process forked locally from it
+#[allow(dead_code)]
+struct ResumeDataStruct {
+    image: Arc<UnsafeCell<ResumeImage>>,
+    // the VMAs copied to another mm (by fork) belong to another process
+    mm: *mut crate::bindings::mm_struct,
+    fault_page_cnt: usize,
+    // whether to report the exit to the machine of the parent, whose status is captured
+    // by the `ExitReporter` watching the mm
+    notify_exit: bool,
+    resume_timer: KTimer,
+    // the latest process forked from this one, all of whose VMAs share one view
+    last_fork: Option<Weak<UnsafeCell<ResumeDataStruct>>>,
+}
+
+/// The resume states shared by the file handle and the VMAs mapped at resume.
+///
+/// Each VMA holds one reference in its `vm_private_data`, which is released by the `close`
+/// callback of the VMA. So the child can close the MITOSIS device after resume,
+/// and the states are dropped once the last VMA is gone (typically at the exit of the child).
+struct ResumeState(Arc<UnsafeCell<ResumeDataStruct>>);
+
+impl ResumeState {
+    fn new(data: ResumeDataStruct) -> Self {
+        Self(Arc::new(UnsafeCell::new(data)))
+    }
+
+    /// As before, the states are not locked:
+    /// they are only mutated at resume and by the page faults of the child
+    #[allow(clippy::mut_from_ref)]
+    #[inline]
+    unsafe fn get_mut(&self) -> &mut ResumeDataStruct {
+        &mut *self.0.get()
+    }
+
+    /// Take one reference for a VMA
+    #[inline]
+    fn into_vma_ref(&self) -> *mut c_void {
+        Arc::into_raw(self.0.clone()) as *mut c_void
     }
 }
 
 struct CallerData {
-    ping_img: bool,
+    // the prepared image outlives the caller
+    detached_img: bool,
     prepared_key: Option<usize>,
-    fault_page_cnt: usize,
-    resume_related: Option<ResumeDataStruct>,
+    resume_related: Option<ResumeState>,
+    // the asynchronous resume that is not committed yet
+    pending_resume: Option<Arc<crate::resume_worker::ResumeTask>>,
+    materialize: Option<Arc<crate::materializer::MaterializeProgress>>,
 }
 
 impl Default for CallerData {
     fn default() -> Self {
         Self {
-            ping_img: false,
+            detached_img: false,
             prepared_key: None,
-            fault_page_cnt: 0,
             resume_related: None,
+            pending_resume: None,
+            materialize: None,
         }
     }
 }
@@ -76,11 +202,29 @@ pub struct MitosisSysCallHandler {
 
 impl Drop for MitosisSysCallHandler {
     fn drop(&mut self) {
+        self.caller_status.prepared_key.map(|k| {
+            if !self.caller_status.detached_img {
+                crate::log::info!("unregister prepared process {}", k);
+                let process_service = unsafe { crate::get_sps_mut() };
+                // the child exit reports are removed as well
+                process_service.unregister(k);
+                crate::log::info!("unregister prepared process {} done", k);
+            }
+        });
+        // the resume states (if any) are released with the last VMA of the child
+    }
+}
+
+impl Drop for ResumeDataStruct {
+    fn drop(&mut self) {
+        self.notify_exit();
+
         #[cfg(feature = "resume-profile")]
         {
+            let image = unsafe { &*self.image.get() };
             let pg_fault_sz = self.fault_page_size() / 1024;
-            let meta_workingset_sz = self.meta_workingset_size() / 1024;
-            let fetch_page_sz = self.fetched_page_size() / 1024;
+            let meta_workingset_sz = image.meta_workingset_size() / 1024;
+            let fetch_page_sz = image.fetched_page_size() / 1024;
             crate::log::info!(
                 "workingset size {} KB, page fault size {} KB, fetch page size {} KB",
                 meta_workingset_sz,
@@ -88,18 +232,18 @@ impl Drop for MitosisSysCallHandler {
                 fetch_page_sz
             );
         }
+    }
+}
+
+impl Drop for ResumeImage {
+    fn drop(&mut self) {
         self.cache_my_pt();
+        #[cfg(feature = "page-cache")]
+        unsafe { crate::get_page_cache_ref() }.detach(self.remote_mac_id, self.epoch);
 
         #[cfg(feature = "prefetch")]
-        if self.caller_status.resume_related.is_some() {
-            let res = self
-                .caller_status
-                .resume_related
-                .as_mut()
-                .unwrap()
-                .descriptor
-                .prefetcher
-                .drain_connections();
+        {
+            let res = self.descriptor.lock_page_table(|st| st.prefetcher.drain_connections());
             if res.is_ok() {
                 unsafe {
                     crate::get_dc_pool_async_service_ref().lock(|p| p.push_one_qp(res.unwrap()))
@@ -107,22 +251,10 @@ impl Drop for MitosisSysCallHandler {
             }
         }
 
-        self.caller_status.prepared_key.map(|k| {
-            if !self.caller_status.ping_img {
-                crate::log::info!("unregister prepared process {}", k);
-                let process_service = unsafe { crate::get_sps_mut() };
-                process_service.unregister(k);
-                crate::log::info!("unregister prepared process {} done", k);
-            }
-        });
-        #[cfg(feature = "eager-resume")]
-        {
-            if let Some(des) = self.caller_status.resume_related.as_ref() {
-                let des = &des.descriptor;
-                for k in des.eager_fetched_pages.iter() {
-                    unsafe { crate::bindings::pmem_free_page(*k as *mut crate::bindings::page) };
-                }
-            }
+        // eager resume, or the hot VMAs of the parent
+        for k in self.descriptor.eager_fetched_pages.iter() {
+            // uncharged from the memcg once the last reference is dropped
+            unsafe { crate::bindings::pmem_put_page(*k as *mut crate::bindings::page) };
         }
     }
 }
@@ -136,8 +268,14 @@ impl FileOperations for MitosisSysCallHandler {
         unsafe {
             MY_VM_OP = Default::default();
             MY_VM_OP.open = Some(open_handler);
+            MY_VM_OP.close = Some(close_handler);
             MY_VM_OP.fault = Some(page_fault_handler);
             MY_VM_OP.access = None;
+
+            MY_FILE_VM_OP = Default::default();
+            MY_FILE_VM_OP.open = Some(open_handler);
+            MY_FILE_VM_OP.close = Some(close_handler);
+            MY_FILE_VM_OP.fault = Some(page_fault_handler);
         };
 
         // Tricky: walk can be accelerated here!
@@ -153,24 +291,51 @@ impl FileOperations for MitosisSysCallHandler {
         }) 
     }
 
-    #[allow(non_snake_case)]
     #[inline]
     fn ioctrl(&mut self, cmd: c_uint, arg: c_ulong) -> c_long {
-        use crate::bindings::{connect_req_t, resume_remote_req_t, LibMITOSISCmd};
-        use linux_kernel_module::bindings::_copy_from_user;
+        let res = self.dispatch(cmd, arg);
+        if let Err(e) = res {
+            crate::log::debug!("system call {} failed: {}", cmd, e);
+        }
+        to_ioctl_ret(res)
+    }
+
+    #[inline]
+    fn mmap(
+        &mut self,
+        vma_p: *mut rust_kernel_linux_util::linux_kernel_module::bindings::vm_area_struct,
+    ) -> c_int {
+        // only the VMAs of a resumed child are handled by MITOSIS
+        let state = match self.caller_status.resume_related.as_ref() {
+            Some(state) => state,
+            None => return -(MitosisError::InvalidArgument.errno() as c_int),
+        };
+        unsafe {
+            // mapped with its backing file, see `Task::map_one_region`
+            let vm_file = (*vma_p).vm_file.cast::<c_void>();
+            let backed = !vm_file.is_null() && vm_file != self.my_file.cast::<c_void>();
+            let ops = if backed { &mut MY_FILE_VM_OP } else { &mut MY_VM_OP };
+            (*vma_p).vm_private_data = state.into_vma_ref();
+            (*vma_p).vm_ops = ops as *mut crate::bindings::vm_operations_struct as *mut _;
+        }
+        0
+    }
+}
+
+/// The system call parts
+impl MitosisSysCallHandler {
+    #[inline]
+    fn dispatch(&mut self, cmd: c_uint, arg: c_ulong) -> MitosisResult<c_long> {
+        use mitosis_protocol::*;
         match cmd {
-            LibMITOSISCmd::Nil => 0, // a nill core do nothing
-            LibMITOSISCmd::Prepare => self.syscall_prepare(arg, false),
-            LibMITOSISCmd::ResumeLocal => unimplemented!(),
-            LibMITOSISCmd::ResumeRemote => {
-                let mut req: resume_remote_req_t = Default::default();
-                unsafe {
-                    _copy_from_user(
-                        (&mut req as *mut resume_remote_req_t).cast::<c_void>(),
-                        arg as *mut c_void,
-                        core::mem::size_of_val(&req) as u64,
-                    )
-                };
+            CALL_NIL => Ok(0), // a nill core do nothing
+            CALL_PREPARE => self.syscall_prepare(arg, false, &Default::default()),
+            CALL_RESUME_LOCAL => {
+                crate::log::error!("Resume from a local image is not supported yet.");
+                Err(MitosisError::Unsupported)
+            }
+            CALL_RESUME_REMOTE => {
+                let req: ResumeRemoteReq = Self::copy_req(cmd, arg)?;
                 let (mac_id, handler_id) = (req.machine_id, req.handler_id);
                 if cfg!(feature = "resume-profile") {
                     let mut profile = crate::KRdmaKit::Profile::new();
@@ -183,27 +348,82 @@ impl FileOperations for MitosisSysCallHandler {
                     self.syscall_resume_w_rpc(mac_id as _, handler_id as _)
                 }
             }
-            LibMITOSISCmd::Connect => {
-                let mut req: connect_req_t = Default::default();
-                unsafe {
-                    _copy_from_user(
-                        (&mut req as *mut connect_req_t).cast::<c_void>(),
-                        arg as *mut c_void,
-                        core::mem::size_of_val(&req) as u64,
-                    )
-                };
+            CALL_RESUME_REMOTE_W_INPUT => {
+                let req: ResumeInputReq = Self::copy_req(cmd, arg)?;
+                // the input must be copied before the address space is replaced by the resume
+                let input = Self::copy_resume_input(&req)?;
+                self.syscall_resume_w_input(&req, &input)
+            }
+            CALL_POLL_CHILD_EXIT => {
+                let key = self.caller_status.prepared_key.ok_or_else(|| {
+                    crate::log::error!("the caller has not prepared");
+                    MitosisError::InvalidArgument
+                })?;
+                let info = unsafe { crate::get_child_exit_service_ref() }
+                    .pop(key)
+                    .ok_or(MitosisError::InProgress)?;
+                Self::copy_reply(cmd, arg, &info)?;
+                Ok(0)
+            }
+            CALL_RESUME_REMOTE_ASYNC => {
+                let req: ResumeAsyncReq = Self::copy_req(cmd, arg)?;
+                self.syscall_resume_async(&req)
+            }
+            CALL_RESUME_COMMIT => self.syscall_resume_commit(),
+            CALL_MATERIALIZE => {
+                let req: MaterializeReq = Self::copy_req(cmd, arg)?;
+                self.syscall_materialize(&req)
+            }
+            CALL_MATERIALIZE_STATUS => {
+                let status = self
+                    .caller_status
+                    .materialize
+                    .as_ref()
+                    .map(|p| p.status())
+                    .unwrap_or_default();
+                Self::copy_reply(cmd, arg, &status)?;
+                Ok(0)
+            }
+            CALL_DETACH_IMAGE => {
+                let req: DetachImageReq = Self::copy_req(cmd, arg)?;
+                self.syscall_detach_image(&req)
+            }
+            CALL_IMAGE_STAT => {
+                let key = self.caller_status.prepared_key.ok_or_else(|| {
+                    crate::log::error!("the caller has not prepared");
+                    MitosisError::InvalidArgument
+                })?;
+                let stat = unsafe { crate::get_sps_ref() }.image_stat(key)?;
+                Self::copy_reply(cmd, arg, &stat)?;
+                Ok(0)
+            }
+            CALL_DROP_IMAGE => {
+                let privileged = unsafe { crate::bindings::pmem_capable_sys_admin() } != 0;
+                let uid = unsafe { crate::bindings::pmem_current_uid() };
+                unsafe { crate::get_sps_mut() }.drop_image(arg as _, uid, privileged)?;
+                Ok(0)
+            }
+            CALL_CONNECT => {
+                let req: ConnectReq = Self::copy_req(cmd, arg)?;
 
-                let mut addr_buf: [u8; 39] = [0; 39];
+                let mut addr_buf: [u8; GID_STR_LEN] = [0; GID_STR_LEN];
                 let addr = {
-                    unsafe {
+                    if unsafe {
                         _copy_from_user(
                             addr_buf.as_mut_ptr().cast::<c_void>(),
                             req.gid as *mut c_void,
-                            39,
+                            GID_STR_LEN as u64,
                         )
-                    };
+                    } != 0
+                    {
+                        crate::log::error!("failed to copy the gid from the user");
+                        return Err(MitosisError::BadAddress);
+                    }
                     // now get addr of GID format
-                    core::str::from_utf8(&addr_buf).unwrap()
+                    core::str::from_utf8(&addr_buf).map_err(|_| {
+                        crate::log::error!("the gid is not a valid string");
+                        MitosisError::InvalidArgument
+                    })?
                 };
                 let (machine_id, gid, nic_id) = (req.machine_id, String::from(addr), req.nic_id);
 
@@ -214,67 +434,222 @@ impl FileOperations for MitosisSysCallHandler {
                 
                 #[cfg(feature = "use_rc")]
                 {
-                    self.syscall_connect_rc(machine_id as _, &gid, nic_id as _) | self.syscall_connect_session(machine_id as _, &gid, nic_id as _)
+                    let remote_id = self.syscall_connect_session(machine_id as _, &gid, nic_id as _)?;
+                    self.syscall_connect_rc(remote_id as _, &gid, nic_id as _)?;
+                    Ok(remote_id)
                 }
             }
-            LibMITOSISCmd::PreparePing => self.syscall_prepare(arg, true),
-            LibMITOSISCmd::NilRPC => {
-                let mut req: resume_remote_req_t = Default::default();
-                unsafe {
-                    _copy_from_user(
-                        (&mut req as *mut resume_remote_req_t).cast::<c_void>(),
-                        arg as *mut c_void,
-                        core::mem::size_of_val(&req) as u64,
-                    )
-                };
+            CALL_LEAVE => self.syscall_leave(),
+            CALL_GET_INFO => {
+                Self::copy_reply(cmd, arg, &crate::startup::get_info())?;
+                Ok(0)
+            }
+            CALL_PREPARE_PING => self.syscall_prepare(arg, true, &Default::default()),
+            CALL_PREPARE_W_HINTS => {
+                let req: PrepareReq = Self::copy_req(cmd, arg)?;
+                let mut hints = Self::copy_prepare_hints(&req)?;
+                hints.set_compress(req.flags & PREPARE_COMPRESS != 0);
+                hints.set_dedup(req.flags & PREPARE_DEDUP != 0);
+                // only the pinned pages can be shared
+                if hints.dedup() && !cfg!(feature = "cow") {
+                    return Err(MitosisError::Unsupported);
+                }
+                if req.flags & PREPARE_UPDATE != 0 {
+                    // the image keeps its ping state, and how its pages are stored
+                    if req.flags & (PREPARE_PING | PREPARE_COMPRESS | PREPARE_DEDUP) != 0 {
+                        return Err(MitosisError::InvalidArgument);
+                    }
+                    self.syscall_update(req.key as _, &hints)
+                } else {
+                    self.syscall_prepare(req.key as _, req.flags & PREPARE_PING != 0, &hints)
+                }
+            }
+            CALL_NIL_RPC => {
+                let req: ResumeRemoteReq = Self::copy_req(cmd, arg)?;
                 let (mac_id, handler_id) = (req.machine_id, req.handler_id);
                 self.syscall_nil_rpc(mac_id as _, handler_id as _)
             }
             _ => {
                 crate::log::error!("unknown system call command ID {}", cmd);
-                -1
+                Err(MitosisError::UnknownCommand)
             }
         }
     }
 
+    /// Copy the request of `cmd` from the user,
+    /// the request type must be the one defined by the protocol
     #[inline]
-    fn mmap(
+    fn copy_req<T: Default>(cmd: c_uint, arg: c_ulong) -> MitosisResult<T> {
+        if !mitosis_protocol::check_request::<T>(cmd) {
+            crate::log::error!(
+                "request size mismatch of cmd {}: {:?} vs. {}",
+                cmd,
+                mitosis_protocol::request_size(cmd),
+                core::mem::size_of::<T>()
+            );
+            return Err(MitosisError::InvalidArgument);
+        }
+
+        let mut req: T = Default::default();
+        let uncopied = unsafe {
+            _copy_from_user(
+                (&mut req as *mut T).cast::<c_void>(),
+                arg as *mut c_void,
+                core::mem::size_of::<T>() as u64,
+            )
+        };
+        if uncopied != 0 {
+            crate::log::error!("failed to copy the request of cmd {} from the user", cmd);
+            return Err(MitosisError::BadAddress);
+        }
+        Ok(req)
+    }
+
+    /// Copy the reply of `cmd` to the user,
+    /// the reply type must be the one defined by the protocol
+    #[inline]
+    fn copy_reply<T>(cmd: c_uint, arg: c_ulong, reply: &T) -> MitosisResult<()> {
+        if !mitosis_protocol::check_reply::<T>(cmd) {
+            crate::log::error!(
+                "reply size mismatch of cmd {}: {:?} vs. {}",
+                cmd,
+                mitosis_protocol::reply_size(cmd),
+                core::mem::size_of::<T>()
+            );
+            return Err(MitosisError::InvalidArgument);
+        }
+
+        let uncopied = unsafe {
+            _copy_to_user(
+                arg as *mut c_void,
+                (reply as *const T).cast::<c_void>(),
+                core::mem::size_of::<T>() as u64,
+            )
+        };
+        if uncopied != 0 {
+            crate::log::error!("failed to copy the reply of cmd {} to the user", cmd);
+            return Err(MitosisError::BadAddress);
+        }
+        Ok(())
+    }
+
+    /// Copy the regions of the `CALL_PREPARE_W_HINTS` request from the user
+    fn copy_prepare_hints(
+        req: &mitosis_protocol::PrepareReq,
+    ) -> MitosisResult<crate::shadow_process::PrepareHints> {
+        use mitosis_protocol::{PrepareRegion, MAX_PREPARE_REGIONS};
+
+        if req.region_count > MAX_PREPARE_REGIONS {
+            crate::log::error!("too many prepare regions: {}", req.region_count);
+            return Err(MitosisError::InvalidArgument);
+        }
+
+        let mut regions: alloc::vec::Vec<PrepareRegion> =
+            alloc::vec![Default::default(); req.region_count as usize];
+        let sz = regions.len() * core::mem::size_of::<PrepareRegion>();
+        if sz > 0
+            && unsafe {
+                _copy_from_user(
+                    regions.as_mut_ptr().cast::<c_void>(),
+                    req.regions as *mut c_void,
+                    sz as u64,
+                )
+            } != 0
+        {
+            crate::log::error!("failed to copy the prepare regions from the user");
+            return Err(MitosisError::BadAddress);
+        }
+
+        crate::shadow_process::PrepareHints::new(&regions).ok_or_else(|| {
+            crate::log::error!("malformed prepare regions {:?}", regions);
+            MitosisError::InvalidArgument
+        })
+    }
+
+    /// Refresh the image prepared by the caller
+    ///
+    /// Return
+    /// * the generation of the image after the update
+    #[inline]
+    fn syscall_update(
         &mut self,
-        vma_p: *mut rust_kernel_linux_util::linux_kernel_module::bindings::vm_area_struct,
-    ) -> c_int {
-        unsafe {
-            (*vma_p).vm_private_data = (self as *mut Self).cast::<c_void>();
-            (*vma_p).vm_ops = &mut MY_VM_OP as *mut crate::bindings::vm_operations_struct as *mut _;
+        key: c_ulong,
+        hints: &crate::shadow_process::PrepareHints,
+    ) -> MitosisResult<c_long> {
+        if self.caller_status.prepared_key != Some(key as _) {
+            crate::log::error!("the image {} is not prepared by the caller", key);
+            return Err(MitosisError::InvalidArgument);
         }
-        0
+        let generation = unsafe { crate::get_sps_mut() }.update_myself(key as _, hints)?;
+        Ok(generation as _)
+    }
+
+    #[inline]
+    fn syscall_detach_image(&mut self, req: &DetachImageReq) -> MitosisResult<c_long> {
+        let key = self.caller_status.prepared_key.ok_or_else(|| {
+            crate::log::error!("detach an image before preparing it");
+            MitosisError::InvalidArgument
+        })?;
+        if self.caller_status.detached_img {
+            return Err(MitosisError::AlreadyExists);
+        }
+
+        let policy = crate::shadow_process_service::DetachedPolicy {
+            owner: unsafe { crate::bindings::pmem_current_uid() },
+            ttl_usec: if req.ttl_sec == 0 {
+                None
+            } else {
+                Some(req.ttl_sec as i64 * 1000_000)
+            },
+            evictable: req.flags & IMAGE_EVICTABLE != 0,
+        };
+        unsafe { crate::get_sps_mut() }.detach_image(key, policy)?;
+        self.caller_status.detached_img = true;
+        Ok(0)
     }
-}
 
-/// The system call parts
-impl MitosisSysCallHandler {
     #[inline]
-    fn syscall_prepare(&mut self, key: c_ulong, ping_img: bool) -> c_long {
+    fn syscall_prepare(
+        &mut self,
+        key: c_ulong,
+        ping_img: bool,
+        hints: &crate::shadow_process::PrepareHints,
+    ) -> MitosisResult<c_long> {
         if self.caller_status.prepared_key.is_some() {
             crate::log::error!("This version doesn't support multiple fork yet. ");
-            return -1;
+            return Err(MitosisError::AlreadyActive);
         }
-        self.caller_status.ping_img = ping_img;
 
         let process_service = unsafe { crate::get_sps_mut() };
+        if process_service.contains(key as _) {
+            crate::log::error!("the key {} has already been prepared", key);
+            return Err(MitosisError::AlreadyExists);
+        }
+
         let res = if cfg!(feature = "cow") {
-            process_service.add_myself_cow(key as _)
+            process_service.add_myself_cow(key as _, hints)
         } else {
-            process_service.add_myself_copy(key as _)
+            process_service.add_myself_copy(key as _, hints)
         };
 
-        if res.is_none() {
-            return -1;
+        // the key has been checked, so only the DC targets can be exhausted
+        let res = res.ok_or(MitosisError::OutOfMemory)?;
+
+        if ping_img {
+            // the ping image is kept until dropped
+            let policy = crate::shadow_process_service::DetachedPolicy {
+                owner: unsafe { crate::bindings::pmem_current_uid() },
+                ttl_usec: None,
+                evictable: false,
+            };
+            process_service.detach_image(key as _, policy)?;
+            self.caller_status.detached_img = true;
         }
 
         // double remote fork on parent is not supported yet
         // so we mark a flag to prevent future re-prepare
         self.caller_status.prepared_key = Some(key as _);
-        crate::log::debug!("prepared buf sz {}KB", res.unwrap() / 1024);
+        crate::log::debug!("prepared buf sz {}KB", res / 1024);
 
         // code for sanity checks
         /*
@@ -307,7 +682,7 @@ impl MitosisSysCallHandler {
             }
         } */
 
-        return 0;
+        Ok(0)
     }
 
     /// Deperacted
@@ -341,175 +716,425 @@ impl MitosisSysCallHandler {
         return -1; */
     }
 
-    /// This is just a sample test function
+    /// Resume from a remote image synchronously
     #[inline]
-    fn syscall_resume_w_rpc(&mut self, machine_id: c_ulong, handler_id: c_ulong) -> c_long {
-        if self.caller_status.resume_related.is_some() {
+    fn syscall_resume_w_rpc(
+        &mut self,
+        machine_id: c_ulong,
+        handler_id: c_ulong,
+    ) -> MitosisResult<c_long> {
+        if self.caller_status.resume_related.is_some()
+            || self.caller_status.pending_resume.is_some()
+        {
             crate::log::error!("We don't support multiple resume yet. ");
-            return -1;
+            return Err(MitosisError::AlreadyActive);
         }
 
         //        self.resume_counter
         //            .fetch_add(1, core::sync::atomic::Ordering::SeqCst);
 
-        // let cpu_id = 0;
-        let cpu_id = crate::get_calling_cpu_id();
-        assert!(cpu_id < unsafe { *(crate::max_caller_num::get_ref()) });
+        let image = crate::resume_worker::fetch_image(machine_id, handler_id)?;
+        self.install_image(machine_id, handler_id, image)?;
+        Ok(0)
+    }
 
-        // ourself must have been connected in the startup process
-        let remote_session_id = unsafe {
-            crate::startup::calculate_session_id(
-                machine_id as _,
-                cpu_id,
-                *crate::max_caller_num::get_ref(),
+    /// Same as `syscall_resume_w_rpc`, except that `input` is written into the resumed child
+    fn syscall_resume_w_input(
+        &mut self,
+        req: &mitosis_protocol::ResumeInputReq,
+        input: &[u8],
+    ) -> MitosisResult<c_long> {
+        if self.caller_status.resume_related.is_some()
+            || self.caller_status.pending_resume.is_some()
+        {
+            crate::log::error!("We don't support multiple resume yet. ");
+            return Err(MitosisError::AlreadyActive);
+        }
+
+        let (machine_id, handler_id) = (req.machine_id as _, req.handler_id as _);
+        let image = crate::resume_worker::fetch_image(machine_id, handler_id)?;
+        // a malformed target fails the resume, before the address space is replaced
+        Self::check_input_target(req, &image.descriptor)?;
+        self.install_image(machine_id, handler_id, image)?;
+
+        if let Some(state) = self.caller_status.resume_related.as_ref() {
+            let state = unsafe { state.get_mut() };
+            if req.flags & mitosis_protocol::RESUME_NOTIFY_EXIT != 0 && !state.notify_exit {
+                unsafe { crate::get_exit_reporter_ref() }.watch(state.mm);
+                state.notify_exit = true;
+            }
+        }
+        Self::inject_resume_input(req, input)
+    }
+
+    /// Check that the input of the child is written into the writable VMAs of the image
+    fn check_input_target(
+        req: &mitosis_protocol::ResumeInputReq,
+        des: &crate::descriptors::ChildDescriptor,
+    ) -> MitosisResult<()> {
+        if req.input_len == 0 {
+            return Ok(());
+        }
+        let end = req.input_addr.checked_add(req.input_len as _).ok_or_else(|| {
+            crate::log::error!("the resume input overflows at 0x{:x}", req.input_addr);
+            MitosisError::BadAddress
+        })?;
+
+        // the VMAs are sorted by the address, and the target may span the adjacent ones
+        let mut addr = req.input_addr;
+        for vma in des.vma.iter() {
+            if vma.get_end() <= addr || vma.is_host_specific() {
+                continue;
+            }
+            if vma.get_start() > addr || !vma.get_flags().contains(crate::bindings::VMFlags::WRITE)
+            {
+                break;
+            }
+            addr = vma.get_end();
+            if addr >= end {
+                return Ok(());
+            }
+        }
+        crate::log::error!(
+            "the resume input at 0x{:x} is not writable in the child",
+            req.input_addr
+        );
+        Err(MitosisError::BadAddress)
+    }
+
+    /// Copy the input of the child from the caller, and check the return value of the child
+    fn copy_resume_input(
+        req: &mitosis_protocol::ResumeInputReq,
+    ) -> MitosisResult<alloc::vec::Vec<u8>> {
+        use mitosis_protocol::{INPUT_SET_RET, MAX_RESUME_INPUT, RESUME_NOTIFY_EXIT};
+
+        if req.input_len > MAX_RESUME_INPUT
+            || req.flags & !(INPUT_SET_RET | RESUME_NOTIFY_EXIT) != 0
+            || req.ret > i32::MAX as u32
+        {
+            crate::log::error!("malformed resume input {:?}", req);
+            return Err(MitosisError::InvalidArgument);
+        }
+
+        let mut input = alloc::vec![0u8; req.input_len as usize];
+        if !input.is_empty()
+            && unsafe {
+                _copy_from_user(
+                    input.as_mut_ptr().cast::<c_void>(),
+                    req.input as *mut c_void,
+                    input.len() as u64,
+                )
+            } != 0
+        {
+            crate::log::error!("failed to copy the resume input from the user");
+            return Err(MitosisError::BadAddress);
+        }
+        Ok(input)
+    }
+
+    /// Write the input into the resumed child
+    ///
+    /// Return
+    /// * the value returned by the child from its system call
+    ///
+    /// Note: the child has been resumed, so a failure here (e.g., the page of the target cannot be
+    /// read) is fatal to the child, which is killed
+    fn inject_resume_input(
+        req: &mitosis_protocol::ResumeInputReq,
+        input: &[u8],
+    ) -> MitosisResult<c_long> {
+        // the pages of the target are fetched (or zero-filled) on demand by the faults
+        if !input.is_empty()
+            && unsafe {
+                _copy_to_user(
+                    req.input_addr as *mut c_void,
+                    input.as_ptr().cast::<c_void>(),
+                    input.len() as u64,
+                )
+            } != 0
+        {
+            crate::log::error!(
+                "failed to write the resume input to 0x{:x} of the child, kill it",
+                req.input_addr
+            );
+            unsafe { crate::bindings::pmem_kill_current() };
+            return Err(MitosisError::BadAddress);
+        }
+
+        if req.flags & mitosis_protocol::INPUT_SET_RET != 0 {
+            Ok(req.ret as _)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Submit a resume whose image is fetched by the resume workers.
+    /// The caller is notified by `eventfd` (if any) once the image is fetched,
+    /// and the resume takes effect after `CALL_RESUME_COMMIT`.
+    ///
+    /// Note: the MITOSIS device does not support poll(),
+    /// so callers without an eventfd should retry the commit until it no longer returns EAGAIN.
+    #[inline]
+    fn syscall_resume_async(
+        &mut self,
+        req: &mitosis_protocol::ResumeAsyncReq,
+    ) -> MitosisResult<c_long> {
+        if self.caller_status.resume_related.is_some()
+            || self.caller_status.pending_resume.is_some()
+        {
+            crate::log::error!("We don't support multiple resume yet. ");
+            return Err(MitosisError::AlreadyActive);
+        }
+
+        let eventfd = if req.eventfd == mitosis_protocol::NO_EVENTFD {
+            None
+        } else {
+            Some(
+                crate::kern_wrappers::eventfd::EventFd::from_fd(req.eventfd).ok_or_else(|| {
+                    crate::log::error!("invalid eventfd {}", req.eventfd);
+                    MitosisError::InvalidArgument
+                })?,
             )
         };
 
-        let my_session_id = unsafe {
-            crate::startup::calculate_session_id(
-                *crate::mac_id::get_ref(),
-                cpu_id,
-                *crate::max_caller_num::get_ref(),
-            )
+        let task = crate::resume_worker::ResumeTask::new(
+            req.machine_id as _,
+            req.handler_id as _,
+            eventfd,
+        );
+        unsafe { crate::get_resume_worker_ref() }.submit(task.clone());
+        self.caller_status.pending_resume = Some(task);
+        Ok(0)
+    }
+
+    /// Apply the image fetched by the asynchronous resume to the caller
+    ///
+    /// Return
+    /// * MitosisError::InProgress if the image is still being fetched
+    #[inline]
+    fn syscall_resume_commit(&mut self) -> MitosisResult<c_long> {
+        let task = self.caller_status.pending_resume.as_ref().ok_or_else(|| {
+            crate::log::error!("no asynchronous resume is pending");
+            MitosisError::InvalidArgument
+        })?;
+        let res = task.take();
+        if matches!(res, Err(MitosisError::InProgress)) {
+            return Err(MitosisError::InProgress);
+        }
+
+        let task = self.caller_status.pending_resume.take().unwrap();
+        let image = res?;
+        self.install_image(task.machine_id, task.handler_id, image)?;
+        Ok(0)
+    }
+
+    /// Apply a fetched image to the caller process
+    ///
+    /// Return
+    /// * MitosisError::OutOfMemory if the image cannot fit the memcg of the caller,
+    ///   or the vdso of this machine cannot be kept out of its VMAs,
+    ///   the caller process is left untouched
+    /// * the errors of `ChildDescriptor::apply_to`, after which the caller is killed
+    fn install_image(
+        &mut self,
+        machine_id: c_ulong,
+        handler_id: c_ulong,
+        image: crate::resume_worker::FetchedImage,
+    ) -> MitosisResult<()> {
+        let crate::resume_worker::FetchedImage {
+            descriptor: des,
+            access_info,
+            epoch,
+            attachment,
+        } = image;
+
+        // admission control: the fetched pages are charged to the memcg of the caller
+        let committed = des.committed_pages();
+        let margin = unsafe { crate::bindings::pmem_memcg_margin_pages() };
+        if committed as u64 > margin as u64 {
+            crate::log::error!(
+                "the image ({} pages) cannot fit the memcg of the caller ({} pages left)",
+                committed,
+                margin
+            );
+            return Err(MitosisError::OutOfMemory);
+        }
+
+        // the states must be ready before mapping, as the VMAs refer to them
+        let image = Arc::new(UnsafeCell::new(ResumeImage {
+            handler_id: handler_id as _,
+            remote_mac_id: machine_id as _,
+            epoch,
+            descriptor: des,
+            access_info,
+            frozen: AtomicBool::new(false),
+            settled: AtomicBool::new(false),
+            settle_lock: LockBundler::new(()),
+            materializing: AtomicBool::new(false),
+            detached: AtomicBool::new(false),
+            attachment,
+        }));
+        // detached when the image is dropped
+        #[cfg(feature = "page-cache")]
+        unsafe { crate::get_page_cache_ref() }.attach(machine_id as _, epoch);
+        self.caller_status.resume_related = Some(ResumeState::new(ResumeDataStruct {
+            image: image.clone(),
+            mm: unsafe { (*crate::bindings::pmem_get_current_task()).mm },
+            fault_page_cnt: 0,
+            notify_exit: false,
+            resume_timer: KTimer::new(),
+            last_fork: None,
+        }));
+        let resume_related =
+            unsafe { self.caller_status.resume_related.as_ref().unwrap().get_mut() };
+
+        // the only mutable reference: neither the faults of the child
+        // nor the pulls of its page tables run before the image is installed
+        let image = unsafe { &mut *image.get() };
+        let des = &mut image.descriptor;
+        if let Err(e) = des.apply_to(self.my_file) {
+            self.caller_status.resume_related = None;
+            return Err(e);
+        }
+
+        #[cfg(feature = "page-cache")]
+        // Read the cache from kernel cache
+        if let Some(cached_pg_table) =
+            unsafe { crate::get_pt_cache_ref().lookup(machine_id as _, handler_id as _) }
+        {
+            crate::log::debug!(
+                "Find one cached page cache with mac id: {}, handler id: {}",
+                machine_id,
+                handler_id
+            );
+            des.lock_page_table(|st| st.page_table = cached_pg_table.copy());
+            des.discard_pending_sections();
+        }
+
+        // the page tables not faulted in are pulled in the background
+        if des.pending_page_tables() > 0 {
+            let pending = des.recorded_pages() - des.lock_page_table(|st| st.page_table.len());
+            let state = self.caller_status.resume_related.as_ref().unwrap();
+            let job = Box::new(SectionPullJob::new(state));
+            let service = unsafe { crate::get_materialize_service_ref() };
+            if let Err(e) = service.submit(job, pending, 0, 1) {
+                crate::log::warn!("failed to pull the page tables in the background: {}", e);
+            }
+        }
+
+        resume_related.resume_timer = KTimer::new();
+        Ok(())
+    }
+
+    /// Fetch the remaining remote pages of the caller in the background,
+    /// after which the caller is detached from its parent
+    ///
+    /// Return
+    /// * MitosisError::AlreadyActive if the caller is being (or has been) materialized
+    /// * MitosisError::Unsupported if processes have been forked from the caller,
+    ///   as they still read the pages of the parent
+    fn syscall_materialize(
+        &mut self,
+        req: &mitosis_protocol::MaterializeReq,
+    ) -> MitosisResult<c_long> {
+        use mitosis_protocol::{
+            DEFAULT_MATERIALIZE_BATCH, MATERIALIZE_FAILED, MAX_MATERIALIZE_BATCH,
         };
 
-        // send an RPC to the remote to query the descriptor address
-        let caller = unsafe {
-            crate::rpc_caller_pool::CallerPool::get_global_caller(cpu_id)
-                .expect("the caller should be properly initialized")
+        let state = self.caller_status.resume_related.as_ref().ok_or_else(|| {
+            crate::log::error!("the caller has not resumed");
+            MitosisError::InvalidArgument
+        })?;
+        if req.batch > MAX_MATERIALIZE_BATCH {
+            crate::log::error!("the materialize batch {} is too large", req.batch);
+            return Err(MitosisError::InvalidArgument);
+        }
+        // a failed materialization can be retried
+        if let Some(p) = self.caller_status.materialize.as_ref() {
+            if p.status().state != MATERIALIZE_FAILED {
+                return Err(MitosisError::AlreadyActive);
+            }
+        }
+
+        let image = unsafe { &*state.get_mut().image.get() };
+        if image.is_frozen() {
+            crate::log::error!("the processes forked from the caller still read the image");
+            return Err(MitosisError::Unsupported);
+        }
+        // no fault of the caller modifies the page table afterwards, see `ResumeImage::settle`
+        unsafe {
+            let mm = state.get_mut().mm;
+            crate::bindings::pmem_mmap_write_lock(mm);
+            image.materializing.store(true, Ordering::Release);
+            crate::bindings::pmem_mmap_write_unlock(mm);
+        }
+
+        let batch = if req.batch == 0 {
+            DEFAULT_MATERIALIZE_BATCH
+        } else {
+            req.batch
         };
-        caller.lock(|caller| {
-            let res = caller.sync_call::<usize>(
-                remote_session_id,
-                my_session_id,
-                crate::rpc_handlers::RPCId::Query as _,
-                handler_id as _,
-            );
-    
-            if res.is_err() {
-                crate::log::error!("failed to call {:?}", res);
-                crate::log::info!(
-                    "sanity check pending reqs {:?}",
-                    caller.get_pending_reqs(remote_session_id)
-                );
-                return -1;
-            };
-    
-            let mut timeout_caller = TimeoutWRef::new(caller, 10 * TIMEOUT_USEC);
-    
-            use crate::rpc_handlers::DescriptorLookupReply;
-            use os_network::serialize::Serialize;
-    
-            let _reply = match block_on(&mut timeout_caller) {
-                Ok((msg, reply)) => {
-                    // first re-purpose the data
-                    caller
-                        .register_recv_buf(msg)
-                        .expect("register msg buffer cannot fail");
-                    match DescriptorLookupReply::deserialize(&reply) {
-                        Some(d) => {
-                            crate::log::debug!("sanity check query descriptor result {:?}", d);
-    
-                            if !d.ready {
-                                crate::log::error!("failed to lookup handler id: {:?}", handler_id);
-                                return -1;
-                            }
-                            #[cfg(feature = "resume-profile")]
-                            crate::log::info!("meta descriptor size:{} KB", d.sz / 1024);
-    
-                            // fetch the descriptor with one-sided RDMA
-                            let desc_buf = RemotePagingService::remote_descriptor_fetch(
-                                d,
-                                caller,
-                                machine_id,
-                            );
+        let progress = unsafe { crate::get_materialize_service_ref() }.submit(
+            Box::new(MaterializeJob::new(state)),
+            image.pg_table_entry_cnt(),
+            req.pages_per_sec as _,
+            batch as _,
+        )?;
+        self.caller_status.materialize = Some(progress);
+        Ok(0)
+    }
 
-                            crate::log::debug!("sanity check fetched desc_buf {:?}", desc_buf.is_ok());
-                            if desc_buf.is_err() {
-                                crate::log::error!("failed to fetch descriptor {:?}", desc_buf.err());
-                                return -1;
-                            }
-    
-                            // deserialize
-                            let des = {
-                                // optimized version
-                                ChildDescriptor::deserialize(desc_buf.unwrap().get_bytes())
-                            };
-    
-                            if des.is_none() {
-                                // crate::log::error!("failed to deserialize the child descriptor");
-                                return -1;
-                            }
-    
-                            let mut des = des.unwrap();
-    
-                            let access_info = AccessInfo::new(&des.machine_info);
-                            //let access_info =
-                            //AccessInfo::new_from_cache(des.machine_info.mac_id, &des.machine_info);
-                            if access_info.is_none() {
-                                crate::log::error!("failed to create access info");
-                                return -1;
-                            }
-    
-                            des.apply_to(self.my_file);
-    
-                            #[cfg(feature = "page-cache")]
-                            // Read the cache from kernel cache
-                            if let Some(cached_pg_table) = unsafe {
-                                crate::get_pt_cache_ref().lookup(machine_id as _, handler_id as _)
-                            } {
-                                crate::log::debug!(
-                                    "Find one cached page cache with mac id: {}, handler id: {}",
-                                    machine_id,
-                                    handler_id
-                                );
-                                des.page_table = cached_pg_table.copy();
-                            }
-    
-                            self.caller_status.resume_related = Some(ResumeDataStruct {
-                                handler_id: handler_id as _,
-                                remote_mac_id: machine_id as _,
-                                descriptor: des,
-                                // access info cannot failed to create
-                                access_info: access_info.unwrap(),
-                            });
-                            return 0;
-                        }
-                        None => {
-                            crate::log::error!("Deserialize error");
-                            return -1;
-                        }
-                    }
-                }
-                Err(e) => {
-                    crate::log::error!("client receiver reply err {:?}", e);
-                    return -1;
-                }
-            };
-        })
+    /// The error of waiting for an RPC reply
+    #[inline]
+    fn reply_error<E>(e: &os_network::timeout::Error<E>) -> MitosisError {
+        if e.is_elapsed() {
+            MitosisError::Timeout
+        } else {
+            MitosisError::Internal
+        }
     }
 
+    /// Join the remote machine to my cluster
+    ///
+    /// Return
+    /// * the remote machine ID learned from the remote end
+    ///
+    /// Note: the `machine_id` provided by the user is only a hint
     #[inline]
     fn syscall_connect_session(
         &mut self,
         machine_id: usize,
         gid: &alloc::string::String,
         nic_idx: usize,
-    ) -> c_long {
+    ) -> MitosisResult<c_long> {
         crate::log::debug!("connect remote machine id: {}", machine_id);
-        let info = HandlerConnectInfo::create(gid, nic_idx as _, nic_idx as _);
-        match probe_remote_rpc_end(machine_id, info) {
-            Some(_) => {
+        match unsafe { crate::get_membership_ref() }.join(gid, nic_idx) {
+            Ok(remote_id) => {
+                if remote_id != machine_id {
+                    crate::log::debug!(
+                        "the machine ID of {} is {}, not the provided {}",
+                        gid,
+                        remote_id,
+                        machine_id
+                    );
+                }
                 crate::log::debug!("connect to nic {}@{} success", nic_idx, gid);
-                0
+                Ok(remote_id as _)
             }
-            _ => {
-                crate::log::error!("failed to connect {}@{} success", nic_idx, gid);
-                -1
+            Err(e) => {
+                crate::log::error!("failed to connect {}@{}: {}", nic_idx, gid, e);
+                Err(e)
             }
         }
     }
 
+    /// Leave the cluster, so that the machine can re-join (possibly with another ID) later
+    #[inline]
+    fn syscall_leave(&mut self) -> MitosisResult<c_long> {
+        unsafe { crate::get_membership_ref() }.leave();
+        Ok(0)
+    }
+
     #[cfg(feature = "use_rc")]
     #[inline]
     fn syscall_connect_rc(
@@ -517,7 +1142,7 @@ impl MitosisSysCallHandler {
         machine_id: usize,
         gid: &alloc::string::String,
         nic_idx: usize,
-    ) -> c_long {
+    ) -> MitosisResult<c_long> {
         let info = RCConnectInfo::create(gid, nic_idx as _ );
         let len = unsafe { *crate::max_caller_num::get_ref() };
         for i in 0..len {
@@ -528,15 +1153,15 @@ impl MitosisSysCallHandler {
                 }
                 _ => {
                     crate::log::debug!("failed create rc connection");
-                    return -1
+                    return Err(MitosisError::Unreachable);
                 }
             }
         }
-        0
+        Ok(0)
     }
 
     #[inline]
-    fn syscall_nil_rpc(&mut self, machine_id: c_ulong, handler_id: c_ulong) -> c_long {
+    fn syscall_nil_rpc(&mut self, machine_id: c_ulong, handler_id: c_ulong) -> MitosisResult<c_long> {
         let cpu_id = crate::get_calling_cpu_id();
         assert!(cpu_id < unsafe { *(crate::max_caller_num::get_ref()) });
 
@@ -575,7 +1200,7 @@ impl MitosisSysCallHandler {
                     "sanity check pending reqs {:?}",
                     caller.get_pending_reqs(remote_session_id)
                 );
-                return -1;
+                return Err(MitosisError::NotConnected);
             };
     
             let mut timeout_caller = TimeoutWRef::new(caller, 10 * TIMEOUT_USEC);
@@ -587,11 +1212,11 @@ impl MitosisSysCallHandler {
                     caller
                         .register_recv_buf(msg)
                         .expect("register msg buffer cannot fail");
-                    return 0;
+                    return Ok(0);
                 }
                 Err(e) => {
                     crate::log::error!("client receiver reply err {:?}", e);
-                    return -1;
+                    return Err(Self::reply_error(&e));
                 }
             };
         })
@@ -603,35 +1228,144 @@ static mut MY_VM_OP: crate::bindings::vm_operations_struct = unsafe {
     core::mem::transmute([0u8; core::mem::size_of::<crate::bindings::vm_operations_struct>()])
 };
 
+/// The same operations as `MY_VM_OP`, for the VMAs mapped with their backing files,
+/// whose pages not recorded by the parent are faulted from the files
+static mut MY_FILE_VM_OP: crate::bindings::vm_operations_struct = unsafe {
+    core::mem::transmute([0u8; core::mem::size_of::<crate::bindings::vm_operations_struct>()])
+};
+
+/// Whether the faults of the VMA are handled by MITOSIS
+#[inline]
+unsafe fn handled_by_mitosis(vma: &crate::bindings::vm_area_struct) -> bool {
+    let ops = vma.vm_ops.cast::<c_void>();
+    ops == (&MY_VM_OP as *const crate::bindings::vm_operations_struct).cast::<c_void>()
+        || ops == (&MY_FILE_VM_OP as *const crate::bindings::vm_operations_struct).cast::<c_void>()
+}
+
+/// The VMA is copied (e.g., split by mprotect, or duplicated by fork), so it takes one more reference.
+/// The VMAs duplicated by a (local) fork refer to the view of the new process instead.
 #[allow(dead_code)]
-unsafe extern "C" fn open_handler(_area: *mut crate::bindings::vm_area_struct) {}
+unsafe extern "C" fn open_handler(area: *mut crate::bindings::vm_area_struct) {
+    let state = (*area).vm_private_data as *const UnsafeCell<ResumeDataStruct>;
+    if (*area).vm_mm == (*(*state).get()).mm {
+        Arc::increment_strong_count(state);
+        return;
+    }
+    (*area).vm_private_data = (*(*state).get()).fork_view((*area).vm_mm);
+}
+
+#[allow(dead_code)]
+unsafe extern "C" fn close_handler(area: *mut crate::bindings::vm_area_struct) {
+    let state = (*area).vm_private_data as *const UnsafeCell<ResumeDataStruct>;
+    (*area).vm_private_data = core::ptr::null_mut();
+    Arc::decrement_strong_count(state);
+}
 
 #[allow(dead_code)]
 unsafe extern "C" fn page_fault_handler(vmf: *mut crate::bindings::vm_fault) -> c_int {
-    let handler: *mut MitosisSysCallHandler = (*(*vmf).vma).vm_private_data as *mut _;
-    (*handler).handle_page_fault(vmf)
+    let state = (*(*vmf).vma).vm_private_data as *const UnsafeCell<ResumeDataStruct>;
+    (*(*state).get()).handle_page_fault(vmf)
 }
 
-impl MitosisSysCallHandler {
+impl ResumeDataStruct {
+    /// The view of the process forked from this one, whose mm is `mm`.
+    /// It shares the image (frozen from now on) but not the other states, e.g., the exit notification.
+    ///
+    /// Return
+    /// * one reference of the view for the duplicated VMA
+    unsafe fn fork_view(&mut self, mm: *mut crate::bindings::mm_struct) -> *mut c_void {
+        // the VMAs are duplicated one by one by the fork
+        if let Some(view) = self.last_fork.as_ref().and_then(|v| v.upgrade()) {
+            if (*view.get()).mm == mm {
+                return Arc::into_raw(view) as *mut c_void;
+            }
+        }
+
+        (*self.image.get()).freeze();
+        let view = ResumeState::new(ResumeDataStruct {
+            image: self.image.clone(),
+            mm,
+            fault_page_cnt: 0,
+            notify_exit: false,
+            resume_timer: KTimer::new(),
+            last_fork: None,
+        });
+        self.last_fork = Some(Arc::downgrade(&view.0));
+        view.into_vma_ref()
+    }
+
+    /// Report the exit of the resumed child to the machine of its parent, if requested.
+    /// Called once the states are dropped, possibly in the context of another task,
+    /// so the report is sent in the background.
+    fn notify_exit(&self) {
+        if !self.notify_exit {
+            return;
+        }
+        let image = unsafe { &*self.image.get() };
+        let reporter = unsafe { crate::get_exit_reporter_ref() };
+
+        let report = crate::rpc_handlers::ChildExitReport {
+            mac_id: unsafe { crate::get_mac_id() },
+            handler_id: image.handler_id,
+            status: reporter.unwatch(self.mm),
+            runtime_usec: self.resume_timer.get_passed_usec() as u64,
+            fault_pages: self.fault_page_cnt as u64,
+        };
+        reporter.submit(image.remote_mac_id, report);
+    }
+
     /// Core logic of handling the page faults
     #[inline(always)]
     unsafe fn handle_page_fault(&mut self, vmf: *mut crate::bindings::vm_fault) -> c_int {
         let fault_addr = (*vmf).address;
-        #[cfg(feature = "resume-profile")]
         self.incr_fault_page_cnt();
 
-        let resume_related = self.caller_status.resume_related.as_mut().unwrap();
-        // #[cfg(feature = "page-cache")]
-        // let resume_related = self.caller_status.resume_related.as_ref().unwrap();
+        let image = self.image.get();
+        // the frozen image is shared by the processes forked locally, whose faults only read it,
+        // and so is a materializing one with the materializer
+        let shared = (*image).is_shared();
+        if shared && !ResumeImage::settle(image) {
+            return crate::bindings::FaultFlags::SIGSEGV.bits()
+                as linux_kernel_module::c_types::c_int;
+        }
+        let resume_related = &*image;
 
         #[cfg(feature = "page-cache")]
         let mut miss_page_cache = false;
-        let phy_addr = resume_related.descriptor.lookup_pg_table(fault_addr);
+        // the pages of a detached child are all local,
+        // e.g., the pages dropped by madvise are zero-filled on the next fault
+        let phy_addr = if resume_related.is_detached() {
+            None
+        } else {
+            // the page table of the VMA may not be fetched yet
+            if !shared && !resume_related.descriptor.ensure_page_table(fault_addr) {
+                return crate::bindings::FaultFlags::SIGSEGV.bits()
+                    as linux_kernel_module::c_types::c_int;
+            }
+            resume_related.descriptor.lookup_pg_table(fault_addr)
+        };
 
         let new_page = {
             if phy_addr.is_none() {
                 None
+            } else if crate::remote_mapping::PhysAddr::new(phy_addr.unwrap()).is_zero() {
+                // a zero page of the parent, mapped without the network read
+                let new_page_p = crate::bindings::pmem_alloc_charged_page(
+                    crate::bindings::PMEM_GFP_HIGHUSER_ZERO,
+                );
+                if new_page_p.is_null() {
+                    None
+                } else {
+                    Some(new_page_p)
+                }
+            } else if shared {
+                // shared with the processes forked locally, or the materializer
+                resume_related
+                    .descriptor
+                    .read_remote_page_shared(fault_addr, &resume_related.access_info)
             } else {
+                // only the resumed child (and the pulls of its page tables) read the image
+                // before it is shared, which write the page table under its lock
                 #[cfg(feature = "page-cache")]
                 {
                     use crate::remote_mapping::PhysAddr;
@@ -650,9 +1384,13 @@ impl MitosisSysCallHandler {
                         } else {
                             // the page access is read/write
                             // Not read only, then copy into a new page
-                            let new_page_p = crate::bindings::pmem_alloc_page(
+                            let new_page_p = crate::bindings::pmem_alloc_charged_page(
                                 crate::bindings::PMEM_GFP_HIGHUSER,
                             );
+                            if new_page_p.is_null() {
+                                return crate::bindings::FaultFlags::OOM.bits()
+                                    as linux_kernel_module::c_types::c_int;
+                            }
 
                             crate::kern_wrappers::copy_page_content_4k(
                                 new_page_p,
@@ -660,14 +1398,45 @@ impl MitosisSysCallHandler {
                             );
                             Some(new_page_p)
                         }
-                    } else {
-                        // Cache miss, fallback into RDMA read
+                    } else if phys_addr.is_prefetch() {
+                        // Cache miss, but the page has been fetched by the prefetcher
                         miss_page_cache = true;
                         resume_related
                             .descriptor
                             .read_remote_page(fault_addr, 
                                 &resume_related.access_info,
                             )
+                    } else {
+                        // Cache miss, the page is read once for the children on this host
+                        miss_page_cache = true;
+                        let descriptor = &resume_related.descriptor;
+                        let access_info = &resume_related.access_info;
+                        let cached = crate::get_page_cache_ref().fetch(
+                            resume_related.remote_mac_id,
+                            resume_related.epoch,
+                            phys_addr.real_addr(),
+                            || descriptor.read_remote_page(fault_addr, access_info),
+                        );
+                        match cached {
+                            // Read only, shared with the other children
+                            Some(p) if phys_addr.is_ro() => {
+                                crate::kern_wrappers::Page::new_from_raw(p).increase_ref_count();
+                                Some(p)
+                            }
+                            // Not read only, then copy into a new page
+                            Some(p) => {
+                                let new_page_p = crate::bindings::pmem_alloc_charged_page(
+                                    crate::bindings::PMEM_GFP_HIGHUSER,
+                                );
+                                if new_page_p.is_null() {
+                                    return crate::bindings::FaultFlags::OOM.bits()
+                                        as linux_kernel_module::c_types::c_int;
+                                }
+                                crate::kern_wrappers::copy_page_content_4k(new_page_p, p);
+                                Some(new_page_p)
+                            }
+                            None => None,
+                        }
                     }
                 }
                 #[cfg(not(feature = "page-cache"))]
@@ -697,24 +1466,47 @@ impl MitosisSysCallHandler {
                         new_page_p as crate::kern_wrappers::mm::VirtAddrType,
                         PhysAddrBitFlag::Cache as _,
                     );
-                    resume_related
-                        .descriptor
-                        .page_table
-                        .force_map(x86_64::VirtAddr::new(fault_addr), PhysAddr::new(kernel_va));
+                    // only missed before the image is shared
+                    resume_related.descriptor.lock_page_table(|st| {
+                        st.page_table
+                            .force_map(x86_64::VirtAddr::new(fault_addr), PhysAddr::new(kernel_va))
+                    });
                 }
                 0
             }
             None => {
-                // check whether the page is anonymous
-                let vma = crate::kern_wrappers::vma::VMA::new(&mut *((*vmf).vma));
-                for &vd in &resume_related.descriptor.vma {
-                    if vd.is_anonymous && (vma.get_start() == vd.get_start()) {
-                        let new_page_p =
-                            crate::bindings::pmem_alloc_page(crate::bindings::PMEM_GFP_HIGHUSER);
+                // the pages not recorded by the parent are read from the backing file
+                let file_ops = (&MY_FILE_VM_OP as *const crate::bindings::vm_operations_struct)
+                    .cast::<c_void>();
+                if phy_addr.is_none() && (*(*vmf).vma).vm_ops.cast::<c_void>() == file_ops {
+                    return crate::bindings::pmem_filemap_fault(vmf) as _;
+                }
 
-                        (*vmf).page = new_page_p as *mut _;
-                        return 0;
+                // check whether the page is anonymous, or excluded by the parent (read as zero)
+                let vma = crate::kern_wrappers::vma::VMA::new(&mut *((*vmf).vma));
+                let descriptor = &resume_related.descriptor;
+                let zero_filled = descriptor.is_excluded(fault_addr)
+                    || descriptor
+                        .vma
+                        .iter()
+                        .any(|vd| vd.is_anonymous && vma.get_start() == vd.get_start());
+                if zero_filled {
+                    let new_page_p = crate::bindings::pmem_alloc_charged_page(
+                        crate::bindings::PMEM_GFP_HIGHUSER_ZERO,
+                    );
+                    if new_page_p.is_null() {
+                        return crate::bindings::FaultFlags::OOM.bits()
+                            as linux_kernel_module::c_types::c_int;
                     }
+
+                    (*vmf).page = new_page_p as *mut _;
+                    return 0;
+                }
+
+                // the page cannot fit the memcg of the child
+                if phy_addr.is_some() && crate::bindings::pmem_memcg_margin_pages() == 0 {
+                    return crate::bindings::FaultFlags::OOM.bits()
+                        as linux_kernel_module::c_types::c_int;
                 }
 
                 crate::log::debug!(
@@ -729,55 +1521,300 @@ impl MitosisSysCallHandler {
     #[allow(dead_code)]
     #[inline]
     fn incr_fault_page_cnt(&mut self) {
-        self.caller_status.fault_page_cnt += 1;
+        self.fault_page_cnt += 1;
     }
 
     /// Page fault size (in Bytes)
     #[allow(dead_code)]
     #[inline]
     fn fault_page_size(&self) -> usize {
-        self.caller_status.fault_page_cnt * 4096 as usize
+        self.fault_page_cnt * 4096 as usize
     }
+}
 
+impl ResumeImage {
     #[allow(dead_code)]
     #[inline]
     fn meta_workingset_size(&self) -> usize {
-        if let Some(meta) = self.caller_status.resume_related.as_ref() {
-            meta.pg_table_entry_cnt() * 4096 as usize
-        } else {
-            0
-        }
+        self.pg_table_entry_cnt() * 4096 as usize
     }
 
     #[cfg(feature = "resume-profile")]
     fn fetched_page_size(&self) -> usize {
-        if let Some(meta) = self.caller_status.resume_related.as_ref() {
-            meta.descriptor.remote_fetched_page_count * 4096 as usize
-        } else {
-            0
+        self.descriptor.remote_fetched_page_count.load(Ordering::Relaxed) * 4096 as usize
+    }
+
+    /// Fetch at most `batch` of the recorded pages that are not mapped in `mm`, and map them.
+    /// The pages are fetched without the mmap lock of `mm`, which is only held for reading
+    /// to find the unmapped pages and to map them, so the faults of the child are not blocked.
+    ///
+    /// Return
+    /// * the number of pages fetched, and whether the walk reaches the end of the VMAs
+    ///
+    /// # Safety
+    /// The image must be materializing, see `is_shared`
+    unsafe fn materialize(
+        image: *mut Self,
+        mm: *mut crate::bindings::mm_struct,
+        cursor: &mut MaterializeCursor,
+        batch: usize,
+    ) -> MitosisResult<(usize, bool)> {
+        use crate::bindings::{pmem_mmap_read_lock, pmem_mmap_read_unlock, pmem_put_page};
+
+        // the page tables are fetched (and the prefetches drained) once
+        if !Self::settle(image) {
+            return Err(MitosisError::Unreachable);
+        }
+        let image = &*image;
+
+        pmem_mmap_read_lock(mm);
+        let (addrs, done) = image.find_unmapped(mm, cursor, batch);
+        pmem_mmap_read_unlock(mm);
+
+        let mut pages = alloc::vec::Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            match image.descriptor.read_remote_page_shared(addr, &image.access_info) {
+                Some(p) => pages.push((addr, p)),
+                None => {
+                    for (_, p) in pages {
+                        pmem_put_page(p);
+                    }
+                    return Err(MitosisError::Unreachable);
+                }
+            }
+        }
+
+        pmem_mmap_read_lock(mm);
+        let res = image.map_fetched(mm, &pages, done);
+        pmem_mmap_read_unlock(mm);
+        for (_, p) in pages {
+            // the PTE takes its own reference of the page
+            pmem_put_page(p);
+        }
+        res.map(|n| (n, done))
+    }
+
+    /// Walk the recorded pages from `cursor`, until `batch` of them are not mapped in `mm`.
+    /// The caller must hold the mmap lock of `mm`.
+    ///
+    /// Return
+    /// * the addresses of the pages not mapped, and whether the walk reaches the end of the VMAs
+    unsafe fn find_unmapped(
+        &self,
+        mm: *mut crate::bindings::mm_struct,
+        cursor: &mut MaterializeCursor,
+        batch: usize,
+    ) -> (alloc::vec::Vec<VirtAddrType>, bool) {
+        let md = MemoryDescriptor::new(mm);
+        let (mut addrs, mut scanned) = (alloc::vec::Vec::new(), 0);
+
+        while let Some(vd) = self.descriptor.vma.get(cursor.vma_idx).copied() {
+            if vd.is_host_specific() || cursor.addr >= vd.get_end() {
+                cursor.vma_idx += 1;
+                cursor.addr = 0;
+                continue;
+            }
+            if addrs.len() >= batch || scanned >= MAX_MATERIALIZE_SCAN {
+                return (addrs, false);
+            }
+            let addr = core::cmp::max(cursor.addr, vd.get_start());
+            cursor.addr = addr + 4096;
+            scanned += 1;
+
+            if self.descriptor.lookup_pg_table(addr).is_none() {
+                continue;
+            }
+            // the range may have been unmapped, or remapped by the child
+            match md.find_vma(addr) {
+                Some(vma) if vma.vm_start <= addr && handled_by_mitosis(vma) => {}
+                _ => continue,
+            };
+            // already faulted in (and possibly swapped out) by the child
+            let pte = crate::bindings::pmem_get_pte(mm, addr);
+            if !pte.is_null() && crate::bindings::pmem_check_pte_none(pte) == 0 {
+                continue;
+            }
+            addrs.push(addr);
+        }
+        (addrs, true)
+    }
+
+    /// Map the fetched pages in `mm`, and detach the child if `done`.
+    /// The caller must hold the mmap lock of `mm`, which excludes the forks of the child.
+    ///
+    /// Return
+    /// * the number of pages mapped
+    unsafe fn map_fetched(
+        &self,
+        mm: *mut crate::bindings::mm_struct,
+        pages: &[(VirtAddrType, *mut crate::bindings::page)],
+        done: bool,
+    ) -> MitosisResult<usize> {
+        if self.is_frozen() {
+            // the processes forked from the child meanwhile still read the image
+            return Err(MitosisError::Unsupported);
+        }
+        let md = MemoryDescriptor::new(mm);
+        let mut mapped = 0;
+        for &(addr, p) in pages {
+            // the range may have been unmapped, or remapped by the child meanwhile
+            let vma = match md.find_vma(addr) {
+                Some(vma) if vma.vm_start <= addr && handled_by_mitosis(vma) => vma,
+                _ => continue,
+            };
+            let res = crate::bindings::pmem_vm_insert_page(vma, addr, p);
+            if res == 0 {
+                mapped += 1;
+                continue;
+            }
+            // faulted in by the child meanwhile
+            let pte = crate::bindings::pmem_get_pte(mm, addr);
+            if !pte.is_null() && crate::bindings::pmem_check_pte_none(pte) == 0 {
+                continue;
+            }
+            crate::log::error!("failed to map the page at 0x{:x}: {}", addr, res);
+            return Err(MitosisError::OutOfMemory);
         }
+        if done {
+            self.detached.store(true, Ordering::Release);
+        }
+        Ok(mapped)
     }
 
     /// Cache my page table in the kernel
-    /// Called only when the process exit
+    /// Called only when all the processes using the image exit
     #[inline]
     fn cache_my_pt(&self) {
         #[cfg(feature = "page-cache")]
-        if let Some(resume_related) = self.caller_status.resume_related.as_ref() {
+        if self.descriptor.pending_page_tables() == 0 {
             // copy to the kernel cache
-
-            let pg_table = resume_related.descriptor.page_table.copy();
+            let pg_table = self.descriptor.lock_page_table(|st| st.page_table.copy());
             unsafe {
-                crate::get_pt_cache_mut().insert(
-                    resume_related.remote_mac_id,
-                    resume_related.handler_id,
-                    pg_table,
-                );
+                crate::get_pt_cache_mut().insert(self.remote_mac_id, self.handler_id, pg_table);
             }
         }
     }
 }
 
+/// The max number of addresses checked by one materialization step,
+/// so that the large VMAs with few recorded pages don't hold the mmap lock of the child for long
+const MAX_MATERIALIZE_SCAN: usize = 64 * 1024;
+
+/// Where the materialization continues: the VMA (index in the descriptor), and the address in it
+#[derive(Default)]
+struct MaterializeCursor {
+    vma_idx: usize,
+    addr: VirtAddrType,
+}
+
+/// The materialization of a resumed child, run by its kernel thread
+struct MaterializeJob {
+    state: Weak<UnsafeCell<ResumeDataStruct>>,
+    // grabbed, so that the pointer stays valid after the exit of the child
+    mm: *mut crate::bindings::mm_struct,
+    cursor: MaterializeCursor,
+}
+
+impl MaterializeJob {
+    fn new(state: &ResumeState) -> Self {
+        let mm = unsafe { state.get_mut() }.mm;
+        unsafe { crate::bindings::pmem_mmgrab(mm) };
+        Self {
+            state: Arc::downgrade(&state.0),
+            mm,
+            cursor: Default::default(),
+        }
+    }
+}
+
+impl Drop for MaterializeJob {
+    fn drop(&mut self) {
+        unsafe { crate::bindings::pmem_mmdrop(self.mm) };
+    }
+}
+
+impl crate::materializer::Materialize for MaterializeJob {
+    fn step(&mut self, batch: usize) -> MitosisResult<(usize, bool)> {
+        use crate::bindings::*;
+
+        if unsafe { pmem_mmget_not_zero(self.mm) } == 0 {
+            crate::log::debug!("the child exits before it is materialized");
+            return Err(MitosisError::NotFound);
+        }
+        let state = match self.state.upgrade() {
+            Some(state) => state,
+            None => {
+                unsafe { pmem_mmput(self.mm) };
+                return Err(MitosisError::NotFound);
+            }
+        };
+        // the pages are charged to the memcg of the child
+        unsafe {
+            pmem_use_mm(self.mm);
+            let image = (*state.get()).image.get();
+            let res = ResumeImage::materialize(image, self.mm, &mut self.cursor, batch);
+            if let Ok((_, true)) = res {
+                // the child is detached even if the parent misses the report
+                (*image).attachment.release();
+            }
+            pmem_unuse_mm(self.mm);
+            pmem_mmput(self.mm);
+            res
+        }
+    }
+}
+
+/// Pulls the page tables of a child resumed with the head section of the descriptor,
+/// run by a kernel thread of the materialize service
+struct SectionPullJob {
+    state: Weak<UnsafeCell<ResumeDataStruct>>,
+    // grabbed, so that the pointer stays valid after the exit of the child
+    mm: *mut crate::bindings::mm_struct,
+}
+
+impl SectionPullJob {
+    fn new(state: &ResumeState) -> Self {
+        let mm = unsafe { state.get_mut() }.mm;
+        unsafe { crate::bindings::pmem_mmgrab(mm) };
+        Self {
+            state: Arc::downgrade(&state.0),
+            mm,
+        }
+    }
+}
+
+impl Drop for SectionPullJob {
+    fn drop(&mut self) {
+        unsafe { crate::bindings::pmem_mmdrop(self.mm) };
+    }
+}
+
+impl crate::materializer::Materialize for SectionPullJob {
+    /// Pull one page table, the progress is counted in the pages recorded in it
+    fn step(&mut self, _batch: usize) -> MitosisResult<(usize, bool)> {
+        use crate::bindings::*;
+
+        let state = self.state.upgrade().ok_or(MitosisError::NotFound)?;
+        // shared with the faults of the child, see `PageTableState`
+        let image = unsafe { &*(*state.get()).image.get() };
+        if image.descriptor.pending_page_tables() == 0 {
+            return Ok((0, true));
+        }
+
+        if unsafe { pmem_mmget_not_zero(self.mm) } == 0 {
+            crate::log::debug!("the child exits before its page tables are pulled");
+            return Err(MitosisError::NotFound);
+        }
+        // fetched without the mmap lock, so that the faults of the child are not blocked.
+        // The faults look up the page table under its lock, which is only held to map
+        // the entries of each chunk fetched, and wait for the section being pulled.
+        let res = image.descriptor.pull_next_section();
+        unsafe { pmem_mmput(self.mm) };
+        let n = res?.unwrap_or(0);
+        Ok((n, image.descriptor.pending_page_tables() == 0))
+    }
+}
+
 unsafe impl Sync for MitosisSysCallHandler {}
 
 unsafe impl Send for MitosisSysCallHandler {}
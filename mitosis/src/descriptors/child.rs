@@ -8,16 +8,17 @@ use os_network::rdma::payload::RDMAOp;
 use os_network::rdma::payload::dc::DCReqPayload;
 use os_network::timeout::TimeoutWRef;
 #[allow(unused_imports)]
-use core::sync::atomic::{compiler_fence, Ordering::SeqCst};
+use core::sync::atomic::{compiler_fence, AtomicU8, Ordering::SeqCst};
 
 use os_network::bytes::BytesMut;
 #[allow(unused_imports)]
 use os_network::future::{Async, Future};
 use os_network::Conn;
 
+use super::layout::{MMLayoutDescriptor, AT_SYSINFO_EHDR};
 use super::rdma::RDMADescriptor;
 use super::reg::RegDescriptor;
-use super::vma::VMADescriptor;
+use super::vma::{FilePath, VMADescriptor};
 
 #[allow(unused_imports)]
 use super::page_table::FlatPageTable;
@@ -28,9 +29,16 @@ use crate::remote_mapping::{PageEntry, PhysAddr, RemotePageTable, RemotePageTabl
 #[allow(unused_imports)]
 use super::parent::{CompactPageTable, Offset, Value};
 
+use crate::error::{MitosisError, MitosisResult};
 use crate::kern_wrappers::mm::{PhyAddrType, VirtAddrType};
-use crate::kern_wrappers::task::Task;
-use crate::remote_paging::AccessInfo;
+use crate::kern_wrappers::task::{Task, VdsoPlan};
+use crate::kern_wrappers::wait_queue::WaitQueue;
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+use crate::remote_paging::{AccessInfo, RemotePagingService};
+use crate::rpc_handlers::RemoteDescriptor;
+use crate::KRdmaKit::consts::MAX_KMALLOC_SZ;
+use os_network::bytes::ToBytes;
+use os_network::msg::UDMsg as RMemory;
 
 #[cfg(feature = "prefetch")]
 use crate::prefetcher::{DCAsyncPrefetcher, StepPrefetcher};
@@ -40,22 +48,213 @@ use crate::prefetcher::{DCAsyncPrefetcher, StepPrefetcher};
 #[allow(dead_code)]
 pub struct ChildDescriptor {
     pub regs: RegDescriptor,
+    pub layout: MMLayoutDescriptor,
 
-    // #[cfg(not(feature = "prefetch"))]
-    // pub page_table: FlatPageTable,
-    //
-    // #[cfg(feature = "prefetch")]
-    pub page_table: RemotePageTable,
+    // the page table, and the states modified with it, see `PageTableState`
+    pub pt_state: BoxedLockBundler<PageTableState>,
 
     pub vma: Vec<VMADescriptor>,
+    pub files: Vec<FilePath>,
+    // the ranges excluded by the parent, see `is_excluded`
+    pub excluded: Vec<(VirtAddrType, VirtAddrType)>,
     pub machine_info: RDMADescriptor,
+    // the page tables not yet fetched, if resumed with the head section of the descriptor
+    pub pending_sections: Option<PendingSections>,
 
-    #[cfg(feature = "prefetch")]
-    pub prefetcher: DCAsyncPrefetcher,
-    #[cfg(feature = "eager-resume")]
+    // pages fetched by `eager_fetch_vma`, released when the child exits
     pub eager_fetched_pages: hashbrown::HashSet<VirtAddrType>,
     #[cfg(feature = "resume-profile")]
-    pub remote_fetched_page_count: usize,
+    pub remote_fetched_page_count: core::sync::atomic::AtomicUsize,
+}
+
+/// The page table of a resumed child, and the states modified with it.
+///
+/// Once resumed, the entries are written by the fetches of the pending sections,
+/// the prefetcher (which marks and consumes the prefetched entries),
+/// and the faults (e.g., the pages cached by the page cache), concurrently with the lookups
+/// of the faults, so they are all done under the lock of `ChildDescriptor::pt_state`.
+pub struct PageTableState {
+    pub page_table: RemotePageTable,
+    #[cfg(feature = "prefetch")]
+    pub prefetcher: DCAsyncPrefetcher,
+}
+
+impl PageTableState {
+    pub fn new(
+        page_table: RemotePageTable,
+        #[cfg(feature = "prefetch")] prefetcher: DCAsyncPrefetcher,
+    ) -> BoxedLockBundler<Self> {
+        LockBundler::new(Self {
+            page_table,
+            #[cfg(feature = "prefetch")]
+            prefetcher,
+        })
+    }
+}
+
+/// The max number of retries of a page table section fetch, before the fault fails
+const SECTION_FETCH_RETRIES: usize = 3;
+
+const SECTION_PENDING: u8 = 0;
+const SECTION_IN_FLIGHT: u8 = 1;
+const SECTION_FETCHED: u8 = 2;
+
+/// The page tables of a child resumed with the head section of the descriptor,
+/// fetched from the parent on the first fault in their VMAs, or in the background
+pub struct PendingSections {
+    remote: RemoteDescriptor,
+    machine_id: usize,
+    // the (offset, length) in the descriptor of the page table of each VMA
+    sections: Vec<(usize, usize)>,
+    // the state of each section, which is mapped by one fetch at a time:
+    // the other fetches of an in-flight section wait on `wq`, and retry if it fails
+    states: Vec<AtomicU8>,
+    remaining: core::sync::atomic::AtomicUsize,
+    wq: WaitQueue,
+}
+
+impl PendingSections {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.remaining.load(SeqCst)
+    }
+
+    /// Claim the `idx`th section for a fetch, or wait for the in-flight fetch of another one
+    ///
+    /// Return
+    /// * false if the section has been fetched
+    fn claim(&self, idx: usize) -> bool {
+        let state = &self.states[idx];
+        loop {
+            match state.compare_exchange(SECTION_PENDING, SECTION_IN_FLIGHT, SeqCst, SeqCst) {
+                Ok(_) => return true,
+                Err(SECTION_FETCHED) => return false,
+                Err(_) => self.wq.wait_until(|| state.load(SeqCst) != SECTION_IN_FLIGHT),
+            }
+        }
+    }
+
+    /// Release the `idx`th section claimed, which is retried by the next fetch if it failed
+    fn release(&self, idx: usize, fetched: bool) {
+        if fetched {
+            self.states[idx].store(SECTION_FETCHED, SeqCst);
+            self.remaining.fetch_sub(1, SeqCst);
+        } else {
+            self.states[idx].store(SECTION_PENDING, SeqCst);
+        }
+        self.wq.wake_up_all();
+    }
+
+    /// Read a piece of a page table section into `buf`,
+    /// retried on the transient failures of the network
+    fn fetch_piece(&self, off: usize, len: usize, buf: &RMemory) -> MitosisResult<()> {
+        let machine_id = self.machine_id as _;
+        let mut tries = 0;
+        loop {
+            match RemotePagingService::remote_descriptor_piece_fetch(
+                &self.remote,
+                off,
+                len,
+                buf,
+                machine_id,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(e) if tries < SECTION_FETCH_RETRIES => {
+                    crate::log::warn!("failed to fetch the page table section {:?}, retry", e);
+                    tries += 1;
+                }
+                Err(e) => {
+                    crate::log::error!("failed to fetch the page table section {:?}", e);
+                    return Err(MitosisError::Unreachable);
+                }
+            }
+        }
+    }
+}
+
+/// Decode a serialized page table (see `CompactPageTable`) fed piece by piece,
+/// where the entry count or an entry split across two pieces is carried over
+#[derive(Default)]
+struct SectionDecoder {
+    // the number of entries, once decoded
+    count: Option<usize>,
+    // whether the padding following the count is not decoded yet
+    padded: bool,
+    decoded: usize,
+    carry: [u8; 16],
+    carry_len: usize,
+}
+
+impl SectionDecoder {
+    const ENTRY_LEN: usize = core::mem::size_of::<Offset>() + core::mem::size_of::<Value>();
+
+    /// The length of the next item, None if all the entries are decoded
+    #[inline]
+    fn next_len(&self) -> Option<usize> {
+        match self.count {
+            None => Some(core::mem::size_of::<usize>()),
+            Some(_) if self.padded => Some(core::mem::size_of::<u32>()),
+            Some(count) if self.decoded < count => Some(Self::ENTRY_LEN),
+            Some(_) => None,
+        }
+    }
+
+    /// Map the entries in `bytes` of the VMA starting at `vma_start`
+    ///
+    /// Return
+    /// * None if `bytes` exceeds the page table
+    fn feed(
+        &mut self,
+        pt: &mut RemotePageTable,
+        vma_start: VirtAddrType,
+        mut bytes: &[u8],
+    ) -> Option<()> {
+        while !bytes.is_empty() {
+            let need = self.next_len()?;
+            let take = core::cmp::min(need - self.carry_len, bytes.len());
+            self.carry[self.carry_len..self.carry_len + take].copy_from_slice(&bytes[..take]);
+            self.carry_len += take;
+            bytes = &bytes[take..];
+            if self.carry_len < need {
+                break;
+            }
+            self.carry_len = 0;
+
+            let item = self.carry.as_ptr();
+            match self.count {
+                None => {
+                    let count: usize = unsafe { core::ptr::read_unaligned(item as *const usize) };
+                    self.count = Some(count);
+                    self.padded = core::mem::size_of::<Offset>()
+                        < core::mem::size_of::<VirtAddrType>()
+                        && count % 2 == 1;
+                }
+                Some(_) if self.padded => self.padded = false,
+                Some(_) => {
+                    let (virt, phy): (Offset, Value) = unsafe {
+                        (
+                            core::ptr::read_unaligned(item as *const Offset),
+                            core::ptr::read_unaligned(
+                                item.add(core::mem::size_of::<Offset>()) as *const Value
+                            ),
+                        )
+                    };
+                    pt.map(
+                        VirtAddr::new(virt as VirtAddrType + vma_start),
+                        PhysAddr::new(phy),
+                    );
+                    self.decoded += 1;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Whether the whole page table has been decoded
+    #[inline]
+    fn is_done(&self) -> bool {
+        self.next_len().is_none()
+    }
 }
 
 impl ChildDescriptor {
@@ -66,31 +265,272 @@ impl ChildDescriptor {
         unimplemented!();
     }
 
+    /// Whether the page at `addr` is excluded by the parent, which is read as zero
+    #[inline]
+    pub fn is_excluded(&self, addr: VirtAddrType) -> bool {
+        Self::in_ranges(&self.excluded, addr)
+    }
+
+    #[inline]
+    fn in_ranges(ranges: &[(VirtAddrType, VirtAddrType)], addr: VirtAddrType) -> bool {
+        ranges.iter().any(|(s, e)| *s <= addr && addr < *e)
+    }
+
     #[inline(always)]
     pub fn lookup_pg_table(&self, virt: VirtAddrType) -> Option<PhyAddrType> {
-        self.page_table
-            .translate(VirtAddr::new(virt))
-            .map(|v| v.as_u64())
+        self.translate(VirtAddr::new(virt)).map(|v| v.as_u64())
+    }
+
+    #[inline(always)]
+    fn translate(&self, virt: VirtAddr) -> Option<PhysAddr> {
+        self.walk_page_table(|pt| pt.translate(virt))
+    }
+
+    /// Walk the page table, which is written concurrently under its lock, see `PageTableState`
+    #[inline(always)]
+    fn walk_page_table<R>(&self, f: impl FnOnce(&RemotePageTable) -> R) -> R {
+        self.pt_state.lock(|st| f(&st.page_table))
+    }
+
+    /// Run `f` with the page table, and the states modified with it, locked
+    #[inline]
+    pub fn lock_page_table<R>(&self, f: impl FnOnce(&mut PageTableState) -> R) -> R {
+        self.pt_state.lock(f)
+    }
+
+    /// An upper bound of the pages charged to the child after resume:
+    /// the pages recorded in the page table, bounded by the sizes of the VMAs
+    pub fn committed_pages(&self) -> usize {
+        let vma_pages: usize = self.vma.iter().map(|v| (v.get_sz() / 4096) as usize).sum();
+        core::cmp::min(self.recorded_pages(), vma_pages)
+    }
+
+    /// The pages recorded in the page table, including the page tables not yet fetched
+    pub fn recorded_pages(&self) -> usize {
+        let pending = self.pending_sections.as_ref().map(|p| {
+            p.sections
+                .iter()
+                .zip(p.states.iter())
+                .filter(|(_, state)| state.load(SeqCst) != SECTION_FETCHED)
+                .map(|((_, len), _)| CompactPageTable::table_len_of(*len))
+                .sum()
+        });
+        self.walk_page_table(|pt| pt.len()) + pending.unwrap_or(0)
+    }
+
+    /// The number of the page tables not yet fetched
+    #[inline]
+    pub fn pending_page_tables(&self) -> usize {
+        self.pending_sections.as_ref().map(|p| p.remaining()).unwrap_or(0)
+    }
+
+    /// Forget the page tables not yet fetched, e.g., when the page table is taken from the cache
+    pub fn discard_pending_sections(&mut self) {
+        self.pending_sections = None;
+    }
+
+    /// Fetch the page table of the VMA covering `addr`, if it is not fetched yet
+    ///
+    /// Return
+    /// * false if the page table cannot be fetched
+    #[inline]
+    pub fn ensure_page_table(&self, addr: VirtAddrType) -> bool {
+        if self.pending_page_tables() == 0 {
+            return true;
+        }
+        match self
+            .vma
+            .iter()
+            .position(|v| v.get_start() <= addr && addr < v.get_end())
+        {
+            Some(idx) => self.fetch_section(idx).is_ok(),
+            None => true,
+        }
+    }
+
+    /// Fetch the page table of the `idx`th VMA, if it is not fetched yet
+    pub fn fetch_section(&self, idx: usize) -> MitosisResult<()> {
+        self.stream_section(idx).map(|_| ())
+    }
+
+    /// Fetch the page tables not yet fetched, e.g., before the page table is shared by forks
+    pub fn fetch_all_sections(&self) -> MitosisResult<()> {
+        for idx in 0..self.vma.len() {
+            self.fetch_section(idx)?;
+        }
+        Ok(())
+    }
+
+    /// Fetch the page table of the first VMA whose page table is not fetched yet,
+    /// e.g., in the background
+    ///
+    /// Return
+    /// * the number of pages recorded in the page table, None if all of them are fetched
+    pub fn pull_next_section(&self) -> MitosisResult<Option<usize>> {
+        let next = match self.pending_sections.as_ref() {
+            // the in-flight one is waited on, as the other fetch may fail
+            Some(pending) if pending.remaining() > 0 => {
+                pending.states.iter().position(|s| s.load(SeqCst) != SECTION_FETCHED)
+            }
+            _ => None,
+        };
+        match next {
+            Some(idx) => self.stream_section(idx).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch the page table of the `idx`th VMA chunk by chunk, and map the entries of each
+    /// chunk once it is read, so that a page table larger than one chunk is never assembled.
+    /// The chunks are read without the lock of the page table, so that the lookups of the other
+    /// faults are not blocked by the network. The section is fetched by one caller at a time,
+    /// and the others wait for it.
+    ///
+    /// Return
+    /// * the number of pages recorded in the page table, 0 if it has been fetched
+    fn stream_section(&self, idx: usize) -> MitosisResult<usize> {
+        let pending = match self.pending_sections.as_ref() {
+            Some(pending) if pending.remaining() > 0 => pending,
+            _ => return Ok(0),
+        };
+        let (off, len) = match pending.sections.get(idx) {
+            Some(section) => *section,
+            None => return Ok(0),
+        };
+        if !pending.claim(idx) {
+            return Ok(0);
+        }
+
+        // the entries mapped by a failed fetch are kept, which are never overwritten by the retry
+        let res = self.map_section(pending, self.vma[idx].get_start(), off, len);
+        pending.release(idx, res.is_ok());
+        res.map(|_| CompactPageTable::table_len_of(len))
+    }
+
+    /// Fetch the page table at (`off`, `len`) of the descriptor and map it piece by piece
+    fn map_section(
+        &self,
+        pending: &PendingSections,
+        vma_start: VirtAddrType,
+        off: usize,
+        len: usize,
+    ) -> MitosisResult<()> {
+        let buf = RemotePagingService::alloc_descriptor_buf(
+            core::cmp::min(len, MAX_KMALLOC_SZ),
+            pending.machine_id as _,
+        );
+        let mut decoder = SectionDecoder::default();
+        for (cur, sz) in RemotePagingService::descriptor_pieces(off, len) {
+            pending.fetch_piece(cur, sz, &buf)?;
+            let bytes = unsafe { core::slice::from_raw_parts(buf.get_bytes().get_ptr(), sz) };
+            self.pt_state
+                .lock(|st| decoder.feed(&mut st.page_table, vma_start, bytes))
+                .ok_or(MitosisError::Protocol)?;
+        }
+        if !decoder.is_done() {
+            return Err(MitosisError::Protocol);
+        }
+        Ok(())
+    }
+
+    /// Map the entries of a serialized page table and advance the cursor
+    fn map_page_table(
+        pt: &mut RemotePageTable,
+        vma_start: VirtAddrType,
+        cur: &mut BytesMut,
+    ) -> Option<()> {
+        // we don't use the `deserialize` method in the compact page table,
+        // because it will incur unnecessary memory copies that is not optimal for the performance
+        let mut page_num: usize = 0;
+        let off = unsafe { cur.memcpy_deserialize(&mut page_num)? };
+        *cur = unsafe { cur.truncate_header(off)? };
+
+        if core::mem::size_of::<Offset>() < core::mem::size_of::<VirtAddrType>()
+            && page_num % 2 == 1
+        {
+            let mut pad: u32 = 0;
+            let off = unsafe { cur.memcpy_deserialize(&mut pad)? };
+            *cur = unsafe { cur.truncate_header(off)? };
+        }
+
+        for _ in 0..page_num {
+            let virt: Offset = unsafe { cur.read_unaligned_at_head() };
+            *cur = unsafe { cur.truncate_header(core::mem::size_of::<Offset>())? };
+
+            let phy: Value = unsafe { cur.read_unaligned_at_head() };
+            *cur = unsafe { cur.truncate_header(core::mem::size_of::<Value>())? };
+
+            pt.map(
+                VirtAddr::new(virt as VirtAddrType + vma_start),
+                PhysAddr::new(phy),
+            );
+        }
+        Some(())
     }
 
     /// Apply the descriptor into current process
+    ///
+    /// Return
+    /// * MitosisError::OutOfMemory if the vdso of this machine cannot be kept out of the VMAs
+    ///   of the parent, the current process is left untouched
+    /// * MitosisError::Internal if a VMA cannot be mapped once the address space is replaced,
+    ///   the current process is killed
     #[inline]
-    pub fn apply_to(&mut self, file: *mut crate::bindings::file) {
+    pub fn apply_to(&mut self, file: *mut crate::bindings::file) -> MitosisResult<()> {
         let mut task = Task::new();
+        // the vdso and vvar of this machine are kept, at the addresses of the parent if possible
+        let vdso_plan = task.plan_vdso(&self.vma, &self.layout)?;
+        let access_info = AccessInfo::new(&self.machine_info).ok_or_else(|| {
+            crate::log::error!("failed to create the access info of the parent");
+            MitosisError::Internal
+        })?;
+        // let access_info = AccessInfo::new_from_cache(self.machine_info.mac_id, &self.machine_info).unwrap();
+
         // 1. Unmap origin vma regions
         task.unmap_self();
+        let res = self.map_regions(&mut task, file, vdso_plan.as_ref(), &access_info);
+        if res.is_err() {
+            // nothing is left to return to
+            unsafe { crate::bindings::pmem_kill_current() };
+        }
+        res
+    }
 
-        #[allow(unused_variables)]
-        let access_info = AccessInfo::new(&self.machine_info).unwrap();
-        // let access_info = AccessInfo::new_from_cache(self.machine_info.mac_id, &self.machine_info).unwrap();
+    /// Map the VMAs of the parent into the current process, whose VMAs have been unmapped
+    fn map_regions(
+        &mut self,
+        task: &mut Task,
+        file: *mut crate::bindings::file,
+        vdso_plan: Option<&VdsoPlan>,
+        access_info: &AccessInfo,
+    ) -> MitosisResult<()> {
+        let vdso = match vdso_plan {
+            Some(plan) => Some(task.keep_vdso(plan)?),
+            None => None,
+        };
 
         // 2. Map new vma regions
-        #[cfg(not(feature = "eager-resume"))]
-        (&self.vma).into_iter().enumerate().for_each(|(i, m)| {
-            let vma = unsafe { task.map_one_region(file, &m, self.vma.get(i + 1)) };
+        // the hot VMAs (marked at prepare) are fetched eagerly after all VMAs are mapped,
+        // and all the VMAs are fetched eagerly with the eager resume
+        let mut hot_vmas = Vec::new();
 
-            #[allow(dead_code)]
-            let vma = vma.unwrap();
+        for (i, m) in self.vma.iter().enumerate() {
+            if m.is_host_specific() {
+                continue;
+            }
+            let vma = unsafe {
+                task.map_one_region(
+                    file,
+                    m,
+                    self.vma.get(i + 1),
+                    &self.layout,
+                    m.get_file_idx().and_then(|idx| self.files.get(idx)),
+                )
+            };
+            let vma = vma.ok_or_else(|| {
+                crate::log::error!("failed to map the VMA at {:x}", m.get_start());
+                MitosisError::Internal
+            })?;
 
             // tune the bits
             let origin_vma_flags =
@@ -100,34 +540,32 @@ impl ChildDescriptor {
                 // set the vma
                 crate::kern_wrappers::vma::VMA::new(vma).set_alloc();
             }
-        });
-
-        #[cfg(feature = "eager-resume")]
-        self.vma.clone().into_iter().enumerate().for_each(|(i, m)| {
-            let vma = unsafe { task.map_one_region(file, &m, self.vma.get(i + 1)) };
-
-            #[allow(dead_code)]
-            let vma = vma.unwrap();
-
-            // tune the bits
-            let origin_vma_flags =
-                unsafe { crate::bindings::VMFlags::from_bits_unchecked(m.flags) };
+            if cfg!(feature = "eager-resume") || m.is_hot() {
+                hot_vmas.push((i, *m, vma));
+            }
+        }
 
-            if origin_vma_flags.contains(crate::bindings::VMFlags::VM_ALLOC) {
-                // set the vma
-                crate::kern_wrappers::vma::VMA::new(vma).set_alloc();
+        for (i, m, vma) in hot_vmas {
+            if let Err(e) = self.fetch_section(i) {
+                crate::log::warn!("failed to fetch the page table of a hot VMA: {}", e);
             }
-            #[cfg(feature = "eager-resume")]
-            self.eager_fetch_vma(&m, vma, &access_info);
-        });
+            self.eager_fetch_vma(&m, vma, access_info);
+        }
 
         // 3. Re-set states
+        if let Some(addr) = vdso {
+            // only for the readers of the mm's auxv, e.g., /proc/self/auxv: the libc of the child
+            // keeps the auxv of the parent, and has resolved the vdso at its startup
+            self.layout.set_auxv(AT_SYSINFO_EHDR, addr);
+        }
+        task.set_mm_layout(&self.layout);
         task.set_mm_reg_states(&self.regs);
+        Ok(())
     }
 }
 
 impl ChildDescriptor {
-    #[cfg(feature = "eager-resume")]
+    /// Fetch all the pages of the VMA, used by eager resume and the hot VMAs
     fn eager_fetch_vma(
         &mut self,
         vma_des: &VMADescriptor,
@@ -182,21 +620,42 @@ impl ChildDescriptor {
         access_info: &AccessInfo,
     ) -> Vec<Option<*mut crate::bindings::page>> {
         let mut res: Vec<Option<*mut crate::bindings::page>> = Vec::with_capacity(addr_list.len());
+        // the last read is signaled, which completes the unsignaled ones before it
+        let last = addr_list.iter().rposition(|va| {
+            self.lookup_pg_table(*va)
+                .map(|pa| Self::needs_page_read(PhysAddr::new(pa)))
+                .unwrap_or(false)
+        });
         for (i, remote_va) in addr_list.iter().enumerate() {
             let remote_pa = self.lookup_pg_table(*remote_va);
             if remote_pa.is_none() {
                 res.push(None);
                 continue;
             }
+            let entry = PhysAddr::new(remote_pa.unwrap());
+            if entry.is_zero() {
+                res.push(unsafe { Self::alloc_zero_page() });
+                continue;
+            }
+            if entry.is_compressed() {
+                res.push(unsafe { Self::read_compressed_page(entry, access_info) });
+                continue;
+            }
 
-            let new_page_p =
-                unsafe { crate::bindings::pmem_alloc_page(crate::bindings::PMEM_GFP_HIGHUSER) };
+            let new_page_p = unsafe {
+                crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER)
+            };
+            if new_page_p.is_null() {
+                crate::log::error!("[batch_read_remote_pages] the memcg is exhausted");
+                res.push(None);
+                continue;
+            }
             let new_page_va = unsafe { crate::bindings::pmem_page_to_virt(new_page_p) } as u64;
 
             let result = {
                 use rust_kernel_rdma_base::bindings::*;
                 let (src, sz) = (remote_pa.unwrap(), 4096);
-                let signaled = i == (addr_list.len() - 1);
+                let signaled = Some(i) == last;
                 let pool_idx = unsafe { crate::bindings::pmem_get_current_cpu() } as usize;
                 let dc_qp =
                     unsafe { crate::get_dc_pool_service_mut().get_dc_qp(pool_idx) }
@@ -217,7 +676,7 @@ impl ChildDescriptor {
                         crate::log::error!("failed to batch read pages {:?}", res);
                     }
 
-                    if i == addr_list.len() - 1 {
+                    if signaled {
                         // wait for the request to complete
                         let mut timeout_dc = TimeoutWRef::new(
                             dc_qp,
@@ -247,7 +706,7 @@ impl ChildDescriptor {
                         "[batch_read_remote_pages] Failed to read the remote page {:?}",
                         e
                     );
-                    unsafe { crate::bindings::pmem_free_page(new_page_p) };
+                    unsafe { crate::bindings::pmem_put_page(new_page_p) };
                     res.push(None)
                 }
             }
@@ -265,7 +724,7 @@ impl ChildDescriptor {
     /// @param access_info: remote network meta info
     #[inline]
     pub unsafe fn read_remote_page(
-        &mut self,
+        &self,
         remote_va: PhyAddrType,
         access_info: &AccessInfo,
     ) -> Option<*mut crate::bindings::page> {
@@ -274,7 +733,18 @@ impl ChildDescriptor {
             return None;
         }
         let remote_pa = remote_pa.unwrap();
-        let new_page_p = crate::bindings::pmem_alloc_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        if PhysAddr::new(remote_pa).is_zero() {
+            return Self::alloc_zero_page();
+        }
+        if PhysAddr::new(remote_pa).is_compressed() {
+            return Self::read_compressed_page(PhysAddr::new(remote_pa), access_info);
+        }
+        let new_page_p =
+            crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        if new_page_p.is_null() {
+            // the memcg of the child is exhausted
+            return None;
+        }
         let new_page_pa = crate::bindings::pmem_page_to_phy(new_page_p) as u64;
         let res = crate::remote_paging::RemotePagingService::remote_read(
             new_page_pa, 
@@ -288,7 +758,7 @@ impl ChildDescriptor {
             Ok(_) => Some(new_page_p),
             Err(e) => {
                 crate::log::error!("Failed to read the remote page {:?}", e);
-                crate::bindings::pmem_free_page(new_page_p);
+                crate::bindings::pmem_put_page(new_page_p);
                 None
             }
         };
@@ -301,7 +771,7 @@ impl ChildDescriptor {
     /// @param access_info: remote network meta info
     #[inline]
     pub unsafe fn read_remote_page_wo_prefetch(
-        &mut self,
+        &self,
         remote_va: VirtAddrType,
         access_info: &AccessInfo,
     ) -> Option<*mut crate::bindings::page> {
@@ -309,8 +779,19 @@ impl ChildDescriptor {
         if remote_pa.is_none() {
             return None;
         }
+        if PhysAddr::new(remote_pa.unwrap()).is_zero() {
+            return Self::alloc_zero_page();
+        }
+        if PhysAddr::new(remote_pa.unwrap()).is_compressed() {
+            return Self::read_compressed_page(PhysAddr::new(remote_pa.unwrap()), access_info);
+        }
 
-        let new_page_p = crate::bindings::pmem_alloc_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        let new_page_p =
+            crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        if new_page_p.is_null() {
+            // the memcg of the child is exhausted
+            return None;
+        }
         let new_page_pa = crate::bindings::pmem_page_to_phy(new_page_p) as u64;
         let res = crate::remote_paging::RemotePagingService::remote_read(
             new_page_pa,
@@ -324,7 +805,7 @@ impl ChildDescriptor {
             Ok(_) => Some(new_page_p),
             Err(e) => {
                 crate::log::error!("Failed to read the remote page {:?}", e);
-                crate::bindings::pmem_free_page(new_page_p);
+                crate::bindings::pmem_put_page(new_page_p);
                 None
             }
         };
@@ -341,11 +822,23 @@ impl ChildDescriptor {
     /// @param access_info: remote network meta info
     #[inline]
     pub unsafe fn read_remote_page(
-        &mut self,
+        &self,
+        remote_va: VirtAddrType,
+        access_info: &AccessInfo,
+    ) -> Option<*mut crate::bindings::page> {
+        // the prefetcher marks and consumes the entries, so it is driven under the lock
+        self.pt_state.lock(|st| self.read_remote_page_locked(st, remote_va, access_info))
+    }
+
+    #[cfg(feature = "prefetch")]
+    #[inline]
+    unsafe fn read_remote_page_locked(
+        &self,
+        st: &mut PageTableState,
         remote_va: VirtAddrType,
         access_info: &AccessInfo,
     ) -> Option<*mut crate::bindings::page> {
-        let (pt, idx) = self.page_table.find_l1_page_idx(VirtAddr::new(remote_va))?;
+        let (pt, idx) = st.page_table.find_l1_page_idx(VirtAddr::new(remote_va))?;
         let l1_page = &mut (*pt);
 
         let mut remote_pa = l1_page[idx];
@@ -366,7 +859,7 @@ impl ChildDescriptor {
                  */
                 while remote_pa == crate::remote_mapping::K_MAGIC_IN_PREFETCH {
                     // poll the prefetcher
-                    self.poll_prefetcher();
+                    Self::poll_prefetcher(&mut st.prefetcher);
                     remote_pa = l1_page[idx];
                     compiler_fence(SeqCst);
                 }
@@ -382,8 +875,19 @@ impl ChildDescriptor {
                 return Some(page);
             }
         }
+        if PhysAddr::new(remote_pa).is_zero() {
+            return Self::alloc_zero_page();
+        }
+        if PhysAddr::new(remote_pa).is_compressed() {
+            return Self::read_compressed_page(PhysAddr::new(remote_pa), access_info);
+        }
 
-        let new_page_p = crate::bindings::pmem_alloc_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        let new_page_p =
+            crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        if new_page_p.is_null() {
+            // the memcg of the child is exhausted
+            return None;
+        }
         let new_page_va = crate::bindings::pmem_page_to_virt(new_page_p) as u64;
 
         let pool_idx = crate::bindings::pmem_get_current_cpu() as usize;
@@ -414,13 +918,16 @@ impl ChildDescriptor {
     
                 // Note, we do the prefetch things here
                 // This can overlap with the networking requests latency
-                // find prefetch pages
-                let pte_iter = RemotePageTableIter::new_from_l1(pt, idx);
-                self.prefetcher.execute_reqs(
+                // find prefetch pages, skipping the ones excluded by the parent
+                let excluded = &self.excluded;
+                let pte_iter = RemotePageTableIter::new_from_l1(pt, idx).filter(|e| {
+                    excluded.is_empty() || !Self::in_ranges(excluded, e.virt_addr())
+                });
+                st.prefetcher.execute_reqs(
                     pte_iter,
                     StepPrefetcher::<PageEntry, { crate::PREFETCH_STEP }>::new(),
                 );
-                self.poll_prefetcher();
+                Self::poll_prefetcher(&mut st.prefetcher);
     
                 // wait for the request to complete
                 let mut timeout_dc = TimeoutWRef::new(dc_qp, TIMEOUT_USEC);
@@ -446,17 +953,144 @@ impl ChildDescriptor {
             Ok(_) => Some(new_page_p),
             Err(e) => {
                 crate::log::error!("Failed to read the remote page {:?}", e);
-                crate::bindings::pmem_free_page(new_page_p);
+                crate::bindings::pmem_put_page(new_page_p);
                 None
             }
         };
     }
 
+    /// Read one page without modifying the page table (nor the prefetcher),
+    /// so that the descriptor can be shared by the processes forked locally from the child.
+    /// The pages already fetched to local (prefetched or cached) are copied.
+    ///
+    /// @param remote_va: remote virt-addr
+    /// @param access_info: remote network meta info
+    pub unsafe fn read_remote_page_shared(
+        &self,
+        remote_va: VirtAddrType,
+        access_info: &AccessInfo,
+    ) -> Option<*mut crate::bindings::page> {
+        let entry = self.translate(VirtAddr::new(remote_va))?;
+        if entry.is_zero() {
+            return Self::alloc_zero_page();
+        }
+        if entry.is_compressed() {
+            return Self::read_compressed_page(entry, access_info);
+        }
+
+        let new_page_p =
+            crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        if new_page_p.is_null() {
+            // the memcg of the child is exhausted
+            return None;
+        }
+
+        if entry.is_prefetch() || entry.is_cache() {
+            let local_page = if entry.is_cache() {
+                entry.convert_to_page()
+            } else {
+                PhysAddr::decode(entry.as_u64()) as *mut page
+            };
+            crate::kern_wrappers::copy_page_content_4k(new_page_p, local_page);
+            return Some(new_page_p);
+        }
+
+        let new_page_pa = crate::bindings::pmem_page_to_phy(new_page_p) as u64;
+        let res = crate::remote_paging::RemotePagingService::remote_read(
+            new_page_pa,
+            PhysAddr::decode(entry.as_u64()),
+            4096,
+            access_info,
+        );
+        match res {
+            Ok(_) => Some(new_page_p),
+            Err(e) => {
+                crate::log::error!("Failed to read the remote page {:?}", e);
+                crate::bindings::pmem_put_page(new_page_p);
+                None
+            }
+        }
+    }
+
+    /// A zeroed page for a zero page of the parent, which is not read
+    #[inline]
+    unsafe fn alloc_zero_page() -> Option<*mut crate::bindings::page> {
+        let new_page_p =
+            crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER_ZERO);
+        if new_page_p.is_null() {
+            // the memcg of the child is exhausted
+            return None;
+        }
+        Some(new_page_p)
+    }
+
+    /// Whether the page of the entry is read as it is, i.e., with a one-sided read of the page
+    #[inline]
+    fn needs_page_read(entry: PhysAddr) -> bool {
+        !entry.is_zero() && !entry.is_compressed()
+    }
+
+    /// Read the compressed copy of a page (see `CompressedStore`),
+    /// and decompress it into a new page
+    unsafe fn read_compressed_page(
+        entry: PhysAddr,
+        access_info: &AccessInfo,
+    ) -> Option<*mut crate::bindings::page> {
+        let (blob_pa, len) = entry.compressed_blob();
+        let new_page_p =
+            crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER);
+        if new_page_p.is_null() {
+            // the memcg of the child is exhausted
+            return None;
+        }
+        // the compressed copy is read into the scratch page of the CPU,
+        // and decompressed from there
+        let ret = crate::get_compress_scratch_ref().with_page(|scratch| {
+            let res = crate::remote_paging::RemotePagingService::remote_read(
+                crate::bindings::pmem_page_to_phy(scratch) as u64,
+                blob_pa,
+                len,
+                access_info,
+            );
+            match res {
+                Ok(_) => crate::bindings::pmem_decompress_page(
+                    crate::bindings::pmem_page_to_virt(scratch) as _,
+                    len as _,
+                    crate::bindings::pmem_page_to_virt(new_page_p) as _,
+                ),
+                Err(e) => {
+                    crate::log::error!("Failed to read the compressed page {:?}", e);
+                    -1
+                }
+            }
+        });
+
+        if ret != 0 {
+            crate::log::error!("Failed to decompress the page at {:#x} ({} bytes)", blob_pa, len);
+            crate::bindings::pmem_put_page(new_page_p);
+            return None;
+        }
+        Some(new_page_p)
+    }
+
+    /// Wait for all the in-flight prefetch requests,
+    /// after which the page table is no longer modified in the background
     #[cfg(feature = "prefetch")]
-    fn poll_prefetcher(&mut self) {
+    pub fn wait_prefetches(&self) {
+        self.pt_state.lock(|st| {
+            while st.prefetcher.num_pending() > 0 {
+                Self::poll_prefetcher(&mut st.prefetcher);
+            }
+        });
+    }
+
+    /// Poll the completed prefetches, which write their entries.
+    /// The caller must hold the lock of the page table.
+    #[cfg(feature = "prefetch")]
+    fn poll_prefetcher(prefetcher: &mut DCAsyncPrefetcher) {
         loop {
             #[allow(non_snake_case)]
-            match self.prefetcher.poll() {
+            match prefetcher.poll() {
                 Ok(Async::Ready(_)) => {
                     // The second poll is likely to succeed
                     // so just continue
@@ -470,88 +1104,71 @@ impl ChildDescriptor {
     }
 
     #[cfg(feature = "resume-profile")]
-    fn incr_fetched_remote_count(&mut self, page_cnt: usize) {
-        self.remote_fetched_page_count += page_cnt;
+    fn incr_fetched_remote_count(&self, page_cnt: usize) {
+        self.remote_fetched_page_count.fetch_add(page_cnt, SeqCst);
     }
 }
 
-impl os_network::serialize::Serialize for ChildDescriptor {
-    fn serialize(&self, _bytes: &mut BytesMut) -> bool {
-        // Note, since we currently don't support multi-fork, so child serialize is not implemented
-        unimplemented!();
+impl ChildDescriptor {
+    /// Deserialize the head section fetched from the parent (see `ParentDescriptor::serialize`),
+    /// whose page tables are fetched later from the chunks of `remote`
+    pub(crate) fn deserialize_head(
+        bytes: &BytesMut,
+        remote: RemoteDescriptor,
+        machine_id: usize,
+    ) -> core::option::Option<Self> {
+        let (mut des, lens, cur) = Self::deserialize_head_w_cursor(bytes)?;
+
+        let mut off = bytes.len() - cur.len();
+        let mut sections = Vec::with_capacity(lens.len());
+        for len in lens {
+            if off + len > remote.reply.sz {
+                crate::log::error!("the page table section exceeds the descriptor");
+                return None;
+            }
+            sections.push((off, len));
+            off += len;
+        }
+        des.pending_sections = Some(PendingSections {
+            remote,
+            machine_id,
+            states: sections.iter().map(|_| AtomicU8::new(SECTION_PENDING)).collect(),
+            remaining: core::sync::atomic::AtomicUsize::new(sections.len()),
+            sections,
+            wq: WaitQueue::new(),
+        });
+        Some(des)
     }
 
-    /// De-serialize from a message buffer
-    /// **Warning**
-    /// - The buffer to be serialized must be generated from the ParentDescriptor.
+    /// Deserialize the head section with an empty page table
     ///
-    /// **TODO**
-    /// - Currently, we don't check the buf len, so this function is **unsafe**
-    fn deserialize(bytes: &BytesMut) -> core::option::Option<Self> {
-        // FIXME: check buf len
-
+    /// Return
+    /// * the descriptor, the lengths of the page tables of the VMAs,
+    ///   and the cursor at the page table of the first VMA
+    fn deserialize_head_w_cursor(
+        bytes: &BytesMut,
+    ) -> core::option::Option<(Self, Vec<usize>, BytesMut)> {
         let mut cur = unsafe { bytes.truncate_header(0).unwrap() };
+        // a descriptor of another format cannot be resumed
+        super::parent::check_format_version(&mut cur)?;
 
         // regs
         let regs = RegDescriptor::deserialize(&cur)?;
         cur = unsafe { cur.truncate_header(regs.serialization_buf_len())? };
 
-        // VMA page counts
-        let mut count: usize = 0;
-        let off = unsafe { cur.memcpy_deserialize(&mut count)? };
-        cur = unsafe { cur.truncate_header(off)? };
+        // mm layout
+        let layout = MMLayoutDescriptor::deserialize(&cur)?;
+        cur = unsafe { cur.truncate_header(layout.serialization_buf_len())? };
 
-        crate::log::debug!("!!!!! start to deserialize vma, count: {}", count);
-
-        // VMA & its corresponding page table
-        let mut pt = RemotePageTable::new();
-
-        let mut vmas = Vec::new();
-
-        for _ in 0..count {
-            let vma = VMADescriptor::deserialize(&cur)?;
-            cur = unsafe { cur.truncate_header(vma.serialization_buf_len())? };
-
-            let vma_start = vma.get_start();
-            vmas.push(vma);
-
-            // now, deserialize the page table of this VMA
-            // we don't use the `deserialize` method in the compact page table,
-            // because it will incur unnecessary memory copies that is not optimal for the performance
-            let mut page_num: usize = 0;
-            let off = unsafe { cur.memcpy_deserialize(&mut page_num)? };
-
-            cur = unsafe { cur.truncate_header(off)? };
-
-            // crate::log::debug!("check page_num: {}", page_num);
-            /*
-            if page_num > 1024 {
-                return None;
-            }*/
-
-            if core::mem::size_of::<Offset>() < core::mem::size_of::<VirtAddrType>()
-                && page_num % 2 == 1
-            {
-                let mut pad: u32 = 0;
-                let off = unsafe { cur.memcpy_deserialize(&mut pad)? };
-                cur = unsafe { cur.truncate_header(off)? };
-            }
-
-            for _ in 0..page_num {
-                let virt: Offset = unsafe { cur.read_unaligned_at_head() };
-                cur = unsafe { cur.truncate_header(core::mem::size_of::<Offset>())? };
-
-                let phy: Value = unsafe { cur.read_unaligned_at_head() };
-                cur = unsafe { cur.truncate_header(core::mem::size_of::<Value>())? };
-
-                pt.map(
-                    VirtAddr::new(virt as VirtAddrType + vma_start),
-                    PhysAddr::new(phy),
-                );
-            }
-        }
+        // VMAs & the lengths of their page tables
+        let (vmas, lens): (Vec<_>, Vec<_>) =
+            super::parent::deserialize_vmas(&mut cur)?.into_iter().unzip();
+        crate::log::debug!("!!!!! start to deserialize vma, count: {}", vmas.len());
 
+        let files = super::parent::deserialize_file_paths(&mut cur)?;
+        let excluded = super::parent::deserialize_excluded(&mut cur)?;
         let machine_info = RDMADescriptor::deserialize(&cur)?;
+        cur = unsafe { cur.truncate_header(machine_info.serialization_buf_len())? };
 
         // TODO: `LinuxMutex` should needs to have `into_inner` to get the underlying data.
         #[cfg(feature = "prefetch")]
@@ -566,25 +1183,60 @@ impl os_network::serialize::Serialize for ChildDescriptor {
         if access_info.is_none() {
             return None;
         }
-        Some(Self {
+        let des = Self {
             regs: regs,
-            page_table: pt,
+            layout: layout,
+            pt_state: PageTableState::new(
+                RemotePageTable::new(),
+                #[cfg(feature = "prefetch")]
+                DCAsyncPrefetcher::new_from_raw(prefetch_conn, access_info.unwrap()),
+            ),
             vma: vmas,
+            files: files,
+            excluded,
             machine_info: machine_info,
+            pending_sections: None,
 
-            #[cfg(feature = "prefetch")]
-            prefetcher: DCAsyncPrefetcher::new_from_raw(prefetch_conn, access_info.unwrap()),
-            #[cfg(feature = "eager-resume")]
             eager_fetched_pages: Default::default(),
             #[cfg(feature = "resume-profile")]
-            remote_fetched_page_count: 0
-        })
+            remote_fetched_page_count: Default::default(),
+        };
+        Some((des, lens, cur))
+    }
+}
+
+impl os_network::serialize::Serialize for ChildDescriptor {
+    fn serialize(&self, _bytes: &mut BytesMut) -> bool {
+        // Note, since we currently don't support multi-fork, so child serialize is not implemented
+        unimplemented!();
+    }
+
+    /// De-serialize from a message buffer
+    /// **Warning**
+    /// - The buffer to be serialized must be generated from the ParentDescriptor.
+    ///
+    /// **TODO**
+    /// - Currently, we don't check the buf len, so this function is **unsafe**
+    fn deserialize(bytes: &BytesMut) -> core::option::Option<Self> {
+        // FIXME: check buf len
+        let (des, lens, mut cur) = Self::deserialize_head_w_cursor(bytes)?;
+
+        // the page table of each VMA
+        for (i, len) in lens.into_iter().enumerate() {
+            let mut section = unsafe { cur.truncate_header(0)? };
+            let vma_start = des.vma[i].get_start();
+            des.pt_state
+                .lock(|st| Self::map_page_table(&mut st.page_table, vma_start, &mut section))?;
+            cur = unsafe { cur.truncate_header(len)? };
+        }
+        Some(des)
     }
 
     fn serialization_buf_len(&self) -> usize {
         unimplemented!();
         /*
         self.regs.serialization_buf_len()
+            + self.layout.serialization_buf_len()
             + self.page_table.serialization_buf_len()
             + core::mem::size_of::<usize>() // the number of VMA descriptors
             + self.vma.len() * core::mem::size_of::<VMADescriptor>()
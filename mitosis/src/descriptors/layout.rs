@@ -0,0 +1,75 @@
+use crate::descriptors::VMADescriptor;
+use crate::kern_wrappers::mm::VirtAddrType;
+
+/// The number of entries of `mm_struct::saved_auxv`, i.e., `AT_VECTOR_SIZE` on x86_64
+pub const AUXV_LEN: usize = 48;
+
+/// Taken from /include/uapi/linux/auxvec.h and arch/x86/include/uapi/asm/auxvec.h
+pub const AT_NULL: u64 = 0;
+pub const AT_SYSINFO_EHDR: u64 = 33;
+
+/// The layout fields of the `mm_struct` that are not covered by the VMAs,
+/// e.g., the heap range used by `brk`, and the arguments read by `/proc/self/cmdline`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MMLayoutDescriptor {
+    pub start_code: VirtAddrType,
+    pub end_code: VirtAddrType,
+    pub start_data: VirtAddrType,
+    pub end_data: VirtAddrType,
+    pub start_brk: VirtAddrType,
+    pub brk: VirtAddrType,
+    pub start_stack: VirtAddrType,
+    pub arg_start: VirtAddrType,
+    pub arg_end: VirtAddrType,
+    pub env_start: VirtAddrType,
+    pub env_end: VirtAddrType,
+    pub saved_auxv: [u64; AUXV_LEN],
+    // the vdso image of the machine, see `Task::keep_vdso`
+    pub vdso_hash: u64,
+}
+
+impl Default for MMLayoutDescriptor {
+    fn default() -> Self {
+        Self {
+            start_code: 0,
+            end_code: 0,
+            start_data: 0,
+            end_data: 0,
+            start_brk: 0,
+            brk: 0,
+            start_stack: 0,
+            arg_start: 0,
+            arg_end: 0,
+            env_start: 0,
+            env_end: 0,
+            saved_auxv: [0; AUXV_LEN],
+            vdso_hash: 0,
+        }
+    }
+}
+
+impl MMLayoutDescriptor {
+    /// Whether the VMA is the heap grown by `brk`
+    #[inline]
+    pub fn is_heap(&self, vma: &VMADescriptor) -> bool {
+        self.start_brk < self.brk
+            && vma.get_start() <= self.start_brk
+            && self.brk <= vma.get_end()
+    }
+
+    /// Update the value of `key` in the saved auxiliary vector, if present
+    pub fn set_auxv(&mut self, key: u64, val: u64) -> bool {
+        for i in (0..AUXV_LEN - 1).step_by(2) {
+            if self.saved_auxv[i] == AT_NULL {
+                break;
+            }
+            if self.saved_auxv[i] == key {
+                self.saved_auxv[i + 1] = val;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl os_network::serialize::Serialize for MMLayoutDescriptor {}
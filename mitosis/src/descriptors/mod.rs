@@ -2,9 +2,10 @@ pub use reg::*;
 pub use page_table::*;
 pub use rdma::RDMADescriptor;
 pub use parent::{CompactPageTable, ParentDescriptor};
-pub use child::ChildDescriptor;
+pub use child::{ChildDescriptor, PageTableState};
 
 pub use vma::*;
+pub use layout::*;
 pub use pair::*;
 
 pub mod parent;
@@ -12,6 +13,7 @@ pub mod child;
 pub mod reg;
 pub mod page_table;
 pub mod vma;
+pub mod layout;
 pub mod pair;
 pub mod rdma;
 
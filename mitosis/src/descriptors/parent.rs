@@ -1,5 +1,9 @@
-use crate::descriptors::{ChildDescriptor, RDMADescriptor, RegDescriptor, VMADescriptor};
+use crate::descriptors::{
+    ChildDescriptor, FilePath, MMLayoutDescriptor, PageTableState, RDMADescriptor, RegDescriptor,
+    VMADescriptor,
+};
 use crate::kern_wrappers::mm::{PhyAddrType, VirtAddrType};
+use crate::mem_pools::ChunkedBuf;
 use crate::{linux_kernel_module, VmallocAllocator};
 use alloc::vec::Vec;
 use os_network::bytes::BytesMut;
@@ -10,7 +14,7 @@ use crate::prefetcher::DCAsyncPrefetcher;
 
 pub(crate) type Offset = u32;
 pub(crate) type Value = PhyAddrType;
-type PageEntry = (Offset, Value); // record the (offset, phy_addr) pair
+pub(crate) type PageEntry = (Offset, Value); // record the (offset, phy_addr) pair
 
 /// This is a simple, condensed page table to represent the parent's
 /// page table in the descriptor.
@@ -24,9 +28,14 @@ pub struct CompactPageTable {
 #[derive(Clone)]
 pub struct ParentDescriptor {
     pub regs: RegDescriptor,
+    pub layout: MMLayoutDescriptor,
     // 2-dimension matrix, each row means one page-table according to one VMA
     pub page_table: Vec<CompactPageTable, VmallocAllocator>,
     pub vma: Vec<VMADescriptor>,
+    // the paths of the files backing the VMAs, indexed by `VMADescriptor::file_idx`
+    pub files: Vec<FilePath>,
+    // the [start, end) ranges excluded at prepare, which the children read as zero-filled pages
+    pub excluded: Vec<(VirtAddrType, VirtAddrType)>,
     pub machine_info: RDMADescriptor,
 }
 
@@ -34,8 +43,11 @@ impl Default for ParentDescriptor {
     fn default() -> Self {
         Self {
             regs: Default::default(),
+            layout: Default::default(),
             page_table: Vec::new_in(VmallocAllocator),
             vma: Vec::new(),
+            files: Vec::new(),
+            excluded: Vec::new(),
             machine_info: Default::default(),
         }
     }
@@ -77,47 +89,89 @@ impl ParentDescriptor {
 
         ChildDescriptor {
             regs: self.regs.clone(),
-            page_table,
+            layout: self.layout,
+            pt_state: PageTableState::new(
+                page_table,
+                #[cfg(feature = "prefetch")]
+                DCAsyncPrefetcher::new_from_raw(prefetch_conn, access_info),
+            ),
             vma: self.vma.clone(),
+            files: self.files.clone(),
+            excluded: self.excluded.clone(),
             machine_info: self.machine_info.clone(),
+            pending_sections: None,
 
-            #[cfg(feature = "prefetch")]
-            prefetcher: DCAsyncPrefetcher::new_from_raw(prefetch_conn, access_info),
-            #[cfg(feature = "eager-resume")]
             eager_fetched_pages: Default::default(),
             #[cfg(feature = "resume-profile")]
-            remote_fetched_page_count: 0,
+            remote_fetched_page_count: Default::default(),
         }
     }
 }
 
-impl ParentDescriptor {
+
+impl CompactPageTable {
+    const ENTRY_LEN: usize = core::mem::size_of::<Offset>() + core::mem::size_of::<Value>();
+
+    /// The number of entries of a table serialized in `len` bytes
     #[inline]
-    fn vma_pg_table_serialization_buf_len(&self) -> usize {
-        let mut result = core::mem::size_of::<usize>();
-        // note that each vma offset-page-table may have different entry length !
-        for vma_pg_table in &self.page_table {
-            result += vma_pg_table.serialization_buf_len();
+    pub(crate) fn table_len_of(len: usize) -> usize {
+        // the padding is shorter than an entry
+        len.saturating_sub(core::mem::size_of::<usize>()) / Self::ENTRY_LEN
+    }
+
+    /// The length of the serialized entry count (and the padding)
+    #[inline]
+    fn header_len(&self) -> usize {
+        self.serialization_buf_len() - self.table_len() * Self::ENTRY_LEN
+    }
+
+    /// Serialize the entries in [start, end) at their positions in the serialized table,
+    /// so that a large table can be serialized by multiple workers.
+    /// The header is serialized with the first entry.
+    ///
+    /// The space of `bytes` must have been checked by the caller.
+    pub(crate) fn serialize_entries(&self, bytes: &BytesMut, start: usize, end: usize) {
+        if start == 0 {
+            self.serialize_header(bytes);
         }
-        result
+        let cur = unsafe {
+            bytes
+                .truncate_header(self.header_len() + start * Self::ENTRY_LEN)
+                .unwrap()
+        };
+        self.write_entries(cur, start, end);
     }
-}
 
-impl os_network::serialize::Serialize for CompactPageTable {
-    /// Serialization format:
-    /// ```
-    /// | inner_pg_table length in bytes <-8 bytes-> | inner_pg_table entries|
-    /// ```
-    fn serialize(&self, bytes: &mut BytesMut) -> bool {
-        if bytes.len() < self.serialization_buf_len() {
-            crate::log::error!(
-                "failed to serialize: buffer space not enough. Need {}, actual {}",
-                self.serialization_buf_len(),
-                bytes.len()
-            );
-            return false;
+    /// Same as `serialize_entries`, except that the serialized table is not contiguous,
+    /// e.g., it spans two chunks: the entries are serialized in small batches on the stack,
+    /// and each batch is passed to `write` with its offset in the serialized table
+    pub(crate) fn serialize_entries_w(
+        &self,
+        start: usize,
+        end: usize,
+        mut write: impl FnMut(usize, &[u8]),
+    ) {
+        // the header is shorter than one entry
+        let mut batch = [0u8; SERIALIZE_BATCH_LEN];
+        if start == 0 {
+            let len = self.header_len();
+            self.serialize_header(&unsafe { BytesMut::from_raw(batch.as_mut_ptr(), len) });
+            write(0, &batch[..len]);
+        }
+
+        let per_batch = SERIALIZE_BATCH_LEN / Self::ENTRY_LEN;
+        let mut cur = start;
+        while cur < end {
+            let next = core::cmp::min(cur + per_batch, end);
+            let len = (next - cur) * Self::ENTRY_LEN;
+            self.write_entries(unsafe { BytesMut::from_raw(batch.as_mut_ptr(), len) }, cur, next);
+            write(self.header_len() + cur * Self::ENTRY_LEN, &batch[..len]);
+            cur = next;
         }
+    }
 
+    /// Serialize the number of entries (and the padding) at the head of `bytes`
+    fn serialize_header(&self, bytes: &BytesMut) {
         let mut cur = unsafe { bytes.truncate_header(0).unwrap() };
         let sz = unsafe {
             cur.memcpy_serialize_at(0, &self.inner_pg_table.len())
@@ -128,18 +182,38 @@ impl os_network::serialize::Serialize for CompactPageTable {
             && self.table_len() % 2 == 1
         {
             let pad: u32 = 0;
-            let sz = unsafe { cur.memcpy_serialize_at(0, &pad).unwrap() };
-            cur = unsafe { cur.truncate_header(sz).unwrap() };
+            unsafe { cur.memcpy_serialize_at(0, &pad).unwrap() };
         }
+    }
 
-        for (offset, paddr) in self.inner_pg_table.iter() {
+    /// Serialize the entries in [start, end) one after another from the head of `cur`
+    fn write_entries(&self, mut cur: BytesMut, start: usize, end: usize) {
+        for (offset, paddr) in self.inner_pg_table[start..end].iter() {
             let sz0 = unsafe { cur.write_unaligned_at_head(*offset) };
             cur = unsafe { cur.truncate_header(sz0).unwrap() };
 
             let sz0 = unsafe { cur.write_unaligned_at_head(*paddr) };
             cur = unsafe { cur.truncate_header(sz0).unwrap() };
         }
+    }
+}
+
+impl os_network::serialize::Serialize for CompactPageTable {
+    /// Serialization format:
+    /// ```
+    /// | inner_pg_table length in bytes <-8 bytes-> | inner_pg_table entries|
+    /// ```
+    fn serialize(&self, bytes: &mut BytesMut) -> bool {
+        if bytes.len() < self.serialization_buf_len() {
+            crate::log::error!(
+                "failed to serialize: buffer space not enough. Need {}, actual {}",
+                self.serialization_buf_len(),
+                bytes.len()
+            );
+            return false;
+        }
 
+        self.serialize_entries(bytes, 0, self.table_len());
         true
     }
 
@@ -186,26 +260,45 @@ impl os_network::serialize::Serialize for CompactPageTable {
     }
 }
 
-impl os_network::serialize::Serialize for ParentDescriptor {
-    /// Serialization format:
-    /// ```
-    /// | RegDescriptor <-sizeof(RegDescriptor)->
-    /// | VMA page table length in bytes <-8 bytes-> | VMAPageMap
-    /// | VMA descriptor length in bytes <-8 bytes-> | VMA descriptor
-    /// | RDMADescriptor |
-    /// ```
-    fn serialize(&self, bytes: &mut BytesMut) -> bool {
-        if bytes.len() < self.serialization_buf_len() {
-            crate::log::error!(
-                "failed to serialize: buffer space not enough. Need {}, actual {}",
-                self.serialization_buf_len(),
-                bytes.len()
-            );
-            return false;
-        }
+/// The max number of page table entries serialized by one job of the prepare workers
+const ENTRIES_PER_SERIALIZE_JOB: usize = 256 * 1024;
+
+/// The bytes of the entries serialized in one batch on the stack, see `serialize_entries_w`
+const SERIALIZE_BATCH_LEN: usize =
+    128 * (core::mem::size_of::<Offset>() + core::mem::size_of::<Value>());
+
+/// The version of the serialization format of the descriptor (see `ParentDescriptor::serialize`),
+/// bumped whenever the format changes, so that a descriptor of another version is rejected
+pub(crate) const DESCRIPTOR_FORMAT_VERSION: u64 = 5;
+
+impl ParentDescriptor {
+    /// The length of the head section, i.e., all but the page tables of the VMAs.
+    /// A child can be resumed with the head, and fetch the page tables later.
+    pub fn head_len(&self) -> usize {
+        core::mem::size_of::<u64>() // the format version
+            + self.regs.serialization_buf_len()
+            + self.layout.serialization_buf_len()
+            + core::mem::size_of::<usize>() // the number of VMA descriptors
+            + self.vma.len() * core::mem::size_of::<VMADescriptor>()
+            + self.vma.len() * core::mem::size_of::<usize>() // the lengths of the page tables
+            + core::mem::size_of::<usize>() // the number of file paths
+            + self.files.len() * core::mem::size_of::<FilePath>()
+            + core::mem::size_of::<usize>() // the number of excluded ranges
+            + self.excluded.len() * core::mem::size_of::<(VirtAddrType, VirtAddrType)>()
+            + self.machine_info.serialization_buf_len()
+    }
+
+    /// Serialize the head section, see `serialize` for the format
+    ///
+    /// Return
+    /// * the cursor at the page table section of the first VMA
+    fn serialize_head(&self, bytes: &BytesMut) -> BytesMut {
+        // 0. format version
+        let cur = unsafe { bytes.truncate_header(0).unwrap() };
+        let sz = unsafe { cur.memcpy_serialize_at(0, &DESCRIPTOR_FORMAT_VERSION).unwrap() };
 
         // 1. Reg
-        let mut cur = unsafe { bytes.truncate_header(0).unwrap() };
+        let mut cur = unsafe { cur.truncate_header(sz).unwrap() };
         self.regs.serialize(&mut cur);
         let mut cur = unsafe {
             // update cursor
@@ -213,17 +306,154 @@ impl os_network::serialize::Serialize for ParentDescriptor {
                 .unwrap()
         };
 
-        // 2. vmas & page table (size)
+        // 2. mm layout
+        self.layout.serialize(&mut cur);
+        let mut cur = unsafe {
+            cur.truncate_header(self.layout.serialization_buf_len())
+                .unwrap()
+        };
+
+        // 3. vmas & the lengths of their page tables
         let sz = unsafe { cur.memcpy_serialize_at(0, &self.page_table.len()).unwrap() };
         let mut cur = unsafe { cur.truncate_header(sz).unwrap() };
         assert_eq!(self.vma.len(), self.page_table.len());
 
-        //   page table (vec)
-        for (i, vma_pg_table) in self.page_table.iter().enumerate() {
-            let vma = self.vma[i];
+        for vma in &self.vma {
             vma.serialize(&mut cur);
             cur = unsafe { cur.truncate_header(vma.serialization_buf_len()).unwrap() };
+        }
+        for vma_pg_table in self.page_table.iter() {
+            let sz = unsafe {
+                cur.memcpy_serialize_at(0, &vma_pg_table.serialization_buf_len())
+                    .unwrap()
+            };
+            cur = unsafe { cur.truncate_header(sz).unwrap() };
+        }
+
+        // 4. the paths of the backing files
+        let sz = unsafe { cur.memcpy_serialize_at(0, &self.files.len()).unwrap() };
+        cur = unsafe { cur.truncate_header(sz).unwrap() };
+        for f in &self.files {
+            f.serialize(&mut cur);
+            cur = unsafe { cur.truncate_header(f.serialization_buf_len()).unwrap() };
+        }
+
+        // 5. the excluded ranges
+        let sz = unsafe { cur.memcpy_serialize_at(0, &self.excluded.len()).unwrap() };
+        cur = unsafe { cur.truncate_header(sz).unwrap() };
+        for range in &self.excluded {
+            let sz = unsafe { cur.memcpy_serialize_at(0, range).unwrap() };
+            cur = unsafe { cur.truncate_header(sz).unwrap() };
+        }
+
+        // 6. machine info
+        self.machine_info.serialize(&mut cur);
+        unsafe { cur.truncate_header(self.machine_info.serialization_buf_len()).unwrap() }
+    }
+
+    /// Same as `serialize`, except that the descriptor is serialized into the chunks of `buf`,
+    /// and the page tables of the VMAs are serialized by the prepare workers in parallel,
+    /// each into its section computed in advance.
+    ///
+    /// The sections are serialized in place, except the ones spanning two chunks,
+    /// see `CompactPageTable::serialize_entries_w`.
+    pub fn serialize_w_workers(&self, buf: &ChunkedBuf) -> bool {
+        if buf.len() < self.serialization_buf_len() {
+            crate::log::error!(
+                "failed to serialize: buffer space not enough. Need {}, actual {}",
+                self.serialization_buf_len(),
+                buf.len()
+            );
+            return false;
+        }
+
+        let head_len = self.head_len();
+        match buf.bytes_at(0, head_len) {
+            Some(head) => {
+                self.serialize_head(&head);
+            }
+            None => {
+                // the head of a process with lots of VMAs may span multiple chunks
+                let mut staging: Vec<u8, VmallocAllocator> =
+                    Vec::with_capacity_in(head_len, VmallocAllocator);
+                self.serialize_head(&unsafe { BytesMut::from_raw(staging.as_mut_ptr(), head_len) });
+                unsafe { buf.write_at(0, core::slice::from_raw_parts(staging.as_ptr(), head_len)) };
+            }
+        }
+
+        // 7. the jobs of (vma index, entries [start, end), offset of the page table section)
+        let mut jobs = Vec::new();
+        let mut off = head_len;
+        for (i, vma_pg_table) in self.page_table.iter().enumerate() {
+            let mut start = 0;
+            loop {
+                let end = core::cmp::min(
+                    start + ENTRIES_PER_SERIALIZE_JOB,
+                    vma_pg_table.table_len(),
+                );
+                jobs.push((i, start, end, off));
+                if end >= vma_pg_table.table_len() {
+                    break;
+                }
+                start = end;
+            }
+            off += vma_pg_table.serialization_buf_len();
+        }
+
+        // the jobs write disjoint parts of the buffer
+        unsafe {
+            crate::get_prepare_worker_ref().run(jobs.len(), core::ptr::null_mut(), &|j| {
+                let (i, start, end, off) = jobs[j];
+                let table = &self.page_table[i];
+                match buf.bytes_at(off, table.serialization_buf_len()) {
+                    Some(section) => table.serialize_entries(&section, start, end),
+                    None => table.serialize_entries_w(start, end, |o, bytes| {
+                        buf.write_at(off + o, bytes)
+                    }),
+                }
+            })
+        };
+
+        true
+    }
+
+    #[inline]
+    fn vma_pg_table_serialization_buf_len(&self) -> usize {
+        // note that each vma offset-page-table may have different entry length !
+        self.page_table
+            .iter()
+            .map(|vma_pg_table| vma_pg_table.serialization_buf_len())
+            .sum()
+    }
+}
+
+impl os_network::serialize::Serialize for ParentDescriptor {
+    /// Serialization format:
+    /// ```
+    /// | the format version <-8 bytes->, see `DESCRIPTOR_FORMAT_VERSION`
+    /// | RegDescriptor <-sizeof(RegDescriptor)->
+    /// | MMLayoutDescriptor <-sizeof(MMLayoutDescriptor)->
+    /// | the number of VMAs <-8 bytes-> | VMA descriptor * the number of VMAs
+    /// | the length of the page table of each VMA in bytes <-8 bytes-> * the number of VMAs
+    /// | the number of file paths <-8 bytes-> | FilePath
+    /// | the number of excluded ranges <-8 bytes-> | (start, end) <-16 bytes-> * the number
+    /// | RDMADescriptor |
+    /// | VMAPageMap of each VMA |
+    /// ```
+    /// All but the VMAPageMaps form the head section, see `head_len`.
+    fn serialize(&self, bytes: &mut BytesMut) -> bool {
+        if bytes.len() < self.serialization_buf_len() {
+            crate::log::error!(
+                "failed to serialize: buffer space not enough. Need {}, actual {}",
+                self.serialization_buf_len(),
+                bytes.len()
+            );
+            return false;
+        }
+        let mut cur = self.serialize_head(bytes);
 
+        // 7. page table (vec)
+        for vma_pg_table in self.page_table.iter() {
             vma_pg_table.serialize(&mut cur);
             cur = unsafe {
                 cur.truncate_header(vma_pg_table.serialization_buf_len())
@@ -231,9 +461,6 @@ impl os_network::serialize::Serialize for ParentDescriptor {
             };
         }
 
-        // 3. finally, machine info
-        self.machine_info.serialize(&mut cur);
-
         true
     }
 
@@ -241,46 +468,120 @@ impl os_network::serialize::Serialize for ParentDescriptor {
     /// - Currently, we don't check the buf len, so this function is **unsafe**
     fn deserialize(bytes: &BytesMut) -> core::option::Option<Self> {
         let mut cur = unsafe { bytes.truncate_header(0).unwrap() };
+        check_format_version(&mut cur)?;
+
         // regs
         let regs = RegDescriptor::deserialize(&cur)?;
         cur = unsafe { cur.truncate_header(regs.serialization_buf_len())? };
 
-        // VMA page counts
-        let mut count: usize = 0;
-        let off = unsafe { cur.memcpy_deserialize(&mut count)? };
-        cur = unsafe { cur.truncate_header(off)? };
+        // mm layout
+        let layout = MMLayoutDescriptor::deserialize(&cur)?;
+        cur = unsafe { cur.truncate_header(layout.serialization_buf_len())? };
 
-        // VMA & its corresponding page table
-        let mut pt = Vec::new_in(VmallocAllocator);
-        let mut vmas = Vec::new();
+        // VMAs & the lengths of their page tables
+        let vmas = deserialize_vmas(&mut cur)?;
 
-        for _ in 0..count {
-            let vma = VMADescriptor::deserialize(&cur)?;
-            cur = unsafe { cur.truncate_header(vma.serialization_buf_len())? };
-            vmas.push(vma);
+        let files = deserialize_file_paths(&mut cur)?;
+        let excluded = deserialize_excluded(&mut cur)?;
+        let machine_info = RDMADescriptor::deserialize(&cur)?;
+        cur = unsafe { cur.truncate_header(machine_info.serialization_buf_len())? };
 
+        // the page table of each VMA
+        let mut pt = Vec::new_in(VmallocAllocator);
+        for _ in 0..vmas.len() {
             let vma_pg_table = CompactPageTable::deserialize(&cur)?;
             cur = unsafe { cur.truncate_header(vma_pg_table.serialization_buf_len())? };
             pt.push(vma_pg_table);
         }
 
-        let machine_info = RDMADescriptor::deserialize(&cur)?;
-
         Some(Self {
             regs,
+            layout,
             page_table: pt,
-            vma: vmas,
+            vma: vmas.into_iter().map(|(vma, _)| vma).collect(),
+            files,
+            excluded,
             machine_info,
         })
     }
 
     fn serialization_buf_len(&self) -> usize {
-        self.regs.serialization_buf_len()
-            + self.vma_pg_table_serialization_buf_len()
-            + core::mem::size_of::<usize>() // the number of VMA descriptors
-            + self.vma.len() * core::mem::size_of::<VMADescriptor>()
-            + self.machine_info.serialization_buf_len()
+        self.head_len() + self.vma_pg_table_serialization_buf_len()
+    }
+}
+
+/// Check the format version of a serialized descriptor and advance the cursor,
+/// shared by the parent and child descriptors
+pub(crate) fn check_format_version(cur: &mut BytesMut) -> core::option::Option<()> {
+    let mut version: u64 = 0;
+    let off = unsafe { cur.memcpy_deserialize(&mut version)? };
+    if version != DESCRIPTOR_FORMAT_VERSION {
+        crate::log::error!(
+            "unsupported descriptor format version {}, expected {}",
+            version,
+            DESCRIPTOR_FORMAT_VERSION
+        );
+        return None;
+    }
+    *cur = unsafe { cur.truncate_header(off)? };
+    Some(())
+}
+
+/// Deserialize the VMA descriptors with the lengths of their page tables,
+/// and advance the cursor, shared by the parent and child descriptors
+pub(crate) fn deserialize_vmas(
+    cur: &mut BytesMut,
+) -> core::option::Option<Vec<(VMADescriptor, usize)>> {
+    let mut count: usize = 0;
+    let off = unsafe { cur.memcpy_deserialize(&mut count)? };
+    *cur = unsafe { cur.truncate_header(off)? };
+
+    let mut vmas = Vec::with_capacity(count);
+    for _ in 0..count {
+        let vma = VMADescriptor::deserialize(cur)?;
+        *cur = unsafe { cur.truncate_header(vma.serialization_buf_len())? };
+        vmas.push((vma, 0));
+    }
+    for (_, len) in vmas.iter_mut() {
+        let off = unsafe { cur.memcpy_deserialize(len)? };
+        *cur = unsafe { cur.truncate_header(off)? };
+    }
+    Some(vmas)
+}
+
+/// Deserialize the file paths and advance the cursor,
+/// shared by the parent and child descriptors
+pub(crate) fn deserialize_file_paths(cur: &mut BytesMut) -> core::option::Option<Vec<FilePath>> {
+    let mut count: usize = 0;
+    let off = unsafe { cur.memcpy_deserialize(&mut count)? };
+    *cur = unsafe { cur.truncate_header(off)? };
+
+    let mut files = Vec::with_capacity(count);
+    for _ in 0..count {
+        let f = FilePath::deserialize(cur)?;
+        *cur = unsafe { cur.truncate_header(f.serialization_buf_len())? };
+        files.push(f);
+    }
+    Some(files)
+}
+
+/// Deserialize the excluded ranges and advance the cursor,
+/// shared by the parent and child descriptors
+pub(crate) fn deserialize_excluded(
+    cur: &mut BytesMut,
+) -> core::option::Option<Vec<(VirtAddrType, VirtAddrType)>> {
+    let mut count: usize = 0;
+    let off = unsafe { cur.memcpy_deserialize(&mut count)? };
+    *cur = unsafe { cur.truncate_header(off)? };
+
+    let mut excluded = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut range: (VirtAddrType, VirtAddrType) = (0, 0);
+        let off = unsafe { cur.memcpy_deserialize(&mut range)? };
+        *cur = unsafe { cur.truncate_header(off)? };
+        excluded.push(range);
     }
+    Some(excluded)
 }
 
 impl Default for CompactPageTable {
@@ -301,4 +602,37 @@ impl CompactPageTable {
     pub fn table_len(&self) -> usize {
         self.inner_pg_table.len()
     }
+
+    /// Reserve the room for `n` more entries, so that adding them does not allocate
+    #[inline]
+    pub fn reserve(&mut self, n: usize) {
+        self.inner_pg_table.reserve(n);
+    }
+
+    /// Append the entries of `other`, whose offsets are larger than the ones in the table
+    #[inline]
+    pub fn append(&mut self, mut other: Self) {
+        self.inner_pg_table.append(&mut other.inner_pg_table);
+    }
+
+    /// The entry recorded at `offset` of the VMA
+    #[inline]
+    pub fn lookup(&self, offset: Offset) -> core::option::Option<Value> {
+        self.inner_pg_table
+            .binary_search_by_key(&offset, |(o, _)| *o)
+            .ok()
+            .map(|i| self.inner_pg_table[i].1)
+    }
+
+    /// The entries sorted by the offset, as they are added by the page table walk
+    #[inline(always)]
+    pub(crate) fn entries(&self) -> &[PageEntry] {
+        &self.inner_pg_table
+    }
+
+    /// The entries to re-encode in place, e.g., by the compressed store
+    #[inline(always)]
+    pub(crate) fn entries_mut(&mut self) -> &mut [PageEntry] {
+        &mut self.inner_pg_table
+    }
 }
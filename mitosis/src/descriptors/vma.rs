@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 use crate::bindings::VMFlags;
 use crate::kern_wrappers::mm::VirtAddrType;
 
@@ -12,8 +14,153 @@ pub struct VMADescriptor {
     pub flags: crate::bindings::vm_flags_t,
     pub prot: crate::bindings::pgprot_t,
     pub is_anonymous: bool,
+    // overlaps a hot region of the prepare, so the child fetches it eagerly
+    pub is_hot: bool,
+    // overlaps an excluded region of the prepare,
+    // so the child reads its untransferred pages as zero
+    pub has_excluded: bool,
+    // whether any page of the VMA is recorded in the page table,
+    // the child handles the faults of the VMA only if so
+    pub has_pages: bool,
+    // whether the VMA has private (i.e., COW-ed) pages, which its backing file does not have
+    pub has_anon_pages: bool,
+    pub kind: VMAKind,
+    // the index of the backing file in the descriptor's file paths, `NO_FILE` if none
+    pub file_idx: u32,
+    // the offset (in pages) of the VMA in the backing file
+    pub pgoff: u64,
+}
+
+pub const NO_FILE: u32 = u32::MAX;
+
+/// The max length of the path of a backing file, longer paths are not recorded
+pub const VMA_PATH_LEN: usize = 256;
+
+/// What the VMA maps in the parent, so that the child shows the same regions
+/// (e.g., in `/proc/<pid>/maps`) as the parent
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VMAKind {
+    Anonymous = 0,
+    Heap = 1,
+    Stack = 2,
+    // backed by a (regular) file
+    File = 3,
+    // mapped by the kernel, e.g., the ones of the drivers
+    Special = 4,
+    // the vdso and vvar are host-specific, the child keeps its own
+    Vdso = 5,
+    Vvar = 6,
+}
+
+impl VMAKind {
+    /// Whether the VMA cannot be transferred from the parent, i.e., [vdso] and [vvar].
+    /// Note that [vsyscall] is a gate area outside of the VMA list, which is the same at every host.
+    #[inline]
+    pub fn is_host_specific(&self) -> bool {
+        matches!(self, VMAKind::Vdso | VMAKind::Vvar)
+    }
+}
+
+impl Default for VMAKind {
+    fn default() -> Self {
+        VMAKind::Anonymous
+    }
+}
+
+/// The path of a file backing the VMAs
+#[derive(Copy, Clone)]
+pub struct FilePath {
+    len: u32,
+    path: [u8; VMA_PATH_LEN],
+    // the identity of the content of the file at the parent
+    identity: FileIdentity,
+}
+
+/// The size and the last modification time of a file, so that the child maps the file
+/// at its machine only if it has the same content as the one of the parent
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct FileIdentity {
+    size: i64,
+    mtime_sec: i64,
+    mtime_nsec: i64,
 }
 
+impl FileIdentity {
+    pub fn new(file: *mut crate::bindings::file) -> Self {
+        let mut res = Self::default();
+        unsafe {
+            crate::bindings::pmem_get_file_identity(
+                file,
+                &mut res.size as *mut i64 as *mut _,
+                &mut res.mtime_sec as *mut i64 as *mut _,
+                &mut res.mtime_nsec as *mut i64 as *mut _,
+            )
+        };
+        res
+    }
+}
+
+impl FilePath {
+    /// Record the path of the file, return None if the path is too long
+    pub fn new(file: *mut crate::bindings::file) -> core::option::Option<Self> {
+        let mut res = Self {
+            len: 0,
+            path: [0; VMA_PATH_LEN],
+            identity: FileIdentity::new(file),
+        };
+        let len = unsafe {
+            crate::bindings::pmem_get_file_path(
+                file,
+                res.path.as_mut_ptr() as *mut _,
+                VMA_PATH_LEN as _,
+            )
+        };
+        if len < 0 {
+            crate::log::debug!("the path of the backing file is not recorded, error {}", len);
+            return None;
+        }
+        res.len = len as _;
+        Some(res)
+    }
+
+    /// Whether the file opened at this machine has the same content as the one of the parent
+    #[inline]
+    pub fn is_identical(&self, file: *mut crate::bindings::file) -> bool {
+        FileIdentity::new(file) == self.identity
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.path[..self.len as usize]
+    }
+
+    /// The NUL-terminated path
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.path.as_ptr()
+    }
+
+    /// Add the path into `paths` if absent, return its index
+    pub fn intern(self, paths: &mut Vec<FilePath>) -> u32 {
+        match paths.iter().position(|p| p.as_bytes() == self.as_bytes()) {
+            Some(idx) => idx as _,
+            None => {
+                paths.push(self);
+                (paths.len() - 1) as _
+            }
+        }
+    }
+}
+
+impl core::fmt::Debug for FilePath {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.write_str(core::str::from_utf8(self.as_bytes()).unwrap_or("<non-utf8 path>"))
+    }
+}
+
+impl os_network::serialize::Serialize for FilePath {}
+
 impl VMADescriptor {
     #[inline]
     pub fn is_stack(&self) -> bool {
@@ -35,6 +182,47 @@ impl VMADescriptor {
         self.is_anonymous
     }
 
+    #[inline]
+    pub fn has_pages(&self) -> bool {
+        self.has_pages
+    }
+
+    #[inline]
+    pub fn has_anon_pages(&self) -> bool {
+        self.has_anon_pages
+    }
+
+    #[inline]
+    pub fn get_kind(&self) -> VMAKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn is_host_specific(&self) -> bool {
+        self.kind.is_host_specific()
+    }
+
+    /// The index of the backing file in the descriptor's file paths
+    #[inline]
+    pub fn get_file_idx(&self) -> core::option::Option<usize> {
+        (self.file_idx != NO_FILE).then(|| self.file_idx as usize)
+    }
+
+    #[inline]
+    pub fn get_pgoff(&self) -> u64 {
+        self.pgoff
+    }
+
+    #[inline]
+    pub fn is_hot(&self) -> bool {
+        self.is_hot
+    }
+
+    #[inline]
+    pub fn has_excluded(&self) -> bool {
+        self.has_excluded
+    }
+
     #[inline]
     pub fn get_sz(&self) -> u64 {
         self.range.1 - self.range.0
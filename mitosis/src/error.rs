@@ -0,0 +1,95 @@
+use crate::linux_kernel_module::c_types::c_long;
+
+use mitosis_protocol::errno::*;
+
+/// Errors of the MITOSIS system calls.
+/// Each error is reported to the user as a distinct errno, see `mitosis_protocol::errno`.
+#[derive(thiserror_no_std::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MitosisError {
+    #[error("the process is not found")]
+    NotFound,
+
+    #[error("the remote machine is not connected")]
+    NotConnected,
+
+    #[error("the remote machine is unreachable")]
+    Unreachable,
+
+    #[error("the remote machine does not reply in time")]
+    Timeout,
+
+    #[error("a prepare or resume is already active")]
+    AlreadyActive,
+
+    #[error("the asynchronous resume is still in progress")]
+    InProgress,
+
+    #[error("the key has been prepared")]
+    AlreadyExists,
+
+    #[error("out of memory or RDMA resources")]
+    OutOfMemory,
+
+    #[error("malformed message from the remote machine")]
+    Protocol,
+
+    #[error("the remote machine refused the connection")]
+    ConnectionRefused,
+
+    #[error("the machine ID has been occupied")]
+    MachineIdConflict,
+
+    #[error("invalid argument")]
+    InvalidArgument,
+
+    #[error("failed to copy from the user")]
+    BadAddress,
+
+    #[error("permission denied")]
+    PermissionDenied,
+
+    #[error("the command is not supported")]
+    Unsupported,
+
+    #[error("unknown command")]
+    UnknownCommand,
+
+    #[error("internal error")]
+    Internal,
+}
+
+pub type MitosisResult<T> = core::result::Result<T, MitosisError>;
+
+impl MitosisError {
+    pub fn errno(&self) -> Errno {
+        match self {
+            Self::NotFound => ENOENT,
+            Self::NotConnected => ENOTCONN,
+            Self::Unreachable => EHOSTUNREACH,
+            Self::Timeout => ETIMEDOUT,
+            Self::AlreadyActive => EALREADY,
+            Self::InProgress => EAGAIN,
+            Self::AlreadyExists => EEXIST,
+            Self::OutOfMemory => ENOMEM,
+            Self::Protocol => EPROTO,
+            Self::ConnectionRefused => ECONNREFUSED,
+            Self::MachineIdConflict => EADDRINUSE,
+            Self::InvalidArgument => EINVAL,
+            Self::BadAddress => EFAULT,
+            Self::PermissionDenied => EACCES,
+            Self::Unsupported => EOPNOTSUPP,
+            Self::UnknownCommand => ENOTTY,
+            Self::Internal => EIO,
+        }
+    }
+}
+
+/// Convert the result of a system call to the return value of the ioctl,
+/// i.e., `-errno` on failures
+#[inline]
+pub fn to_ioctl_ret(res: MitosisResult<c_long>) -> c_long {
+    match res {
+        Ok(ret) => ret,
+        Err(e) => -(e.errno() as c_long),
+    }
+}
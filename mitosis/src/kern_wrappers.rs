@@ -3,6 +3,8 @@
 /// * mm_struct - abstracted in mm::MemoryDescriptor 
 /// * task_struct - abstracted in task::Task
 /// * vma_struct - abstracted in vma::VMA
+/// * eventfd_ctx - abstracted in eventfd::EventFd
+/// * wait_queue_head_t - abstracted in wait_queue::WaitQueue
 /// 
 /// vma_iters module also includes useful code for iterating pages belonging to a VMA
 pub mod mm;
@@ -10,6 +12,8 @@ pub mod task;
 pub mod vma;
 pub mod vma_iters;
 pub mod page;
+pub mod eventfd;
+pub mod wait_queue;
 
 pub use page::{Page, copy_page_content_4k};
 
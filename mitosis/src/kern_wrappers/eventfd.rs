@@ -0,0 +1,35 @@
+use crate::bindings::{
+    eventfd_ctx, pmem_eventfd_ctx_fdget, pmem_eventfd_ctx_put, pmem_eventfd_signal,
+};
+use crate::linux_kernel_module::c_types::c_int;
+
+/// A reference to the user's eventfd, which can be signaled from any kernel thread
+pub struct EventFd {
+    ctx: *mut eventfd_ctx,
+}
+
+impl EventFd {
+    /// Get the eventfd from the `fd` of the current process
+    pub fn from_fd(fd: c_int) -> Option<Self> {
+        let ctx = unsafe { pmem_eventfd_ctx_fdget(fd) };
+        if ctx.is_null() {
+            return None;
+        }
+        Some(Self { ctx })
+    }
+
+    /// Add 1 to the counter of the eventfd, which wakes up its pollers
+    #[inline]
+    pub fn signal(&self) {
+        unsafe { pmem_eventfd_signal(self.ctx) };
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe { pmem_eventfd_ctx_put(self.ctx) };
+    }
+}
+
+unsafe impl Send for EventFd {}
+unsafe impl Sync for EventFd {}
@@ -17,6 +17,7 @@ pub struct MemoryDescriptor {
 pub mod mmap_flags {
     pub const MAP_SHARED: crate::linux_kernel_module::c_types::c_ulong = 0x01;
     pub const MAP_PRIVATE: crate::linux_kernel_module::c_types::c_ulong = 0x02;
+    pub const MAP_ANONYMOUS: crate::linux_kernel_module::c_types::c_ulong = 0x20;
 }
 
 #[allow(dead_code)]
@@ -27,6 +28,15 @@ impl MemoryDescriptor {
         }
     }
 
+    pub fn get_raw_ptr(&self) -> *mut mm_struct {
+        self.mm_inner as *const _ as *mut _
+    }
+
+    /// The end of the user address space
+    pub fn get_task_size(&self) -> VirtAddrType {
+        self.mm_inner.task_size
+    }
+
     pub fn get_vma_iter(&self) -> VMAIter {
         VMAIter::new(self)
     }
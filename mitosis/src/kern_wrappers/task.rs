@@ -1,4 +1,4 @@
-use super::mm::MemoryDescriptor;
+use super::mm::{MemoryDescriptor, VirtAddrType};
 pub(crate) use crate::bindings::{pmem_get_current_task, task_struct};
 
 #[allow(unused_imports)]
@@ -18,17 +18,237 @@ impl Task {
 }
 
 use crate::descriptors::*;
+use crate::error::{MitosisError, MitosisResult};
+
+/// The lowest address of the holes for the vdso, i.e., the default `vm.mmap_min_addr`
+const MIN_MMAP_ADDR: VirtAddrType = 0x10000;
+
+/// Where the [vdso] and [vvar] of the task are kept at resume, see `Task::plan_vdso`
+pub struct VdsoPlan {
+    // the (start, len) of the host-specific regions of the task
+    regions: alloc::vec::Vec<(VirtAddrType, VirtAddrType)>,
+    // the lowest start of the regions
+    lo: VirtAddrType,
+    vdso: VirtAddrType,
+    // where the lowest start of the regions is moved to, in the order of preference,
+    // all out of the VMAs of the parent
+    targets: alloc::vec::Vec<VirtAddrType>,
+}
 
 impl Task {
-    /// Unmap all of the VMA in the task
+    /// Unmap all of the VMA in the task, except the host-specific ones (i.e., [vdso] and [vvar])
     #[inline]
     pub fn unmap_self(&self) {
         let mut md = self.get_memory_descriptor();
         self.get_memory_descriptor().get_vma_iter().for_each(|m| {
-            md.unmap_region(m.get_start() as _, m.get_sz() as _);
+            if !m.get_kind().is_host_specific() {
+                md.unmap_region(m.get_start() as _, m.get_sz() as _);
+            }
         });
     }
 
+    /// Plan where the [vdso] and [vvar] of the task are kept once the VMAs of the parent
+    /// (`parent_vmas`) are mapped, before the address space is replaced.
+    ///
+    /// They are moved to where the parent maps them if possible,
+    /// so that the pointers into the vdso held by the parent (e.g., cached by the libc) remain valid.
+    /// The task keeps its own (host-specific) contents, so they are moved only if the vdso image
+    /// of this machine is the same as the parent's one (`layout.vdso_hash`, see `pmem_vdso_hash`).
+    /// Otherwise, they stay where they are, or move to a hole of the parent's layout
+    /// if they intersect its VMAs.
+    ///
+    /// Return
+    /// * None if the task has no vdso
+    /// * MitosisError::OutOfMemory if the layout of the parent has no room for them
+    pub fn plan_vdso(
+        &self,
+        parent_vmas: &[VMADescriptor],
+        layout: &MMLayoutDescriptor,
+    ) -> MitosisResult<core::option::Option<VdsoPlan>> {
+        let mine: alloc::vec::Vec<(VMAKind, VirtAddrType, VirtAddrType)> = self
+            .get_memory_descriptor()
+            .get_vma_iter()
+            .map(|m| (m.get_kind(), m.get_start(), m.get_end()))
+            .filter(|(k, _, _)| k.is_host_specific())
+            .collect();
+        let my_vdso = match mine.iter().find(|(k, _, _)| *k == VMAKind::Vdso) {
+            Some(v) => *v,
+            None => return Ok(None),
+        };
+        let lo = mine.iter().map(|(_, s, _)| *s).min().unwrap();
+        let hi = mine.iter().map(|(_, _, e)| *e).max().unwrap();
+
+        let occupied = Self::layout_ranges(parent_vmas, layout);
+        let is_free = |start: VirtAddrType| {
+            occupied.iter().all(|(s, e)| start + (hi - lo) <= *s || *e <= start)
+        };
+
+        let mut targets = alloc::vec::Vec::new();
+        if let Some(parent_vdso) = parent_vmas.iter().find(|m| m.get_kind() == VMAKind::Vdso) {
+            // the vvar is addressed relative to the vdso, so they must be moved together
+            let find_parent = |kind| parent_vmas.iter().find(|m| m.get_kind() == kind);
+            let same_layout = mine.iter().all(|(k, s, e)| match find_parent(*k) {
+                Some(p) => {
+                    p.get_sz() == e - s
+                        && p.get_start().wrapping_sub(parent_vdso.get_start())
+                            == s.wrapping_sub(my_vdso.1)
+                }
+                None => false,
+            });
+            let my_hash = unsafe { crate::bindings::pmem_vdso_hash() };
+            if same_layout && my_hash != 0 && my_hash == layout.vdso_hash {
+                targets.push(lo.wrapping_add(parent_vdso.get_start().wrapping_sub(my_vdso.1)));
+            } else {
+                crate::log::warn!("the vdso of the parent mismatches the one of this machine");
+            }
+        }
+        targets.push(lo);
+        let task_size = self.get_memory_descriptor().get_task_size();
+        if let Some(hole) = Self::find_hole(&occupied, hi - lo, task_size) {
+            targets.push(hole);
+        }
+        targets.retain(|t| is_free(*t));
+        targets.dedup();
+        if targets.is_empty() {
+            crate::log::error!("no room for the vdso ({} bytes) in the parent's layout", hi - lo);
+            return Err(MitosisError::OutOfMemory);
+        }
+
+        Ok(Some(VdsoPlan {
+            regions: mine.iter().map(|(_, s, e)| (*s, e - s)).collect(),
+            lo,
+            vdso: my_vdso.1,
+            targets,
+        }))
+    }
+
+    /// Move the [vdso] and [vvar] of the task as planned by `plan_vdso`,
+    /// once the VMAs of the task are unmapped
+    ///
+    /// Return
+    /// * the address of the task's vdso afterwards,
+    ///   which differs from the parent's one if the vdsos of the two hosts mismatch
+    /// * MitosisError::Internal if they are left in the way of the VMAs of the parent
+    pub fn keep_vdso(&self, plan: &VdsoPlan) -> MitosisResult<VirtAddrType> {
+        for target in plan.targets.iter() {
+            let delta = target.wrapping_sub(plan.lo);
+            if delta == 0 || unsafe { self.move_regions(&plan.regions, delta) } {
+                return Ok(plan.vdso.wrapping_add(delta));
+            }
+            // moved back to where they were
+            crate::log::warn!("failed to move the vdso by {:x}", delta);
+        }
+        crate::log::error!("the vdso at {:x} intersects the VMAs of the parent", plan.vdso);
+        Err(MitosisError::Internal)
+    }
+
+    /// The ranges occupied by the VMAs of the parent once mapped by `map_one_region`,
+    /// except the host-specific ones
+    fn layout_ranges(
+        parent_vmas: &[VMADescriptor],
+        layout: &MMLayoutDescriptor,
+    ) -> alloc::vec::Vec<(VirtAddrType, VirtAddrType)> {
+        parent_vmas
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| !m.is_host_specific())
+            .map(|(i, m)| {
+                let sz = Self::mapped_sz(m, parent_vmas.get(i + 1), layout);
+                (m.get_start(), m.get_start() + sz)
+            })
+            .collect()
+    }
+
+    /// Find the highest hole of `len` bytes below `task_size` out of the (sorted) `occupied` ranges
+    fn find_hole(
+        occupied: &[(VirtAddrType, VirtAddrType)],
+        len: VirtAddrType,
+        task_size: VirtAddrType,
+    ) -> core::option::Option<VirtAddrType> {
+        let mut top = task_size;
+        for (s, e) in occupied.iter().rev() {
+            if *e <= top && top - e >= len {
+                return Some(top - len);
+            }
+            top = core::cmp::min(top, *s);
+        }
+        (top >= MIN_MMAP_ADDR + len).then(|| top - len)
+    }
+
+    /// Move the (start, len) regions by `delta` as a whole,
+    /// through a free area if the source and the destination overlap.
+    /// The regions are moved back if any of them cannot be moved, so they are never split.
+    unsafe fn move_regions(
+        &self,
+        regions: &[(VirtAddrType, VirtAddrType)],
+        delta: VirtAddrType,
+    ) -> bool {
+        use crate::bindings::{pmem_mremap, pmem_vm_mmap, PMEM_MREMAP_FIXED, PMEM_MREMAP_MAYMOVE};
+        use crate::kern_wrappers::mm::mmap_flags::{MAP_ANONYMOUS, MAP_PRIVATE};
+
+        let overlaps =
+            |a: VirtAddrType, b: VirtAddrType, len: VirtAddrType| a < b + len && b < a + len;
+        let lo = regions.iter().map(|(s, _)| *s).min().unwrap_or(0);
+        let hi = regions.iter().map(|(s, l)| s + l).max().unwrap_or(0);
+
+        let mremap = |src: VirtAddrType, len: VirtAddrType, dst: VirtAddrType| {
+            pmem_mremap(src, len, len, (PMEM_MREMAP_MAYMOVE | PMEM_MREMAP_FIXED) as _, dst)
+                as VirtAddrType
+                == dst
+        };
+        let move_all = |from: VirtAddrType, delta: VirtAddrType| {
+            let moved = regions
+                .iter()
+                .take_while(|(s, l)| {
+                    let src = from + (s - lo);
+                    mremap(src, *l, src.wrapping_add(delta))
+                })
+                .count();
+            if moved == regions.len() {
+                return true;
+            }
+            for (s, l) in &regions[..moved] {
+                let src = from + (s - lo);
+                if !mremap(src.wrapping_add(delta), *l, src) {
+                    crate::log::error!("failed to move back the region at {:x}", src);
+                }
+            }
+            false
+        };
+
+        let target = lo.wrapping_add(delta);
+        if !overlaps(lo, target, hi - lo) {
+            return move_all(lo, delta);
+        }
+
+        // reserve a free area for the intermediate move
+        let tmp = pmem_vm_mmap(
+            core::ptr::null_mut(),
+            0,
+            hi - lo,
+            0,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            0,
+        );
+        if tmp >= (-4095i64) as VirtAddrType {
+            return false;
+        }
+        if overlaps(tmp, target, hi - lo) {
+            self.get_memory_descriptor().unmap_region(tmp, (hi - lo) as _);
+            return false;
+        }
+        if !move_all(lo, tmp.wrapping_sub(lo)) {
+            self.get_memory_descriptor().unmap_region(tmp, (hi - lo) as _);
+            return false;
+        }
+        if !move_all(tmp, target.wrapping_sub(tmp)) {
+            // the regions have not left the free area, which they return from
+            move_all(tmp, lo.wrapping_sub(tmp));
+            return false;
+        }
+        true
+    }
+
     /// Map one region into current task
     #[inline]
     pub unsafe fn map_one_region(
@@ -36,41 +256,39 @@ impl Task {
         file: *mut crate::bindings::file,
         vma_meta: &VMADescriptor,
         next_vma: core::option::Option<&VMADescriptor>,
+        layout: &MMLayoutDescriptor,
+        backing: Option<&FilePath>,
     ) -> Option<&'static mut crate::bindings::vm_area_struct> {
         use crate::bindings::{pmem_vm_mmap, VMFlags};
 
         let ret = {
-            // we need to extend the VMA of heap & stack to avoid corrupting
-            let mut extended_map_area_sz = 1024 * 1024 * 1024; // 1GB
-            // let mut extended_map_area_sz = 0;
-            if next_vma.is_some() {
-                assert!(next_vma.unwrap().get_start() >= vma_meta.get_end());
-                extended_map_area_sz = core::cmp::min(
-                    extended_map_area_sz,
-                    next_vma.unwrap().get_start() - vma_meta.get_end(),
-                );
-            } else { 
-                extended_map_area_sz = 0;
-            }
-
             if vma_meta.is_anonymous() {
+                // not backed by the device file, so the kernel names them properly,
+                // e.g., [heap] and [stack] with the restored mm layout
                 pmem_vm_mmap(
-                    file,
+                    core::ptr::null_mut(),
                     vma_meta.get_start(),
-                    vma_meta.get_sz() + extended_map_area_sz,
+                    Self::mapped_sz(vma_meta, next_vma, layout),
                     vma_meta.get_mmap_flags(),
-                    crate::kern_wrappers::mm::mmap_flags::MAP_PRIVATE,
+                    crate::kern_wrappers::mm::mmap_flags::MAP_PRIVATE
+                        | crate::kern_wrappers::mm::mmap_flags::MAP_ANONYMOUS,
                     0,
                 )
             } else {
-                pmem_vm_mmap(
-                    file,
-                    vma_meta.get_start(),
-                    vma_meta.get_sz(),
-                    vma_meta.get_mmap_flags(),
-                    crate::kern_wrappers::mm::mmap_flags::MAP_PRIVATE,
-                    0,
-                )
+                let backed = Self::map_backing_file(vma_meta, backing);
+                if backed == vma_meta.get_start() {
+                    backed
+                } else {
+                    // the special mappings, or the file is absent at this machine
+                    pmem_vm_mmap(
+                        file,
+                        vma_meta.get_start(),
+                        vma_meta.get_sz(),
+                        vma_meta.get_mmap_flags(),
+                        crate::kern_wrappers::mm::mmap_flags::MAP_PRIVATE,
+                        0,
+                    )
+                }
             }
         };
         if ret != vma_meta.get_start() {
@@ -80,21 +298,96 @@ impl Task {
             .get_memory_descriptor()
             .find_vma(vma_meta.get_start())
             .unwrap();
+        // MITOSIS handles the faults of the anonymous VMAs only if the parent has pages in them.
+        // The ones mapped with a backing file keep the operations of its file system, unless
+        // the parent has written their private pages, which are faulted from the parent,
+        // while the others are faulted from the file
+        let backed = !vma.vm_file.is_null() && vma.vm_file != file;
+        let anonymous = vma.vm_file.is_null() && vma.vm_ops.is_null();
+        if (anonymous && vma_meta.has_pages()) || (backed && vma_meta.has_anon_pages()) {
+            let mmap = (*(*file).f_op).mmap.unwrap();
+            if mmap(file, vma as *mut _) != 0 {
+                return None;
+            }
+        }
         if vma_meta.is_stack() {
             vma.vm_flags = (VMFlags::from_bits_unchecked(vma.vm_flags) | VMFlags::STACK).bits();
         } else {
             vma.vm_flags =
                 (VMFlags::from_bits_unchecked(vma.vm_flags) | VMFlags::DONTEXPAND).bits();
         }
-        #[cfg(feature = "eager-resume")]
-        {
-            vma.vm_flags = (crate::bindings::VMFlags::from_bits_unchecked(vma.vm_flags)
-                | crate::bindings::VMFlags::MIXEDMAP)
-                .bits();
-        }
         return Some(vma);
     }
 
+    /// The length mapped for the VMA by `map_one_region`.
+    /// We need to extend the anonymous VMAs to avoid corrupting,
+    /// except the heap, which grows through brk since its layout is restored.
+    fn mapped_sz(
+        vma_meta: &VMADescriptor,
+        next_vma: core::option::Option<&VMADescriptor>,
+        layout: &MMLayoutDescriptor,
+    ) -> VirtAddrType {
+        let extended_map_area_sz = 1024 * 1024 * 1024; // 1GB
+        if !vma_meta.is_anonymous() || layout.is_heap(vma_meta) {
+            return vma_meta.get_sz();
+        }
+        match next_vma {
+            Some(next) => {
+                assert!(next.get_start() >= vma_meta.get_end());
+                vma_meta.get_sz()
+                    + core::cmp::min(extended_map_area_sz, next.get_start() - vma_meta.get_end())
+            }
+            None => vma_meta.get_sz(),
+        }
+    }
+
+    /// Map the VMA with its backing file at this machine (if any),
+    /// return the mapped address, or 0 if it cannot be mapped.
+    ///
+    /// The VMA falls back to the remote paging (i.e., the device file) if the file has
+    /// a different content, or it is a writable shared mapping, whose updates are not shared
+    /// across the machines. The private pages written by the parent are not in the file,
+    /// so the pages recorded by the parent are still faulted from it, see `map_one_region`.
+    unsafe fn map_backing_file(
+        vma_meta: &VMADescriptor,
+        backing: Option<&FilePath>,
+    ) -> crate::linux_kernel_module::c_types::c_ulong {
+        use crate::bindings::{pmem_open_file, pmem_put_file, pmem_vm_mmap, VMFlags};
+
+        let path = match (vma_meta.get_kind(), backing) {
+            (VMAKind::File, Some(path)) => path,
+            _ => return 0,
+        };
+        if vma_meta.get_flags().contains(VMFlags::SHARED | VMFlags::MAY_WRITE) {
+            crate::log::warn!(
+                "the writable shared mapping of {:?} is not shared with the parent",
+                path
+            );
+            return 0;
+        }
+        let f = pmem_open_file(path.as_ptr() as *const _);
+        if f.is_null() {
+            crate::log::debug!("failed to open the backing file {:?}", path);
+            return 0;
+        }
+        if !path.is_identical(f) {
+            crate::log::debug!("the backing file {:?} differs from the parent's", path);
+            pmem_put_file(f);
+            return 0;
+        }
+        let ret = pmem_vm_mmap(
+            f,
+            vma_meta.get_start(),
+            vma_meta.get_sz(),
+            vma_meta.get_mmap_flags(),
+            crate::kern_wrappers::mm::mmap_flags::MAP_PRIVATE,
+            vma_meta.get_pgoff() << 12,
+        );
+        // the mapping holds its own reference
+        pmem_put_file(f);
+        ret
+    }
+
     #[inline]
     pub fn set_mm_reg_states(&mut self, regs: &RegDescriptor) {
         self.get_memory_descriptor().flush_tlb_all();
@@ -103,6 +396,49 @@ impl Task {
         self.set_tls_gs(regs.gs);
     }
 
+    /// Restore the layout fields of the mm, must be called after the VMAs are mapped,
+    /// and without the mmap_sem held
+    pub fn set_mm_layout(&mut self, layout: &MMLayoutDescriptor) {
+        unsafe { crate::bindings::pmem_mm_layout_lock(self.task_inner.mm) };
+        let mm = unsafe { &mut *self.task_inner.mm };
+        mm.start_code = layout.start_code;
+        mm.end_code = layout.end_code;
+        mm.start_data = layout.start_data;
+        mm.end_data = layout.end_data;
+        mm.start_brk = layout.start_brk;
+        mm.brk = layout.brk;
+        mm.start_stack = layout.start_stack;
+        mm.arg_start = layout.arg_start;
+        mm.arg_end = layout.arg_end;
+        mm.env_start = layout.env_start;
+        mm.env_end = layout.env_end;
+        let len = core::cmp::min(AUXV_LEN, mm.saved_auxv.len());
+        mm.saved_auxv[..len].copy_from_slice(&layout.saved_auxv[..len]);
+        unsafe { crate::bindings::pmem_mm_layout_unlock(mm) };
+    }
+
+    pub fn generate_layout_descriptor(&self) -> MMLayoutDescriptor {
+        let mm = unsafe { &*self.task_inner.mm };
+        let mut res = MMLayoutDescriptor {
+            start_code: mm.start_code,
+            end_code: mm.end_code,
+            start_data: mm.start_data,
+            end_data: mm.end_data,
+            start_brk: mm.start_brk,
+            brk: mm.brk,
+            start_stack: mm.start_stack,
+            arg_start: mm.arg_start,
+            arg_end: mm.arg_end,
+            env_start: mm.env_start,
+            env_end: mm.env_end,
+            vdso_hash: unsafe { crate::bindings::pmem_vdso_hash() },
+            ..Default::default()
+        };
+        let len = core::cmp::min(AUXV_LEN, mm.saved_auxv.len());
+        res.saved_auxv[..len].copy_from_slice(&mm.saved_auxv[..len]);
+        res
+    }
+
     pub fn generate_reg_descriptor(&self) -> RegDescriptor {
         RegDescriptor {
             others: self.get_stack_registers(),
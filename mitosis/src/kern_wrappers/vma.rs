@@ -14,6 +14,41 @@ impl<'a> VMA<'a> {
             flags: self.get_raw_flags(),
             prot: self.get_prot(),
             is_anonymous: self.is_anonymous(),
+            is_hot: false,
+            has_excluded: false,
+            has_pages: false,
+            has_anon_pages: !self.vma_inner.anon_vma.is_null(),
+            kind: self.get_kind(),
+            file_idx: crate::descriptors::NO_FILE,
+            pgoff: self.vma_inner.vm_pgoff,
+        }
+    }
+
+    /// Generate the descriptor, recording the path of the backing file (if any) in `files`
+    pub fn generate_descriptor_w_files(
+        &self,
+        hints: &crate::shadow_process::PrepareHints,
+        files: &mut alloc::vec::Vec<crate::descriptors::FilePath>,
+    ) -> crate::descriptors::VMADescriptor {
+        let mut res = self.generate_descriptor_w_hints(hints);
+        if res.kind == crate::descriptors::VMAKind::File {
+            if let Some(path) = crate::descriptors::FilePath::new(self.vma_inner.vm_file) {
+                res.file_idx = path.intern(files);
+            }
+        }
+        res
+    }
+
+    /// Generate the descriptor with the hints provided at prepare
+    pub fn generate_descriptor_w_hints(
+        &self,
+        hints: &crate::shadow_process::PrepareHints,
+    ) -> crate::descriptors::VMADescriptor {
+        let (start, end) = self.get_range();
+        crate::descriptors::VMADescriptor {
+            is_hot: hints.overlaps_hot(start, end),
+            has_excluded: hints.overlaps_excluded(start, end),
+            ..self.generate_descriptor()
         }
     }
 
@@ -42,6 +77,35 @@ impl<'a> VMA<'a> {
         self.vma_inner.vm_ops.is_null()
     }
 
+    /// What the VMA maps, following how the kernel names it in `/proc/<pid>/maps`
+    pub fn get_kind(&self) -> crate::descriptors::VMAKind {
+        use crate::descriptors::VMAKind;
+
+        if !self.vma_inner.vm_file.is_null() {
+            return VMAKind::File;
+        }
+        if !self.is_anonymous() {
+            let name = unsafe { crate::bindings::pmem_get_vma_name(self.vma_inner as *const _ as *mut _) };
+            if name.is_null() {
+                return VMAKind::Special;
+            }
+            return match ptr2string(name as *const _).as_str() {
+                "[vdso]" => VMAKind::Vdso,
+                "[vvar]" => VMAKind::Vvar,
+                _ => VMAKind::Special,
+            };
+        }
+        let mm = unsafe { &*self.vma_inner.vm_mm };
+        // the heap ends at the page of brk
+        if self.get_start() >= mm.start_brk && self.get_end() <= (mm.brk + 4095) & !4095 {
+            VMAKind::Heap
+        } else if self.get_start() <= mm.start_stack && self.get_end() >= mm.start_stack {
+            VMAKind::Stack
+        } else {
+            VMAKind::Anonymous
+        }
+    }
+
     /// whether this VMA is a stack
     pub fn is_stack(&self) -> bool {
         self.get_flags().contains(VMFlags::STACK)
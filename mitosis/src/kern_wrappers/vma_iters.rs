@@ -157,6 +157,36 @@ impl VMWalkEngine {
         self.walk_inner(vma, (*vma).vm_start, (*vma).vm_end);
     }
 
+    /// Walk the pages of `vma` in [start, end)
+    #[allow(non_camel_case_types)]
+    pub unsafe fn walk_range(
+        &mut self,
+        vma: *mut vm_area_struct,
+        start: crate::linux_kernel_module::c_types::c_ulong,
+        end: crate::linux_kernel_module::c_types::c_ulong,
+    ) {
+        self.walk_callbacks.vma = vma;
+        self.walk_callbacks.mm = (*vma).vm_mm;
+        self.walk_inner(vma, start, end);
+    }
+
+    /// Same as `walk_range`, except that the PTEs are visited with their PTE table locked,
+    /// so the `pte_entry` must not sleep, e.g., to allocate.
+    /// The `pmd_entry` (if any) is called before each PTE table is locked.
+    /// The caller must hold the mmap_sem of the mm.
+    #[allow(non_camel_case_types)]
+    pub unsafe fn walk_range_locked(
+        &mut self,
+        vma: *mut vm_area_struct,
+        start: crate::linux_kernel_module::c_types::c_ulong,
+        end: crate::linux_kernel_module::c_types::c_ulong,
+    ) {
+        self.walk_callbacks.vma = vma;
+        self.walk_callbacks.mm = (*vma).vm_mm;
+        // FIXME: not handling the error code
+        pmem_call_walk_range_locked(start, end, &mut self.walk_callbacks as *mut _);
+    }
+
     #[allow(non_camel_case_types)]
     unsafe fn walk_inner(
         &mut self,
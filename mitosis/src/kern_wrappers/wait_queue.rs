@@ -0,0 +1,64 @@
+use crate::bindings::{
+    pmem_alloc_wait_queue, pmem_free_wait_queue, pmem_wait_event, pmem_wait_event_interruptible,
+    pmem_wait_event_interruptible_timeout, pmem_wake_up_all, wait_queue_head_t,
+};
+use crate::linux_kernel_module::c_types::{c_int, c_void};
+
+/// A kernel wait queue: the threads sleep on it until their conditions hold,
+/// and are woken up to re-check them by `wake_up_all`
+pub struct WaitQueue {
+    wq: *mut wait_queue_head_t,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            wq: unsafe { pmem_alloc_wait_queue() },
+        }
+    }
+
+    /// Wake up the waiters, must be called after their conditions are changed
+    #[inline]
+    pub fn wake_up_all(&self) {
+        unsafe { pmem_wake_up_all(self.wq) };
+    }
+
+    /// Sleep until `cond` holds, e.g., in the page fault handlers
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut cond: F) {
+        unsafe { pmem_wait_event(self.wq, Some(Self::check::<F>), &mut cond as *mut F as _) };
+    }
+
+    /// Sleep until `cond` holds, or the sleep is interrupted, e.g., by the stop of a kernel thread.
+    /// The kernel threads should check `kthread::should_stop` in `cond`.
+    pub fn wait_interruptible<F: FnMut() -> bool>(&self, mut cond: F) {
+        unsafe {
+            pmem_wait_event_interruptible(self.wq, Some(Self::check::<F>), &mut cond as *mut F as _)
+        };
+    }
+
+    /// Same as `wait_interruptible`, except that the sleep lasts at most `timeout_usec`
+    pub fn wait_interruptible_timeout<F: FnMut() -> bool>(&self, mut cond: F, timeout_usec: u64) {
+        unsafe {
+            pmem_wait_event_interruptible_timeout(
+                self.wq,
+                Some(Self::check::<F>),
+                &mut cond as *mut F as _,
+                timeout_usec as _,
+            )
+        };
+    }
+
+    extern "C" fn check<F: FnMut() -> bool>(arg: *mut c_void) -> c_int {
+        let cond = unsafe { &mut *(arg as *mut F) };
+        cond() as c_int
+    }
+}
+
+impl Drop for WaitQueue {
+    fn drop(&mut self) {
+        unsafe { pmem_free_wait_queue(self.wq) };
+    }
+}
+
+unsafe impl Send for WaitQueue {}
+unsafe impl Sync for WaitQueue {}
@@ -62,6 +62,9 @@ pub struct Config {
 
     // my machine ID
     pub machine_id: usize,
+    // whether to negotiate my machine ID with the peers,
+    // if so, `machine_id` is ignored
+    pub auto_machine_id: bool,
     // how many CPU core is available on the machine
     pub max_core_cnt: usize,
     // gid is RDMA address
@@ -72,6 +75,13 @@ pub struct Config {
     pub max_cluster_size: usize,
 
     pub mem_pool_size: usize,
+
+    // number of kernel threads fetching the images of the asynchronous resumes
+    pub async_resume_threads: usize,
+
+    // number of kernel threads walking the page tables of the prepares,
+    // 0 to walk on the calling threads
+    pub prepare_threads: usize,
 }
 
 impl Default for Config {
@@ -81,11 +91,14 @@ impl Default for Config {
             num_nics_used: 1,
             rpc_threads_num: 2,
             machine_id: 0,
+            auto_machine_id: false,
             max_core_cnt: 48,
             peers_gid: Vec::new(),
             init_dc_targets: 256,
             max_cluster_size: 128,
             mem_pool_size: 20,
+            async_resume_threads: 2,
+            prepare_threads: 4,
         }
     }
 }
@@ -116,6 +129,11 @@ impl Config {
         self.machine_id
     }
 
+    pub fn set_auto_machine_id(&mut self, auto: bool) -> &mut Self {
+        self.auto_machine_id = auto;
+        self
+    }
+
     pub fn add_gid(&mut self, gid: alloc::string::String) -> &mut Self {
         self.peers_gid.push(gid);
         self
@@ -139,6 +157,21 @@ impl Config {
         self.mem_pool_size = sz;
         self
     }
+
+    pub fn set_max_cluster_size(&mut self, sz: usize) -> &mut Self {
+        self.max_cluster_size = sz;
+        self
+    }
+
+    pub fn set_async_resume_threads(&mut self, num: usize) -> &mut Self {
+        self.async_resume_threads = num;
+        self
+    }
+
+    pub fn set_prepare_threads(&mut self, num: usize) -> &mut Self {
+        self.prepare_threads = num;
+        self
+    }
 }
 
 // kernel-space global variables
@@ -304,6 +337,16 @@ pub unsafe fn set_service_rpc(arg: crate::rpc_service::Service)
 /// A pool of connected RPC clients
 pub mod rpc_caller_pool;
 
+/// Machines that join or leave the cluster dynamically
+pub mod membership;
+
+declare_global!(membership_service, crate::membership::MembershipService);
+
+#[inline]
+pub unsafe fn get_membership_ref() -> &'static crate::membership::MembershipService {
+    crate::membership_service::get_ref()
+}
+
 declare_global!(
     service_caller_pool,
     crate::rpc_caller_pool::CallerPool<'static>
@@ -326,6 +369,54 @@ pub unsafe fn set_rpc_caller_pool(arg: crate::rpc_caller_pool::CallerPool<'stati
     crate::service_caller_pool::init(arg);
 }
 
+/// Kernel threads serving the asynchronous resumes
+pub mod resume_worker;
+
+declare_global!(resume_worker_service, crate::resume_worker::ResumeWorkerService);
+
+#[inline]
+pub unsafe fn get_resume_worker_ref() -> &'static crate::resume_worker::ResumeWorkerService {
+    crate::resume_worker_service::get_ref()
+}
+
+/// Kernel threads walking the page tables and serializing the descriptors of the prepares
+pub mod prepare_worker;
+
+declare_global!(prepare_worker_service, crate::prepare_worker::PrepareWorkerService);
+
+#[inline]
+pub unsafe fn get_prepare_worker_ref() -> &'static crate::prepare_worker::PrepareWorkerService {
+    crate::prepare_worker_service::get_ref()
+}
+
+/// Exit reports of the remote children
+pub mod child_exit;
+
+declare_global!(child_exit_service, crate::child_exit::ChildExitService);
+
+#[inline]
+pub unsafe fn get_child_exit_service_ref() -> &'static crate::child_exit::ChildExitService {
+    crate::child_exit_service::get_ref()
+}
+
+declare_global!(exit_reporter, crate::child_exit::ExitReporter);
+
+/// The reporter of the exits of the children resumed on this machine
+#[inline]
+pub unsafe fn get_exit_reporter_ref() -> &'static crate::child_exit::ExitReporter {
+    crate::exit_reporter::get_ref()
+}
+
+/// Kernel threads fetching the remaining pages of the resumed children
+pub mod materializer;
+
+declare_global!(materialize_service, crate::materializer::MaterializeService);
+
+#[inline]
+pub unsafe fn get_materialize_service_ref() -> &'static crate::materializer::MaterializeService {
+    crate::materialize_service::get_ref()
+}
+
 #[cfg(feature = "use_rc")]
 /// A pool of rc connection
 pub mod rc_conn_pool;
@@ -422,6 +513,13 @@ pub unsafe fn get_mem_pool_mut() -> &'static mut crate::mem_pools::MemPool {
     crate::mem_pool::get_mut()
 }
 
+declare_global!(compress_scratch, crate::shadow_process::ScratchPages);
+
+#[inline]
+pub unsafe fn get_compress_scratch_ref() -> &'static crate::shadow_process::ScratchPages {
+    crate::compress_scratch::get_ref()
+}
+
 // TODO: need add locks
 declare_global!(global_pt_cache, crate::remote_pt_cache::RemotePageTableCache);
 
@@ -435,8 +533,16 @@ pub unsafe fn get_pt_cache_mut() -> &'static mut crate::remote_pt_cache::RemoteP
     crate::global_pt_cache::get_mut()
 }
 
+declare_global!(global_page_cache, crate::remote_page_cache::SharedPageCache);
+
+#[inline]
+pub unsafe fn get_page_cache_ref() -> &'static crate::remote_page_cache::SharedPageCache {
+    crate::global_page_cache::get_ref()
+}
+
 // pub mod resume;
 pub mod core_syscall_handler;
+pub mod error;
 pub mod syscalls;
 
 pub mod bindings;
@@ -454,3 +560,4 @@ pub mod prefetcher;
 pub mod lock_bundler;
 
 pub mod remote_pt_cache;
+pub mod remote_page_cache;
@@ -0,0 +1,200 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use rust_kernel_linux_util::kthread;
+use rust_kernel_linux_util::kthread::JoinHandler;
+use rust_kernel_linux_util::timer::KTimer;
+
+use mitosis_protocol::{MaterializeStatus, MATERIALIZE_DONE, MATERIALIZE_FAILED, MATERIALIZE_RUNNING};
+
+use crate::error::{MitosisError, MitosisResult};
+use crate::kern_wrappers::wait_queue::WaitQueue;
+use crate::linux_kernel_module::c_types::c_void;
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+/// The materialization of one resumed child, driven by its kernel thread
+pub trait Materialize {
+    /// Fetch at most `batch` of the remaining remote pages
+    ///
+    /// Return
+    /// * the number of pages fetched, and whether no remote page is left
+    fn step(&mut self, batch: usize) -> MitosisResult<(usize, bool)>;
+}
+
+/// The progress of a materialization, shared by its kernel thread and the child
+pub struct MaterializeProgress {
+    state: AtomicU32,
+    fetched: AtomicU64,
+    total: u64,
+}
+
+impl MaterializeProgress {
+    fn new(total: u64) -> Self {
+        Self {
+            state: AtomicU32::new(MATERIALIZE_RUNNING),
+            fetched: AtomicU64::new(0),
+            total,
+        }
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        self.state.load(Ordering::Acquire) == MATERIALIZE_RUNNING
+    }
+
+    pub fn status(&self) -> MaterializeStatus {
+        MaterializeStatus {
+            state: self.state.load(Ordering::Acquire),
+            reserved: 0,
+            fetched_pages: self.fetched.load(Ordering::Relaxed),
+            total_pages: self.total,
+        }
+    }
+}
+
+struct MaterializeTask {
+    job: Box<dyn Materialize>,
+    progress: Arc<MaterializeProgress>,
+    // 0 for no limit
+    pages_per_sec: usize,
+    batch: usize,
+}
+
+impl MaterializeTask {
+    fn run(&mut self, wq: &WaitQueue) {
+        let timer = KTimer::new();
+        let mut fetched: usize = 0;
+        while !kthread::should_stop() {
+            // the pages fetched so far must not exceed the rate,
+            // so sleep until they do not (or the thread is stopped)
+            if self.pages_per_sec > 0 {
+                let due_usec = fetched as u64 * 1000_000 / self.pages_per_sec as u64;
+                let passed_usec = timer.get_passed_usec() as u64;
+                if due_usec > passed_usec {
+                    wq.wait_interruptible_timeout(kthread::should_stop, due_usec - passed_usec);
+                    continue;
+                }
+            }
+
+            match self.job.step(self.batch) {
+                Ok((n, done)) => {
+                    fetched += n;
+                    self.progress.fetched.fetch_add(n as u64, Ordering::Relaxed);
+                    if done {
+                        self.progress.state.store(MATERIALIZE_DONE, Ordering::Release);
+                        return;
+                    }
+                }
+                Err(e) => {
+                    crate::log::error!("failed to materialize after {} pages: {}", fetched, e);
+                    self.progress.state.store(MATERIALIZE_FAILED, Ordering::Release);
+                    return;
+                }
+            }
+            kthread::yield_now();
+        }
+        // stopped by the unloading of the module
+        self.progress.state.store(MATERIALIZE_FAILED, Ordering::Release);
+    }
+}
+
+/// One kernel thread per materializing child.
+///
+/// The threads of the finished materializations idle until they are joined,
+/// which happens at the next submission or at the end of the service.
+pub struct MaterializeService {
+    threads: BoxedLockBundler<Vec<(Arc<MaterializeProgress>, JoinHandler)>>,
+    // the rate-limited and the idle threads sleep on it until they are stopped
+    wq: WaitQueue,
+}
+
+impl MaterializeService {
+    pub fn new() -> Self {
+        Self {
+            threads: LockBundler::new(Vec::new()),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// Start a kernel thread running `job` until no remote page is left
+    ///
+    /// Arguments
+    /// * total: the number of pages reported as the total of the progress
+    pub fn submit(
+        &self,
+        job: Box<dyn Materialize>,
+        total: usize,
+        pages_per_sec: usize,
+        batch: usize,
+    ) -> MitosisResult<Arc<MaterializeProgress>> {
+        self.reap();
+
+        let progress = Arc::new(MaterializeProgress::new(total as u64));
+        let arg_ptr = Box::into_raw(Box::new(MaterializeTask {
+            job,
+            progress: progress.clone(),
+            pages_per_sec,
+            batch,
+        }));
+        let builder = kthread::Builder::new()
+            .set_name(alloc::string::String::from("MITOSIS materializer"))
+            .set_parameter(arg_ptr as *mut c_void);
+        match builder.spawn(Self::worker) {
+            Ok(handler) => {
+                self.threads.lock(|t| t.push((progress.clone(), handler)));
+                Ok(progress)
+            }
+            Err(_) => {
+                crate::log::error!("failed to spawn the materializer");
+                drop(unsafe { Box::from_raw(arg_ptr) });
+                Err(MitosisError::OutOfMemory)
+            }
+        }
+    }
+
+    /// Join the threads whose materializations have finished
+    fn reap(&self) {
+        let finished: Vec<_> = self.threads.lock(|t| {
+            let mut res = Vec::new();
+            let mut i = 0;
+            while i < t.len() {
+                if t[i].0.is_running() {
+                    i += 1;
+                } else {
+                    res.push(t.swap_remove(i).1);
+                }
+            }
+            res
+        });
+        for handler in finished {
+            handler.join();
+        }
+    }
+
+    extern "C" fn worker(ctx: *mut c_void) -> i32 {
+        let service = unsafe { crate::get_materialize_service_ref() };
+        let mut task = unsafe { Box::from_raw(ctx as *mut MaterializeTask) };
+        task.run(&service.wq);
+        // release the job (and the mm of the child) before idling
+        drop(task);
+
+        // woken up by the stop
+        service.wq.wait_interruptible(kthread::should_stop);
+        0
+    }
+}
+
+impl Drop for MaterializeService {
+    fn drop(&mut self) {
+        let threads = self.threads.lock(|t| core::mem::take(t));
+        for (_, handler) in threads {
+            handler.join();
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use crate::KRdmaKit::consts::MAX_KMALLOC_SZ;
 use alloc::{vec::Vec, sync::Arc};
+use os_network::bytes::{BytesMut, ToBytes};
 use os_network::{msg::UDMsg as RMemory, KRdmaKit::context::Context};
 
+/// The max number of buffers storing one serialized descriptor,
+/// bounded by the chunk table following the lookup reply in one RPC message
+pub const MAX_DESCRIPTOR_CHUNKS: usize = 256;
+
 /// Memory pool stores serialization buffers for the descriptor.
 /// This is used for speedup large buffer allocations:
 /// For 4MB allocation, kernel may take 1ms
@@ -55,3 +60,84 @@ impl MemPool {
         self.capacity
     }
 }
+
+/// A serialized descriptor stored in the buffers of the pool,
+/// where each chunk (but the last) is filled up to `MAX_KMALLOC_SZ`
+pub struct ChunkedBuf {
+    chunks: Vec<RMemory>,
+    len: usize,
+}
+
+impl ChunkedBuf {
+    /// Serialize a descriptor of `len` bytes with `serialize` into the buffers of the pool.
+    /// The descriptor is serialized in place, see `bytes_at` and `write_at`.
+    ///
+    /// Return None if the descriptor needs more than `MAX_DESCRIPTOR_CHUNKS` buffers,
+    /// or fails to be serialized
+    pub fn new<F>(pool: &mut MemPool, len: usize, serialize: F) -> core::option::Option<Self>
+    where
+        F: FnOnce(&Self) -> bool,
+    {
+        let num = core::cmp::max(1, (len + MAX_KMALLOC_SZ - 1) / MAX_KMALLOC_SZ);
+        if num > MAX_DESCRIPTOR_CHUNKS {
+            crate::log::error!(
+                "the descriptor ({} KB) exceeds the max number of chunks {}",
+                len / 1024,
+                MAX_DESCRIPTOR_CHUNKS
+            );
+            return None;
+        }
+
+        let buf = Self {
+            chunks: (0..num).map(|_| pool.pop_one()).collect(),
+            len,
+        };
+        if !serialize(&buf) {
+            return None;
+        }
+        Some(buf)
+    }
+
+    /// The length of the serialized descriptor
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The bytes [off, off + len) of the descriptor, if they are stored in one chunk
+    pub fn bytes_at(&self, off: usize, len: usize) -> core::option::Option<BytesMut> {
+        let (idx, inner) = (off / MAX_KMALLOC_SZ, off % MAX_KMALLOC_SZ);
+        if off + len > self.len || inner + len > MAX_KMALLOC_SZ {
+            return None;
+        }
+        Some(unsafe { BytesMut::from_raw(self.chunks[idx].get_bytes().get_ptr().add(inner), len) })
+    }
+
+    /// Copy `src` to the bytes at `off` of the descriptor, which may span multiple chunks.
+    ///
+    /// # Safety
+    /// The range must be within the descriptor, and the concurrent writers
+    /// (e.g., the prepare workers) must write disjoint ranges.
+    pub unsafe fn write_at(&self, off: usize, src: &[u8]) {
+        let mut done = 0;
+        while done < src.len() {
+            let (idx, inner) = ((off + done) / MAX_KMALLOC_SZ, (off + done) % MAX_KMALLOC_SZ);
+            let sz = core::cmp::min(MAX_KMALLOC_SZ - inner, src.len() - done);
+            core::ptr::copy_nonoverlapping(
+                src.as_ptr().add(done),
+                self.chunks[idx].get_bytes().get_ptr().add(inner),
+                sz,
+            );
+            done += sz;
+        }
+    }
+
+    /// The (RDMA address, length) of the chunks, in the order of the descriptor
+    pub fn chunks(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        let len = self.len;
+        self.chunks.iter().enumerate().map(move |(i, c)| {
+            let off = i * MAX_KMALLOC_SZ;
+            (c.get_pa(), core::cmp::min(MAX_KMALLOC_SZ, len - off))
+        })
+    }
+}
@@ -0,0 +1,499 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+use os_network::serialize::Serialize;
+use os_network::timeout::TimeoutWRef;
+use os_network::{block_on, KRdmaKit::comm_manager::Explorer};
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+use crate::error::{MitosisError, MitosisResult};
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+use crate::rpc_caller_pool::{CallerPool, ConnectError};
+use crate::rpc_handlers::{JoinReply, JoinRequest, JoinStatus, LeaveRequest, RPCId};
+use crate::rpc_service::HandlerConnectInfo;
+use crate::startup::{calculate_session_id, probe_remote_rpc_end};
+
+const TIMEOUT_USEC: i64 = 1000_000; // 1s
+
+/// The caller used to handshake with a new peer
+const BOOTSTRAP_CALLER_IDX: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberState {
+    Active,
+    // The member has left the cluster. Only the member with the same GID can reclaim its ID.
+    Left,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub gid: String,
+    pub nic_idx: usize,
+    pub state: MemberState,
+}
+
+/// The membership table maintained by each machine:
+/// machine ID <-> (GID, state)
+///
+/// Note: my own machine ID is not recorded in the table.
+pub struct MemberTable {
+    members: HashMap<usize, Member>,
+    gid_index: HashMap<String, usize>,
+    my_gid: String,
+    max_cluster_size: usize,
+
+    // whether my machine ID has been known by others
+    // if so, the ID cannot be changed anymore
+    id_fixed: bool,
+}
+
+impl MemberTable {
+    pub fn new(my_gid: String, max_cluster_size: usize, id_fixed: bool) -> Self {
+        Self {
+            members: HashMap::new(),
+            gid_index: HashMap::new(),
+            my_gid,
+            max_cluster_size,
+            id_fixed,
+        }
+    }
+
+    #[inline]
+    pub fn my_gid(&self) -> &String {
+        &self.my_gid
+    }
+
+    #[inline]
+    pub fn is_id_fixed(&self) -> bool {
+        self.id_fixed
+    }
+
+    #[inline]
+    pub fn fix_id(&mut self) {
+        self.id_fixed = true;
+    }
+
+    #[inline]
+    pub fn get(&self, mac_id: usize) -> core::option::Option<&Member> {
+        self.members.get(&mac_id)
+    }
+
+    /// Return the machine ID of an active member with the given gid
+    #[inline]
+    pub fn lookup_active(&self, gid: &String) -> core::option::Option<usize> {
+        let id = *self.gid_index.get(gid)?;
+        match self.members.get(&id) {
+            Some(m) if m.state == MemberState::Active => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn active_members(&self) -> Vec<usize> {
+        self.members
+            .iter()
+            .filter(|(_, m)| m.state == MemberState::Active)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Check whether the `mac_id` can be used by the machine with `gid`
+    pub fn is_id_available(&self, mac_id: usize, gid: &String) -> bool {
+        if mac_id >= self.max_cluster_size || mac_id == unsafe { crate::get_mac_id() } {
+            return false;
+        }
+        match self.members.get(&mac_id) {
+            Some(m) => m.gid == *gid,
+            None => true,
+        }
+    }
+
+    /// Find the smallest free machine ID, starting from `start` (wrap around)
+    pub fn allocate_id(&self, start: usize) -> core::option::Option<usize> {
+        let my_id = unsafe { crate::get_mac_id() };
+        (0..self.max_cluster_size)
+            .map(|i| (start + i) % self.max_cluster_size)
+            .find(|id| *id != my_id && !self.members.contains_key(id))
+    }
+
+    /// Record an (active) member. If the GID was assigned with another ID, the old one is dropped.
+    pub fn add(&mut self, mac_id: usize, gid: &String, nic_idx: usize) {
+        if let Some(old) = self.gid_index.insert(gid.clone(), mac_id) {
+            if old != mac_id {
+                crate::log::info!("machine {} re-assigned ID {} -> {}", gid, old, mac_id);
+                self.members.remove(&old);
+            }
+        }
+        self.members.insert(
+            mac_id,
+            Member {
+                gid: gid.clone(),
+                nic_idx,
+                state: MemberState::Active,
+            },
+        );
+    }
+
+    /// Mark the member as left. Return false if the member does not exist.
+    pub fn mark_left(&mut self, mac_id: usize) -> bool {
+        match self.members.get_mut(&mac_id) {
+            Some(m) => {
+                m.state = MemberState::Left;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handle a leave request from a remote machine.
+    /// The request is ignored if the ID does not belong to the machine with `gid`.
+    pub fn on_leave(&mut self, mac_id: usize, gid: &String) -> bool {
+        match self.members.get(&mac_id) {
+            Some(m) if m.gid == *gid => self.mark_left(mac_id),
+            _ => {
+                crate::log::warn!("machine {} leaves with an unknown ID {}", gid, mac_id);
+                false
+            }
+        }
+    }
+
+    /// Handle a join request from a remote machine
+    ///
+    /// Return
+    /// * Ok(()) if the request is accepted
+    /// * Err(id) if the ID conflicts, the id is a free ID suggested to the joiner
+    pub fn on_join(
+        &mut self,
+        mac_id: usize,
+        gid: &String,
+        nic_idx: usize,
+    ) -> core::result::Result<(), usize> {
+        if !self.is_id_available(mac_id, gid) {
+            crate::log::warn!("machine {} joins with a conflicting ID {}", gid, mac_id);
+            return Err(self.allocate_id(mac_id).unwrap_or(self.max_cluster_size));
+        }
+        self.add(mac_id, gid, nic_idx);
+
+        // others know my ID now
+        self.fix_id();
+        Ok(())
+    }
+}
+
+/// The membership service used to join or leave the cluster dynamically.
+/// Joining a peer works as follows:
+/// 1. connect a bootstrap session to the peer by its GID, carrying my machine ID.
+///    The peer refuses the connection if the ID is occupied by another session.
+/// 2. call the `Join` RPC with my (ID, GID). The peer checks the ID with its member table,
+///    and replies its own machine ID.
+/// 3. connect the rest RPC sessions to the peer using the learned ID.
+///
+/// If my machine ID is not fixed (i.e., auto-assigned and never known by others),
+/// the conflicting ID is re-negotiated with the ID suggested by the peer.
+pub struct MembershipService {
+    table: BoxedLockBundler<MemberTable>,
+
+    // serialize the join & leave process
+    join_lock: BoxedLockBundler<()>,
+
+    // whether my machine ID is negotiated with the peers
+    auto_machine_id: bool,
+}
+
+enum Handshake {
+    Accepted(usize),
+    // the suggested free ID (if any)
+    Conflict(core::option::Option<usize>),
+    Failed(MitosisError),
+}
+
+impl MembershipService {
+    pub fn new(config: &crate::Config) -> core::option::Option<Self> {
+        let context = unsafe { crate::get_rdma_context_ref(0)? };
+        let my_gid = Explorer::gid_to_string(&context.query_gid(config.default_nic_port, 0).ok()?);
+        Some(Self {
+            table: LockBundler::new(MemberTable::new(
+                my_gid,
+                config.max_cluster_size,
+                !config.auto_machine_id,
+            )),
+            join_lock: LockBundler::new(()),
+            auto_machine_id: config.auto_machine_id,
+        })
+    }
+
+    #[inline]
+    pub fn lock<R>(&self, f: impl FnOnce(&mut MemberTable) -> R) -> R {
+        self.table.lock(f)
+    }
+
+    /// Whether my machine ID is negotiated with the peers
+    #[inline]
+    pub fn is_auto_machine_id(&self) -> bool {
+        self.auto_machine_id
+    }
+
+    /// Join the machine with the `gid` to my cluster
+    ///
+    /// Return
+    /// * the remote machine ID
+    pub fn join(&self, gid: &String, nic_idx: usize) -> MitosisResult<usize> {
+        let gid = Self::canonical_gid(gid).ok_or(MitosisError::InvalidArgument)?;
+        self.join_lock.lock(|_| self.join_inner(&gid, nic_idx))
+    }
+
+    fn join_inner(&self, gid: &String, nic_idx: usize) -> MitosisResult<usize> {
+        if let Some(id) = self.lock(|t| t.lookup_active(gid)) {
+            crate::log::debug!("machine {} has already joined with ID {}", gid, id);
+            return Ok(id);
+        }
+
+        let info = HandlerConnectInfo::create(gid, nic_idx as _, nic_idx as _);
+        let max_cluster_size = unsafe { *crate::max_cluster_size::get_ref() };
+
+        for _ in 0..max_cluster_size {
+            let my_id = unsafe { crate::get_mac_id() };
+            let next_id = match self.handshake(gid, &info, my_id) {
+                Handshake::Accepted(remote_id) => {
+                    self.lock(|t| {
+                        t.add(remote_id, gid, nic_idx);
+                        t.fix_id();
+                    });
+                    probe_remote_rpc_end(remote_id, info).ok_or(MitosisError::Unreachable)?;
+                    crate::log::info!("machine {} joined with ID {}", gid, remote_id);
+                    return Ok(remote_id);
+                }
+                Handshake::Conflict(suggested) => {
+                    if self.lock(|t| t.is_id_fixed()) {
+                        crate::log::error!(
+                            "my machine ID {} conflicts at {}, yet it cannot be changed",
+                            my_id,
+                            gid
+                        );
+                        return Err(MitosisError::MachineIdConflict);
+                    }
+                    self.lock(|t| match suggested {
+                        Some(id) if t.is_id_available(id, t.my_gid()) => Some(id),
+                        _ => t.allocate_id(my_id + 1),
+                    })
+                }
+                Handshake::Failed(e) => return Err(e),
+            };
+
+            match next_id {
+                Some(id) => {
+                    crate::log::info!("re-assign my machine ID {} -> {}", my_id, id);
+                    unsafe { *crate::mac_id::get_mut() = id };
+                }
+                None => {
+                    crate::log::error!("no free machine ID left in the cluster");
+                    return Err(MitosisError::MachineIdConflict);
+                }
+            }
+        }
+        crate::log::error!("failed to negotiate a machine ID with {}", gid);
+        Err(MitosisError::MachineIdConflict)
+    }
+
+    fn handshake(&self, gid: &String, info: &HandlerConnectInfo, my_id: usize) -> Handshake {
+        let len = unsafe { crate::get_rpc_caller_pool_ref().len() };
+        let max_cluster_size = unsafe { *crate::max_cluster_size::get_ref() };
+
+        // the bootstrap session ID never collides with the sessions of the members
+        let bootstrap_id = calculate_session_id(max_cluster_size, BOOTSTRAP_CALLER_IDX, len);
+        let my_session_id = calculate_session_id(my_id, BOOTSTRAP_CALLER_IDX, len);
+
+        let remote_gid = match Explorer::string_to_gid(&info.gid) {
+            Ok(g) => g,
+            Err(_) => return Handshake::Failed(MitosisError::InvalidArgument),
+        };
+        let res = unsafe { crate::get_rpc_caller_pool_mut() }.connect_session_at(
+            BOOTSTRAP_CALLER_IDX,
+            bootstrap_id,
+            my_session_id,
+            os_network::ud::UDHyperMeta {
+                gid: remote_gid,
+                service_id: info.service_id,
+                qd_hint: info.qd_hint,
+                local_port: info.local_port,
+            },
+        );
+        match res {
+            Ok(_) => {}
+            Err(ConnectError::Refused) => {
+                crate::log::warn!("machine ID {} has been occupied at {}", my_id, gid);
+                return Handshake::Conflict(None);
+            }
+            Err(e) => {
+                crate::log::error!("failed to connect the bootstrap session to {}: {:?}", gid, e);
+                return Handshake::Failed(match e {
+                    ConnectError::Meta => MitosisError::Unreachable,
+                    _ => MitosisError::Internal,
+                });
+            }
+        };
+
+        let my_gid = self.lock(|t| t.my_gid().clone());
+        let req = JoinRequest {
+            mac_id: my_id,
+            nic_idx: 0,
+            gid: Explorer::string_to_gid(&my_gid).unwrap_or_default(),
+        };
+
+        let caller = unsafe {
+            CallerPool::get_global_caller(BOOTSTRAP_CALLER_IDX)
+                .expect("the caller should be properly initialized")
+        };
+        caller.lock(|caller| {
+            let reply = {
+                let res = caller.sync_call::<JoinRequest>(
+                    bootstrap_id,
+                    my_session_id,
+                    RPCId::Join as _,
+                    req,
+                );
+                if res.is_err() {
+                    crate::log::error!("failed to call join {:?}", res);
+                    Err(MitosisError::Internal)
+                } else {
+                    let mut timeout_caller = TimeoutWRef::new(caller, TIMEOUT_USEC);
+                    match block_on(&mut timeout_caller) {
+                        Ok((msg, reply)) => {
+                            let reply = JoinReply::deserialize(&reply);
+                            caller
+                                .register_recv_buf(msg)
+                                .expect("register msg buffer cannot fail");
+                            reply.ok_or(MitosisError::Protocol)
+                        }
+                        Err(e) => {
+                            crate::log::error!("client receiver reply err {:?}", e);
+                            if e.is_elapsed() {
+                                Err(MitosisError::Timeout)
+                            } else {
+                                Err(MitosisError::Internal)
+                            }
+                        }
+                    }
+                }
+            };
+
+            let remote_id = match reply {
+                Ok(r) if r.status == JoinStatus::Accepted => r.mac_id,
+                Ok(r) => {
+                    let _ = caller.disconnect(bootstrap_id, my_session_id);
+                    return Handshake::Conflict(Some(r.suggested_id));
+                }
+                Err(e) => {
+                    let _ = caller.disconnect(bootstrap_id, my_session_id);
+                    return Handshake::Failed(e);
+                }
+            };
+
+            // the remote ID must not conflict with the members I known
+            if !self.lock(|t| t.is_id_available(remote_id, gid)) {
+                crate::log::error!(
+                    "the ID {} of machine {} conflicts with my members",
+                    remote_id,
+                    gid
+                );
+                Self::send_leave(caller, bootstrap_id, my_session_id, my_id, &my_gid);
+                let _ = caller.disconnect(bootstrap_id, my_session_id);
+                return Handshake::Failed(MitosisError::MachineIdConflict);
+            }
+
+            // reuse the bootstrap session as the formal one
+            let session_id = calculate_session_id(remote_id, BOOTSTRAP_CALLER_IDX, len);
+            if !caller.rename_session(bootstrap_id, session_id) {
+                crate::log::error!("session {} to machine {} is occupied", session_id, gid);
+                let _ = caller.disconnect(bootstrap_id, my_session_id);
+                return Handshake::Failed(MitosisError::MachineIdConflict);
+            }
+            Handshake::Accepted(remote_id)
+        })
+    }
+
+    /// Leave the cluster: notify all the active members and disconnect all the sessions.
+    /// My machine ID can be re-negotiated in later joins if it is auto-assigned.
+    pub fn leave(&self) {
+        self.join_lock.lock(|_| {
+            let members = self.lock(|t| t.active_members());
+            let my_gid = self.lock(|t| t.my_gid().clone());
+            let my_id = unsafe { crate::get_mac_id() };
+            let len = unsafe { crate::get_rpc_caller_pool_ref().len() };
+
+            for remote_id in members {
+                for i in 0..len {
+                    let session_id = calculate_session_id(remote_id, i, len);
+                    let my_session_id = calculate_session_id(my_id, i, len);
+                    let caller = unsafe {
+                        CallerPool::get_global_caller(i)
+                            .expect("the caller should be properly initialized")
+                    };
+                    caller.lock(|caller| {
+                        if i == BOOTSTRAP_CALLER_IDX && caller.session_connected(session_id) {
+                            Self::send_leave(caller, session_id, my_session_id, my_id, &my_gid);
+                        }
+                        if let Err(e) = caller.disconnect(session_id, my_session_id) {
+                            crate::log::warn!("failed to disconnect session {}: {:?}", session_id, e);
+                        }
+                    });
+                }
+                self.lock(|t| t.mark_left(remote_id));
+                crate::log::info!("leave machine {}", remote_id);
+            }
+
+            if self.auto_machine_id {
+                self.lock(|t| t.id_fixed = false);
+            }
+        })
+    }
+
+    fn send_leave(
+        caller: &mut crate::rpc_caller_pool::UDCaller,
+        session_id: usize,
+        my_session_id: usize,
+        my_id: usize,
+        my_gid: &String,
+    ) {
+        let req = LeaveRequest {
+            mac_id: my_id,
+            gid: Explorer::string_to_gid(my_gid).unwrap_or_default(),
+        };
+        if caller
+            .sync_call::<LeaveRequest>(session_id, my_session_id, RPCId::Leave as _, req)
+            .is_err()
+        {
+            crate::log::warn!("failed to send the leave request to session {}", session_id);
+            return;
+        }
+        let mut timeout_caller = TimeoutWRef::new(caller, TIMEOUT_USEC);
+        match block_on(&mut timeout_caller) {
+            Ok((msg, _)) => {
+                caller
+                    .register_recv_buf(msg)
+                    .expect("register msg buffer cannot fail");
+            }
+            Err(e) => crate::log::warn!("leave request reply err {:?}", e),
+        }
+    }
+
+    /// GIDs in different formats (e.g., upper/lower cases) should be treated as the same
+    #[inline]
+    pub fn canonical_gid(gid: &String) -> core::option::Option<String> {
+        Some(Explorer::gid_to_string(&Explorer::string_to_gid(gid).ok()?))
+    }
+}
+
+/// Generate a provisional machine ID from the GID of the machine.
+/// The ID is re-negotiated with the peers if it conflicts.
+pub fn provisional_machine_id(gid: &str, max_cluster_size: usize) -> usize {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in gid.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % max_cluster_size as u64) as usize
+}
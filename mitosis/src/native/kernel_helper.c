@@ -7,6 +7,10 @@
 #include <linux/ptrace.h>
 #include <linux/cpumask.h>
 #include <linux/smp.h>
+#include <linux/highmem.h>
+#include <linux/lz4.h>
+#include <linux/jhash.h>
+#include <linux/version.h>
 
 struct thread_info *
 pmem_get_current_thread_info(void)
@@ -40,6 +44,60 @@ int pmem_call_walk_range(unsigned long addr,
   return (*walk_page_range)(addr, end, walk);
 }
 
+// split the huge pmd before its PTEs are walked, as walk_page_range does
+static void pmem_split_huge_pmd(struct vm_area_struct *vma, pmd_t *pmd, unsigned long addr)
+{
+#ifdef CONFIG_TRANSPARENT_HUGEPAGE
+  static void (*k_split_huge_pmd)(struct vm_area_struct * vma, pmd_t * pmd,
+                                  unsigned long address, bool freeze, struct page *page) = NULL;
+  if (!(pmd_trans_huge(*pmd) || pmd_devmap(*pmd)))
+    return;
+  if (!k_split_huge_pmd)
+    k_split_huge_pmd = (void *)kallsyms_lookup_name("__split_huge_pmd");
+  (*k_split_huge_pmd)(vma, pmd, addr, false, NULL);
+#endif
+}
+
+// walk the PTEs of a pmd with the PTE table locked, on behalf of the walk in walk->private
+static int pmem_locked_pmd_entry(pmd_t *pmd, unsigned long addr, unsigned long end,
+                                 struct mm_walk *walk)
+{
+  struct mm_walk *orig = walk->private;
+  pte_t *start_pte, *pte;
+  spinlock_t *ptl;
+  int err = 0;
+
+  if (orig->pmd_entry) {
+    err = orig->pmd_entry(pmd, addr, end, orig);
+    if (err)
+      return err;
+  }
+
+  pmem_split_huge_pmd(walk->vma, pmd, addr);
+  if (pmd_trans_unstable(pmd))
+    return 0;
+
+  start_pte = pte = pte_offset_map_lock(walk->mm, pmd, addr, &ptl);
+  for (; addr != end; pte++, addr += PAGE_SIZE) {
+    err = orig->pte_entry(pte, addr, addr + PAGE_SIZE, orig);
+    if (err)
+      break;
+  }
+  pte_unmap_unlock(start_pte, ptl);
+  return err;
+}
+
+int pmem_call_walk_range_locked(unsigned long addr,
+                                unsigned long end,
+                                struct mm_walk *walk)
+{
+  struct mm_walk locked = *walk;
+  locked.pmd_entry = pmem_locked_pmd_entry;
+  locked.pte_entry = NULL;
+  locked.private = walk;
+  return pmem_call_walk_range(addr, end, &locked);
+}
+
 void pmem_flush_tlb_all(void)
 {
   static void (*k_flush_tlb_all)(void) = NULL;
@@ -92,6 +150,64 @@ long pmem_do_arch_prctl_64(struct task_struct *task, int option, unsigned long a
   return (*do_arch_prctl_64)(task, option, arg2);
 }
 
+const char *
+pmem_get_vma_name(struct vm_area_struct *vma)
+{
+  if (vma->vm_ops && vma->vm_ops->name)
+    return vma->vm_ops->name(vma);
+  return NULL;
+}
+
+long pmem_mremap(unsigned long addr,
+                 unsigned long old_len,
+                 unsigned long new_len,
+                 unsigned long flags,
+                 unsigned long new_addr)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 17, 0) && defined(CONFIG_ARCH_HAS_SYSCALL_WRAPPER)
+  // the syscalls take their arguments in the registers of pt_regs since 4.17
+  static long (*k_mremap)(const struct pt_regs *regs) = NULL;
+  struct pt_regs regs = {
+      .di = addr,
+      .si = old_len,
+      .dx = new_len,
+      .r10 = flags,
+      .r8 = new_addr,
+  };
+  const char *name = "__x64_sys_mremap";
+#else
+  static long (*k_mremap)(unsigned long addr, unsigned long old_len,
+                          unsigned long new_len, unsigned long flags,
+                          unsigned long new_addr) = NULL;
+  const char *name = "sys_mremap";
+#endif
+  if (!k_mremap)
+    k_mremap = (void *)kallsyms_lookup_name(name);
+  if (!k_mremap)
+  {
+    printk(KERN_ERR "kernel-helper error: "
+                    "can't find kernel function %s\n", name);
+    return -ENOSYS;
+  }
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 17, 0) && defined(CONFIG_ARCH_HAS_SYSCALL_WRAPPER)
+  return (*k_mremap)(&regs);
+#else
+  return (*k_mremap)(addr, old_len, new_len, flags, new_addr);
+#endif
+}
+
+#include <asm/vdso.h>
+
+u64 pmem_vdso_hash(void)
+{
+  static const struct vdso_image *image = NULL;
+  if (!image)
+    image = (void *)kallsyms_lookup_name("vdso_image_64");
+  if (!image)
+    return 0;
+  return jhash(image->data, image->size, 0) | (image->size << 32);
+}
+
 pte_t *
 pmem_get_pte(struct mm_struct *mm, unsigned long addr)
 {
@@ -139,6 +255,11 @@ unsigned int pmem_check_pte_present(pte_t *pte)
   return pte_present(*pte);
 }
 
+unsigned int pmem_check_pte_none(pte_t *pte)
+{
+  return pte_none(*pte);
+}
+
 // The higher 12 bit of a pte entry should be masked when we are extracting physical address
 #define X86_64_PTE_HIGHER_BIT_MASK ~(((long) 1 << 63) >> 11)
 
@@ -354,6 +475,43 @@ pmem_pte_to_page(pte_t *pte)
   return pte_page(*pte);
 }
 
+int pmem_pte_maps_zero(pte_t *pte)
+{
+  return is_zero_pfn(pte_pfn(*pte));
+}
+
+int pmem_page_is_zero(struct page *page)
+{
+  void *va;
+  int zero;
+
+  va = kmap_atomic(page);
+  zero = memchr_inv(va, 0, PAGE_SIZE) == NULL;
+  kunmap_atomic(va);
+  return zero;
+}
+
+unsigned int pmem_compress_wrkmem_size(void)
+{
+  return LZ4_MEM_COMPRESS;
+}
+
+int pmem_compress_page(const void *src, void *dst, int dst_len, void *wrkmem)
+{
+  return LZ4_compress_default(src, dst, PAGE_SIZE, dst_len, wrkmem);
+}
+
+int pmem_decompress_page(const void *src, int src_len, void *dst)
+{
+  int ret = LZ4_decompress_safe(src, dst, src_len, PAGE_SIZE);
+  return ret == PAGE_SIZE ? 0 : -EINVAL;
+}
+
+u32 pmem_hash_page(const void *va)
+{
+  return jhash2(va, PAGE_SIZE / sizeof(u32), 0);
+}
+
 // Credits:
 // From https://stackoverflow.com/questions/32175346/how-i-get-absolute-path-in-kernel-space-from-file-descriptor
 void print_file_path(struct file *file)
@@ -370,4 +528,337 @@ void print_file_path(struct file *file)
   printk("path: %s\n", path);
 out:
   free_page((unsigned long)tmp);
-}
\ No newline at end of file
+}
+
+int pmem_get_file_path(struct file *file, char *buf, int len)
+{
+  char *path = d_path(&file->f_path, buf, len);
+  int sz;
+  if (IS_ERR(path))
+    return PTR_ERR(path);
+
+  // d_path fills the buffer from its end
+  sz = buf + len - path - 1;
+  memmove(buf, path, sz + 1);
+  return sz;
+}
+
+void pmem_get_file_identity(struct file *file, long long *size, long long *mtime_sec,
+                            long long *mtime_nsec)
+{
+  struct inode *inode = file_inode(file);
+  *size = i_size_read(inode);
+  *mtime_sec = inode->i_mtime.tv_sec;
+  *mtime_nsec = inode->i_mtime.tv_nsec;
+}
+
+struct file *
+pmem_open_file(const char *path)
+{
+  struct file *f = filp_open(path, O_RDONLY, 0);
+  if (IS_ERR(f))
+    return NULL;
+  return f;
+}
+
+// eventfd related
+#include <linux/eventfd.h>
+
+struct eventfd_ctx *
+pmem_eventfd_ctx_fdget(int fd)
+{
+  struct eventfd_ctx *ctx = eventfd_ctx_fdget(fd);
+  if (IS_ERR(ctx))
+    return NULL;
+  return ctx;
+}
+
+void pmem_eventfd_signal(struct eventfd_ctx *ctx)
+{
+  eventfd_signal(ctx, 1);
+}
+
+void pmem_eventfd_ctx_put(struct eventfd_ctx *ctx)
+{
+  eventfd_ctx_put(ctx);
+}
+
+// exit related
+#include <linux/profile.h>
+#include <linux/sched/signal.h>
+
+static void (*pmem_exit_hook_fn)(struct mm_struct *mm, int status) = NULL;
+
+static int pmem_task_exit(struct notifier_block *nb, unsigned long val, void *data)
+{
+  struct task_struct *tsk = data;
+  int status = 0;
+
+  if (tsk->mm == NULL || (tsk->flags & PF_KTHREAD))
+    return NOTIFY_OK;
+  // the exit_code of the task is not set yet, while the status of a group exit
+  // (i.e., exit_group(2) and the fatal signals) is. The plain exit(2) of the last thread
+  // is reported as 0, which the libc never issues.
+  if (tsk->signal->flags & SIGNAL_GROUP_EXIT)
+    status = tsk->signal->group_exit_code;
+  (*pmem_exit_hook_fn)(tsk->mm, status);
+  return NOTIFY_OK;
+}
+
+static struct notifier_block pmem_exit_nb = {
+    .notifier_call = pmem_task_exit,
+};
+
+int pmem_register_exit_hook(void (*hook)(struct mm_struct *mm, int status))
+{
+  pmem_exit_hook_fn = hook;
+  return profile_event_register(PROFILE_TASK_EXIT, &pmem_exit_nb);
+}
+
+void pmem_unregister_exit_hook(void)
+{
+  profile_event_unregister(PROFILE_TASK_EXIT, &pmem_exit_nb);
+}
+
+void pmem_kill_current(void)
+{
+  send_sig(SIGKILL, current, 1);
+}
+
+// wait queue related
+#include <linux/slab.h>
+#include <linux/wait.h>
+
+wait_queue_head_t *pmem_alloc_wait_queue(void)
+{
+  wait_queue_head_t *wq = kmalloc(sizeof(*wq), GFP_KERNEL | __GFP_NOFAIL);
+  init_waitqueue_head(wq);
+  return wq;
+}
+
+void pmem_free_wait_queue(wait_queue_head_t *wq)
+{
+  kfree(wq);
+}
+
+void pmem_wake_up_all(wait_queue_head_t *wq)
+{
+  wake_up_all(wq);
+}
+
+void pmem_wait_event(wait_queue_head_t *wq, int (*cond)(void *), void *arg)
+{
+  wait_event(*wq, cond(arg));
+}
+
+int pmem_wait_event_interruptible(wait_queue_head_t *wq, int (*cond)(void *), void *arg)
+{
+  return wait_event_interruptible(*wq, cond(arg));
+}
+
+long pmem_wait_event_interruptible_timeout(wait_queue_head_t *wq, int (*cond)(void *), void *arg,
+                                           unsigned long timeout_usec)
+{
+  return wait_event_interruptible_timeout(*wq, cond(arg), usecs_to_jiffies(timeout_usec));
+}
+
+// memcg related
+#include <linux/memcontrol.h>
+
+#ifdef CONFIG_MEMCG
+// the limit of the page counter, renamed to max since 4.19
+static inline unsigned long
+pmem_page_counter_limit(struct page_counter *counter)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 19, 0)
+  return READ_ONCE(counter->max);
+#else
+  return READ_ONCE(counter->limit);
+#endif
+}
+
+// the memcg the pages of the current mm are charged to, must be called under rcu_read_lock.
+// the kernel threads using the mm of a child (see pmem_use_mm) are accounted to the child
+static struct mem_cgroup *
+pmem_current_memcg(void)
+{
+  struct task_struct *owner = current->mm ? rcu_dereference(current->mm->owner) : NULL;
+  return mem_cgroup_from_css(task_css(owner ? owner : current, memory_cgrp_id));
+}
+#endif
+
+unsigned long
+pmem_memcg_margin_pages(void)
+{
+#ifdef CONFIG_MEMCG
+  struct mem_cgroup *memcg;
+  unsigned long margin = ULONG_MAX;
+
+  if (mem_cgroup_disabled())
+    return ULONG_MAX;
+
+  rcu_read_lock();
+  for (memcg = pmem_current_memcg(); memcg; memcg = parent_mem_cgroup(memcg)) {
+    unsigned long limit = pmem_page_counter_limit(&memcg->memory);
+    unsigned long usage = page_counter_read(&memcg->memory);
+    margin = min(margin, limit > usage ? limit - usage : 0);
+  }
+  rcu_read_unlock();
+  return margin;
+#else
+  return ULONG_MAX;
+#endif
+}
+
+struct page *
+pmem_alloc_charged_page(gfp_t gfp_mask)
+{
+  struct page *page = alloc_page(gfp_mask);
+  if (page == NULL)
+    return NULL;
+
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(5, 8, 0)
+  if (mem_cgroup_charge(page, current->mm, gfp_mask)) {
+    __free_page(page);
+    return NULL;
+  }
+#else
+  {
+    struct mem_cgroup *memcg;
+    if (mem_cgroup_try_charge(page, current->mm, gfp_mask, &memcg, false)) {
+      __free_page(page);
+      return NULL;
+    }
+    mem_cgroup_commit_charge(page, memcg, false, false);
+  }
+#endif
+  return page;
+}
+
+void pmem_uncharge_page(struct page *page)
+{
+  mem_cgroup_uncharge(page);
+}
+
+#include <linux/mmu_context.h>
+#include <linux/sched/mm.h>
+
+void pmem_mmgrab(struct mm_struct *mm)
+{
+  mmgrab(mm);
+}
+
+void pmem_mmdrop(struct mm_struct *mm)
+{
+  mmdrop(mm);
+}
+
+int pmem_mmget_not_zero(struct mm_struct *mm)
+{
+  return mmget_not_zero(mm);
+}
+
+void pmem_mmput(struct mm_struct *mm)
+{
+  mmput(mm);
+}
+
+void pmem_use_mm(struct mm_struct *mm)
+{
+  use_mm(mm);
+}
+
+void pmem_unuse_mm(struct mm_struct *mm)
+{
+  unuse_mm(mm);
+}
+
+void pmem_mmap_write_lock(struct mm_struct *mm)
+{
+  down_write(&mm->mmap_sem);
+}
+
+void pmem_mmap_write_unlock(struct mm_struct *mm)
+{
+  up_write(&mm->mmap_sem);
+}
+
+void pmem_mmap_read_lock(struct mm_struct *mm)
+{
+  down_read(&mm->mmap_sem);
+}
+
+void pmem_mmap_read_unlock(struct mm_struct *mm)
+{
+  up_read(&mm->mmap_sem);
+}
+
+// the same locks as prctl(PR_SET_MM_MAP): arg_lock is introduced in 4.18,
+// before which the fields are protected by the mmap_sem held for write
+void pmem_mm_layout_lock(struct mm_struct *mm)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 18, 0)
+  down_read(&mm->mmap_sem);
+  spin_lock(&mm->arg_lock);
+#else
+  down_write(&mm->mmap_sem);
+#endif
+}
+
+void pmem_mm_layout_unlock(struct mm_struct *mm)
+{
+#if LINUX_VERSION_CODE >= KERNEL_VERSION(4, 18, 0)
+  spin_unlock(&mm->arg_lock);
+  up_read(&mm->mmap_sem);
+#else
+  up_write(&mm->mmap_sem);
+#endif
+}
+
+#include <linux/cred.h>
+#include <linux/capability.h>
+#include <linux/shrinker.h>
+
+unsigned int pmem_current_uid(void)
+{
+  return __kuid_val(current_uid());
+}
+
+int pmem_capable_sys_admin(void)
+{
+  return capable(CAP_SYS_ADMIN);
+}
+
+static unsigned long (*pmem_shrink_count_fn)(void) = NULL;
+static unsigned long (*pmem_shrink_scan_fn)(unsigned long nr) = NULL;
+
+static unsigned long pmem_shrink_count(struct shrinker *s, struct shrink_control *sc)
+{
+  return (*pmem_shrink_count_fn)();
+}
+
+static unsigned long pmem_shrink_scan(struct shrinker *s, struct shrink_control *sc)
+{
+  unsigned long freed = (*pmem_shrink_scan_fn)(sc->nr_to_scan);
+  // the rest is freed asynchronously
+  return freed ? freed : SHRINK_STOP;
+}
+
+static struct shrinker pmem_shrinker = {
+    .count_objects = pmem_shrink_count,
+    .scan_objects = pmem_shrink_scan,
+    .seeks = DEFAULT_SEEKS,
+};
+
+int pmem_register_shrinker(unsigned long (*count)(void),
+                           unsigned long (*scan)(unsigned long nr))
+{
+  pmem_shrink_count_fn = count;
+  pmem_shrink_scan_fn = scan;
+  return register_shrinker(&pmem_shrinker);
+}
+
+void pmem_unregister_shrinker(void)
+{
+  unregister_shrinker(&pmem_shrinker);
+}
@@ -11,6 +11,7 @@
 #include <asm/pgtable_types.h>
 #include <asm/tlb.h>
 #include <linux/vmalloc.h>
+#include <linux/wait.h>
 #include <asm/tlbflush.h>
 
 #include <linux/gfp.h>
@@ -37,6 +38,14 @@ int pmem_call_walk_range(unsigned long addr,
                          unsigned long end,
                          struct mm_walk *walk);
 
+// same as pmem_call_walk_range, except that the pte_entry is called with the PTE table locked
+// (see pte_offset_map_lock), so it must not sleep. The pmd_entry (if any) is called before
+// each PTE table is locked, e.g., to reserve the memory used by the pte_entry.
+// the mmap_sem of the mm must be held
+int pmem_call_walk_range_locked(unsigned long addr,
+                                unsigned long end,
+                                struct mm_walk *walk);
+
 int pmem_call_walk_vma(struct vm_area_struct *vm, struct mm_walk *walk);
 
 unsigned long
@@ -62,6 +71,21 @@ pmem_mmap_region(struct file *file,
                  vm_flags_t vm_flags,
                  unsigned long pgoff);
 
+// the name of the special mapping, e.g., "[vdso]" and "[vvar]", NULL if none
+const char *
+pmem_get_vma_name(struct vm_area_struct *vma);
+
+// mremap(2) on the current mm
+long pmem_mremap(unsigned long addr,
+                 unsigned long old_len,
+                 unsigned long new_len,
+                 unsigned long flags,
+                 unsigned long new_addr);
+
+// the hash of the (64-bit) vdso image of this kernel, with its size in the upper 32 bits;
+// 0 if the image cannot be found
+u64 pmem_vdso_hash(void);
+
 void pmem_flush_tlb_range(struct vm_area_struct *vma, unsigned long start, unsigned long end);
 
 void pmem_flush_tlb_all(void);
@@ -70,6 +94,7 @@ void pmem_flush_tlb_mm(struct mm_struct *mm);
 
 void pmem_clear_pte_present(pte_t *pte);
 unsigned int pmem_check_pte_present(pte_t *pte);
+unsigned int pmem_check_pte_none(pte_t *pte);
 
 struct pt_regs *
 pmem_get_current_pt_regs(void);
@@ -125,16 +150,24 @@ const unsigned long PMEM_PROT_EXEC = PROT_EXEC;
 const unsigned long PMEM_PROT_GROWSUP = PROT_GROWSUP;
 const unsigned long PMEM_PROT_GROWSDOWN = PROT_GROWSDOWN;
 
+/*
+ MRemap flags
+*/
+const unsigned long PMEM_MREMAP_MAYMOVE = MREMAP_MAYMOVE;
+const unsigned long PMEM_MREMAP_FIXED = MREMAP_FIXED;
+
 /*
  Page fault flags
  */
 const unsigned int PMEM_VM_FAULT_SIGSEGV = VM_FAULT_SIGSEGV;
+const unsigned int PMEM_VM_FAULT_OOM = VM_FAULT_OOM;
 
 /*
  gfp related
  */
 const gfp_t PMEM_GFP_HIGHUSER = GFP_HIGHUSER;
 const gfp_t PMEM_GFP_USER = GFP_USER;
+const gfp_t PMEM_GFP_HIGHUSER_ZERO = GFP_HIGHUSER | __GFP_ZERO;
 
 /*
  About fs and gs
@@ -164,6 +197,17 @@ void pmem_put_file(struct file *f);
 
 void print_file_path(struct file *file);
 
+// write the (NUL-terminated) path of the file to buf, return its length or a negative errno
+int pmem_get_file_path(struct file *file, char *buf, int len);
+
+// the size and the last modification time of the file
+void pmem_get_file_identity(struct file *file, long long *size, long long *mtime_sec,
+                            long long *mtime_nsec);
+
+// open the file at path read-only, return NULL on failure
+struct file *
+pmem_open_file(const char *path);
+
 /*
   page related
 */
@@ -181,4 +225,133 @@ unsigned int pmem_check_pte_write(pte_t *pte);
 struct page *
 pmem_pte_to_page(pte_t *pte);
 
+// non-zero if the pte maps the shared zero page, whose content needs no check,
+// as it is never written (unlike a page that merely contains zeros)
+int pmem_pte_maps_zero(pte_t *pte);
+
+// non-zero if the page is filled with zeros.
+// the content is only stable if the page is write-protected, e.g., marked COW
+int pmem_page_is_zero(struct page *page);
+
+/*
+  page compression related (LZ4)
+ */
+// the size of the work memory of pmem_compress_page
+unsigned int pmem_compress_wrkmem_size(void);
+
+// compress the page at src (a kernel address) into at most dst_len bytes of dst,
+// return the compressed length, or 0 if it does not fit
+int pmem_compress_page(const void *src, void *dst, int dst_len, void *wrkmem);
+
+// decompress src_len bytes at src into the page at dst (a kernel address),
+// return 0 if a whole page is decompressed
+int pmem_decompress_page(const void *src, int src_len, void *dst);
+
+// the hash of the content of the page at va (a kernel address), used by the deduplication
+u32 pmem_hash_page(const void *va);
+
+/*
+  eventfd related
+ */
+struct eventfd_ctx;
+
+// return NULL if the fd is not an eventfd
+struct eventfd_ctx *
+pmem_eventfd_ctx_fdget(int fd);
+
+void pmem_eventfd_signal(struct eventfd_ctx *ctx);
+
+void pmem_eventfd_ctx_put(struct eventfd_ctx *ctx);
+
+/*
+  exit related, there is at most one exit hook registered by MITOSIS
+ */
+// hook: called in the context of each exiting user task before its mm is released,
+// with the mm and the exit status of its process in the format of wait(2)
+int pmem_register_exit_hook(void (*hook)(struct mm_struct *mm, int status));
+void pmem_unregister_exit_hook(void);
+
+// kill the process of the current task once it returns to the user space
+void pmem_kill_current(void);
+
+/*
+  wait queue related
+ */
+// never fails
+wait_queue_head_t *pmem_alloc_wait_queue(void);
+void pmem_free_wait_queue(wait_queue_head_t *wq);
+
+void pmem_wake_up_all(wait_queue_head_t *wq);
+
+// sleep until cond(arg) returns non-zero
+void pmem_wait_event(wait_queue_head_t *wq, int (*cond)(void *), void *arg);
+
+// same as pmem_wait_event, except that the sleep is interruptible, e.g., by kthread_stop,
+// return non-zero if interrupted
+int pmem_wait_event_interruptible(wait_queue_head_t *wq, int (*cond)(void *), void *arg);
+
+// same as pmem_wait_event_interruptible, except that the sleep lasts at most timeout_usec
+long pmem_wait_event_interruptible_timeout(wait_queue_head_t *wq, int (*cond)(void *), void *arg,
+                                           unsigned long timeout_usec);
+
+/*
+  memcg related
+ */
+// the number of pages that can still be charged to the memcg of the current mm (or task),
+// bounded by its ancestors; ULONG_MAX if the memcg is disabled or unlimited
+unsigned long pmem_memcg_margin_pages(void);
+
+// allocate a page charged to the memcg of the current mm (the child's, see pmem_use_mm),
+// return NULL if the memcg is exhausted.
+// the page must be released with pmem_put_page, and the last reference uncharges it
+struct page *
+pmem_alloc_charged_page(gfp_t gfp_mask);
+
+// uncharge a page owned by the host from its memcg, e.g., a page shared by all the children.
+// the page must be neither mapped nor on the LRU yet
+void pmem_uncharge_page(struct page *page);
+
+/*
+  mm related, used to populate the mm of another task from a kernel thread
+ */
+// pin the mm_struct (not its address space), released by pmem_mmdrop
+void pmem_mmgrab(struct mm_struct *mm);
+void pmem_mmdrop(struct mm_struct *mm);
+
+// pin the address space, return 0 if the mm is already torn down;
+// released by pmem_mmput
+int pmem_mmget_not_zero(struct mm_struct *mm);
+void pmem_mmput(struct mm_struct *mm);
+
+// switch the current kernel thread to the mm, e.g., so that the pages are accounted to its memcg
+void pmem_use_mm(struct mm_struct *mm);
+void pmem_unuse_mm(struct mm_struct *mm);
+
+void pmem_mmap_write_lock(struct mm_struct *mm);
+void pmem_mmap_write_unlock(struct mm_struct *mm);
+void pmem_mmap_read_lock(struct mm_struct *mm);
+void pmem_mmap_read_unlock(struct mm_struct *mm);
+
+// lock the layout fields of the mm (e.g., brk, arg_start and saved_auxv) for write
+void pmem_mm_layout_lock(struct mm_struct *mm);
+void pmem_mm_layout_unlock(struct mm_struct *mm);
+
+/*
+  credential related
+ */
+// the (global) uid of the current task
+unsigned int pmem_current_uid(void);
+
+// non-zero if the current task has CAP_SYS_ADMIN
+int pmem_capable_sys_admin(void);
+
+/*
+  shrinker related, there is at most one shrinker registered by MITOSIS
+ */
+// count: the number of pages that can be freed
+// scan: free (or schedule the freeing of) nr pages, return the number freed right now
+int pmem_register_shrinker(unsigned long (*count)(void),
+                           unsigned long (*scan)(unsigned long nr));
+void pmem_unregister_shrinker(void);
+
 #endif
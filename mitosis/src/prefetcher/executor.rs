@@ -106,8 +106,9 @@ impl DCAsyncPrefetcher {
     /// If this is not the case, we need to 2 bits to identify
     /// whether the remote page is in the prefetch state.
     #[inline]
-    pub fn execute_reqs<P, const NUM : usize>(&mut self, mut iter: RemotePageTableIter, strategy: P)
+    pub fn execute_reqs<I, P, const NUM : usize>(&mut self, mut iter: I, strategy: P)
     where
+        I: Iterator<Item = PrefetchReq>,
         P: Prefetch<NUM, Item = PrefetchReq>,
     {
         let reqs = strategy.generate_request(&mut iter);
@@ -121,6 +122,19 @@ impl DCAsyncPrefetcher {
                 // this page has been prefetched, or at least in the list
                 continue;
             }
+            if phyaddr.is_zero() || phyaddr.is_compressed() {
+                // zero-filled (or decompressed) on the fault
+                continue;
+            }
+
+            // the prefetched pages are charged to the memcg of the child,
+            // stop prefetching once the memcg is exhausted
+            let user_page = unsafe {
+                crate::bindings::pmem_alloc_charged_page(crate::bindings::PMEM_GFP_HIGHUSER)
+            };
+            if user_page.is_null() {
+                return;
+            }
 
             // 1. set the page table entry's bottom bit to 1 to prevent future prefetch
             let remote_pa = phyaddr.real_addr();
@@ -131,8 +145,6 @@ impl DCAsyncPrefetcher {
             pte_page[reqs[i].index] = K_MAGIC_IN_PREFETCH;
 
             // 2. submit the RDMA request to read the page
-            let user_page =
-                unsafe { crate::bindings::pmem_alloc_page(crate::bindings::PMEM_GFP_HIGHUSER) };
             let new_page_va = unsafe { crate::bindings::pmem_page_to_virt(user_page) as u64 };
 
             // TODO: doorbell optimization
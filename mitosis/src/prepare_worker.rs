@@ -0,0 +1,212 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_kernel_linux_util::kthread;
+use rust_kernel_linux_util::kthread::JoinHandler;
+
+use crate::bindings::mm_struct;
+use crate::kern_wrappers::wait_queue::WaitQueue;
+use crate::linux_kernel_module::c_types::c_void;
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+/// The jobs of one `PrepareWorkerService::run`, claimed by the workers one index at a time
+struct Batch {
+    // borrowed from the caller of `run`, which outlives the batch as the caller waits for it
+    job: *const dyn Fn(usize),
+    count: usize,
+    // the mm of the caller, used by the workers to access the user memory (if not null)
+    mm: *mut mm_struct,
+
+    next: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+impl Batch {
+    /// Claim the next job, the claim of the last one closes the batch in `open`,
+    /// see `PrepareWorkerService`
+    #[inline]
+    fn claim(&self, open: &AtomicUsize) -> core::option::Option<usize> {
+        let idx = self.next.fetch_add(1, Ordering::AcqRel);
+        if idx + 1 == self.count {
+            open.fetch_sub(1, Ordering::AcqRel);
+        }
+        if idx < self.count {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn has_unclaimed(&self) -> bool {
+        self.next.load(Ordering::Acquire) < self.count
+    }
+
+    /// Run the jobs from `first` until none is left to claim
+    ///
+    /// Return
+    /// * whether the batch is finished by the jobs
+    fn run_from(&self, first: usize, open: &AtomicUsize) -> bool {
+        if !self.mm.is_null() {
+            unsafe { crate::bindings::pmem_use_mm(self.mm) };
+        }
+        let mut done = 0;
+        let mut next = Some(first);
+        while let Some(idx) = next {
+            unsafe { (*self.job)(idx) };
+            done += 1;
+            next = self.claim(open);
+        }
+        if !self.mm.is_null() {
+            unsafe { crate::bindings::pmem_unuse_mm(self.mm) };
+        }
+
+        // the caller (and its mm) may be gone once the batch is finished
+        self.finished.fetch_add(done, Ordering::AcqRel) + done >= self.count
+    }
+
+    #[inline]
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire) >= self.count
+    }
+}
+
+/// The slots written by the jobs of a `PrepareWorkerService::run`,
+/// where the job `i` exclusively owns the slot `i`
+pub struct JobSlots<T> {
+    slots: Vec<UnsafeCell<T>>,
+}
+
+impl<T: Default> JobSlots<T> {
+    pub fn new(count: usize) -> Self {
+        let mut slots = Vec::with_capacity(count);
+        slots.resize_with(count, Default::default);
+        Self { slots }
+    }
+}
+
+impl<T> JobSlots<T> {
+    /// # Safety
+    /// Only the job `idx` can access the slot `idx` during the run
+    #[allow(clippy::mut_from_ref)]
+    #[inline]
+    pub unsafe fn get_mut(&self, idx: usize) -> &mut T {
+        &mut *self.slots[idx].get()
+    }
+
+    pub fn into_inner(self) -> impl Iterator<Item = T> {
+        self.slots.into_iter().map(|s| s.into_inner())
+    }
+}
+
+/// Kernel threads that walk the page tables and serialize the descriptors of the prepares
+/// in parallel. The caller of `run` waits until all its jobs are done,
+/// so the jobs can borrow the data of the caller.
+pub struct PrepareWorkerService {
+    queue: BoxedLockBundler<VecDeque<Arc<Batch>>>,
+    // the number of the queued batches with unclaimed jobs, checked by the idle workers
+    open: AtomicUsize,
+    // the idle workers sleep on it until a batch is queued (or they are stopped)
+    wq: WaitQueue,
+    // the callers of `run` sleep on it until their batches are finished
+    finished_wq: WaitQueue,
+    threads: Vec<JoinHandler>,
+}
+
+impl PrepareWorkerService {
+    pub fn new() -> Self {
+        Self {
+            queue: LockBundler::new(VecDeque::new()),
+            open: AtomicUsize::new(0),
+            wq: WaitQueue::new(),
+            finished_wq: WaitQueue::new(),
+            threads: Vec::new(),
+        }
+    }
+
+    /// Start the workers, must be called after the service is installed as the global one
+    pub fn start(&mut self, num: usize) -> core::option::Option<()> {
+        for i in 0..num {
+            let arg_ptr = Box::into_raw(Box::new(i));
+            let builder = kthread::Builder::new()
+                .set_name(alloc::format!("MITOSIS prepare worker {}", i))
+                .set_parameter(arg_ptr as *mut c_void);
+            self.threads.push(builder.spawn(Self::worker).ok()?);
+        }
+        Some(())
+    }
+
+    /// Run `job(0)` to `job(count - 1)` on the workers, and wait for them.
+    /// The jobs run on the caller if no worker is started.
+    ///
+    /// # Safety
+    /// * The jobs must be safe to run concurrently with each other, see `JobSlots`
+    /// * `mm` must be the mm of the caller, which is used by the workers to access its user memory,
+    ///   or null if the jobs do not access the user memory
+    pub unsafe fn run(&self, count: usize, mm: *mut mm_struct, job: &dyn Fn(usize)) {
+        if self.threads.is_empty() || count == 0 {
+            (0..count).for_each(job);
+            return;
+        }
+
+        // the borrow is erased, since the batch is not run after it is finished
+        let job: *const (dyn Fn(usize) + '_) = job;
+        let batch = Arc::new(Batch {
+            job: core::mem::transmute(job),
+            count,
+            mm,
+            next: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+        });
+        self.queue.lock(|q| q.push_back(batch.clone()));
+        self.open.fetch_add(1, Ordering::AcqRel);
+        self.wq.wake_up_all();
+
+        self.finished_wq.wait_until(|| batch.is_finished());
+        self.queue.lock(|q| q.retain(|b| !Arc::ptr_eq(b, &batch)));
+    }
+
+    extern "C" fn worker(ctx: *mut c_void) -> i32 {
+        let id = unsafe { Box::from_raw(ctx as *mut usize) };
+        crate::log::debug!("MITOSIS prepare worker {} started", id);
+
+        let service = unsafe { crate::get_prepare_worker_ref() };
+        while !kthread::should_stop() {
+            service.wq.wait_interruptible(|| {
+                kthread::should_stop() || service.open.load(Ordering::Acquire) > 0
+            });
+
+            // the batches exhausted are removed by their callers
+            let batch = service
+                .queue
+                .lock(|q| q.iter().find(|b| b.has_unclaimed()).cloned());
+            // the mm is used only after a job is claimed, i.e., before the batch is finished
+            let open = &service.open;
+            if let Some((batch, first)) = batch.as_ref().and_then(|b| b.claim(open).map(|i| (b, i)))
+            {
+                if batch.run_from(first, open) {
+                    service.finished_wq.wake_up_all();
+                }
+            }
+        }
+
+        crate::log::info!("MITOSIS prepare worker {} ended", id);
+        0
+    }
+}
+
+impl Drop for PrepareWorkerService {
+    fn drop(&mut self) {
+        while let Some(handler) = self.threads.pop() {
+            handler.join();
+        }
+    }
+}
@@ -7,7 +7,9 @@ use rust_kernel_rdma_base::VmallocAllocator;
 use core::fmt;
 use core::ops::{Index, IndexMut};
 
-use crate::remote_mapping::page_structures::PhysAddrBitFlag::{Cache, Prefetch, ReadOnly};
+use crate::remote_mapping::page_structures::PhysAddrBitFlag::{
+    Cache, Compressed, Prefetch, ReadOnly, Zero,
+};
 pub use x86_64::{
     align_down, align_up,
     structures::paging::{Page, Size4KiB},
@@ -64,7 +66,7 @@ impl Drop for PageTable {
                         if physaddr.is_prefetch() {
                             // free the page
                             let page = PhysAddr::decode(*entry as _) as *mut crate::bindings::page;
-                            unsafe { crate::bindings::pmem_free_page(page) };
+                            unsafe { crate::bindings::pmem_put_page(page) };
                         }
                     }
                 }
@@ -367,21 +369,29 @@ pub enum PhysAddrBitFlag {
     Prefetch = 0b0001,
     Cache = 0b0010,
     ReadOnly = 0b0100,
+    Zero = 0b1000,
+    Compressed = 0b10000,
 }
 
 impl PhysAddrBitFlag {
     pub fn mask() -> u64 {
-        Prefetch as u64 | Cache as u64 | ReadOnly as u64
+        Prefetch as u64 | Cache as u64 | ReadOnly as u64 | Zero as u64 | Compressed as u64
     }
 }
 
+/// The length of a compressed page is encoded above the physical address bits
+const COMPRESSED_LEN_SHIFT: u64 = 52;
+
+/// The alignment of a compressed page in the store, which leaves the flag bits free
+pub const COMPRESSED_ALIGN: usize = 32;
+
 /// Credits: most code is from x86_64, just remove unnecessary checks
 /// If the crate updates, we can switch back to it
 ///
 /// Encoding formation:
 ///
-/// |   *mut page   |   ro bit  |   cache bit   |   prefetch bit    |
-/// |   63          |       1   |       1       |       1           |
+/// | *mut page | compressed bit | zero bit | ro bit | cache bit | prefetch bit |
+/// |   63      |       1        |    1     |   1    |     1     |      1       |
 ///
 /// - The Prefetch flag is only set at child-side (DCAsyncPreFetcher). It means the
 ///   page has already been async fetched.
@@ -389,6 +399,13 @@ impl PhysAddrBitFlag {
 /// - The Cache flag is only set at child-side (Child trigger the cache miss and set it as COW)
 ///
 /// - The ReadOnly flag is only set at parent-side (walk the whole pte, and set read-only according to page flag)
+///
+/// - The Zero flag is only set at parent-side (the page is filled with zeros).
+///   The child maps a zeroed page instead of reading it.
+///
+/// - The Compressed flag is only set at parent-side (prepared with `PREPARE_COMPRESS`).
+///   The address is the one of the compressed page in the store, and its length
+///   is encoded in the bits 52 to 63, see `encode_compressed`.
 impl PhysAddr {
     /// Creates a new physical address.
     ///
@@ -492,8 +509,38 @@ impl PhysAddr {
         self.0 & ReadOnly as u64 == ReadOnly as u64
     }
 
+    /// Get Zero bit value
+    #[inline(always)]
+    pub fn is_zero(&self) -> bool {
+        self.0 & Zero as u64 == Zero as u64
+    }
+
+    /// Get Compressed bit value
+    #[inline(always)]
+    pub fn is_compressed(&self) -> bool {
+        self.0 & Compressed as u64 == Compressed as u64
+    }
+
+    /// Encode a compressed page of `len` bytes at `blob_addr`,
+    /// which is aligned to `COMPRESSED_ALIGN`, and `len` is less than a page
+    #[inline(always)]
+    pub fn encode_compressed(blob_addr: u64, len: usize, flag: u64) -> u64 {
+        Self::encode(blob_addr, flag | Compressed as u64) | ((len as u64) << COMPRESSED_LEN_SHIFT)
+    }
+
+    /// The address and the length of the compressed page
+    #[inline(always)]
+    pub fn compressed_blob(&self) -> (u64, usize) {
+        (self.real_addr(), (self.0 >> COMPRESSED_LEN_SHIFT) as usize)
+    }
+
+    /// The address without the flags, and without the length of a compressed page.
+    /// The bits 52 to 63 are kept otherwise, e.g., in the kernel virtual addresses.
     #[inline(always)]
     pub fn real_addr(&self) -> u64 {
+        if self.is_compressed() {
+            return Self::decode(self.0) & ((1 << COMPRESSED_LEN_SHIFT) - 1);
+        }
         Self::decode(self.0)
     }
 
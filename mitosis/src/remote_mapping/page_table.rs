@@ -171,6 +171,24 @@ impl PageEntry {
     }
 }
 
+impl PageEntry {
+    /// The virtual address mapped by the entry,
+    /// assembled from the indexes of the entry and its page tables
+    pub fn virt_addr(&self) -> crate::kern_wrappers::mm::VirtAddrType {
+        let mut addr = (self.index as u64) << 12;
+        let mut shift = 12;
+        let mut pt = self.page;
+        unsafe {
+            while !(*pt).get_upper_level_page().is_null() {
+                shift += 9;
+                addr |= ((*pt).get_upper_level_page_index() as u64) << shift;
+                pt = (*pt).get_upper_level_page();
+            }
+        }
+        addr
+    }
+}
+
 impl crate::prefetcher::NeedPrefetch for PageEntry {
     fn need_prefetch(&self) -> bool {
         self.addr.bottom_bit() == false
@@ -0,0 +1,181 @@
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::{AtomicPtr, AtomicU8};
+
+use hashbrown::HashMap;
+
+use crate::bindings::page;
+use crate::kern_wrappers::mm::PhyAddrType;
+use crate::kern_wrappers::wait_queue::WaitQueue;
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+/// The image of a cached page: the origin machine, and the epoch of the image,
+/// which is unique on the origin across the images and their generations
+type ImageKey = (usize, u64);
+
+const FETCHING: u8 = 0;
+const READY: u8 = 1;
+const FAILED: u8 = 2;
+
+/// A page read by the first fault on the host, which the other faults wait on
+#[derive(Default)]
+struct CacheSlot {
+    state: AtomicU8,
+    page: AtomicPtr<page>,
+}
+
+impl CacheSlot {
+    /// Sleep on `wq` until the read of the first fault is done
+    ///
+    /// Return
+    /// * None if the read failed, e.g., the memcg of its child is exhausted
+    fn wait(&self, wq: &WaitQueue) -> Option<*mut page> {
+        wq.wait_until(|| self.state.load(SeqCst) != FETCHING);
+        match self.state.load(SeqCst) {
+            READY => Some(self.page.load(SeqCst)),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for CacheSlot {
+    fn drop(&mut self) {
+        let p = self.page.load(SeqCst);
+        if !p.is_null() {
+            // freed once the children mapping it have also released it
+            unsafe { crate::bindings::pmem_put_page(p) };
+        }
+    }
+}
+
+#[derive(Default)]
+struct CachedImage {
+    // the children of the image on the host
+    children: usize,
+    // remote PA -> the page
+    pages: HashMap<PhyAddrType, Arc<CacheSlot>>,
+}
+
+/// A per-host cache of the remote pages read by the concurrent children of the same image,
+/// so that each page is read once over the network by all the children on the host.
+///
+/// The concurrent faults on the same page are deduplicated: the first one reads the page,
+/// and the others wait on its read. The pages of an image are released once its last child
+/// on the host exits, unlike `RemotePageTableCache`, which keeps the page table of the exited ones.
+///
+/// The cached pages are owned by the host, and are not charged to the memcg of any child.
+pub struct SharedPageCache {
+    images: BoxedLockBundler<HashMap<ImageKey, CachedImage>>,
+    // the faults waiting on the reads of the others sleep on it
+    wq: WaitQueue,
+}
+
+impl Default for SharedPageCache {
+    fn default() -> Self {
+        Self {
+            images: LockBundler::new(HashMap::new()),
+            wq: WaitQueue::new(),
+        }
+    }
+}
+
+impl SharedPageCache {
+    /// A child resumed from the image `epoch` of machine `remote_mac_id` starts reading its pages
+    pub fn attach(&self, remote_mac_id: usize, epoch: u64) {
+        self.images.lock(|images| {
+            images.entry((remote_mac_id, epoch)).or_default().children += 1;
+        });
+    }
+
+    /// A child of the image exits, the cached pages are released with the last child
+    pub fn detach(&self, remote_mac_id: usize, epoch: u64) {
+        let released = self.images.lock(|images| {
+            let key = (remote_mac_id, epoch);
+            let image = images.get_mut(&key)?;
+            image.children -= 1;
+            if image.children > 0 {
+                return None;
+            }
+            images.remove(&key)
+        });
+        if let Some(image) = released {
+            crate::log::debug!(
+                "release {} cached pages of image {} from machine {}",
+                image.pages.len(),
+                epoch,
+                remote_mac_id
+            );
+        }
+    }
+
+    /// Return the page at `remote_pa` of the image, read with `read` by the first fault on
+    /// the host. The page is owned by the cache, and stays valid until the caller's child detaches.
+    ///
+    /// Return
+    /// * None if the page cannot be read, or the caller's child is not attached
+    pub fn fetch(
+        &self,
+        remote_mac_id: usize,
+        epoch: u64,
+        remote_pa: PhyAddrType,
+        read: impl FnOnce() -> Option<*mut page>,
+    ) -> Option<*mut page> {
+        let key = (remote_mac_id, epoch);
+        let slot = loop {
+            let (slot, owned) = self.images.lock(|images| {
+                let image = images.get_mut(&key)?;
+                if let Some(slot) = image.pages.get(&remote_pa) {
+                    return Some((slot.clone(), false));
+                }
+                let slot = Arc::new(CacheSlot::default());
+                image.pages.insert(remote_pa, slot.clone());
+                Some((slot, true))
+            })?;
+            if owned {
+                break slot;
+            }
+            // a second fault waits on the read of the first one
+            if let Some(p) = slot.wait(&self.wq) {
+                return Some(p);
+            }
+            // the first read failed, retry with mine
+        };
+
+        let res = read();
+        match res {
+            Some(p) => {
+                // read (and charged) by the caller's child, but shared by all the children
+                unsafe { crate::bindings::pmem_uncharge_page(p) };
+                slot.page.store(p, SeqCst);
+                slot.state.store(READY, SeqCst);
+                self.wq.wake_up_all();
+            }
+            None => {
+                slot.state.store(FAILED, SeqCst);
+                self.wq.wake_up_all();
+                self.images.lock(|images| {
+                    if let Some(image) = images.get_mut(&key) {
+                        let stale = image
+                            .pages
+                            .get(&remote_pa)
+                            .map(|s| Arc::ptr_eq(s, &slot))
+                            .unwrap_or(false);
+                        if stale {
+                            image.pages.remove(&remote_pa);
+                        }
+                    }
+                });
+            }
+        }
+        res
+    }
+
+    /// The number of the cached pages on the host
+    pub fn num(&self) -> usize {
+        self.images
+            .lock(|images| images.values().map(|i| i.pages.len()).sum())
+    }
+}
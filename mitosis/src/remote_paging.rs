@@ -80,22 +80,119 @@ impl AccessInfo {
 pub struct RemotePagingService;
 
 use crate::remote_mapping::PhysAddr;
+use crate::rpc_handlers::RemoteDescriptor;
+use crate::KRdmaKit::consts::MAX_KMALLOC_SZ;
+use alloc::vec::Vec;
+use os_network::bytes::{BytesMut, ToBytes};
 use os_network::msg::UDMsg as RMemory;
+use rust_kernel_rdma_base::VmallocAllocator;
+
+/// The head section (or the whole descriptor of one chunk) fetched from the parent
+pub(crate) enum DescriptorBuf {
+    // read directly, for a range within one chunk
+    Registered(RMemory),
+    // assembled from the chunks
+    Staged(Vec<u8, VmallocAllocator>, BytesMut),
+}
+
+impl DescriptorBuf {
+    pub(crate) fn get_bytes(&self) -> &BytesMut {
+        match self {
+            Self::Registered(buf) => buf.get_bytes(),
+            Self::Staged(_, bytes) => bytes,
+        }
+    }
+}
 
 impl RemotePagingService {
+    /// Fetch the bytes [off, off + len) of the descriptor into a contiguous buffer.
+    /// A range within one chunk is read directly, otherwise the range is read
+    /// piece by piece into a bounce buffer, and assembled in a staging buffer.
+    ///
+    /// It is used for the head section, which spans multiple chunks only for a process
+    /// with lots of VMAs. The page tables are fetched as streams, see `descriptor_pieces`.
+    pub(crate) fn remote_descriptor_range_fetch(
+        d: &RemoteDescriptor,
+        off: usize,
+        len: usize,
+        machine_id: c_ulong,
+    ) -> Result<DescriptorBuf, os_network::rdma::Err> {
+        if off % MAX_KMALLOC_SZ + len <= MAX_KMALLOC_SZ {
+            let buf = Self::alloc_descriptor_buf(len, machine_id);
+            Self::remote_descriptor_piece_fetch(d, off, len, &buf, machine_id)?;
+            return Ok(DescriptorBuf::Registered(buf));
+        }
+
+        let mut staging: Vec<u8, VmallocAllocator> = Vec::with_capacity_in(len, VmallocAllocator);
+        let bounce = Self::alloc_descriptor_buf(MAX_KMALLOC_SZ, machine_id);
+        for (cur, sz) in Self::descriptor_pieces(off, len) {
+            Self::remote_descriptor_piece_fetch(d, cur, sz, &bounce, machine_id)?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    bounce.get_bytes().get_ptr(),
+                    staging.as_mut_ptr().add(cur - off),
+                    sz,
+                )
+            };
+        }
+        let bytes = unsafe { BytesMut::from_raw(staging.as_mut_ptr(), len) };
+        Ok(DescriptorBuf::Staged(staging, bytes))
+    }
+
+    /// Split the bytes [off, off + len) of the descriptor into the (offset, length) pieces,
+    /// each stored in one chunk
+    pub(crate) fn descriptor_pieces(
+        off: usize,
+        len: usize,
+    ) -> impl Iterator<Item = (usize, usize)> {
+        let (mut cur, end) = (off, off + len);
+        core::iter::from_fn(move || {
+            if cur >= end {
+                return None;
+            }
+            let sz = core::cmp::min(MAX_KMALLOC_SZ - cur % MAX_KMALLOC_SZ, end - cur);
+            cur += sz;
+            Some((cur - sz, sz))
+        })
+    }
+
+    /// Read a piece of the descriptor (see `descriptor_pieces`) into `buf`
+    #[inline]
+    pub(crate) fn remote_descriptor_piece_fetch(
+        d: &RemoteDescriptor,
+        off: usize,
+        len: usize,
+        buf: &RMemory,
+        machine_id: c_ulong,
+    ) -> Result<(), os_network::rdma::Err> {
+        let pa = d.chunk_pa[off / MAX_KMALLOC_SZ] + (off % MAX_KMALLOC_SZ) as u64;
+        Self::remote_chunk_fetch(&d.reply, pa, buf, len, machine_id)
+    }
+
     #[cfg(not(feature = "use_rc"))]
     #[inline]
-    pub(crate) fn remote_descriptor_fetch(
-        d: crate::rpc_handlers::DescriptorLookupReply,
-        _caller: &mut crate::rpc_caller_pool::UDCaller,
+    pub(crate) fn alloc_descriptor_buf(sz: usize, _machine_id: c_ulong) -> RMemory {
+        let pool_idx = unsafe { crate::bindings::pmem_get_current_cpu() } as usize;
+        let dc_qp = unsafe { crate::get_dc_pool_service_mut().get_dc_qp(pool_idx) }
+            .expect("failed to get DCQP");
+        dc_qp.lock(|dc_qp| RMemory::new(sz, 0, dc_qp.get_qp().ctx().clone()))
+    }
+
+    #[cfg(not(feature = "use_rc"))]
+    /// read `sz` bytes of the descriptor chunk at `pa` into `descriptor_buf`
+    #[inline]
+    fn remote_chunk_fetch(
+        d: &crate::rpc_handlers::DescriptorLookupReply,
+        pa: u64,
+        descriptor_buf: &RMemory,
+        sz: usize,
         _machine_id: c_ulong,
-    ) -> Result<RMemory, <DCRemoteDevice as Future>::Error> {
+    ) -> Result<(), <DCRemoteDevice as Future>::Error> {
         let pool_idx = unsafe { crate::bindings::pmem_get_current_cpu() } as usize;
         let dc_qp = unsafe { crate::get_dc_pool_service_mut().get_dc_qp(pool_idx) }
             .expect("failed to get DCQP");
 
         dc_qp.lock(|dc_qp| {
-            let descriptor_buf = RMemory::new(d.sz, 0, dc_qp.get_qp().ctx().clone());
             let point = DatagramEndpoint::new(
                 dc_qp.get_qp().ctx(),
                 1, // local port is default to 1
@@ -112,16 +209,16 @@ impl RemotePagingService {
             unsafe {
                 remote_device.read(
                     &point,
-                    &d.pa,
+                    &pa,
                     &DCKeys::new(d.rkey),
                     &mut descriptor_buf.get_pa(),
-                    &d.sz)
+                    &sz)
             }?;
             
             // wait for the request to complete
             let mut timeout_device = Timeout::new(remote_device, 10 * TIMEOUT_USEC);
             match block_on(&mut timeout_device) {
-                Ok(_) => Ok(descriptor_buf),
+                Ok(_) => Ok(()),
                 Err(e) => {
                     if e.is_elapsed() {
                         // The fallback path? DC cannot distinguish from failures
@@ -179,11 +276,7 @@ impl RemotePagingService {
 
     #[cfg(feature = "use_rc")]
     #[inline]
-    pub(crate) fn remote_descriptor_fetch(
-        d: crate::rpc_handlers::DescriptorLookupReply,
-        _caller: &mut crate::rpc_caller_pool::UDCaller,
-        machine_id: c_ulong,
-    ) -> Result<RMemory, <RCRemoteDevice as Future>::Error> {
+    fn descriptor_rc_conn(machine_id: c_ulong) -> RCConn {
         let cpu_id = crate::get_calling_cpu_id();
         let session_id = unsafe {
             crate::startup::calculate_session_id(
@@ -194,22 +287,40 @@ impl RemotePagingService {
         };
 
         let rc_pool = unsafe { crate::get_rc_conn_pool_ref(cpu_id).expect("failed get rc conn pool") };
-        let rc = rc_pool.get_rc_conn(session_id).expect("failed get rc conn").clone();
+        rc_pool.get_rc_conn(session_id).expect("failed get rc conn").clone()
+    }
 
-        let descriptor_buf = RMemory::new(d.sz, 0, rc.get_qp().ctx().clone());
+    #[cfg(feature = "use_rc")]
+    #[inline]
+    pub(crate) fn alloc_descriptor_buf(sz: usize, machine_id: c_ulong) -> RMemory {
+        let rc = Self::descriptor_rc_conn(machine_id);
+        RMemory::new(sz, 0, rc.get_qp().ctx().clone())
+    }
+
+
+    #[cfg(feature = "use_rc")]
+    #[inline]
+    fn remote_chunk_fetch(
+        d: &crate::rpc_handlers::DescriptorLookupReply,
+        pa: u64,
+        descriptor_buf: &RMemory,
+        sz: usize,
+        machine_id: c_ulong,
+    ) -> Result<(), <RCRemoteDevice as Future>::Error> {
+        let rc = Self::descriptor_rc_conn(machine_id);
         let mut remote_device = RCRemoteDevice::new(rc);
         unsafe {
             remote_device.read(
                 &(),
-                &d.pa,
+                &pa,
                 &RCKeys::new(d.rc_rkey),
                 &mut descriptor_buf.get_pa(),
-                &d.sz)
+                &sz)
         }?;
 
         let mut timeout_device = Timeout::new(remote_device, 10 * TIMEOUT_USEC);
         match block_on(&mut timeout_device) {
-            Ok(_) => Ok(descriptor_buf),
+            Ok(_) => Ok(()),
             Err(e) => {
                 Err(e.into_inner().unwrap())
             }
@@ -0,0 +1,293 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use rust_kernel_linux_util::kthread;
+use rust_kernel_linux_util::kthread::JoinHandler;
+
+use os_network::timeout::TimeoutWRef;
+use os_network::block_on;
+
+use crate::child_exit::Attachment;
+use crate::descriptors::ChildDescriptor;
+use crate::error::{MitosisError, MitosisResult};
+use crate::kern_wrappers::eventfd::EventFd;
+use crate::kern_wrappers::wait_queue::WaitQueue;
+use crate::linux_kernel_module::c_types::{c_ulong, c_void};
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+use crate::remote_paging::{AccessInfo, RemotePagingService};
+use crate::KRdmaKit::consts::MAX_KMALLOC_SZ;
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+const TIMEOUT_USEC: i64 = 1000_000; // 1s
+
+/// The image of a remote process, fetched but not yet applied to the caller process
+pub struct FetchedImage {
+    pub descriptor: ChildDescriptor,
+    pub access_info: AccessInfo,
+    // the epoch of the image generation, see `SharedPageCache`
+    pub epoch: u64,
+    // released once the image is no longer read, e.g., it is dropped without being applied
+    pub(crate) attachment: Attachment,
+}
+
+/// Query the descriptor of `handler_id` at the remote machine, and fetch it with one-sided RDMA.
+/// Can be called in any (kernel) thread.
+///
+/// The caller is attached to the image by the query, which is released if the fetch fails.
+pub fn fetch_image(machine_id: c_ulong, handler_id: c_ulong) -> MitosisResult<FetchedImage> {
+    let attachment =
+        unsafe { crate::get_exit_reporter_ref() }.attach(machine_id as _, handler_id as _);
+    let req = crate::rpc_handlers::DescriptorLookupRequest {
+        handler_id: handler_id as _,
+        mac_id: unsafe { crate::get_mac_id() },
+        token: attachment.token(),
+    };
+
+    let cpu_id = crate::get_calling_cpu_id();
+    assert!(cpu_id < unsafe { *(crate::max_caller_num::get_ref()) });
+
+    // ourself must have been connected in the startup process
+    let remote_session_id = unsafe {
+        crate::startup::calculate_session_id(
+            machine_id as _,
+            cpu_id,
+            *crate::max_caller_num::get_ref(),
+        )
+    };
+
+    let my_session_id = unsafe {
+        crate::startup::calculate_session_id(
+            *crate::mac_id::get_ref(),
+            cpu_id,
+            *crate::max_caller_num::get_ref(),
+        )
+    };
+
+    // send an RPC to the remote to query the descriptor address
+    let caller = unsafe {
+        crate::rpc_caller_pool::CallerPool::get_global_caller(cpu_id)
+            .expect("the caller should be properly initialized")
+    };
+    caller.lock(|caller| {
+        let res = caller.sync_call(
+            remote_session_id,
+            my_session_id,
+            crate::rpc_handlers::RPCId::Query as _,
+            req,
+        );
+
+        if res.is_err() {
+            crate::log::error!("failed to call {:?}", res);
+            crate::log::info!(
+                "sanity check pending reqs {:?}",
+                caller.get_pending_reqs(remote_session_id)
+            );
+            // the session to the remote is not established
+            return Err(MitosisError::NotConnected);
+        };
+
+        let mut timeout_caller = TimeoutWRef::new(caller, 10 * TIMEOUT_USEC);
+
+        use crate::rpc_handlers::RemoteDescriptor;
+        use os_network::serialize::Serialize;
+
+        match block_on(&mut timeout_caller) {
+            Ok((msg, reply)) => {
+                // first re-purpose the data
+                caller
+                    .register_recv_buf(msg)
+                    .expect("register msg buffer cannot fail");
+                let remote = RemoteDescriptor::deserialize(&reply).ok_or_else(|| {
+                    crate::log::error!("Deserialize error");
+                    MitosisError::Protocol
+                })?;
+                let d = remote.reply;
+                crate::log::debug!("sanity check query descriptor result {:?}", d);
+
+                if !d.ready {
+                    crate::log::error!("failed to lookup handler id: {:?}", handler_id);
+                    return Err(MitosisError::NotFound);
+                }
+                #[cfg(feature = "resume-profile")]
+                crate::log::info!("meta descriptor size:{} KB", d.sz / 1024);
+
+                // a descriptor of multiple chunks is resumed with its head section,
+                // and the page tables are fetched on demand (or in the background)
+                let lazy = d.sz > MAX_KMALLOC_SZ && d.head_sz > 0 && d.head_sz < d.sz;
+                let len = if lazy { d.head_sz } else { d.sz };
+
+                // fetch the descriptor with one-sided RDMA
+                let desc_buf =
+                    RemotePagingService::remote_descriptor_range_fetch(&remote, 0, len, machine_id)
+                        .map_err(|e| {
+                            crate::log::error!("failed to fetch descriptor {:?}", e);
+                            MitosisError::Unreachable
+                        })?;
+
+                // deserialize
+                let descriptor = if lazy {
+                    ChildDescriptor::deserialize_head(desc_buf.get_bytes(), remote, machine_id as _)
+                } else {
+                    ChildDescriptor::deserialize(desc_buf.get_bytes())
+                }
+                .ok_or(MitosisError::Protocol)?;
+
+                let access_info = AccessInfo::new(&descriptor.machine_info).ok_or_else(|| {
+                    // the DC pool is exhausted
+                    crate::log::error!("failed to create access info");
+                    MitosisError::OutOfMemory
+                })?;
+
+                Ok(FetchedImage {
+                    descriptor,
+                    access_info,
+                    epoch: d.epoch,
+                    attachment,
+                })
+            }
+            Err(e) => {
+                crate::log::error!("client receiver reply err {:?}", e);
+                if e.is_elapsed() {
+                    Err(MitosisError::Timeout)
+                } else {
+                    Err(MitosisError::Internal)
+                }
+            }
+        }
+    })
+}
+
+pub enum ResumeState {
+    Pending,
+    Fetched(FetchedImage),
+    Failed(MitosisError),
+    // the result has been taken by the caller
+    Taken,
+}
+
+/// An asynchronous resume submitted by the user
+pub struct ResumeTask {
+    pub machine_id: c_ulong,
+    pub handler_id: c_ulong,
+    state: BoxedLockBundler<ResumeState>,
+    eventfd: Option<EventFd>,
+}
+
+impl ResumeTask {
+    pub fn new(machine_id: c_ulong, handler_id: c_ulong, eventfd: Option<EventFd>) -> Arc<Self> {
+        Arc::new(Self {
+            machine_id,
+            handler_id,
+            state: LockBundler::new(ResumeState::Pending),
+            eventfd,
+        })
+    }
+
+    #[inline]
+    pub fn is_pending(&self) -> bool {
+        self.state.lock(|s| matches!(s, ResumeState::Pending))
+    }
+
+    /// Take the fetched image
+    ///
+    /// Return
+    /// * MitosisError::InProgress if the image is still being fetched
+    pub fn take(&self) -> MitosisResult<FetchedImage> {
+        self.state.lock(|s| match core::mem::replace(s, ResumeState::Taken) {
+            ResumeState::Pending => {
+                *s = ResumeState::Pending;
+                Err(MitosisError::InProgress)
+            }
+            ResumeState::Fetched(image) => Ok(image),
+            ResumeState::Failed(e) => Err(e),
+            ResumeState::Taken => Err(MitosisError::InvalidArgument),
+        })
+    }
+
+    fn run(&self) {
+        let res = fetch_image(self.machine_id, self.handler_id);
+        self.state.lock(|s| {
+            *s = match res {
+                Ok(image) => ResumeState::Fetched(image),
+                Err(e) => ResumeState::Failed(e),
+            }
+        });
+        if let Some(e) = self.eventfd.as_ref() {
+            e.signal();
+        }
+    }
+}
+
+/// Kernel threads that fetch the images of the asynchronous resumes
+pub struct ResumeWorkerService {
+    queue: BoxedLockBundler<VecDeque<Arc<ResumeTask>>>,
+    // the number of the queued tasks, checked by the idle workers without the lock
+    queued: AtomicUsize,
+    // the idle workers sleep on it until a task is submitted (or they are stopped)
+    wq: WaitQueue,
+    threads: Vec<JoinHandler>,
+}
+
+impl ResumeWorkerService {
+    pub fn new() -> Self {
+        Self {
+            queue: LockBundler::new(VecDeque::new()),
+            queued: AtomicUsize::new(0),
+            wq: WaitQueue::new(),
+            threads: Vec::new(),
+        }
+    }
+
+    /// Start the workers, must be called after the service is installed as the global one
+    pub fn start(&mut self, num: usize) -> core::option::Option<()> {
+        for i in 0..num {
+            let arg_ptr = Box::into_raw(Box::new(i));
+            let builder = kthread::Builder::new()
+                .set_name(alloc::format!("MITOSIS resume worker {}", i))
+                .set_parameter(arg_ptr as *mut c_void);
+            self.threads.push(builder.spawn(Self::worker).ok()?);
+        }
+        Some(())
+    }
+
+    #[inline]
+    pub fn submit(&self, task: Arc<ResumeTask>) {
+        self.queue.lock(|q| q.push_back(task));
+        self.queued.fetch_add(1, SeqCst);
+        self.wq.wake_up_all();
+    }
+
+    extern "C" fn worker(ctx: *mut c_void) -> i32 {
+        let id = unsafe { Box::from_raw(ctx as *mut usize) };
+        crate::log::debug!("MITOSIS resume worker {} started", id);
+
+        let service = unsafe { crate::get_resume_worker_ref() };
+        while !kthread::should_stop() {
+            service.wq.wait_interruptible(|| {
+                kthread::should_stop() || service.queued.load(SeqCst) > 0
+            });
+            if let Some(task) = service.queue.lock(|q| q.pop_front()) {
+                service.queued.fetch_sub(1, SeqCst);
+                task.run();
+            }
+        }
+
+        crate::log::info!("MITOSIS resume worker {} ended", id);
+        0
+    }
+}
+
+impl Drop for ResumeWorkerService {
+    fn drop(&mut self) {
+        while let Some(handler) = self.threads.pop() {
+            handler.join();
+        }
+        // the pending tasks are dropped without being fetched
+        self.queue.lock(|q| q.clear());
+    }
+}
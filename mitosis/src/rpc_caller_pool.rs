@@ -35,6 +35,17 @@ pub struct CallerPool<'a> {
 
 use os_network::MetaFactory;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectError {
+    /// Failed to create the meta data of the remote end
+    Meta,
+    /// The session ID has been connected at the local side
+    AlreadyConnected,
+    /// The remote end refused the connection, e.g., my session ID is occupied
+    Refused,
+    Fatal,
+}
+
 impl<'a> CallerPool<'a> {
     #[inline(always)]
     pub unsafe fn get_global_caller(
@@ -65,17 +76,18 @@ impl<'a> CallerPool<'a> {
         session_id: usize,
         my_session_id : usize,
         meta: UDHyperMeta,
-    ) -> core::option::Option<()> {
+    ) -> core::result::Result<(), ConnectError> {
         // fetch by sidr connect
-        let meta = self.create_meta_at(idx, meta)?;
-        let my_gid = self.contexts.get(idx).unwrap().query_gid(1, 0).unwrap();
+        let meta = self.create_meta_at(idx, meta).ok_or(ConnectError::Meta)?;
+        let context = (*self.contexts.get(idx).unwrap()).clone();
+        let my_gid = context.query_gid(1, 0).unwrap();
         let (hint, service_id) = self.metas.get(idx).unwrap().clone();
 
-        let caller = self.get_caller(idx)?;
+        let caller = self.get_caller(idx).ok_or(ConnectError::Meta)?;
         caller.lock(|caller| {
             if caller.session_connected(session_id) {
                 crate::log::warn!("The session {} has already connected.", session_id);
-                return None;
+                return Err(ConnectError::AlreadyConnected);
             }
     
             let client_session = caller.get_transport_mut().create(meta).unwrap();
@@ -97,13 +109,27 @@ impl<'a> CallerPool<'a> {
                 .unwrap();
     
             // wait for the completion
-            let (msg, _reply) = os_network::block_on(caller).expect("should succeed");
-    
-            // TODO: check the_reply is correct
-    
-            caller.register_recv_buf(msg).unwrap();
-
-            Some(())
+            match os_network::block_on(caller) {
+                Ok((msg, _reply)) => {
+                    caller.register_recv_buf(msg).unwrap();
+                    Ok(())
+                }
+                Err(e) => {
+                    crate::log::warn!("The session {} is not connected: {:?}", session_id, e);
+                    caller.remove_session(session_id);
+
+                    // the failed reply consumes a receive buffer, so we re-post one
+                    caller
+                        .register_recv_buf(UDMsg::new(4096, 0, context))
+                        .expect("failed to register receive buffer for the RPC caller");
+
+                    if e.is_connect_error() {
+                        Err(ConnectError::Refused)
+                    } else {
+                        Err(ConnectError::Fatal)
+                    }
+                }
+            }
         })
     }
 
@@ -1,6 +1,11 @@
 use core::fmt::Write;
 use os_network::bytes::BytesMut;
 use os_network::serialize::Serialize;
+use os_network::KRdmaKit::comm_manager::Explorer;
+use alloc::vec::Vec;
+
+use crate::mem_pools::MAX_DESCRIPTOR_CHUNKS;
+use crate::KRdmaKit::consts::MAX_KMALLOC_SZ;
 
 #[derive(Debug)]
 #[repr(usize)]
@@ -11,6 +16,14 @@ pub enum RPCId {
     Echo = 2,
     // Resume fork by fetching remote descriptor
     Query = 3,
+    // Join the cluster with my machine ID
+    Join = 4,
+    // Leave the cluster
+    Leave = 5,
+    // Report the exit of a child to the machine of its parent
+    ChildExit = 6,
+    // Tell the machine of the parent that a child no longer reads its image
+    Detach = 7,
 }
 
 pub(crate) fn handle_nil(_input: &BytesMut, _output: &mut BytesMut) -> usize {
@@ -23,10 +36,29 @@ pub(crate) fn handle_echo(input: &BytesMut, output: &mut BytesMut) -> usize {
     64
 }
 
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct DescriptorLookupRequest {
+    // the key of the image
+    pub(crate) handler_id: usize,
+    // the machine of the child, and the token of its attachment (see `Attachment`),
+    // with which the retried lookups of one resume attach the child once
+    pub(crate) mac_id: usize,
+    pub(crate) token: u64,
+}
+
+impl os_network::serialize::Serialize for DescriptorLookupRequest {}
+
+/// The reply is followed by the RDMA addresses of the chunks storing the descriptor,
+/// each (but the last) of `MAX_KMALLOC_SZ` bytes, see `RemoteDescriptor`
 #[derive(Debug, Default, Copy, Clone)]
 pub(crate) struct DescriptorLookupReply {
-    pub(crate) pa: u64,
+    // the number of the chunks
+    pub(crate) chunks: u32,
     pub(crate) sz: usize,
+    // the length of the head section, with which the child can be resumed
+    pub(crate) head_sz: usize,
+    // the epoch of the image generation, see `SharedPageCache`
+    pub(crate) epoch: u64,
     pub(crate) ready: bool,
 
     // for remote dct access
@@ -43,12 +75,52 @@ pub(crate) struct DescriptorLookupReply {
 
 impl os_network::serialize::Serialize for DescriptorLookupReply {}
 
+/// A descriptor looked up on the machine of the parent: the reply and the chunk table
+/// following it, which is kept on the heap
+#[derive(Debug)]
+pub(crate) struct RemoteDescriptor {
+    pub(crate) reply: DescriptorLookupReply,
+    pub(crate) chunk_pa: Vec<u64>,
+}
+
+impl RemoteDescriptor {
+    /// Deserialize the reply of `handle_descriptor_addr_lookup`
+    pub(crate) fn deserialize(bytes: &BytesMut) -> core::option::Option<Self> {
+        let reply = DescriptorLookupReply::deserialize(bytes)?;
+        let chunks = reply.chunks as usize;
+        if chunks > MAX_DESCRIPTOR_CHUNKS || reply.sz > chunks * MAX_KMALLOC_SZ {
+            return None;
+        }
+        let mut cur = unsafe { bytes.truncate_header(reply.serialization_buf_len())? };
+        let mut chunk_pa = Vec::with_capacity(chunks);
+        for _ in 0..chunks {
+            let mut pa: u64 = 0;
+            let off = unsafe { cur.memcpy_deserialize(&mut pa)? };
+            cur = unsafe { cur.truncate_header(off)? };
+            chunk_pa.push(pa);
+        }
+        Some(Self { reply, chunk_pa })
+    }
+}
+
 pub(crate) fn handle_descriptor_addr_lookup(input: &BytesMut, output: &mut BytesMut) -> usize {
-    let mut key: usize = 0;
-    unsafe { input.memcpy_deserialize(&mut key) };
+    let req = match DescriptorLookupRequest::deserialize(input) {
+        Some(req) => req,
+        None => {
+            crate::log::error!("failed to deserialize the lookup request");
+            return 0;
+        }
+    };
+    let key = req.handler_id;
 
     let process_service = unsafe { crate::get_sps_mut() };
-    let buf = process_service.query_descriptor_buf(key);
+    // the chunks are copied out, as the image may be unregistered once the lock is released.
+    // The child reads the pages of the image until it releases the attachment.
+    let child = (req.mac_id, req.token);
+    let buf = process_service.lookup_descriptor(key, child, |buf, head_sz, epoch| {
+        let chunk_pa: Vec<u64> = buf.chunks().map(|(pa, _)| pa).collect();
+        (chunk_pa, buf.len(), head_sz, epoch)
+    });
 
     if buf.is_none() {
         crate::log::error!("empty addr, key:{}!", key);
@@ -68,11 +140,14 @@ pub(crate) fn handle_descriptor_addr_lookup(input: &BytesMut, output: &mut Bytes
     #[cfg(feature = "use_rc")]
     let rc_server = unsafe { crate::get_rc_service_ref(rc_server_idx).expect("fatal: cannot get the created rc service") };
     
-    let reply = match buf {
-        Some((addr, len)) => {
+    let chunk_pa = buf.as_ref().map(|(chunk_pa, ..)| chunk_pa.as_slice()).unwrap_or(&[]);
+    let reply = match buf.as_ref() {
+        Some((chunk_pa, sz, head_sz, epoch)) => {
             DescriptorLookupReply {
-                pa: addr.get_pa(),
-                sz: len,
+                chunks: chunk_pa.len() as u32,
+                sz: *sz,
+                head_sz: *head_sz,
+                epoch: *epoch,
                 ready: true,
 
                 rkey: dc_target.ctx().rkey(),
@@ -88,8 +163,10 @@ pub(crate) fn handle_descriptor_addr_lookup(input: &BytesMut, output: &mut Bytes
         None => {
             crate::log::error!("Failed to find the handner with id: {}!", key);
             DescriptorLookupReply {
-                pa: 0,
+                chunks: 0,
                 sz: 0,
+                head_sz: 0,
+                epoch: 0,
                 ready: false,
 
                 rkey: 0,
@@ -104,7 +181,150 @@ pub(crate) fn handle_descriptor_addr_lookup(input: &BytesMut, output: &mut Bytes
         }
     };
 
+    reply.serialize(output);
+    let mut cur = unsafe { output.truncate_header(reply.serialization_buf_len()).unwrap() };
+    for pa in chunk_pa {
+        let off = match unsafe { cur.memcpy_serialize_at(0, pa) } {
+            Some(off) => off,
+            None => {
+                crate::log::error!("the chunk table exceeds the reply buffer");
+                return 0;
+            }
+        };
+        cur = unsafe { cur.truncate_header(off).unwrap() };
+    }
+    reply.serialization_buf_len() + chunk_pa.len() * core::mem::size_of::<u64>()
+}
+
+
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct JoinRequest {
+    pub(crate) mac_id: usize,
+    pub(crate) nic_idx: usize,
+    pub(crate) gid: rust_kernel_rdma_base::ib_gid,
+}
+
+impl os_network::serialize::Serialize for JoinRequest {}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum JoinStatus {
+    #[default]
+    Accepted = 0,
+    // the requested machine ID is occupied
+    Conflict = 1,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct JoinReply {
+    pub(crate) status: JoinStatus,
+    // the machine ID of the replier
+    pub(crate) mac_id: usize,
+    // a free machine ID if the status is conflict
+    pub(crate) suggested_id: usize,
+}
+
+impl os_network::serialize::Serialize for JoinReply {}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct LeaveRequest {
+    pub(crate) mac_id: usize,
+    pub(crate) gid: rust_kernel_rdma_base::ib_gid,
+}
+
+impl os_network::serialize::Serialize for LeaveRequest {}
+
+pub(crate) fn handle_join(input: &BytesMut, output: &mut BytesMut) -> usize {
+    let req = match JoinRequest::deserialize(input) {
+        Some(req) => req,
+        None => {
+            crate::log::error!("failed to deserialize the join request");
+            return 0;
+        }
+    };
+    let gid = Explorer::gid_to_string(&req.gid);
+
+    let membership = unsafe { crate::get_membership_ref() };
+    let reply = match membership.lock(|t| t.on_join(req.mac_id, &gid, req.nic_idx)) {
+        Ok(_) => {
+            crate::log::info!("machine {} joined with ID {}", gid, req.mac_id);
+            JoinReply {
+                status: JoinStatus::Accepted,
+                mac_id: unsafe { crate::get_mac_id() },
+                suggested_id: req.mac_id,
+            }
+        }
+        Err(suggested_id) => JoinReply {
+            status: JoinStatus::Conflict,
+            mac_id: unsafe { crate::get_mac_id() },
+            suggested_id,
+        },
+    };
+
     reply.serialize(output);
     reply.serialization_buf_len()
 }
 
+pub(crate) fn handle_leave(input: &BytesMut, _output: &mut BytesMut) -> usize {
+    match LeaveRequest::deserialize(input) {
+        Some(req) => {
+            let gid = Explorer::gid_to_string(&req.gid);
+            let membership = unsafe { crate::get_membership_ref() };
+            if membership.lock(|t| t.on_leave(req.mac_id, &gid)) {
+                crate::log::info!("machine {} with ID {} left", gid, req.mac_id);
+            }
+        }
+        None => crate::log::error!("failed to deserialize the leave request"),
+    };
+    0
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct ChildExitReport {
+    // the machine of the child
+    pub(crate) mac_id: usize,
+    // the key of the image that the child resumed from
+    pub(crate) handler_id: usize,
+    pub(crate) status: i32,
+    pub(crate) runtime_usec: u64,
+    pub(crate) fault_pages: u64,
+}
+
+impl os_network::serialize::Serialize for ChildExitReport {}
+
+pub(crate) fn handle_child_exit(input: &BytesMut, _output: &mut BytesMut) -> usize {
+    match ChildExitReport::deserialize(input) {
+        Some(report) => {
+            crate::log::debug!("child exit {:?}", report);
+            // the processes forked locally from the child may still read the image,
+            // which is released by the detach report instead
+            unsafe { crate::get_child_exit_service_ref() }.push(report);
+        }
+        None => crate::log::error!("failed to deserialize the child exit report"),
+    };
+    0
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct DetachReport {
+    // the machine of the child
+    pub(crate) mac_id: usize,
+    // the key of the image that the child resumed from
+    pub(crate) handler_id: usize,
+    // the token of the attachment released, see `DescriptorLookupRequest`
+    pub(crate) token: u64,
+}
+
+impl os_network::serialize::Serialize for DetachReport {}
+
+pub(crate) fn handle_detach(input: &BytesMut, _output: &mut BytesMut) -> usize {
+    match DetachReport::deserialize(input) {
+        Some(report) => {
+            crate::log::debug!("child detached {:?}", report);
+            let child = (report.mac_id, report.token);
+            unsafe { crate::get_sps_mut() }.detach_child(report.handler_id, child);
+        }
+        None => crate::log::error!("failed to deserialize the detach report"),
+    };
+    0
+}
@@ -262,6 +262,18 @@ impl Service {
         rpc_server
             .get_mut_service()
             .register(RPCId::Query as _, handle_descriptor_addr_lookup);
+        rpc_server
+            .get_mut_service()
+            .register(RPCId::Join as _, handle_join);
+        rpc_server
+            .get_mut_service()
+            .register(RPCId::Leave as _, handle_leave);
+        rpc_server
+            .get_mut_service()
+            .register(RPCId::ChildExit as _, handle_child_exit);
+        rpc_server
+            .get_mut_service()
+            .register(RPCId::Detach as _, handle_detach);
 
         // register msg buffers
         // pre-most receive buffers
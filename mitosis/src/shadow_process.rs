@@ -1,8 +1,13 @@
 pub use vma::*;
 pub use page_table::*;
 pub use page::*;
+pub use hints::*;
+pub use compress::*;
+pub use dedup::*;
 
-use crate::descriptors::{ParentDescriptor, CompactPageTable};
+use crate::descriptors::{ParentDescriptor, CompactPageTable, VMADescriptor};
+use crate::kern_wrappers::mm::{MemoryDescriptor, VirtAddrType};
+use crate::prepare_worker::JobSlots;
 use alloc::vec::Vec;
 use rust_kernel_rdma_base::VmallocAllocator;
 
@@ -20,18 +25,171 @@ pub struct ShadowProcess {
     // FIXME: maybe we should use enum for this?
     copy_shadow_pagetable: core::option::Option<ShadowPageTable<Copy4KPage>>,
     cow_shadow_pagetable: core::option::Option<ShadowPageTable<COW4KPage>>,
+
+    // the compressed copies of the recorded pages, if prepared with `PREPARE_COMPRESS`
+    compressed: core::option::Option<CompressedStore>,
 }
 
 impl ShadowProcess {
     pub fn get_descriptor_ref(&self) -> &ParentDescriptor {
         &self.descriptor
     }
+
+    /// The number of pages pinned (or copied) by the shadow process
+    pub fn page_count(&self) -> usize {
+        self.copy_shadow_pagetable.as_ref().map(|pt| pt.len()).unwrap_or(0)
+            + self.cow_shadow_pagetable.as_ref().map(|pt| pt.len()).unwrap_or(0)
+    }
+
+    /// The stats of the recorded pages, e.g., the ratio of the compression
+    pub fn image_stat(&self) -> mitosis_protocol::ImageStat {
+        let mut res = mitosis_protocol::ImageStat::default();
+        for pt in self.descriptor.page_table.iter() {
+            res.pages += pt.table_len() as u64;
+            res.zero_pages += pt
+                .entries()
+                .iter()
+                .filter(|(_, v)| crate::remote_mapping::PhysAddr::new(*v).is_zero())
+                .count() as u64;
+        }
+
+        let (compressed_pages, compressed_bytes) = self
+            .compressed
+            .as_ref()
+            .map(|c| (c.compressed_pages() as u64, c.compressed_bytes() as u64))
+            .unwrap_or((0, 0));
+        res.compressed_pages = compressed_pages;
+        // the pages stored as they are are read in whole
+        res.stored_bytes =
+            (res.pages - res.zero_pages - compressed_pages) * 4096 + compressed_bytes;
+        res
+    }
+
+    /// Whether the shadow process can be refreshed by `update_cow_w_hints`
+    pub fn is_updatable(&self) -> bool {
+        self.compressed.is_none() && self.cow_shadow_pagetable.is_some()
+    }
+
+    /// Refresh the shadow process (prepared by `new_cow_w_hints`) with the current state of
+    /// the caller, which must be the process that prepared it.
+    ///
+    /// Only the pages changed since the previous generation are marked COW and pinned again,
+    /// see `VMACOWPTUpdater`. The VMAs are matched by their start addresses.
+    /// The replaced pages are retired instead of released, see `RetiredPages`.
+    ///
+    /// Return
+    /// * the number of the changed pages, and the replaced VMAs and pages,
+    ///   None if the shadow process copies the pages, or stores them compressed
+    pub fn update_cow_w_hints(
+        &mut self,
+        hints: &PrepareHints,
+    ) -> core::option::Option<(usize, RetiredPages)> {
+        if self.compressed.is_some() {
+            return None;
+        }
+        let mut pins = self.cow_shadow_pagetable.take()?.into_pins();
+        let mut shadow_pt = ShadowPageTable::<COW4KPage>::new();
+        let mut shadow_vmas: Vec<ShadowVMA<'static>> = Vec::new();
+
+        let mut vma_descriptors = Vec::new();
+        let mut vma_page_table: Vec<CompactPageTable, VmallocAllocator> = Vec::new_in(VmallocAllocator);
+        let task = crate::kern_wrappers::task::Task::new();
+        let mut mm = task.get_memory_descriptor();
+
+        // the PTEs are write-protected under the mmap_sem, until the TLB is flushed
+        unsafe { crate::bindings::pmem_mmap_read_lock(mm.get_raw_ptr()) };
+        let mut files = Vec::new();
+        for vma in mm.get_vma_iter() {
+            vma_descriptors.push(vma.generate_descriptor_w_files(hints, &mut files));
+            shadow_vmas.push(ShadowVMA::new(vma, true));
+            vma_page_table.push(Default::default());
+        }
+
+        let empty = CompactPageTable::default();
+        let mut changed = 0;
+        for idx in 0..vma_descriptors.len() {
+            if vma_descriptors[idx].is_host_specific() {
+                continue;
+            }
+            // the VMAs of the previous generation are sorted by the address as well
+            let start = vma_descriptors[idx].get_start();
+            let prev_flat = self
+                .descriptor
+                .vma
+                .binary_search_by_key(&start, |v| v.get_start())
+                .map(|i| &self.descriptor.page_table[i])
+                .unwrap_or(&empty);
+
+            let pt: &mut CompactPageTable = vma_page_table.get_mut(idx).unwrap();
+            changed += VMACOWPTUpdater::new(
+                &shadow_vmas[idx],
+                &mut shadow_pt,
+                pt,
+                hints,
+                prev_flat,
+                &mut pins,
+            )
+            .generate();
+            vma_descriptors[idx].has_pages = pt.table_len() > 0;
+        }
+        // clear the TLB
+        mm.flush_tlb_mm();
+        unsafe { crate::bindings::pmem_mmap_read_unlock(mm.get_raw_ptr()) };
+
+        let mut retired = RetiredPages {
+            vmas: core::mem::replace(&mut self.shadow_vmas, shadow_vmas),
+            pagetable: ShadowPageTable::new(),
+        };
+        pins.retire_into(&mut retired.pagetable);
+        self.cow_shadow_pagetable = Some(shadow_pt);
+
+        self.descriptor.regs = task.generate_reg_descriptor();
+        self.descriptor.layout = task.generate_layout_descriptor();
+        self.descriptor.page_table = vma_page_table;
+        self.descriptor.vma = vma_descriptors;
+        self.descriptor.files = files;
+        self.descriptor.excluded = hints.excluded().to_vec();
+        Some((changed, retired))
+    }
+}
+
+/// The VMAs and pages of a shadow process replaced by an update,
+/// which may still be read by the children resumed from the previous generations
+pub struct RetiredPages {
+    #[allow(dead_code)] // released with the pages
+    vmas: Vec<ShadowVMA<'static>>,
+    pagetable: ShadowPageTable<COW4KPage>,
+}
+
+impl Default for RetiredPages {
+    fn default() -> Self {
+        Self {
+            vmas: Vec::new(),
+            pagetable: ShadowPageTable::new(),
+        }
+    }
+}
+
+impl RetiredPages {
+    /// The number of pages pinned
+    pub fn page_count(&self) -> usize {
+        self.pagetable.len()
+    }
 }
 
 impl ShadowProcess {
     /// Crate a new shadow processing by marking all the
     /// memories of the original one to copy-on-write(COW).
     pub fn new_cow(rdma_descriptor: crate::descriptors::RDMADescriptor) -> Self {
+        Self::new_cow_w_hints(rdma_descriptor, &Default::default())
+    }
+
+    /// Same as `new_cow`, except that the pages in the excluded ranges of `hints`
+    /// are not marked COW and not recorded in the descriptor
+    pub fn new_cow_w_hints(
+        rdma_descriptor: crate::descriptors::RDMADescriptor,
+        hints: &PrepareHints,
+    ) -> Self {
         let mut shadow_pt = ShadowPageTable::<COW4KPage>::new();
         let mut shadow_vmas: Vec<ShadowVMA<'static>> = Vec::new();
 
@@ -41,34 +199,56 @@ impl ShadowProcess {
         let task = crate::kern_wrappers::task::Task::new();
         let mut mm = task.get_memory_descriptor();
 
+        // held for the whole walk of the workers, until the TLB is flushed
+        unsafe { crate::bindings::pmem_mmap_read_lock(mm.get_raw_ptr()) };
+        let mut files = Vec::new();
         for vma in mm.get_vma_iter() {
-            vma_descriptors.push(vma.generate_descriptor());
+            vma_descriptors.push(vma.generate_descriptor_w_files(hints, &mut files));
             shadow_vmas.push(ShadowVMA::new(vma, true));
             vma_page_table.push(Default::default());
         }
 
-        for (idx, _) in mm.get_vma_iter().enumerate() {
-            let pt: &mut CompactPageTable = vma_page_table.get_mut(idx).unwrap();
-            let s_vma = shadow_vmas.get(idx).unwrap();
-            VMACOWPTGenerator::new(s_vma, &mut shadow_pt, pt).generate();
-        }
-        // clear the TLB
+        walk_in_parallel(
+            &mm,
+            &shadow_vmas,
+            &mut vma_descriptors,
+            &mut vma_page_table,
+            &mut shadow_pt,
+            &|s_vma, shadow_pt, pt, start, end| {
+                VMACOWPTGenerator::new(s_vma, shadow_pt, pt, hints).generate_range(start, end)
+            },
+        );
+        // clear the TLB, after all the workers have marked the pages COW
         mm.flush_tlb_mm();
+        unsafe { crate::bindings::pmem_mmap_read_unlock(mm.get_raw_ptr()) };
+        let compressed = Self::compress_w_hints(&mut vma_page_table, hints);
 
         Self {
             shadow_vmas,
             cow_shadow_pagetable: Some(shadow_pt),
             copy_shadow_pagetable: None,
+            compressed,
             descriptor: ParentDescriptor {
                 machine_info: rdma_descriptor,
                 regs: task.generate_reg_descriptor(),
+                layout: task.generate_layout_descriptor(),
                 page_table: vma_page_table,
                 vma: vma_descriptors,
+                files,
+                excluded: hints.excluded().to_vec(),
             },
         }
     }
 
     pub fn new_copy(rdma_descriptor: crate::descriptors::RDMADescriptor) -> Self {
+        Self::new_copy_w_hints(rdma_descriptor, &Default::default())
+    }
+
+    /// Same as `new_copy`, except that the pages in the excluded ranges of `hints` are not copied
+    pub fn new_copy_w_hints(
+        rdma_descriptor: crate::descriptors::RDMADescriptor,
+        hints: &PrepareHints,
+    ) -> Self {
         let mut shadow_pt = ShadowPageTable::<Copy4KPage>::new();
         let mut shadow_vmas: Vec<ShadowVMA<'static>> = Vec::new();
 
@@ -79,34 +259,124 @@ impl ShadowProcess {
         let mm = task.get_memory_descriptor();
 
         // crate::log::debug!("before iterating the VMAs");
+        let mut files = Vec::new();
         for vma in mm.get_vma_iter() {
-            vma_descriptors.push(vma.generate_descriptor());
+            vma_descriptors.push(vma.generate_descriptor_w_files(hints, &mut files));
             shadow_vmas.push(ShadowVMA::new(vma, false));
             vma_page_table.push(Default::default());
         }
 
         // crate::log::debug!("before iterating the page table");
-        for (idx, _) in mm.get_vma_iter().enumerate() {
-            let pt: &mut CompactPageTable = vma_page_table.get_mut(idx).unwrap();
-            let s_vma = shadow_vmas.get(idx).unwrap();
-            VMACopyPTGenerator::new(s_vma, &mut shadow_pt, pt).generate();
-        }
+        walk_in_parallel(
+            &mm,
+            &shadow_vmas,
+            &mut vma_descriptors,
+            &mut vma_page_table,
+            &mut shadow_pt,
+            &|s_vma, shadow_pt, pt, start, end| {
+                VMACopyPTGenerator::new(s_vma, shadow_pt, pt, hints).generate_range(start, end)
+            },
+        );
+        let compressed = Self::compress_w_hints(&mut vma_page_table, hints);
 
         Self {
             shadow_vmas,
             cow_shadow_pagetable: None,
             copy_shadow_pagetable: Some(shadow_pt),
+            compressed,
             descriptor: ParentDescriptor {
                 machine_info: rdma_descriptor,
                 regs: task.generate_reg_descriptor(),
+                layout: task.generate_layout_descriptor(),
                 page_table: vma_page_table,
                 vma: vma_descriptors,
+                files,
+                excluded: hints.excluded().to_vec(),
             },
         }
     }
 }
 
+impl ShadowProcess {
+    /// Build the compressed store of the recorded pages, if `hints` asks for it
+    fn compress_w_hints(
+        vma_page_table: &mut [CompactPageTable],
+        hints: &PrepareHints,
+    ) -> core::option::Option<CompressedStore> {
+        if !hints.compress() {
+            return None;
+        }
+        let store = CompressedStore::build(vma_page_table);
+        crate::log::debug!(
+            "compressed {} pages into {} KB",
+            store.compressed_pages(),
+            store.compressed_bytes() / 1024
+        );
+        Some(store)
+    }
+}
+
+/// The max number of pages walked by one job of the prepare workers,
+/// so that a large VMA is walked by multiple workers
+const PAGES_PER_WALK_JOB: VirtAddrType = 64 * 1024;
+
+/// Walk the VMAs with the prepare workers, each job walks a range of a VMA with `walk`.
+/// The page tables of the ranges are merged in the order of the address.
+///
+/// The COW walk write-protects the PTEs under their locks, so its caller holds the mmap_sem
+/// (for read) across the walk and the flush of the TLB. The copy walk reads the pages with
+/// faults, which take the mmap_sem themselves, so it is walked without it.
+fn walk_in_parallel<P: GetPhyAddr>(
+    mm: &MemoryDescriptor,
+    shadow_vmas: &[ShadowVMA<'static>],
+    vma_descriptors: &mut [VMADescriptor],
+    vma_page_table: &mut [CompactPageTable],
+    shadow_pt: &mut ShadowPageTable<P>,
+    walk: &dyn Fn(
+        &ShadowVMA,
+        &mut ShadowPageTable<P>,
+        &mut CompactPageTable,
+        VirtAddrType,
+        VirtAddrType,
+    ),
+) {
+    let mut jobs = Vec::new();
+    for (idx, vma) in vma_descriptors.iter().enumerate() {
+        // the child keeps its own vdso and vvar
+        if vma.is_host_specific() {
+            continue;
+        }
+        let mut start = vma.get_start();
+        while start < vma.get_end() {
+            let end = core::cmp::min(start + PAGES_PER_WALK_JOB * 4096, vma.get_end());
+            jobs.push((idx, start, end));
+            start = end;
+        }
+    }
+
+    let slots: JobSlots<(CompactPageTable, ShadowPageTable<P>)> = JobSlots::new(jobs.len());
+    // each job only writes its own slot, and the pages of different jobs are disjoint
+    unsafe {
+        crate::get_prepare_worker_ref().run(jobs.len(), mm.get_raw_ptr(), &|i| {
+            let (idx, start, end) = jobs[i];
+            let (pt, pages) = slots.get_mut(i);
+            walk(&shadow_vmas[idx], pages, pt, start, end);
+        })
+    };
+
+    for (&(idx, _, _), (pt, pages)) in jobs.iter().zip(slots.into_inner()) {
+        vma_page_table[idx].append(pt);
+        shadow_pt.append(pages);
+    }
+    for (idx, vma) in vma_descriptors.iter_mut().enumerate() {
+        vma.has_pages = vma_page_table[idx].table_len() > 0;
+    }
+}
+
 pub mod vma;
 pub mod page_table;
 pub mod page;
+pub mod hints;
+pub mod compress;
+pub mod dedup;
 
@@ -0,0 +1,201 @@
+use alloc::vec::Vec;
+
+use crate::bindings::*;
+use crate::descriptors::parent::{PageEntry, Value};
+use crate::descriptors::CompactPageTable;
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+use crate::prepare_worker::JobSlots;
+use crate::remote_mapping::{PhysAddr, PhysAddrBitFlag, COMPRESSED_ALIGN};
+use rust_kernel_rdma_base::VmallocAllocator;
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+const PAGE_SIZE: usize = 4096;
+
+/// A page is stored compressed only if it shrinks to at most this length,
+/// otherwise the children read the original page
+const MAX_COMPRESSED_LEN: usize = PAGE_SIZE * 3 / 4;
+
+/// The max number of pages compressed by one job of the prepare workers
+const PAGES_PER_COMPRESS_JOB: usize = 16 * 1024;
+
+/// The recorded pages of an image prepared with `PREPARE_COMPRESS`,
+/// compressed with LZ4 and packed into the kernel pages of the store.
+///
+/// The entries of the compressed pages in the descriptor are re-encoded with the address
+/// and the length of their compressed copies, see `PhysAddr::encode_compressed`.
+/// The children read the copies with one-sided reads, and decompress them at the page faults.
+#[derive(Default)]
+pub struct CompressedStore {
+    pages: Vec<*mut page>,
+    // the offset of the free space in the last page
+    tail: usize,
+    compressed_pages: usize,
+    compressed_bytes: usize,
+}
+
+impl CompressedStore {
+    /// Compress the recorded pages of the VMAs with the prepare workers,
+    /// and re-encode their entries in `page_table`.
+    /// The pages that do not shrink enough (or once the store cannot grow) keep their entries.
+    pub fn build(page_table: &mut [CompactPageTable]) -> Self {
+        let mut jobs = Vec::new();
+        for (idx, pt) in page_table.iter().enumerate() {
+            let mut start = 0;
+            while start < pt.table_len() {
+                let end = core::cmp::min(start + PAGES_PER_COMPRESS_JOB, pt.table_len());
+                jobs.push((idx, start, end));
+                start = end;
+            }
+        }
+
+        // each job packs its pages into its own store, and records the re-encoded entries
+        let slots: JobSlots<(Self, Vec<(usize, Value)>)> = JobSlots::new(jobs.len());
+        let tables = &*page_table;
+        unsafe {
+            crate::get_prepare_worker_ref().run(jobs.len(), core::ptr::null_mut(), &|i| {
+                let (idx, start, end) = jobs[i];
+                let (store, updates) = slots.get_mut(i);
+                store.compress_entries(&tables[idx].entries()[start..end], start, updates);
+            })
+        };
+
+        let mut res = Self::default();
+        for (&(idx, _, _), (mut store, updates)) in jobs.iter().zip(slots.into_inner()) {
+            let entries = page_table[idx].entries_mut();
+            for (i, value) in updates {
+                entries[i].1 = value;
+            }
+            res.pages.append(&mut store.pages);
+            res.compressed_pages += store.compressed_pages;
+            res.compressed_bytes += store.compressed_bytes;
+        }
+        res
+    }
+
+    /// The number of the pages stored compressed
+    #[inline]
+    pub fn compressed_pages(&self) -> usize {
+        self.compressed_pages
+    }
+
+    /// The length of the compressed pages in total
+    #[inline]
+    pub fn compressed_bytes(&self) -> usize {
+        self.compressed_bytes
+    }
+
+    /// Compress the pages of `entries`, which start at the index `first` of their table,
+    /// and record the indexes and the re-encoded values of the compressed ones in `updates`
+    fn compress_entries(
+        &mut self,
+        entries: &[PageEntry],
+        first: usize,
+        updates: &mut Vec<(usize, Value)>,
+    ) {
+        let mut wrkmem: Vec<u8, VmallocAllocator> = Vec::new_in(VmallocAllocator);
+        wrkmem.resize(unsafe { pmem_compress_wrkmem_size() } as usize, 0);
+        let mut buf: Vec<u8, VmallocAllocator> = Vec::new_in(VmallocAllocator);
+        buf.resize(MAX_COMPRESSED_LEN, 0);
+
+        for (i, (_, value)) in entries.iter().enumerate() {
+            let entry = PhysAddr::new(*value);
+            // the zero pages are not read at all
+            if entry.is_zero() {
+                continue;
+            }
+            let len = unsafe {
+                pmem_compress_page(
+                    pmem_phys_to_virt(entry.real_addr()),
+                    buf.as_mut_ptr().cast(),
+                    MAX_COMPRESSED_LEN as _,
+                    wrkmem.as_mut_ptr().cast(),
+                )
+            };
+            if len <= 0 {
+                continue;
+            }
+            let addr = match self.push(&buf[..len as usize]) {
+                Some(addr) => addr,
+                None => {
+                    crate::log::warn!("failed to grow the compressed store");
+                    return;
+                }
+            };
+            let ro = *value & PhysAddrBitFlag::ReadOnly as u64;
+            updates.push((first + i, PhysAddr::encode_compressed(addr, len as _, ro)));
+        }
+    }
+
+    /// Copy a compressed page into the store
+    ///
+    /// Return
+    /// * the physical address of the copy, None if no page can be allocated
+    fn push(&mut self, blob: &[u8]) -> core::option::Option<u64> {
+        if self.pages.is_empty() || self.tail + blob.len() > PAGE_SIZE {
+            let p = unsafe { pmem_alloc_page(crate::linux_kernel_module::bindings::GFP_KERNEL) };
+            if p.is_null() {
+                return None;
+            }
+            self.pages.push(p);
+            self.tail = 0;
+        }
+
+        let p = *self.pages.last().unwrap();
+        unsafe {
+            let dst = (pmem_page_to_virt(p) as *mut u8).add(self.tail);
+            core::ptr::copy_nonoverlapping(blob.as_ptr(), dst, blob.len());
+        }
+        let addr = unsafe { pmem_page_to_phy(p) } + self.tail as u64;
+
+        self.tail = (self.tail + blob.len() + COMPRESSED_ALIGN - 1) & !(COMPRESSED_ALIGN - 1);
+        self.compressed_pages += 1;
+        self.compressed_bytes += blob.len();
+        Some(addr)
+    }
+}
+
+impl Drop for CompressedStore {
+    fn drop(&mut self) {
+        for p in self.pages.drain(..) {
+            unsafe { pmem_free_page(p) };
+        }
+    }
+}
+
+/// The scratch pages of the children, one per CPU, into which the compressed copies
+/// are read at the page faults before being decompressed
+pub struct ScratchPages {
+    pool: Vec<BoxedLockBundler<*mut page>>,
+}
+
+impl ScratchPages {
+    pub fn new(config: &crate::Config) -> core::option::Option<Self> {
+        // the pages allocated are freed on failures
+        let mut res = Self { pool: Vec::new() };
+        for _ in 0..config.max_core_cnt {
+            let p = unsafe { pmem_alloc_page(crate::linux_kernel_module::bindings::GFP_KERNEL) };
+            if p.is_null() {
+                return None;
+            }
+            res.pool.push(LockBundler::new(p));
+        }
+        Some(res)
+    }
+
+    /// Run `f` with the scratch page of the current CPU.
+    /// The page is locked, as the faulting thread may sleep on the read and migrate.
+    pub fn with_page<R>(&self, f: impl FnOnce(*mut page) -> R) -> R {
+        let idx = unsafe { pmem_get_current_cpu() } as usize % self.pool.len();
+        self.pool[idx].lock(|p| f(*p))
+    }
+}
+
+impl Drop for ScratchPages {
+    fn drop(&mut self) {
+        for p in self.pool.drain(..) {
+            p.lock(|p| unsafe { pmem_free_page(*p) });
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+use crate::bindings::{pmem_hash_page, pmem_phys_to_virt, VMFlags};
+use crate::descriptors::VMADescriptor;
+use crate::kern_wrappers::mm::PhyAddrType;
+use crate::lock_bundler::LockBundler;
+use crate::prepare_worker::JobSlots;
+use crate::remote_mapping::{PhysAddr, PhysAddrBitFlag};
+
+use super::{COW4KPage, GetPhyAddr, ShadowPageTable, ShadowProcess};
+
+#[allow(unused_imports)]
+use crate::linux_kernel_module;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The max number of pages hashed by one job of the prepare workers
+const PAGES_PER_HASH_JOB: usize = 16 * 1024;
+
+/// A page pinned once for all the images recording an identical page, see `DedupIndex`
+pub struct SharedPage {
+    pin: COW4KPage,
+    hash: u32,
+}
+
+impl SharedPage {
+    #[inline]
+    fn addr(&self) -> PhyAddrType {
+        self.pin.get_physical_addr()
+    }
+
+    #[inline]
+    fn content(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.pin.get_kva() as *const u8, PAGE_SIZE) }
+    }
+}
+
+/// The references of an image to the shared pages, one per entry recording a shared page
+#[derive(Default)]
+pub struct SharedRefs {
+    // each with whether the image released its own pin of the page for it
+    refs: Vec<(Arc<SharedPage>, bool)>,
+}
+
+impl SharedRefs {
+    /// The number of the pins of the image released by the deduplication
+    pub fn saved_pins(&self) -> usize {
+        self.refs.iter().filter(|(_, saved)| *saved).count()
+    }
+
+    pub fn append(&mut self, other: &mut SharedRefs) {
+        self.refs.append(&mut other.refs);
+    }
+}
+
+/// The content-hash index of the pages pinned by the images prepared with `PREPARE_DEDUP`.
+///
+/// The first image recording a page moves its pin into the index. The following images
+/// recording an identical page release their own pins, and record the shared page instead.
+/// The index counts the references of the images to each shared page,
+/// and a shared page is unpinned once no image records it, see `release`.
+#[derive(Default)]
+pub struct DedupIndex {
+    pages: HashMap<u32, Vec<IndexedPage>>,
+    // the pins released by the images, i.e., the references of the shared pages but the first
+    saved: usize,
+}
+
+struct IndexedPage {
+    page: Arc<SharedPage>,
+    // the references of the images (and generations), one per entry recording the page
+    refs: usize,
+}
+
+impl DedupIndex {
+    /// The number of the pins saved by the deduplication, across all the images
+    #[inline]
+    pub fn saved_pages(&self) -> usize {
+        self.saved
+    }
+
+    #[inline]
+    fn find(&mut self, hash: u32, content: &[u8]) -> core::option::Option<&mut IndexedPage> {
+        self.pages
+            .get_mut(&hash)?
+            .iter_mut()
+            .find(|p| p.page.content() == content)
+    }
+
+    /// Drop the references of an unregistered image (or generation) to the shared pages,
+    /// the pages recorded by no other image are unpinned
+    pub fn release(&mut self, refs: SharedRefs) {
+        for (page, _) in refs.refs {
+            let bucket = match self.pages.get_mut(&page.hash) {
+                Some(bucket) => bucket,
+                None => continue,
+            };
+            let pos = match bucket.iter().position(|p| Arc::ptr_eq(&p.page, &page)) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            bucket[pos].refs -= 1;
+            if bucket[pos].refs > 0 {
+                self.saved -= 1;
+                continue;
+            }
+            bucket.swap_remove(pos);
+            if bucket.is_empty() {
+                self.pages.remove(&page.hash);
+            }
+        }
+    }
+}
+
+impl ShadowProcess {
+    /// Share the recorded pages identical to the ones in `index`, and add the others to it.
+    /// Only the pages pinned by the shadow process (i.e., marked COW) are shared,
+    /// the pages are hashed by the prepare workers before the index is locked.
+    ///
+    /// The pages of the shared VMAs and the writable file VMAs are never shared:
+    /// they are modified in place by the other mappers (or the writes to the file),
+    /// which would corrupt every image sharing them.
+    ///
+    /// Return
+    /// * the references of the shared pages recorded by the shadow process
+    pub fn dedup(&mut self, index: &LockBundler<DedupIndex>) -> SharedRefs {
+        let mut refs = SharedRefs::default();
+        let mut pins = match self.cow_shadow_pagetable.take() {
+            Some(pt) => pt.into_pins(),
+            None => return refs,
+        };
+
+        let mut jobs = Vec::new();
+        for (idx, pt) in self.descriptor.page_table.iter().enumerate() {
+            if !Self::dedupable(&self.descriptor.vma[idx]) {
+                continue;
+            }
+            let mut start = 0;
+            while start < pt.table_len() {
+                let end = core::cmp::min(start + PAGES_PER_HASH_JOB, pt.table_len());
+                jobs.push((idx, start, end));
+                start = end;
+            }
+        }
+
+        // the (index, hash) of the candidate entries of each job
+        let slots: JobSlots<Vec<(usize, u32)>> = JobSlots::new(jobs.len());
+        let (tables, pinned) = (&self.descriptor.page_table, &pins);
+        unsafe {
+            crate::get_prepare_worker_ref().run(jobs.len(), core::ptr::null_mut(), &|i| {
+                let (idx, start, end) = jobs[i];
+                let hashes = slots.get_mut(i);
+                for (j, (_, value)) in tables[idx].entries()[start..end].iter().enumerate() {
+                    let entry = PhysAddr::new(*value);
+                    if entry.is_zero()
+                        || entry.is_compressed()
+                        || !pinned.contains(entry.real_addr())
+                    {
+                        continue;
+                    }
+                    let hash = pmem_hash_page(pmem_phys_to_virt(entry.real_addr()));
+                    hashes.push((start + j, hash));
+                }
+            })
+        };
+
+        let page_table = &mut self.descriptor.page_table;
+        index.lock(|index| {
+            for (&(idx, _, _), hashes) in jobs.iter().zip(slots.into_inner()) {
+                let entries = page_table[idx].entries_mut();
+                for (j, hash) in hashes {
+                    let value = entries[j].1;
+                    let addr = PhysAddr::new(value).real_addr();
+                    let content = unsafe {
+                        core::slice::from_raw_parts(pmem_phys_to_virt(addr) as *const u8, PAGE_SIZE)
+                    };
+
+                    match index.find(hash, content) {
+                        Some(indexed) => {
+                            drop(pins.take(addr));
+                            indexed.refs += 1;
+                            let shared = indexed.page.clone();
+                            entries[j].1 = PhysAddr::encode(
+                                shared.addr(),
+                                value & PhysAddrBitFlag::mask(),
+                            );
+                            index.saved += 1;
+                            refs.refs.push((shared, true));
+                        }
+                        None => {
+                            // None if the pin has been moved by a duplicated entry of the page
+                            if let Some(pin) = pins.take(addr) {
+                                let page = Arc::new(SharedPage { pin, hash });
+                                let indexed = IndexedPage { page: page.clone(), refs: 1 };
+                                index.pages.entry(hash).or_default().push(indexed);
+                                refs.refs.push((page, false));
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        let mut shadow_pt = ShadowPageTable::new();
+        pins.retire_into(&mut shadow_pt);
+        self.cow_shadow_pagetable = Some(shadow_pt);
+        refs
+    }
+
+    /// Whether the pages recorded for the VMA can be shared, i.e., are not modified in place
+    #[inline]
+    fn dedupable(vma: &VMADescriptor) -> bool {
+        let flags = vma.get_flags();
+        !flags.contains(VMFlags::SHARED)
+            && !(vma.get_file_idx().is_some() && flags.contains(VMFlags::WRITE))
+    }
+
+    /// Take the references of `refs` to the shared pages no longer recorded,
+    /// e.g., the ones replaced by an update, which are kept for the previous generations
+    pub fn take_unrecorded(&self, refs: &mut SharedRefs) -> SharedRefs {
+        // the number of the entries recording each shared page
+        let mut recorded: HashMap<PhyAddrType, usize> =
+            refs.refs.iter().map(|(p, _)| (p.addr(), 0)).collect();
+        for pt in self.descriptor.page_table.iter() {
+            for (_, value) in pt.entries() {
+                let entry = PhysAddr::new(*value);
+                if entry.is_zero() || entry.is_compressed() {
+                    continue;
+                }
+                if let Some(n) = recorded.get_mut(&entry.real_addr()) {
+                    *n += 1;
+                }
+            }
+        }
+
+        let (mut kept, mut taken) = (Vec::new(), Vec::new());
+        for r in refs.refs.drain(..) {
+            match recorded.get_mut(&r.0.addr()) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    kept.push(r);
+                }
+                _ => taken.push(r),
+            }
+        }
+        refs.refs = kept;
+        SharedRefs { refs: taken }
+    }
+}
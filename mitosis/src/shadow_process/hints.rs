@@ -0,0 +1,112 @@
+use alloc::vec::Vec;
+
+use crate::kern_wrappers::mm::VirtAddrType;
+
+use mitosis_protocol::{PrepareRegion, REGION_EXCLUDE, REGION_HOT};
+
+const PAGE_SIZE: VirtAddrType = 4096;
+
+/// The address ranges provided by the user at prepare,
+/// which tune what (and when) the child fetches from the shadow process.
+///
+/// * The pages in the excluded ranges are not recorded in the descriptor,
+///   the child reads them as zero-filled pages.
+/// * The VMAs overlapping the hot ranges are fetched eagerly at resume.
+/// * The recorded pages are stored compressed if `compress` is set, see `CompressedStore`.
+/// * The pinned pages identical to the ones of other images are shared if `dedup` is set,
+///   see `DedupIndex`.
+#[derive(Default, Debug, Clone)]
+pub struct PrepareHints {
+    // [start, end) ranges, aligned to pages
+    excluded: Vec<(VirtAddrType, VirtAddrType)>,
+    hot: Vec<(VirtAddrType, VirtAddrType)>,
+    compress: bool,
+    dedup: bool,
+}
+
+impl PrepareHints {
+    /// Build the hints from the regions of the user
+    ///
+    /// Return
+    /// * None if any region is malformed, e.g., an empty range or an unknown hint
+    pub fn new(regions: &[PrepareRegion]) -> core::option::Option<Self> {
+        let mut res = Self::default();
+        for r in regions {
+            if r.start >= r.end {
+                return None;
+            }
+            let range = (
+                r.start & !(PAGE_SIZE - 1),
+                (r.end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            );
+            match r.hint {
+                REGION_EXCLUDE => res.excluded.push(range),
+                REGION_HOT => res.hot.push(range),
+                _ => return None,
+            }
+        }
+        Some(res)
+    }
+
+    /// Store the recorded pages compressed, as `PREPARE_COMPRESS`
+    #[inline]
+    pub fn set_compress(&mut self, compress: bool) -> &mut Self {
+        self.compress = compress;
+        self
+    }
+
+    #[inline]
+    pub fn compress(&self) -> bool {
+        self.compress
+    }
+
+    /// Share the identical pages with the other images, as `PREPARE_DEDUP`
+    #[inline]
+    pub fn set_dedup(&mut self, dedup: bool) -> &mut Self {
+        self.dedup = dedup;
+        self
+    }
+
+    #[inline]
+    pub fn dedup(&self) -> bool {
+        self.dedup
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.excluded.is_empty() && self.hot.is_empty()
+    }
+
+    /// The excluded [start, end) ranges, recorded in the descriptor for the children
+    #[inline]
+    pub fn excluded(&self) -> &[(VirtAddrType, VirtAddrType)] {
+        &self.excluded
+    }
+
+    /// Whether the page at `addr` should not be transferred
+    #[inline]
+    pub fn is_excluded(&self, addr: VirtAddrType) -> bool {
+        self.excluded.iter().any(|(s, e)| *s <= addr && addr < *e)
+    }
+
+    /// Whether the [start, end) range overlaps any excluded range
+    #[inline]
+    pub fn overlaps_excluded(&self, start: VirtAddrType, end: VirtAddrType) -> bool {
+        Self::overlaps(&self.excluded, start, end)
+    }
+
+    /// Whether the [start, end) range overlaps any hot range
+    #[inline]
+    pub fn overlaps_hot(&self, start: VirtAddrType, end: VirtAddrType) -> bool {
+        Self::overlaps(&self.hot, start, end)
+    }
+
+    #[inline]
+    fn overlaps(
+        ranges: &[(VirtAddrType, VirtAddrType)],
+        start: VirtAddrType,
+        end: VirtAddrType,
+    ) -> bool {
+        ranges.iter().any(|(s, e)| *s < end && start < *e)
+    }
+}
@@ -1,6 +1,8 @@
 use rust_kernel_rdma_base::VmallocAllocator;
 use alloc::vec::Vec;
 
+use crate::kern_wrappers::mm::PhyAddrType;
+
 pub trait GetPhyAddr {
     fn get_physical_addr(&self) -> crate::kern_wrappers::mm::PhyAddrType;
 }
@@ -28,4 +30,72 @@ where
     pub fn len(&self) -> usize {
         self.table.len()
     }
+
+    /// Reserve the room for `n` more pages, so that adding them does not allocate
+    #[inline]
+    pub fn reserve(&mut self, n: usize) {
+        self.table.reserve(n);
+    }
+
+    /// Index the pages by their physical addresses, see `PagePins`
+    pub fn into_pins(self) -> PagePins<P> {
+        let mut pages = Vec::with_capacity_in(self.table.len(), VmallocAllocator);
+        for p in self.table {
+            pages.push((p.get_physical_addr(), Some(p)));
+        }
+        pages.sort_unstable_by_key(|(addr, _)| *addr);
+        PagePins { pages }
+    }
+
+    /// Move the pages of `other` into this table
+    pub fn append(&mut self, other: Self) -> &mut Self {
+        self.table.extend(other.table);
+        self
+    }
+}
+
+impl<P> Default for ShadowPageTable<P>
+where
+    P: GetPhyAddr,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The pages of a shadow page table indexed by their physical addresses,
+/// so that the ones still mapped by the process can be moved to its next image
+pub struct PagePins<P: GetPhyAddr> {
+    // sorted by the physical address, a page may be pinned more than once, e.g., the zero page
+    pages: Vec<(PhyAddrType, core::option::Option<P>), VmallocAllocator>,
+}
+
+impl<P> PagePins<P>
+where
+    P: GetPhyAddr,
+{
+    /// Whether a pin of the page at `addr` is left
+    pub fn contains(&self, addr: PhyAddrType) -> bool {
+        let start = self.pages.partition_point(|(a, _)| *a < addr);
+        self.pages[start..]
+            .iter()
+            .take_while(|(a, _)| *a == addr)
+            .any(|(_, p)| p.is_some())
+    }
+
+    /// Take one pin of the page at `addr`, if any is left
+    pub fn take(&mut self, addr: PhyAddrType) -> core::option::Option<P> {
+        let start = self.pages.partition_point(|(a, _)| *a < addr);
+        self.pages[start..]
+            .iter_mut()
+            .take_while(|(a, _)| *a == addr)
+            .find_map(|(_, p)| p.take())
+    }
+
+    /// Move the pages that are not taken into `table`
+    pub fn retire_into(self, table: &mut ShadowPageTable<P>) {
+        for p in self.pages.into_iter().filter_map(|(_, p)| p) {
+            table.add_page(p);
+        }
+    }
 }
@@ -1,12 +1,12 @@
 // use alloc::string::String;
 use crate::bindings::*;
-use crate::kern_wrappers::mm::VirtAddrType;
+use crate::kern_wrappers::mm::{PhyAddrType, VirtAddrType};
 use crate::kern_wrappers::vma::VMA;
 
 #[allow(unused_imports)]
 use crate::linux_kernel_module;
 
-use super::{COW4KPage, Copy4KPage, GetPhyAddr};
+use super::{COW4KPage, Copy4KPage, GetPhyAddr, PrepareHints};
 
 /// The shadow VMA is just a wrapper over the original process's VMA
 /// The difference is that, upon creation, it will change the process's
@@ -61,6 +61,7 @@ pub(crate) struct VMACopyPTGenerator<'a, 'b> {
     vma: &'a ShadowVMA<'a>,
     inner: &'b mut CopyPageTable,
     inner_flat: &'b mut crate::descriptors::CompactPageTable,
+    hints: &'a PrepareHints,
 }
 
 impl<'a, 'b> VMACopyPTGenerator<'a, 'b> {
@@ -68,11 +69,13 @@ impl<'a, 'b> VMACopyPTGenerator<'a, 'b> {
         vma: &'a ShadowVMA,
         inner: &'b mut CopyPageTable,
         inner_flat: &'b mut crate::descriptors::CompactPageTable,
+        hints: &'a PrepareHints,
     ) -> Self {
         Self {
             vma: vma,
             inner: inner,
             inner_flat: inner_flat,
+            hints: hints,
         }
     }
 }
@@ -81,15 +84,15 @@ use crate::kern_wrappers::vma_iters::*;
 use crate::remote_mapping::{PhysAddr, PhysAddrBitFlag};
 
 impl VMACopyPTGenerator<'_, '_> {
-    pub fn generate(&self) {
+    /// Walk the pages in [start, end) of the VMA, which can be a part of it.
+    /// The pages are read from the current mm, so a prepare worker must use the mm of the VMA.
+    pub fn generate_range(&self, start: VirtAddrType, end: VirtAddrType) {
         let mut walk: mm_walk = Default::default();
         walk.pte_entry = Some(Self::handle_pte_entry);
         walk.private = self as *const _ as *mut crate::linux_kernel_module::c_types::c_void;
 
         let mut engine = VMWalkEngine::new(walk);
-        unsafe { engine.walk(self.vma.vma_inner.get_raw_ptr()) };
-
-        // crate::log::debug!("walk done");
+        unsafe { engine.walk_range(self.vma.vma_inner.get_raw_ptr(), start, end) };
     }
 
     #[allow(non_upper_case_globals)]
@@ -104,11 +107,20 @@ impl VMACopyPTGenerator<'_, '_> {
 
         let phy_addr = pmem_get_phy_from_pte(pte);
 
-        if phy_addr > 0 {
+        if phy_addr > 0 && !my.hints.is_excluded(addr as _) {
+            let start = my.vma.vma_inner.get_start();
+            // the zero pages are neither copied nor read by the children
+            if pmem_pte_maps_zero(pte) != 0 {
+                my.inner_flat.add_one(
+                    (addr as VirtAddrType - start) as _,
+                    PhysAddr::encode(0, PhysAddrBitFlag::Zero as _) as _,
+                );
+                return 0;
+            }
+
             let copied_page = Copy4KPage::new(addr as _).expect("Fail to copy from user space");
             // my.inner_flat.add_one(addr, copied_page.get_physical_addr());
             {
-                let start = my.vma.vma_inner.get_start();
                 my.inner_flat.add_one(
                     (addr as VirtAddrType - start) as _,
                     copied_page.get_physical_addr() as _,
@@ -127,6 +139,7 @@ pub(crate) struct VMACOWPTGenerator<'a, 'b> {
     vma: &'a ShadowVMA<'a>,
     inner: &'b mut COWPageTable,
     inner_flat: &'b mut crate::descriptors::CompactPageTable,
+    hints: &'a PrepareHints,
 }
 
 impl<'a, 'b> VMACOWPTGenerator<'a, 'b> {
@@ -134,23 +147,54 @@ impl<'a, 'b> VMACOWPTGenerator<'a, 'b> {
         vma: &'a ShadowVMA,
         inner: &'b mut COWPageTable,
         inner_flat: &'b mut crate::descriptors::CompactPageTable,
+        hints: &'a PrepareHints,
     ) -> Self {
         Self {
             vma,
             inner,
             inner_flat,
+            hints,
         }
     }
 }
 
 impl VMACOWPTGenerator<'_, '_> {
-    pub fn generate(&self) {
+    /// Walk the pages in [start, end) of the VMA, which can be a part of it.
+    /// The PTEs are write-protected under the PTE locks, and the caller must hold the mmap_sem,
+    /// and flush the TLB (before releasing it) after all the ranges are walked.
+    pub fn generate_range(&self, start: VirtAddrType, end: VirtAddrType) {
         let mut walk: mm_walk = Default::default();
+        walk.pmd_entry = Some(Self::handle_pmd_entry);
         walk.pte_entry = Some(Self::handle_pte_entry);
         walk.private = self as *const _ as *mut crate::linux_kernel_module::c_types::c_void;
 
         let mut engine = VMWalkEngine::new(walk);
-        unsafe { engine.walk(self.vma.vma_inner.get_raw_ptr()) };
+        unsafe { engine.walk_range_locked(self.vma.vma_inner.get_raw_ptr(), start, end) };
+    }
+
+    /// Reserve the room for the pages of the PTE table, which is walked with its lock held
+    #[allow(non_upper_case_globals)]
+    pub unsafe extern "C" fn handle_pmd_entry(
+        _pmd: *mut pmd_t,
+        addr: crate::linux_kernel_module::c_types::c_ulong,
+        next: crate::linux_kernel_module::c_types::c_ulong,
+        walk: *mut mm_walk,
+    ) -> crate::linux_kernel_module::c_types::c_int {
+        let my: &mut Self = &mut (*((*walk).private as *mut Self));
+        Self::reserve(my.inner, my.inner_flat, addr as _, next as _);
+        0
+    }
+
+    #[inline]
+    fn reserve(
+        inner: &mut COWPageTable,
+        inner_flat: &mut crate::descriptors::CompactPageTable,
+        addr: VirtAddrType,
+        next: VirtAddrType,
+    ) {
+        let n = ((next - addr) / 4096) as usize;
+        inner.reserve(n);
+        inner_flat.reserve(n);
     }
 
     #[allow(non_upper_case_globals)]
@@ -161,27 +205,196 @@ impl VMACOWPTGenerator<'_, '_> {
         _next: crate::linux_kernel_module::c_types::c_ulong,
         walk: *mut mm_walk,
     ) -> crate::linux_kernel_module::c_types::c_int {
+        use core::intrinsics::likely;
+        let my: &mut Self = &mut (*((*walk).private as *mut Self));
+
+        let phy_addr = pmem_get_phy_from_pte(pte);
+        // the excluded pages are neither marked COW nor recorded
+        if likely(phy_addr > 0) && !my.hints.is_excluded(addr as _) {
+            Self::record_page(my.vma, my.inner, my.inner_flat, pte, addr as _, phy_addr);
+        }
+        0
+    }
+
+    /// Mark the page mapped by `pte` COW (if the VMA is writable) and record it
+    #[inline]
+    unsafe fn record_page(
+        vma: &ShadowVMA,
+        inner: &mut COWPageTable,
+        inner_flat: &mut crate::descriptors::CompactPageTable,
+        pte: *mut pte_t,
+        addr: VirtAddrType,
+        mut phy_addr: u64,
+    ) {
         use core::intrinsics::{likely, unlikely};
+        if unlikely(vma.has_write_permission()) {
+            inner.add_page(COW4KPage::new(pmem_pte_to_page(pte)).unwrap());
+            pmem_clear_pte_write(pte);
+        }
+        let read_only = pmem_check_pte_write(pte) == 0;
+        if likely(read_only) {
+            // Read only page
+            phy_addr = PhysAddr::encode(phy_addr, PhysAddrBitFlag::ReadOnly as _);
+        }
+        // the children map a zeroed page instead of reading it,
+        // and the page is still recorded for the update of the image.
+        // A written page is checked once write-protected, so its content no longer changes
+        if pmem_pte_maps_zero(pte) != 0
+            || (read_only && pmem_page_is_zero(pmem_pte_to_page(pte)) != 0)
+        {
+            phy_addr = PhysAddr::encode(phy_addr, PhysAddrBitFlag::Zero as _);
+        }
+        // #[cfg(not(feature = "fast-descriptors"))]
+        // my.inner_flat.add_one(addr, phy_addr);
+        // #[cfg(feature = "fast-descriptors")]
+        {
+            let start = vma.vma_inner.get_start();
+            inner_flat.add_one((addr - start) as _, phy_addr as _);
+        }
+    }
+}
+
+/// This iterator will traverse the pages of VMA to refresh its page table
+/// recorded by the previous `VMACOWPTGenerator` (or `VMACOWPTUpdater`).
+///
+/// Since the recorded pages are write-protected, the pages written since then
+/// are re-mapped by the COW faults. So a page is unchanged iff its PTE still maps
+/// the recorded page without the write permission, whose pin is moved from `pins`.
+/// The other pages are marked COW and recorded as `VMACOWPTGenerator` does.
+pub(crate) struct VMACOWPTUpdater<'a, 'b> {
+    vma: &'a ShadowVMA<'a>,
+    inner: &'b mut COWPageTable,
+    inner_flat: &'b mut crate::descriptors::CompactPageTable,
+    hints: &'a PrepareHints,
+
+    prev_flat: &'a crate::descriptors::CompactPageTable,
+    pins: &'b mut super::PagePins<COW4KPage>,
+    // the next entry of `prev_flat` to compare
+    cursor: usize,
+    changed: usize,
+}
+
+impl<'a, 'b> VMACOWPTUpdater<'a, 'b> {
+    pub fn new(
+        vma: &'a ShadowVMA,
+        inner: &'b mut COWPageTable,
+        inner_flat: &'b mut crate::descriptors::CompactPageTable,
+        hints: &'a PrepareHints,
+        prev_flat: &'a crate::descriptors::CompactPageTable,
+        pins: &'b mut super::PagePins<COW4KPage>,
+    ) -> Self {
+        Self {
+            vma,
+            inner,
+            inner_flat,
+            hints,
+            prev_flat,
+            pins,
+            cursor: 0,
+            changed: 0,
+        }
+    }
+}
+
+impl VMACOWPTUpdater<'_, '_> {
+    /// Whether the page mapped by `pte` is still identical to the shared page recorded by
+    /// the previous entry `prev`. The page must be write-protected, so that it is not written
+    /// after the comparison, see `VMACOWPTGenerator::record_page`.
+    unsafe fn is_shared_copy(&self, prev: u64, pte: *mut pte_t, phy_addr: PhyAddrType) -> bool {
+        let prev = PhysAddr::new(prev);
+        if pmem_check_pte_write(pte) != 0
+            || prev.is_zero()
+            || prev.is_compressed()
+            || self.pins.contains(prev.real_addr())
+        {
+            return false;
+        }
+        let shared =
+            core::slice::from_raw_parts(pmem_phys_to_virt(prev.real_addr()) as *const u8, 4096);
+        let mine = core::slice::from_raw_parts(pmem_phys_to_virt(phy_addr) as *const u8, 4096);
+        shared == mine
+    }
+
+    /// Walk the VMA under the PTE locks, the caller must hold the mmap_sem,
+    /// see `VMACOWPTGenerator::generate_range`
+    ///
+    /// Return
+    /// * the number of pages changed (or newly mapped) since the previous page table
+    pub fn generate(&mut self) -> usize {
+        let mut walk: mm_walk = Default::default();
+        walk.pmd_entry = Some(Self::handle_pmd_entry);
+        walk.pte_entry = Some(Self::handle_pte_entry);
+        walk.private = self as *mut _ as *mut crate::linux_kernel_module::c_types::c_void;
+
+        let mut engine = VMWalkEngine::new(walk);
+        let vma = &self.vma.vma_inner;
+        unsafe { engine.walk_range_locked(vma.get_raw_ptr(), vma.get_start(), vma.get_end()) };
+        self.changed
+    }
+
+    #[allow(non_upper_case_globals)]
+    pub unsafe extern "C" fn handle_pmd_entry(
+        _pmd: *mut pmd_t,
+        addr: crate::linux_kernel_module::c_types::c_ulong,
+        next: crate::linux_kernel_module::c_types::c_ulong,
+        walk: *mut mm_walk,
+    ) -> crate::linux_kernel_module::c_types::c_int {
+        let my: &mut Self = &mut (*((*walk).private as *mut Self));
+        VMACOWPTGenerator::reserve(my.inner, my.inner_flat, addr as _, next as _);
+        0
+    }
+
+    #[allow(non_upper_case_globals)]
+    #[allow(unused_variables)]
+    pub unsafe extern "C" fn handle_pte_entry(
+        pte: *mut pte_t,
+        addr: crate::linux_kernel_module::c_types::c_ulong,
+        _next: crate::linux_kernel_module::c_types::c_ulong,
+        walk: *mut mm_walk,
+    ) -> crate::linux_kernel_module::c_types::c_int {
+        use core::intrinsics::likely;
         let my: &mut Self = &mut (*((*walk).private as *mut Self));
 
-        let mut phy_addr = pmem_get_phy_from_pte(pte);
-        if likely(phy_addr > 0) {
-            if unlikely(my.vma.has_write_permission()) {
-                my.inner
-                    .add_page(COW4KPage::new(pmem_pte_to_page(pte)).unwrap());
-                pmem_clear_pte_write(pte);
+        let phy_addr = pmem_get_phy_from_pte(pte);
+        if !likely(phy_addr > 0) || my.hints.is_excluded(addr as _) {
+            return 0;
+        }
+
+        // both the walk and the previous entries are in the order of the address
+        let offset = (addr as VirtAddrType - my.vma.vma_inner.get_start()) as _;
+        let prev = my.prev_flat.entries();
+        while my.cursor < prev.len() && prev[my.cursor].0 < offset {
+            my.cursor += 1;
+        }
+        let prev_entry = prev.get(my.cursor).filter(|(o, _)| *o == offset).map(|(_, v)| *v);
+        let unchanged = prev_entry.filter(|v| PhysAddr::decode(*v) == phy_addr);
+
+        match unchanged {
+            Some(v) if likely(pmem_check_pte_write(pte) == 0) => {
+                if my.vma.has_write_permission() {
+                    // e.g., the VMA was read-only at the previous prepare
+                    let pin = my.pins.take(phy_addr);
+                    my.inner.add_page(
+                        pin.unwrap_or_else(|| COW4KPage::new(pmem_pte_to_page(pte)).unwrap()),
+                    );
+                }
+                my.inner_flat.add_one(offset, v);
             }
-            if likely(pmem_check_pte_write(pte) == 0) {
-                // Read only page
-                phy_addr = PhysAddr::encode(phy_addr, PhysAddrBitFlag::ReadOnly as _);
+            // a page shared by the deduplication is recorded at the address of the shared page
+            // (not pinned by the process), which is kept if the page has not been written since
+            None if prev_entry.map(|v| my.is_shared_copy(v, pte, phy_addr)).unwrap_or(false) => {
+                my.inner_flat.add_one(offset, prev_entry.unwrap());
             }
-            // #[cfg(not(feature = "fast-descriptors"))]
-            // my.inner_flat.add_one(addr, phy_addr);
-            // #[cfg(feature = "fast-descriptors")]
-            {
-                let start = my.vma.vma_inner.get_start();
-                my.inner_flat
-                    .add_one((addr as VirtAddrType - start) as _, phy_addr as _);
+            _ => {
+                VMACOWPTGenerator::record_page(
+                    my.vma,
+                    my.inner,
+                    my.inner_flat,
+                    pte,
+                    addr as _,
+                    phy_addr,
+                );
+                my.changed += 1;
             }
         }
         0
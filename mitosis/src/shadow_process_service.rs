@@ -1,10 +1,13 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::Ordering::SeqCst;
-use core::sync::atomic::{compiler_fence};
+use core::sync::atomic::{compiler_fence, AtomicI64, AtomicU64, AtomicUsize};
 
 use hashbrown::HashMap;
 use os_network::rdma::dc::DCTarget;
+use rust_kernel_linux_util::kthread;
+use rust_kernel_linux_util::kthread::JoinHandler;
+use rust_kernel_linux_util::timer::KTimer;
 
 #[allow(unused_imports)]
 use crate::descriptors::{ChildDescriptor, RDMADescriptor};
@@ -13,31 +16,117 @@ use crate::shadow_process::*;
 #[allow(unused_imports)]
 use crate::linux_kernel_module;
 
+use crate::error::{MitosisError, MitosisResult};
 use crate::get_mem_pool_mut;
-use os_network::bytes::ToBytes;
-use os_network::{msg::UDMsg as RMemory, serialize::Serialize};
+use crate::mem_pools::ChunkedBuf;
+use crate::linux_kernel_module::c_types::{c_ulong, c_void};
+use crate::lock_bundler::{BoxedLockBundler, LockBundler};
+use os_network::serialize::Serialize;
+
+/// A child attached to an image: its machine, and the token of its attachment (see `Attachment`)
+pub(crate) type ChildId = (usize, u64);
 
 struct ProcessBundler {
-    #[allow(dead_code)]
-    process: ShadowProcess,
-    serialized_buf: RMemory,
-    serialized_buf_len: usize,
+    // None while the process is updated out of the lock, see `update_myself`
+    process: core::option::Option<ShadowProcess>,
+    // the pages pinned (or copied) by the process, which are counted during the update as well
+    process_pages: usize,
+    serialized_buf: ChunkedBuf,
+    // the length of the head section of the serialized descriptor
+    head_len: usize,
+    // bumped by each update of the image
+    generation: u32,
+    // unique on the host across the images and their generations,
+    // which keys the pages cached by the children (see `SharedPageCache`)
+    epoch: u64,
+    // the previous generations, in order, which may still be read by the children
+    retired: Vec<RetiredGeneration>,
 
     #[allow(dead_code)] // place holder to prevent NIC release the resources
     bound_dc_targets: Vec<Arc<DCTarget>>,
+
+    // the children that have looked up the image and not released their attachments,
+    // with the generation each one resumed from
+    attached_children: HashMap<ChildId, u32>,
+
+    // set once the image outlives the process preparing it
+    detached: core::option::Option<DetachedImage>,
+    // the time (on the clock of the service) of the latest lookup by a child
+    last_used: AtomicI64,
+    pages: usize,
+
+    // the references of the pages shared with the other images, if prepared with `PREPARE_DEDUP`
+    shared_pages: SharedRefs,
+    dedup: bool,
+}
+
+/// The states of a previous generation of an image kept for its children
+struct RetiredGeneration {
+    generation: u32,
+    // read by the children until released, None if the update failed to serialize the next
+    // generation, where the children keep resuming from the buffer of this one
+    #[allow(dead_code)]
+    serialized_buf: core::option::Option<ChunkedBuf>,
+    // the pages replaced by the update, recorded by this generation (and maybe earlier ones)
+    pages: RetiredPages,
+    shared_pages: SharedRefs,
+}
+
+/// The next generation of an image, updated and serialized out of the lock
+struct UpdatedImage {
+    process: ShadowProcess,
+    shared_pages: SharedRefs,
+    // None if the descriptor exceeds the max number of chunks
+    serialized_buf: core::option::Option<ChunkedBuf>,
+    // the pages (and shared pages) replaced by the update
+    retired_pages: RetiredPages,
+    retired_shared: SharedRefs,
+}
+
+impl RetiredGeneration {
+    #[inline]
+    fn release_shared(&mut self, index: &mut DedupIndex) {
+        index.release(core::mem::take(&mut self.shared_pages));
+    }
+}
+
+/// The policy of an image that stays registered after the process preparing it exits
+#[derive(Debug, Clone, Copy)]
+pub struct DetachedPolicy {
+    // only the owner (or the admin) can drop the image
+    pub owner: u32,
+    // None to keep the image until dropped
+    pub ttl_usec: core::option::Option<i64>,
+    // whether the image can be unregistered under memory pressure
+    pub evictable: bool,
+}
+
+struct DetachedImage {
+    policy: DetachedPolicy,
+    since: KTimer,
+}
+
+impl DetachedImage {
+    #[inline]
+    fn is_expired(&self) -> bool {
+        self.policy
+            .ttl_usec
+            .map(|ttl| self.since.get_passed_usec() >= ttl)
+            .unwrap_or(false)
+    }
 }
 
 impl ProcessBundler {
-    fn new(process: ShadowProcess, targets: Arc<DCTarget>) -> Self {
+    fn new(process: ShadowProcess, targets: Arc<DCTarget>) -> core::option::Option<Self> {
+        let pages = process.page_count();
         let len = process.get_descriptor_ref().serialization_buf_len();
         crate::log::debug!(
             "Alloc serialization buf sz {} KB",
             len / 1024
         );
-        let mut buf = unsafe { get_mem_pool_mut() }.pop_one();
-        crate::log::debug!("serialization buf allocation done!");
-
-        process.get_descriptor_ref().serialize(buf.get_bytes_mut());
+        let buf = ChunkedBuf::new(unsafe { get_mem_pool_mut() }, len, |buf| {
+            process.get_descriptor_ref().serialize_w_workers(buf)
+        })?;
         compiler_fence(SeqCst);
 
         crate::log::debug!("Process bundle descriptor len: {}", buf.len());
@@ -45,49 +134,227 @@ impl ProcessBundler {
         let mut bound_targets = Vec::new();
         bound_targets.push(targets);
 
-        Self {
-            process: process,
+        Some(Self {
+            head_len: process.get_descriptor_ref().head_len(),
+            process: Some(process),
+            process_pages: pages,
             serialized_buf: buf,
-            serialized_buf_len: len,
+            generation: 0,
+            epoch: 0,
+            retired: Vec::new(),
             bound_dc_targets: bound_targets,
-        }
+            attached_children: HashMap::new(),
+            detached: None,
+            last_used: AtomicI64::new(0),
+            pages,
+            shared_pages: SharedRefs::default(),
+            dedup: false,
+        })
     }
 
     fn get_serialize_buf_sz(&self) -> usize {
         self.serialized_buf.len()
     }
+
+    /// Serialize the descriptor of the updated `process` into a new buffer,
+    /// which sleeps on the workers, so it is called out of the lock
+    fn serialize(process: &ShadowProcess) -> core::option::Option<ChunkedBuf> {
+        let len = process.get_descriptor_ref().serialization_buf_len();
+        let buf = ChunkedBuf::new(unsafe { get_mem_pool_mut() }, len, |buf| {
+            process.get_descriptor_ref().serialize_w_workers(buf)
+        });
+        compiler_fence(SeqCst);
+        buf
+    }
+
+    /// Swap the `updated` image in, and retire the previous generation
+    /// with the pages (and shared pages) replaced by the update
+    ///
+    /// Return
+    /// * MitosisError::OutOfMemory if the descriptor exceeds the max number of chunks,
+    ///   where the children keep resuming from the previous generation
+    fn refresh(&mut self, updated: UpdatedImage) -> MitosisResult<()> {
+        let UpdatedImage {
+            process,
+            shared_pages,
+            serialized_buf,
+            retired_pages,
+            retired_shared,
+        } = updated;
+        let serialized_buf = serialized_buf.map(|buf| {
+            self.head_len = process.get_descriptor_ref().head_len();
+            core::mem::replace(&mut self.serialized_buf, buf)
+        });
+        self.process_pages = process.page_count();
+        self.process = Some(process);
+        self.shared_pages = shared_pages;
+
+        let failed = serialized_buf.is_none();
+        // the pages replaced are still read by the children of the current generation on failure
+        self.retired.push(RetiredGeneration {
+            generation: self.generation,
+            serialized_buf,
+            pages: retired_pages,
+            shared_pages: retired_shared,
+        });
+        self.count_pages();
+        if failed {
+            return Err(MitosisError::OutOfMemory);
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Take the previous generations that no attached child may read,
+    /// i.e., the ones older than the generations of all the attached children
+    fn take_released(&mut self) -> Vec<RetiredGeneration> {
+        let oldest = self
+            .attached_children
+            .values()
+            .copied()
+            .fold(self.generation, core::cmp::min);
+        let n = self.retired.iter().take_while(|r| r.generation < oldest).count();
+        let released: Vec<_> = self.retired.drain(..n).collect();
+        if !released.is_empty() {
+            self.count_pages();
+        }
+        released
+    }
+
+    /// Drop the references of the image (and its previous generations) to the shared pages
+    fn release_shared(&mut self, index: &mut DedupIndex) {
+        index.release(core::mem::take(&mut self.shared_pages));
+        for r in self.retired.iter_mut() {
+            r.release_shared(index);
+        }
+    }
+
+    #[inline]
+    fn count_pages(&mut self) {
+        self.pages = self.process_pages
+            + self.retired.iter().map(|r| r.pages.page_count()).sum::<usize>();
+    }
 }
 
 pub struct ShadowProcessService {
     registered_processes: HashMap<usize, ProcessBundler>,
+    // the unregistered images (with their keys) still read by the attached children,
+    // each of which is dropped with the release of its last child
+    orphans: Vec<(usize, ProcessBundler)>,
+    // guards every access to `registered_processes` and `orphans`, which are shared by
+    // the system calls, the RPC handlers and the reaper of the detached images
+    lock: BoxedLockBundler<()>,
+    // the pages shared across the images, always locked after `lock`
+    dedup_index: BoxedLockBundler<DedupIndex>,
+    // the epoch of the next image (or generation) registered, see `ProcessBundler::epoch`
+    next_epoch: AtomicU64,
+
+    clock: KTimer,
+    // the pages of the evictable detached images
+    evictable_pages: AtomicUsize,
+    // the pages requested by the shrinker, freed by the reaper
+    pressure: AtomicUsize,
+    reaper: core::option::Option<JoinHandler>,
+    shrinker_registered: bool,
 }
 
 impl ShadowProcessService {
     pub fn new() -> Self {
         Self {
             registered_processes: Default::default(),
+            orphans: Vec::new(),
+            lock: LockBundler::new(()),
+            dedup_index: LockBundler::new(DedupIndex::default()),
+            next_epoch: AtomicU64::new(1),
+            clock: KTimer::new(),
+            evictable_pages: AtomicUsize::new(0),
+            pressure: AtomicUsize::new(0),
+            reaper: None,
+            shrinker_registered: false,
         }
     }
 
-    pub fn query_descriptor_buf(&self, key: usize) -> core::option::Option<(&RMemory, usize)> {
-        self.registered_processes
-            .get(&key)
-            .map(|s| (&s.serialized_buf, s.serialized_buf_len))
+    /// Start reaping the expired (and evicted) detached images,
+    /// must be called after the service is installed as the global one
+    pub fn start_reaper(&mut self) -> core::option::Option<()> {
+        let builder = kthread::Builder::new()
+            .set_name(alloc::string::String::from("MITOSIS image reaper"))
+            .set_parameter(core::ptr::null_mut());
+        self.reaper = Some(builder.spawn(Self::reaper).ok()?);
+
+        let ret = unsafe {
+            crate::bindings::pmem_register_shrinker(
+                Some(Self::count_evictable),
+                Some(Self::request_eviction),
+            )
+        };
+        if ret != 0 {
+            crate::log::warn!("failed to register the shrinker: {}", ret);
+        }
+        self.shrinker_registered = ret == 0;
+        Some(())
     }
 
-    pub fn query_descriptor(
-        &self,
+    /// Attach the `child` to the image `key`, and call `f` with the serialized descriptor of
+    /// the image, the length of its head section and the epoch of its generation.
+    /// The image cannot be unregistered during the call, so `f` should not block for long.
+    ///
+    /// The child is attached once however many times it looks up the image,
+    /// and the pages are kept until it releases the attachment, see `detach_child`.
+    pub(crate) fn lookup_descriptor<R>(
+        &mut self,
         key: usize,
-    ) -> core::option::Option<&crate::descriptors::ParentDescriptor> {
-        self.registered_processes
-            .get(&key)
-            .map(|s| s.process.get_descriptor_ref())
+        child: ChildId,
+        f: impl FnOnce(&ChunkedBuf, usize, u64) -> R,
+    ) -> core::option::Option<R> {
+        let Self {
+            lock,
+            registered_processes,
+            clock,
+            ..
+        } = self;
+        lock.lock(|_| {
+            let s = registered_processes.get_mut(&key)?;
+            s.attached_children.insert(child, s.generation);
+            s.last_used.store(clock.get_passed_usec(), SeqCst);
+            Some(f(&s.serialized_buf, s.head_len, s.epoch))
+        })
+    }
+
+    /// Whether the key has been taken by a registered image
+    pub fn contains(&self, key: usize) -> bool {
+        self.lock
+            .lock(|_| self.registered_processes.contains_key(&key))
     }
 
+    /// The stats of the recorded pages of the image `key`
+    ///
+    /// Return
+    /// * MitosisError::InProgress if the image is being updated
+    pub fn image_stat(&self, key: usize) -> MitosisResult<mitosis_protocol::ImageStat> {
+        self.lock.lock(|_| {
+            let s = self
+                .registered_processes
+                .get(&key)
+                .ok_or(MitosisError::NotFound)?;
+            let mut res = s.process.as_ref().ok_or(MitosisError::InProgress)?.image_stat();
+            res.dedup_pages = s.shared_pages.saved_pins() as _;
+            res.host_dedup_pages = self.dedup_index.lock(|index| index.saved_pages()) as _;
+            Ok(res)
+        })
+    }
+
+    /// Register the caller process by copying its pages.
+    /// The pages in the excluded ranges of `hints` are not transferred to the children.
+    ///
     /// # Return
     /// * The size of the serialization buffer
-    pub fn add_myself_copy(&mut self, key: usize) -> core::option::Option<usize> {
-        if self.registered_processes.contains_key(&key) {
+    pub fn add_myself_copy(
+        &mut self,
+        key: usize,
+        hints: &PrepareHints,
+    ) -> core::option::Option<usize> {
+        if self.contains(key) {
             crate::log::warn!(
                 "Failed to prepare: the register key {} has already been taken. ",
                 key
@@ -98,20 +365,27 @@ impl ShadowProcessService {
         let (target, descriptor) = RDMADescriptor::new_from_dc_target_pool()?;
 
         let bundler = ProcessBundler::new(
-            crate::shadow_process::ShadowProcess::new_copy(descriptor),
+            crate::shadow_process::ShadowProcess::new_copy_w_hints(descriptor, hints),
             target,
-        );
+        )?;
         let ret = bundler.get_serialize_buf_sz();
 
-        self.registered_processes.insert(key, bundler);
+        self.insert(key, bundler)?;
 
         return Some(ret);
     }
 
+    /// Register the caller process by marking its pages COW, see `add_myself_copy`.
+    /// The pinned pages identical to the ones of the other images are shared if `hints.dedup()`.
+    ///
     /// # Return
-    /// * The size of the serialization buffer    
-    pub fn add_myself_cow(&mut self, key: usize) -> core::option::Option<usize> {
-        if self.registered_processes.contains_key(&key) {
+    /// * The size of the serialization buffer
+    pub fn add_myself_cow(
+        &mut self,
+        key: usize,
+        hints: &PrepareHints,
+    ) -> core::option::Option<usize> {
+        if self.contains(key) {
             crate::log::warn!(
                 "Failed to prepare: the register key {} has already been taken. ",
                 key
@@ -121,18 +395,408 @@ impl ShadowProcessService {
 
         let (target, descriptor) = RDMADescriptor::new_from_dc_target_pool()?;
 
-        let bundler = ProcessBundler::new(
-            crate::shadow_process::ShadowProcess::new_cow(descriptor),
-            target,
-        );
+        let mut process = crate::shadow_process::ShadowProcess::new_cow_w_hints(descriptor, hints);
+        let shared = if hints.dedup() {
+            process.dedup(&self.dedup_index)
+        } else {
+            SharedRefs::default()
+        };
+
+        let mut bundler = match ProcessBundler::new(process, target) {
+            Some(bundler) => bundler,
+            None => {
+                self.dedup_index.lock(|index| index.release(shared));
+                return None;
+            }
+        };
+        bundler.shared_pages = shared;
+        bundler.dedup = hints.dedup();
         let ret = bundler.get_serialize_buf_sz();
 
-        self.registered_processes.insert(key, bundler);
+        self.insert(key, bundler)?;
 
         return Some(ret);
     }
 
+    /// Refresh the image `key` prepared by the caller with its current state,
+    /// only the pages changed since the previous generation are recorded again.
+    ///
+    /// The pages of the previous generations are kept for the children resumed from them,
+    /// until the children of each generation (and the older ones) release their attachments.
+    ///
+    /// Return
+    /// * the generation of the image after the update
+    /// * MitosisError::Unsupported if the image copies the pages, i.e., without the cow feature,
+    ///   or stores them compressed
+    /// * MitosisError::InProgress if the image is being updated, e.g., by another thread
+    ///
+    /// The re-recorded pages of an image prepared with `PREPARE_DEDUP` are shared again.
+    /// The image is updated out of the lock of the service, and swapped in at last.
+    pub fn update_myself(&mut self, key: usize, hints: &PrepareHints) -> MitosisResult<u32> {
+        let Self {
+            lock,
+            dedup_index,
+            registered_processes,
+            orphans,
+            evictable_pages,
+            next_epoch,
+            ..
+        } = self;
+        // the process is taken as a private copy, while the children keep looking up the image
+        let (mut process, mut shared_pages, generation, dedup) = lock.lock(|_| {
+            let s = registered_processes
+                .get_mut(&key)
+                .ok_or(MitosisError::NotFound)?;
+            let process = s.process.take().ok_or(MitosisError::InProgress)?;
+            if !process.is_updatable() {
+                s.process = Some(process);
+                return Err(MitosisError::Unsupported);
+            }
+            Ok((process, core::mem::take(&mut s.shared_pages), s.generation, s.dedup))
+        })?;
+
+        // the walk, the dedup and the serialization sleep, so they are done out of the lock.
+        // The update always succeeds once the process `is_updatable`
+        let (changed, retired_pages) = process.update_cow_w_hints(hints).unwrap_or_default();
+        // the unchanged pages keep their shared pages, see `VMACOWPTUpdater`
+        let retired_shared = if dedup {
+            let mut shared = process.dedup(dedup_index);
+            shared_pages.append(&mut shared);
+            process.take_unrecorded(&mut shared_pages)
+        } else {
+            SharedRefs::default()
+        };
+        let serialized_buf = ProcessBundler::serialize(&process);
+        let updated = UpdatedImage {
+            process,
+            shared_pages,
+            serialized_buf,
+            retired_pages,
+            retired_shared,
+        };
+
+        // swapped into the image taken, which may have been unregistered (as an orphan)
+        let swapped = lock.lock(|_| {
+            let taken = |s: &ProcessBundler| s.generation == generation && s.process.is_none();
+            let (s, registered) = match registered_processes.get_mut(&key) {
+                Some(s) if taken(s) => (s, true),
+                _ => match orphans.iter_mut().find(|(k, s)| *k == key && taken(s)) {
+                    Some((_, s)) => (s, false),
+                    None => return Err(updated),
+                },
+            };
+            let prev_pages = s.pages;
+            let res = s.refresh(updated);
+            if res.is_ok() {
+                // the children of the new generation do not share the cached pages
+                // of the previous ones
+                s.epoch = next_epoch.fetch_add(1, SeqCst);
+            }
+            let mut released = s.take_released();
+            dedup_index.lock(|index| released.iter_mut().for_each(|r| r.release_shared(index)));
+
+            let evictable = s.detached.as_ref().map(|d| d.policy.evictable).unwrap_or(false);
+            if registered && evictable {
+                evictable_pages.fetch_add(s.pages, SeqCst);
+                evictable_pages.fetch_sub(prev_pages, SeqCst);
+            }
+            Ok((res.map(|_| s.generation), released))
+        });
+
+        let (res, released) = match swapped {
+            Ok(swapped) => swapped,
+            Err(updated) => {
+                // released during the update, so no child reads the pages
+                crate::log::warn!("the image {} has been released during the update", key);
+                let UpdatedImage {
+                    shared_pages,
+                    retired_shared,
+                    ..
+                } = updated;
+                dedup_index.lock(|index| {
+                    index.release(shared_pages);
+                    index.release(retired_shared);
+                });
+                return Err(MitosisError::NotFound);
+            }
+        };
+        // the pages are unpinned out of the lock
+        drop(released);
+        let generation = res?;
+        crate::log::debug!(
+            "update image {} to generation {}: {} pages changed",
+            key,
+            generation,
+            changed
+        );
+        Ok(generation)
+    }
+
+    /// The `child` of the image `key` no longer reads its pages, e.g., it exited or is
+    /// materialized. Releasing an attachment twice (or an unknown one) has no effect.
+    /// The unregistered image is dropped with its last child.
+    pub(crate) fn detach_child(&mut self, key: usize, child: ChildId) {
+        let Self {
+            lock,
+            dedup_index,
+            registered_processes,
+            orphans,
+            evictable_pages,
+            ..
+        } = self;
+        let (generations, image) = lock.lock(|_| {
+            if let Some(s) = registered_processes.get_mut(&key) {
+                if s.attached_children.remove(&child).is_some() {
+                    // the previous generations may be no longer read
+                    let prev_pages = s.pages;
+                    let mut released = s.take_released();
+                    dedup_index
+                        .lock(|index| released.iter_mut().for_each(|r| r.release_shared(index)));
+                    if s.detached.as_ref().map(|d| d.policy.evictable).unwrap_or(false) {
+                        evictable_pages.fetch_sub(prev_pages - s.pages, SeqCst);
+                    }
+                    return (released, None);
+                }
+            }
+            let idx = orphans.iter_mut().position(|(k, s)| {
+                *k == key && s.attached_children.remove(&child).is_some()
+            });
+            let idx = match idx {
+                Some(idx) if orphans[idx].1.attached_children.is_empty() => idx,
+                _ => return (Vec::new(), None),
+            };
+            let (_, mut s) = orphans.swap_remove(idx);
+            dedup_index.lock(|index| s.release_shared(index));
+            (Vec::new(), Some(s))
+        });
+        // the pages are unpinned out of the lock
+        drop(generations);
+        if let Some(s) = image {
+            crate::log::info!("release the unregistered image {} ({} pages)", key, s.pages);
+            drop(s);
+        }
+    }
+
     pub fn unregister(&mut self, key: usize) {
-        self.registered_processes.remove(&key);
+        self.remove(key);
+    }
+
+    /// Keep the image `key` registered after the process preparing it exits
+    ///
+    /// Return
+    /// * MitosisError::AlreadyExists if the image has been detached
+    pub fn detach_image(&mut self, key: usize, policy: DetachedPolicy) -> MitosisResult<()> {
+        let Self {
+            lock,
+            registered_processes,
+            evictable_pages,
+            ..
+        } = self;
+        lock.lock(|_| {
+            let s = registered_processes
+                .get_mut(&key)
+                .ok_or(MitosisError::NotFound)?;
+            if s.detached.is_some() {
+                return Err(MitosisError::AlreadyExists);
+            }
+            if policy.evictable {
+                evictable_pages.fetch_add(s.pages, SeqCst);
+            }
+            s.detached = Some(DetachedImage {
+                policy,
+                since: KTimer::new(),
+            });
+            Ok(())
+        })
+    }
+
+    /// Unregister the detached image `key` on behalf of the user `uid`
+    ///
+    /// Return
+    /// * MitosisError::InvalidArgument if the image is still owned by the process preparing it
+    /// * MitosisError::PermissionDenied if the user does not own the image
+    pub fn drop_image(&mut self, key: usize, uid: u32, privileged: bool) -> MitosisResult<()> {
+        let owner = self.lock.lock(|_| {
+            let s = self
+                .registered_processes
+                .get(&key)
+                .ok_or(MitosisError::NotFound)?;
+            s.detached
+                .as_ref()
+                .map(|d| d.policy.owner)
+                .ok_or(MitosisError::InvalidArgument)
+        })?;
+        if owner != uid && !privileged {
+            return Err(MitosisError::PermissionDenied);
+        }
+        self.unregister(key);
+        Ok(())
+    }
+
+    /// Unregister the detached images whose TTLs have passed.
+    /// The images with attached children are kept until the children exit (or detach),
+    /// as the children still read their pages.
+    fn reap_expired(&mut self) {
+        let expired = |s: &ProcessBundler| {
+            s.attached_children.is_empty()
+                && s.detached.as_ref().map(|d| d.is_expired()).unwrap_or(false)
+        };
+        let candidates: Vec<usize> = self.lock.lock(|_| {
+            self.registered_processes
+                .iter()
+                .filter(|(_, s)| expired(s))
+                .map(|(k, _)| *k)
+                .collect()
+        });
+        for key in candidates {
+            // a child may attach in between
+            if self.remove_if(key, expired).is_some() {
+                crate::log::info!("the detached image {} expires", key);
+            }
+        }
+    }
+
+    /// Unregister the evictable detached images without attached children,
+    /// the least recently used first, until at least `pages` are freed
+    fn evict(&mut self, pages: usize) {
+        let evictable = |s: &ProcessBundler| {
+            s.attached_children.is_empty()
+                && s.detached.as_ref().map(|d| d.policy.evictable).unwrap_or(false)
+        };
+        let mut candidates: Vec<(i64, usize)> = self.lock.lock(|_| {
+            self.registered_processes
+                .iter()
+                .filter(|(_, s)| evictable(s))
+                .map(|(k, s)| (s.last_used.load(SeqCst), *k))
+                .collect()
+        });
+        candidates.sort_unstable();
+
+        let mut freed = 0;
+        for (_, key) in candidates {
+            if freed >= pages {
+                break;
+            }
+            if let Some(n) = self.remove_if(key, evictable) {
+                crate::log::info!("evict the detached image {} ({} pages)", key, n);
+                freed += n;
+            }
+        }
+    }
+
+    /// Return
+    /// * None if the key has been taken meanwhile, and the image is dropped
+    fn insert(&mut self, key: usize, mut bundler: ProcessBundler) -> core::option::Option<()> {
+        bundler.epoch = self.next_epoch.fetch_add(1, SeqCst);
+        let Self {
+            lock,
+            dedup_index,
+            registered_processes,
+            ..
+        } = self;
+        let dropped = lock.lock(|_| {
+            if registered_processes.contains_key(&key) {
+                return Some(bundler);
+            }
+            registered_processes.insert(key, bundler);
+            None
+        });
+        let mut dropped = match dropped {
+            Some(s) => s,
+            None => return Some(()),
+        };
+        crate::log::warn!("the register key {} has been taken during the prepare", key);
+        dedup_index.lock(|index| dropped.release_shared(index));
+        None
+    }
+
+    fn remove(&mut self, key: usize) {
+        self.remove_if(key, |_| true);
+        // no one polls the reports once the image is gone
+        unsafe { crate::get_child_exit_service_ref() }.remove(key);
+    }
+
+    /// Unregister the image `key` if `pred` holds, checked under the lock.
+    /// An image with attached children is kept (as an orphan) until they release it.
+    ///
+    /// Return
+    /// * the number of pages of the removed image
+    fn remove_if(
+        &mut self,
+        key: usize,
+        pred: impl Fn(&ProcessBundler) -> bool,
+    ) -> core::option::Option<usize> {
+        let Self {
+            lock,
+            dedup_index,
+            registered_processes,
+            orphans,
+            evictable_pages,
+            ..
+        } = self;
+        let (pages, removed) = lock.lock(|_| {
+            if !pred(registered_processes.get(&key)?) {
+                return None;
+            }
+            let mut s = registered_processes.remove(&key)?;
+            if s.detached.as_ref().map(|d| d.policy.evictable).unwrap_or(false) {
+                evictable_pages.fetch_sub(s.pages, SeqCst);
+            }
+            let pages = s.pages;
+            if !s.attached_children.is_empty() {
+                crate::log::info!(
+                    "unregister process {} while {} children still read its pages",
+                    key,
+                    s.attached_children.len()
+                );
+                // the registration drops its reference, and the last child drops the image
+                orphans.push((key, s));
+                return Some((pages, None));
+            }
+            dedup_index.lock(|index| s.release_shared(index));
+            Some((pages, Some(s)))
+        })?;
+        // the pages are unpinned out of the lock
+        drop(removed);
+        Some(pages)
+    }
+
+    extern "C" fn reaper(_ctx: *mut c_void) -> i32 {
+        let service = unsafe { crate::get_sps_mut() };
+        while !kthread::should_stop() {
+            service.reap_expired();
+            let pages = service.pressure.swap(0, SeqCst);
+            if pages > 0 {
+                service.evict(pages);
+            }
+            kthread::sleep(1);
+        }
+        0
+    }
+
+    /// Called by the shrinker
+    unsafe extern "C" fn count_evictable() -> c_ulong {
+        crate::get_sps_ref().evictable_pages.load(SeqCst) as _
+    }
+
+    /// Called by the shrinker in the reclaim context, where the images cannot be freed.
+    /// The requests are not accumulated: the reaper frees at most the largest one
+    /// since its last run, bounded by the evictable pages.
+    unsafe extern "C" fn request_eviction(nr: c_ulong) -> c_ulong {
+        let service = crate::get_sps_ref();
+        let nr = core::cmp::min(nr as usize, service.evictable_pages.load(SeqCst));
+        service.pressure.fetch_max(nr, SeqCst);
+        0
+    }
+}
+
+impl Drop for ShadowProcessService {
+    fn drop(&mut self) {
+        if self.shrinker_registered {
+            unsafe { crate::bindings::pmem_unregister_shrinker() };
+        }
+        if let Some(reaper) = self.reaper.take() {
+            reaper.join();
+        }
     }
 }
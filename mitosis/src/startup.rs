@@ -8,7 +8,54 @@ use rust_kernel_linux_util::timer::KTimer;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 
+/// The features, limits of this MITOSIS instance, which can be queried by the user
+pub fn get_info() -> mitosis_protocol::MitosisInfo {
+    use mitosis_protocol::*;
+
+    let mut features = 0;
+    if cfg!(feature = "cow") {
+        features |= FEATURE_COW;
+    }
+    if cfg!(feature = "eager-resume") {
+        features |= FEATURE_EAGER_RESUME;
+    }
+    if cfg!(feature = "prefetch") {
+        features |= FEATURE_PREFETCH;
+    }
+    if cfg!(feature = "page-cache") {
+        features |= FEATURE_PAGE_CACHE;
+    }
+    if cfg!(feature = "use_rc") {
+        features |= FEATURE_USE_RC;
+    }
+    if cfg!(feature = "resume-profile") {
+        features |= FEATURE_RESUME_PROFILE;
+    }
+    if unsafe { crate::get_membership_ref() }.is_auto_machine_id() {
+        features |= FEATURE_AUTO_MACHINE_ID;
+    }
+
+    unsafe {
+        MitosisInfo {
+            abi_version: ABI_VERSION,
+            size: core::mem::size_of::<MitosisInfo>() as _,
+            features,
+            machine_id: crate::get_mac_id() as _,
+            nic_count: crate::rdma_contexts::get_ref().len() as _,
+            max_cluster_size: *crate::max_cluster_size::get_ref() as _,
+            max_caller_num: *crate::max_caller_num::get_ref() as _,
+            prefetch_step: crate::PREFETCH_STEP as _,
+            reserved: 0,
+        }
+    }
+}
+
 pub fn check_global_configurations() {
+    crate::log::info!(
+        "[check]: ioctl protocol ABI version {}.",
+        mitosis_protocol::ABI_VERSION
+    );
+
     if cfg!(feature = "eager-resume") {
         crate::log::info!("[check]: eager resume mode is on.")
     } else {
@@ -31,7 +78,7 @@ pub fn check_global_configurations() {
     }
 
     if cfg!(feature = "page-cache") {
-        crate::log::info!("[check]: Cache remote page table optimization is enabled.")
+        crate::log::info!("[check]: Cache remote page tables and pages optimization is enabled.")
     } else {
         crate::log::info!("[check]: Not cache remote page table.")
     }
@@ -60,6 +107,28 @@ pub fn init_mitosis(config: &crate::Config) -> core::option::Option<()> {
     start_rdma(config).expect("fail to create RDMA context");
     crate::log::info!("Initialize RDMA context done");
 
+    // membership of the cluster
+    unsafe {
+        crate::membership_service::init(
+            crate::membership::MembershipService::new(config)
+                .expect("Failed to create the membership service"),
+        );
+
+        if config.auto_machine_id {
+            let my_gid = crate::get_membership_ref().lock(|t| t.my_gid().clone());
+            let id = crate::membership::provisional_machine_id(&my_gid, config.max_cluster_size);
+            *crate::mac_id::get_mut() = id;
+            crate::log::info!("Auto assign provisional machine ID={} from gid {}", id, my_gid);
+        } else if config.machine_id >= config.max_cluster_size {
+            crate::log::error!(
+                "machine ID {} exceeds the max cluster size {}",
+                config.machine_id,
+                config.max_cluster_size
+            );
+            return None;
+        }
+    };
+
     // high-level RDMA-related data structures
 
     // UD factory
@@ -143,15 +212,42 @@ pub fn init_mitosis(config: &crate::Config) -> core::option::Option<()> {
     // Global shadow process service
     unsafe { crate::sp_service::init(crate::shadow_process_service::ShadowProcessService::new()) };
 
+    // workers of the prepares
+    unsafe {
+        crate::prepare_worker_service::init(crate::prepare_worker::PrepareWorkerService::new());
+        crate::prepare_worker_service::get_mut().start(config.prepare_threads)?;
+    };
+
     // Memory pool for the shadow process service
     // The context is not important here as we only allocate a slice of memory
     unsafe { crate::mem_pool::init(crate::mem_pools::MemPool::new(config.mem_pool_size, crate::get_rdma_context_ref(0).unwrap().clone())) };
 
+    // scratch pages of the faults on the compressed pages
+    match crate::shadow_process::ScratchPages::new(config) {
+        Some(scratch) => unsafe { crate::compress_scratch::init(scratch) },
+        None => {
+            crate::log::error!("failed to allocate the scratch pages of the compressed pages");
+            return None;
+        }
+    };
+
+    // exit reports of the children, filled by the RPC handlers
+    unsafe { crate::child_exit_service::init(crate::child_exit::ChildExitService::new()) };
+
+    // reaper of the detached images, which removes their exit reports as well
+    if unsafe { crate::get_sps_mut() }.start_reaper().is_none() {
+        crate::log::error!("failed to start the reaper of the detached images");
+        return None;
+    }
+
     // cache for storing the remote page table cache
     unsafe {
         crate::global_pt_cache::init(crate::remote_pt_cache::RemotePageTableCache::default())
     };
 
+    // cache for the remote pages read by the concurrent children on this host
+    unsafe { crate::global_page_cache::init(crate::remote_page_cache::SharedPageCache::default()) };
+
 
     unsafe {
         crate::service_rpc::init(Default::default());
@@ -195,6 +291,23 @@ pub fn init_rpc(config: &crate::Config,
     };
 
 
+    // workers of the asynchronous resumes, which use the RPC callers
+    unsafe {
+        crate::resume_worker_service::init(crate::resume_worker::ResumeWorkerService::new());
+        crate::resume_worker_service::get_mut().start(config.async_resume_threads)?;
+    };
+
+    // materializers of the resumed children, which also use the RPC callers
+    unsafe {
+        crate::materialize_service::init(crate::materializer::MaterializeService::new());
+    };
+
+    // reporter of the exits of the resumed children, which also uses the RPC callers
+    unsafe {
+        crate::exit_reporter::init(crate::child_exit::ExitReporter::new());
+        crate::exit_reporter::get_mut().start()?;
+    };
+
     crate::log::info!("Start waiting for the RPC servers to start...");
     crate::rpc_service::wait_handlers_ready_barrier(config.rpc_threads_num);
     crate::log::info!("All RPC thread handlers initialized!");
@@ -218,6 +331,14 @@ pub fn start_instance(config: crate::Config) -> core::option::Option<()> {
 pub fn end_instance() {
     crate::log::info!("Stop MITOSIS instance, start cleaning up...");
     unsafe {
+        // stop the resume workers before the resources they use
+        crate::resume_worker_service::drop();
+        crate::materialize_service::drop();
+        crate::exit_reporter::drop();
+
+        // notify the peers, so that they can release my sessions
+        crate::get_membership_ref().leave();
+
         crate::ud_factories::drop();
         crate::dc_factories::drop();
         #[cfg(feature = "use_rc")]
@@ -241,13 +362,18 @@ pub fn end_instance() {
         crate::dc_pool_service_async::drop();
 
         crate::service_caller_pool::drop();
+        crate::membership_service::drop();
 
 
         crate::log::debug!("drop shadow process service");
         crate::sp_service::drop();
+        crate::prepare_worker_service::drop();
         crate::mem_pool::drop();
+        crate::compress_scratch::drop();
 
         crate::global_pt_cache::drop();
+        crate::global_page_cache::drop();
+        crate::child_exit_service::drop();
 
         crate::global_locks::drop();
     };
@@ -257,11 +383,14 @@ pub fn end_instance() {
 }
 
 /// calculate the session ID of the remote end handler
-/// FIXME: this calculation is not very precise, need further refinement
+/// The machine ID is either configured or negotiated by the membership service,
+/// which guarantees that it is smaller than the max cluster size.
+/// Note: `mac_id == max_cluster_size` is reserved for the bootstrap session of the membership.
 pub fn calculate_session_id(mac_id: usize, thread_id: usize, max_callers: usize) -> usize {
     mac_id * max_callers + thread_id
 }
 
+use crate::rpc_caller_pool::ConnectError;
 use os_network::{ud::UDHyperMeta, KRdmaKit::comm_manager::Explorer};
 
 /// Connect the RPC session to the remote nodes
@@ -281,7 +410,7 @@ pub fn probe_remote_rpc_end(
         let gid = Explorer::string_to_gid(&connect_info.gid).ok()?;
         // assert_ne!(session_id, my_session_id);
 
-        unsafe { crate::get_rpc_caller_pool_mut() }.connect_session_at(
+        let res = unsafe { crate::get_rpc_caller_pool_mut() }.connect_session_at(
             i,
             session_id, // Notice: it is very important to ensure that session ID is unique!
             my_session_id,
@@ -292,7 +421,15 @@ pub fn probe_remote_rpc_end(
                 qd_hint: connect_info.qd_hint,
                 local_port: connect_info.local_port,
             },
-        )?;
+        );
+        match res {
+            // the session may have been connected during the membership handshake
+            Ok(_) | Err(ConnectError::AlreadyConnected) => {}
+            Err(e) => {
+                crate::log::error!("failed to connect session {}: {:?}", session_id, e);
+                return None;
+            }
+        }
     }
     Some(())
 }
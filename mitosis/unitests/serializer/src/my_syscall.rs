@@ -262,8 +262,11 @@ impl MySyscallHandler {
 
         let descriptor = ParentDescriptor {
             regs: task.generate_reg_descriptor(),
+            layout: task.generate_layout_descriptor(),
             page_table: pg_table,
             vma,
+            files: Vec::new(),
+            excluded: Vec::new(),
             machine_info: mac_info.clone(),
         };
 
@@ -288,6 +291,9 @@ impl MySyscallHandler {
             return 0;
         }
         let result = result.unwrap();
+        if result.layout != descriptor.layout {
+            log::error!("failed to deserialize mm layout");
+        }
         if result.page_table.len() != descriptor.page_table.len() {
             log::error!(
                 "failed to deserialize page table, {}, {}",
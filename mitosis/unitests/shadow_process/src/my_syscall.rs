@@ -25,6 +25,7 @@ impl FileOperations for MySyscallHandler {
             1 => self.handle_page_test(arg),
             3 => self.handle_page_table_test(arg),
             4 => self.handle_basic_2(arg),
+            5 => self.handle_zeroed_page_test(arg),
             _ => {
                 crate::log::error!("unknown system call command ID {}", cmd);
                 -1
@@ -118,4 +119,42 @@ impl MySyscallHandler {
         log::debug!("page table test done");
         0
     }
+
+    // `arg` points to two pages: the first is written and then zeroed, the second is written
+    #[inline(always)]
+    fn handle_zeroed_page_test(&self, arg: c_ulong) -> c_long {
+        use mitosis::remote_mapping::PhysAddr;
+
+        let mut mac_info: mitosis::descriptors::RDMADescriptor = Default::default();
+        mac_info.set_rkey(0xdeadbeaf).set_service_id(73);
+
+        let sp = ShadowProcess::new_cow(mac_info);
+        let des = sp.get_descriptor_ref();
+        let addr = arg as mm::VirtAddrType;
+        let idx = match des
+            .vma
+            .iter()
+            .position(|v| v.get_start() <= addr && addr + 2 * 4096 <= v.get_end())
+        {
+            Some(idx) => idx,
+            None => {
+                log::error!("no VMA covers the pages at {:x}", addr);
+                return 0;
+            }
+        };
+
+        let start = des.vma[idx].get_start();
+        let lookup = |va: mm::VirtAddrType| des.page_table[idx].lookup((va - start) as _);
+        match (lookup(addr), lookup(addr + 4096)) {
+            (Some(zeroed), Some(written))
+                if PhysAddr::new(zeroed).is_zero() && !PhysAddr::new(written).is_zero() =>
+            {
+                log::debug!("the zeroed page is elided");
+            }
+            (zeroed, written) => {
+                log::error!("wrong entries of the pages: {:?} {:?}", zeroed, written);
+            }
+        }
+        0
+    }
 }
@@ -81,3 +81,26 @@ fn test_basic_2() {
         assert_eq!(dmesg_contains(&String::from("ERROR")), false);
     });
 }
+
+#[test]
+fn test_zeroed_page() {
+    with_kernel_module(|| {
+        let mut client = MClientOptions::new()
+            .set_device_name(DEFAULT_SYSCALL_PATH.to_string())
+            .open()
+            .unwrap();
+
+        // the first page is written and then zeroed, the second one stays written
+        let layout = std::alloc::Layout::from_size_align(2 * 4096, 4096).unwrap();
+        let buf = unsafe { std::alloc::alloc(layout) };
+        unsafe {
+            std::ptr::write_bytes(buf, 0xff, 2 * 4096);
+            std::ptr::write_bytes(buf, 0, 4096);
+        }
+
+        client.test_w_arg(5, buf).unwrap();
+        unsafe { std::alloc::dealloc(buf, layout) };
+
+        assert_eq!(dmesg_contains(&String::from("ERROR")), false);
+    });
+}
@@ -107,6 +107,7 @@ where
         meta: SS::HyperMeta,
     ) -> Result<(), SS::IOResult>
     where
+        SS: RPCConn<IOResult = <SS as Future>::Error>,
         SS::ReqPayload: ToBytes,
     {
         let mut msg_buf = SS::ReqPayload::create(R::MTU, 0, self.inner_receiver.get_context());
@@ -118,15 +119,60 @@ where
         // FIXME: timeout?
         // In our UD-based session, it will never happen
         // Yet, we should handle this
-        match crate::block_on(&mut s) {
-            Ok(_) => {}
-            _ => panic!(),
-        };
+        crate::block_on(&mut s)?;
 
         self.connected_sessions.insert(session_id, (s, msg_buf));
         Ok(())
     }
 
+    /// Dis-connect the session, so that the remote end can release the corresponding session.
+    /// The remote end will not reply to the dis-connect message.
+    ///
+    /// Return `Ok(false)` if the session does not exist.
+    ///
+    pub fn disconnect(
+        &mut self,
+        session_id: usize,
+        my_session_id: usize,
+    ) -> Result<bool, SS::IOResult>
+    where
+        SS: RPCConn<IOResult = <SS as Future>::Error>,
+    {
+        let (mut s, mut msg_buf) = match self.connected_sessions.remove(&session_id) {
+            Some(ss) => ss,
+            None => return Ok(false),
+        };
+
+        let req_sz = DisConnectStubFactory::new(my_session_id)
+            .generate(msg_buf.get_bytes_mut())
+            .unwrap();
+        s.post(&msg_buf, req_sz, true)?;
+
+        // wait for the message to be sent, so that the buffer can be safely released
+        crate::block_on(&mut s)?;
+        Ok(true)
+    }
+
+    /// Only remove the session at the local side, e.g., the remote end refused the connection.
+    pub fn remove_session(&mut self, session_id: usize) -> bool {
+        self.connected_sessions.remove(&session_id).is_some()
+    }
+
+    /// Change the user-defined ID of a connected session.
+    /// Return false if the old session does not exist or the new ID has been used.
+    pub fn rename_session(&mut self, session_id: usize, new_session_id: usize) -> bool {
+        if self.connected_sessions.contains_key(&new_session_id) {
+            return false;
+        }
+        match self.connected_sessions.remove(&session_id) {
+            Some(ss) => {
+                self.connected_sessions.insert(new_session_id, ss);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Get the corresponding session
     pub fn get_ss(&self, session_id: usize) -> Option<&(SS, <SS as RPCConn<SS>>::ReqPayload)> {
         self.connected_sessions.get(&session_id)
@@ -189,6 +235,7 @@ where
                                     })))
                                 }
                                 header::ReplyStatus::NotExist => Err(CallError::no_id()),
+                                header::ReplyStatus::Refused => Err(CallError::connect_error()),
                             }
                         }
                         None => Err(CallError::fatal()),
@@ -322,4 +369,12 @@ impl<T> CallError<T> {
     pub fn connect_error() -> CallError<T> {
         CallError(CallKind::ConnectError)
     }
+
+    /// Returns `true` if the remote end refused the connection
+    pub fn is_connect_error(&self) -> bool {
+        match self.0 {
+            CallKind::ConnectError => true,
+            _ => false,
+        }
+    }
 }
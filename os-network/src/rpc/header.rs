@@ -32,6 +32,7 @@ pub enum ReplyStatus {
     #[default]
     Ok = 1, // a success call
     NotExist = 3, // function is not registered in the service
+    Refused = 4, // the session ID has been occupied by another session
 }
 
 #[derive(Debug, Default)]
@@ -40,6 +41,7 @@ enum RPCMeta {
     Request(CallStub),
     Reply(ReplyStatus),
     Connect(ConnectStub),
+    DisConnect(ConnectStub),
     #[default]
     None,
 }
@@ -71,6 +73,14 @@ impl MsgHeader {
         }
     }
 
+    pub fn gen_disconnect_stub(session_id: usize) -> Self {
+        Self {
+            marker: ReqType::DisConnect,
+            payload: 0,
+            meta: RPCMeta::DisConnect(ConnectStub(session_id)),
+        }
+    }
+
     pub fn gen_call_stub(session_id: usize, rpc_id: usize, payload: usize) -> Self {
         Self {
             marker: ReqType::Request,
@@ -106,6 +116,14 @@ impl MsgHeader {
         }
     }
 
+    #[inline]
+    pub fn get_disconnect_stub(&self) -> core::option::Option<&ConnectStub> {
+        match &self.meta {
+            RPCMeta::DisConnect(s) => Some(s),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn get_reply_stub(&self) -> core::option::Option<&ReplyStatus> {
         match &self.meta {
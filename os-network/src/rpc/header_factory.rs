@@ -27,6 +27,25 @@ impl ConnectStubFactory {
     }
 }
 
+pub struct DisConnectStubFactory(usize);
+
+impl DisConnectStubFactory {
+    #[inline]
+    pub fn new(id: usize) -> Self {
+        Self(id)
+    }
+
+    /// Generate the dis-connect stub, it carries no payload
+    ///
+    /// Return
+    /// * if succeed, return the real message size
+    ///
+    #[inline]
+    pub fn generate(self, msg: &mut BytesMut) -> core::option::Option<usize> {
+        unsafe { msg.memcpy_serialize_at(0, &MsgHeader::gen_disconnect_stub(self.0)) }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CallStubFactory {
     session_id: usize,
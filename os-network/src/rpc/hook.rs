@@ -98,6 +98,41 @@ where
         } 
         return ret;           
     }
+
+    /// Reply a refusal to a connect request without adding it to the connected sessions.
+    /// The reply is sent through a temporal session created from the request's meta.
+    fn send_refuse_reply(&mut self, connect_args: &BytesMut) -> Result<(), Error<R::Error>>
+    where
+        MF: MetaFactory<Meta = F::ConnMeta>,
+        R: GetContext<
+            Context = <<<F as RPCFactory>::ConnType as RPCConn>::ReqPayload as AllocMsgBuf>::Context,
+        >,
+    {
+        let mut session_meta: <MF as MetaFactory>::HyperMeta = Default::default();
+        unsafe {
+            connect_args
+                .memcpy_deserialize(&mut session_meta)
+                .ok_or(Error::corrupted())?
+        };
+        let connect_meta = self
+            .meta_factory
+            .create_meta(session_meta)
+            .map_err(|_| Error::session_creation_error())?;
+        let mut session = self
+            .session_factory
+            .create(connect_meta)
+            .map_err(|_| Error::session_creation_error())?;
+
+        let mut buf = R::MsgBuf::create(R::MTU, 0, self.transport.get_context());
+        let msg_sz = ReplyStubFactory::new(ReplyStatus::Refused, 0)
+            .generate(buf.get_bytes_mut())
+            .unwrap(); // should succeed
+        session.post(&buf, msg_sz, true).map_err(|_| Error::fatal())?;
+
+        // the temporal session (and its buffer) is released after the reply is sent
+        crate::block_on(&mut session).map_err(|_| Error::fatal())?;
+        Ok(())
+    }
 }
 
 #[allow(unused_imports)]
@@ -185,7 +220,9 @@ where
                                 "duplicate connect session ID: {}",
                                 meta.get_session_id()
                             );
-                            return Err(Error::session_creation_error());
+                            // the session ID is occupied by another client,
+                            // we must refuse it explicitly; otherwise the client will hang
+                            self.send_refuse_reply(&rpc_args)?;
                         }
 
                         // handle connect message done
@@ -230,8 +267,14 @@ where
 
                     // handle the session dis-connect
                     super::header::ReqType::DisConnect => {
-                        // TODO @Yuhan: handle the dis connect part
-                        todo!();
+                        let meta = msg_header.get_disconnect_stub().ok_or(Error::corrupted())?;
+                        // the client will not wait for the reply
+                        if self.connected_sessions.remove(&meta.get_session_id()).is_none() {
+                            crate::log::debug!(
+                                "dis-connect a non-exist session ID: {}",
+                                meta.get_session_id()
+                            );
+                        }
                     }
 
                     // Error case
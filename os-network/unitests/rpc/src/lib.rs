@@ -85,7 +85,18 @@ fn test_rpc_headers() -> Result<(), TestError> {
         })?;
     if stub.get_session_id() != my_session_id {
         log::error!("Failed to gen correct session id, expected {}, got: {}.", my_session_id, stub.get_session_id());
-        Err(TestError::Error("Session id error."))
+        return Err(TestError::Error("Session id error."));
+    }
+
+    let disconnect_header = MsgHeader::gen_disconnect_stub(my_session_id);
+    let stub = disconnect_header.get_disconnect_stub()
+        .ok_or_else(|| {
+            log::error!("Failed to gen dis-connection stub.");
+            TestError::Error("Dis-connect stub gen error.")
+        })?;
+    if !disconnect_header.is_disconnect() || stub.get_session_id() != my_session_id {
+        log::error!("Failed to gen correct dis-connect stub: {:?}.", disconnect_header);
+        Err(TestError::Error("Dis-connect stub error."))
     } else {
         Ok(())
     }
@@ -324,6 +335,17 @@ fn test_ud_rpc_elegant() -> Result<(), TestError> {
         TestError::Error("Endpoint error.")
     })?;
 
+    // the endpoint used to issue a duplicate connect request
+    let dup_endpoint = factory.create_meta(UDHyperMeta {
+        gid,
+        service_id,
+        qd_hint: DEFAULT_QD_HINT as usize,
+        local_port: client_port,
+    }).map_err(|_| {
+        log::error!("Create endpoint error.");
+        TestError::Error("Endpoint error.")
+    })?;
+
     // create client-side session and receiver
     let client_session = client_ud.create(endpoint).unwrap();
     let mut client_receiver = UDReceiverFactory::new()
@@ -421,6 +443,60 @@ fn test_ud_rpc_elegant() -> Result<(), TestError> {
         })?;
     log::debug!("sanity check client rpc result: {:?}", res.1);
 
+    // a duplicate connect with the same session ID must be refused by the server
+    let mut caller = caller_timeout.into_inner();
+    let dup_session = caller.get_transport_mut().create(dup_endpoint).unwrap();
+    caller
+        .connect(
+            my_session_id + 1,
+            my_session_id,
+            dup_session,
+            UDHyperMeta {
+                gid,
+                service_id: service_id,
+                qd_hint: CLIENT_QD_HINT as usize,
+                local_port: client_port,
+            },
+        ).map_err(|_| {
+            log::error!("Client caller connect error.");
+            TestError::Error("Caller connect error.")
+        })?;
+
+    rpc_server.reset_timer(timeout_usec);
+    block_on(&mut rpc_server)
+        .map_err(|e| {
+            log::error!("Server receiver process err {:?}", e);
+            TestError::Error("Server receiver error.")
+        })?;
+
+    let mut caller_timeout = Timeout::new(caller, timeout_usec);
+    match block_on(&mut caller_timeout) {
+        Err(e) if e.into_inner().map(|e| e.is_connect_error()).unwrap_or(false) => {}
+        _ => {
+            log::error!("The duplicate connect is not refused.");
+            return Err(TestError::Error("Duplicate connect error."));
+        }
+    };
+
+    // the refused session is only removed locally, then dis-connect the valid one
+    let mut caller = caller_timeout.into_inner();
+    assert!(caller.remove_session(my_session_id + 1));
+    let disconnected = caller
+        .disconnect(my_session_id, my_session_id)
+        .map_err(|_| {
+            log::error!("Client caller dis-connect error.");
+            TestError::Error("Caller dis-connect error.")
+        })?;
+    assert!(disconnected);
+    assert!(!caller.session_connected(my_session_id));
+
+    rpc_server.reset_timer(timeout_usec);
+    block_on(&mut rpc_server)
+        .map_err(|e| {
+            log::error!("Server receiver process err {:?}", e);
+            TestError::Error("Server receiver error.")
+        })?;
+
     let rpc_server = rpc_server.into_inner();
     log::debug!("final check hook status {:?}", rpc_server);
     Ok(())    